@@ -0,0 +1,19 @@
+//! Satisfies the `-lOpenCL` that `cl-sys` asks for with an empty archive,
+//! so binaries build and start without an OpenCL library; `src/icd.rs`
+//! defines the entry points and loads the real library at run time.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    // macOS links the OpenCL framework, which every system has.
+    if env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("macos") {
+        return;
+    }
+    let dir = PathBuf::from(env::var("OUT_DIR").expect("cargo sets OUT_DIR"));
+    let name = if env::var("CARGO_CFG_TARGET_ENV").as_deref() == Ok("msvc") { "OpenCL.lib" } else { "libOpenCL.a" };
+    fs::write(dir.join(name), b"!<arch>\n").expect("OUT_DIR is writable");
+    println!("cargo:rustc-link-search=native={}", dir.display());
+}
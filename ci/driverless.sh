@@ -0,0 +1,22 @@
+#!/bin/sh
+# Builds and runs with no OpenCL library anywhere: nothing on the link or
+# load path, and SIMPLE_GPU_OPENCL pointing at a file that does not exist.
+# Binaries must not depend on libOpenCL and must fall back to the
+# interpreter.
+set -eu
+cd "$(dirname "$0")/.."
+unset LIBRARY_PATH LD_LIBRARY_PATH
+export SIMPLE_GPU_OPENCL=/nonexistent/libOpenCL.so
+
+cargo build --offline --bins --examples
+for bin in target/debug/simple_gpu target/debug/examples/id_check target/debug/examples/operator \
+    target/debug/examples/shuffle target/debug/examples/vector_byte; do
+    if readelf -d "$bin" | grep -q libOpenCL; then
+        echo "$bin depends on libOpenCL" >&2
+        exit 1
+    fi
+done
+for example in id_check operator shuffle vector_byte fft; do
+    "target/debug/examples/$example"
+done
+cargo test --offline
@@ -2,7 +2,8 @@ use ocl::builders::ProgramBuilder;
 use ocl::{ Context, Device, DeviceType,  Platform, Queue};
 use std::{fs};
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
 
     let platforms = Platform::list();
 
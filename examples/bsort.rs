@@ -3,7 +3,8 @@ use ocl::{builders::KernelBuilder, flags, Buffer, Context, Device, DeviceType, P
 const ASCENDING: i32 = 0;
 const DESCENDING: i32 = -1;
 
-fn main() -> ocl::Result<()> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
     let src = std::fs::read_to_string("hello_kernel.cl").expect("Failed to read bsort8.cl");
     
     let platform = Platform::list().into_iter().next().unwrap();
@@ -1,10 +1,11 @@
 use ocl::{builders::KernelBuilder, flags, Buffer, Context, Device, DeviceType, Platform, Program, Queue};
-use rand::Rng;
+use simple_gpu::datagen::{DataGen, Distribution};
+use simple_gpu::reference;
 
-const DIRECTION: i32 = 0;
 const NUM_FLOATS: usize = 8192*4;
 
-fn main() -> ocl::Result<()> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
     let src = std::fs::read_to_string("hello_kernel.cl").expect("Failed to read bsort.cl");
     
     let platform = Platform::list().into_iter().next().unwrap();
@@ -14,18 +15,18 @@ fn main() -> ocl::Result<()> {
     }
     let device = devices.into_iter().next().unwrap();
     
-    let context = Context::builder() .platform(platform).devices(device.clone()) .build()?;
-    let queue = Queue::new(&context, device.clone(), None)?;
+    let context = Context::builder() .platform(platform).devices(device) .build()?;
+    let queue = Queue::new(&context, device, None)?;
     
-    let mut data: Vec<f32> = (0..NUM_FLOATS)
-        .map(|_| rand::thread_rng().r#gen::<f32>() * 10000.0)
-        .collect();
+    let mut datagen = DataGen::from_env();
+    let input: Vec<f32> = datagen.values(Distribution::Uniform, NUM_FLOATS, 10000.0);
     
+    let mut data = input.clone();
     println!("First 16 values: {:?}", &data[0..16]);
     
     let program = Program::builder()
         .src(src)
-        .devices(device.clone())
+        .devices(device)
         .build(&context)?;
     
     let max_wg_size = device.max_wg_size()?;
@@ -71,30 +72,23 @@ fn main() -> ocl::Result<()> {
     println!("First 16 sorted values: {:?}", &data[0..16]);
     println!("Last 16 sorted values: {:?}", &data[NUM_FLOATS-16..NUM_FLOATS]);
     
-    let mut check = true;
-    if DIRECTION == 0 {
-        for i in 1..NUM_FLOATS {
-            if data[i] < data[i-1] {
-                check = false;
-                println!("Sort failed at index {}: {} > {}", i, data[i-1], data[i]);
-                break;
-            }
+    // bsort_init sorts each work-group's block, alternating direction.
+    let mut expected = input;
+    reference::bsort_init(&mut expected, local_memory_size);
+    let check = match reference::diff_exact(&data, &expected) {
+        Ok(()) => true,
+        Err(mismatch) => {
+            println!("Sort failed: {}", mismatch);
+            false
         }
-    } else {
-        for i in 1..NUM_FLOATS {
-            if data[i] > data[i-1] {
-                check = false;
-                println!("Sort failed at index {}: {} < {}", i, data[i-1], data[i]);
-                break;
-            }
-        }
-    }
+    };
     println!("Data size: {} floats ({:.2} MB)", NUM_FLOATS, NUM_FLOATS as f32 * 4.0 / 1024.0 / 1024.0);
     
     if check {
         println!("Bitonic sort SUCCEEDED!");
     } else {
         println!("Bitonic sort FAILED!");
+        datagen.report_failure();
     }
     Ok(())
 }
\ No newline at end of file
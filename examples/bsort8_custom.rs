@@ -1,11 +1,26 @@
-use ocl::{builders::KernelBuilder, flags, Buffer, Context, Device, DeviceType, Platform, Program, Queue};
+// usage: bsort8_custom [DISTRIBUTION]
+//
+// DISTRIBUTION is one of simple_gpu::datagen's (default uniform); the seed
+// comes from SIMPLE_GPU_SEED when set.
+
+use ocl::{flags, Buffer, Context, Device, DeviceType, Platform, Queue};
+use simple_gpu::datagen::{DataGen, Distribution};
+use simple_gpu::kernel_cache::KernelCache;
 use std::time::Instant;
-use rand::Rng;
 
 const DIRECTION: i32 = 0;
 const NUM_FLOATS: usize = 1048576;
 
-fn main() -> ocl::Result<()> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
+    let distribution = match std::env::args().nth(1).map(|name| name.parse::<Distribution>()) {
+        None => Distribution::Uniform,
+        Some(Ok(distribution)) => distribution,
+        Some(Err(err)) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
     println!("Bitonic Sort - Processing {} floats", NUM_FLOATS);
     
     let src = std::fs::read_to_string("hello_kernel.cl").expect("Failed to read bsort.cl");
@@ -19,25 +34,18 @@ fn main() -> ocl::Result<()> {
     
     let context = Context::builder()
         .platform(platform)
-        .devices(device.clone())
+        .devices(device)
         .build()?;
-    let queue = Queue::new(&context, device.clone(), None)?;
+    let queue = Queue::new(&context, device, None)?;
     
-    println!("Generating random data...");
+    println!("Generating {} data...", distribution);
     let start_time = Instant::now();
-    let mut rng = rand::thread_rng();
-    let mut data: Vec<f32> = (0..NUM_FLOATS)
-        .map(|_| rng.r#gen::<f32>() * 1000000.0)
-        .collect();
+    let mut datagen = DataGen::from_env();
+    let mut data: Vec<f32> = datagen.values(distribution, NUM_FLOATS, 1000000.0);
     println!("Data generation took: {:?}", start_time.elapsed());
     
     println!("First 16 values: {:?}", &data[0..16]);
     
-    let program = Program::builder()
-        .src(src)
-        .devices(device.clone())
-        .build(&context)?;
-    
     let max_wg_size = device.max_wg_size()?;
     let mut local_size = 1;
     while local_size * 2 <= max_wg_size {
@@ -65,114 +73,55 @@ fn main() -> ocl::Result<()> {
     println!("Starting bitonic sort...");
     let sort_start = Instant::now();
     
-    let kernel_init = KernelBuilder::new()
-        .program(&program)
-        .name("bsort_init")
-        .queue(queue.clone())
-        .arg(&data_buffer)
-        .arg_local::<f32>(local_memory_size)
-        .global_work_size(global_size)
-        .local_work_size(local_size)
-        .build()?;
-    
-    println!("Executing initial sort...");
-    unsafe {
-        kernel_init.cmd()
-            .queue(&queue)
-            .enq()?;
+    // Each kernel is created once; the loops below only rebind the stage
+    // scalars before re-enqueueing.
+    let mut kernels = KernelCache::build(&context, device, &src)?;
+    for name in ["bsort_init", "bsort_stage_n", "bsort_stage_0", "bsort_merge", "bsort_merge_last"] {
+        let kernel = kernels.kernel(name)?;
+        kernel.set_buffer_by_name("g_data", &data_buffer)?;
+        kernel.set_local_by_name::<f32>("l_data", local_memory_size)?;
+        kernel.global_work_size(global_size).local_work_size(local_size);
     }
-    
+    kernels.set_arg_by_name("bsort_merge", "dir", DIRECTION)?;
+    kernels.set_arg_by_name("bsort_merge_last", "dir", DIRECTION)?;
+
+    println!("Executing initial sort...");
+    unsafe { kernels.enq("bsort_init", &queue)? };
+
     let num_stages = global_size / local_size;
     println!("Number of stages: {}", num_stages);
-    
+
     let mut high_stage = 2;
     while high_stage < num_stages {
         println!("Processing high_stage: {}", high_stage);
-        
+
+        let stage_n = kernels.kernel("bsort_stage_n")?;
+        stage_n.set_arg_by_name("high_stage", high_stage as i32)?;
         let mut stage = high_stage;
         while stage > 1 {
-            let kernel_stage_n = KernelBuilder::new()
-                .program(&program)
-                .name("bsort_stage_n")
-                .queue(queue.clone())
-                .arg(&data_buffer)
-                .arg_local::<f32>(local_memory_size)
-                .arg(stage as i32)
-                .arg(high_stage as i32)
-                .global_work_size(global_size)
-                .local_work_size(local_size)
-                .build()?;
-                
-            unsafe {
-                kernel_stage_n.cmd()
-                    .queue(&queue)
-                    .enq()?;
-            }
-            
+            stage_n.set_arg_by_name("stage", stage as i32)?;
+            unsafe { stage_n.enq(&queue)? };
             stage >>= 1;
         }
-        
-        let kernel_stage_0 = KernelBuilder::new()
-            .program(&program)
-            .name("bsort_stage_0")
-            .queue(queue.clone())
-            .arg(&data_buffer)
-            .arg_local::<f32>(local_memory_size)
-            .arg(high_stage as i32)
-            .global_work_size(global_size)
-            .local_work_size(local_size)
-            .build()?;
-            
-        unsafe {
-            kernel_stage_0.cmd()
-                .queue(&queue)
-                .enq()?;
-        }
-        
+
+        kernels.set_arg_by_name("bsort_stage_0", "high_stage", high_stage as i32)?;
+        unsafe { kernels.enq("bsort_stage_0", &queue)? };
+
         high_stage <<= 1;
     }
-    
+
     println!("Performing bitonic merge...");
+    let merge = kernels.kernel("bsort_merge")?;
     let mut stage = num_stages;
     while stage > 1 {
-        let kernel_merge = KernelBuilder::new()
-            .program(&program)
-            .name("bsort_merge")
-            .queue(queue.clone())
-            .arg(&data_buffer)
-            .arg_local::<f32>(local_memory_size)
-            .arg(stage as i32)
-            .arg(DIRECTION)
-            .global_work_size(global_size)
-            .local_work_size(local_size)
-            .build()?;
-            
-        unsafe {
-            kernel_merge.cmd()
-                .queue(&queue)
-                .enq()?;
-        }
-        
+        merge.set_arg_by_name("stage", stage as i32)?;
+        unsafe { merge.enq(&queue)? };
         stage >>= 1;
     }
-    
-    let kernel_merge_last = KernelBuilder::new()
-        .program(&program)
-        .name("bsort_merge_last")
-        .queue(queue.clone())
-        .arg(&data_buffer)
-        .arg_local::<f32>(local_memory_size)
-        .arg(DIRECTION)
-        .global_work_size(global_size)
-        .local_work_size(local_size)
-        .build()?;
-        
-    unsafe {
-        kernel_merge_last.cmd()
-            .queue(&queue)
-            .enq()?;
-    }
-    
+
+    unsafe { kernels.enq("bsort_merge_last", &queue)? };
+    println!("Kernels created: {}", kernels.len());
+
     println!("Sorting completed in: {:?}", sort_start.elapsed());
     
     println!("Reading results...");
@@ -217,6 +166,7 @@ fn main() -> ocl::Result<()> {
         println!("Bitonic sort SUCCEEDED!");
     } else {
         println!("Bitonic sort FAILED!");
+        datagen.report_failure();
     }
     
     Ok(())
@@ -1,40 +1,35 @@
-use ocl::{ flags, Context, Device, DeviceType, Platform};
+use ocl::flags;
+use simple_gpu::backend::{self, Backend, OclBackend};
+use simple_gpu::partition::{Partitioner, Rounding};
 
-fn main() -> ocl::Result<()> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
 
-    let platforms = Platform::list();
-
-    let platform = platforms.into_iter().next().expect("No platforms found");
-
-    let mut devices = Device::list(platform, Some(DeviceType::GPU)).unwrap_or_default();
-    if devices.is_empty() {
-        devices = Device::list(platform, Some(DeviceType::CPU)).unwrap_or_default();
-    }
-    let dev = devices.into_iter().next().expect("No devices found");
-
-    let context = Context::builder().platform(platform).devices(dev).build()?;
+    let (_platform, dev) = backend::default_device(&OclBackend)?;
+    let context = OclBackend.create_context(&[dev])?;
     let main_data = vec![0.0f32; 100];
 
     let main_buffer = ocl::Buffer::<f32>::builder().context(&context).len(main_data.len()).copy_host_slice(&main_data).flags(flags::MEM_READ_ONLY).build()?;
-    // let sub_buffer = ocl::Buffer::<f32>::builder().parent(&main_buffer).region(MemoryRegion::new(30 * std::mem::size_of::<f32>(), 20 * std::mem::size_of::<f32>())).flags(flags::MEM_READ_ONLY).build()?;
-    // let sub_buffer = main_buffer.create_sub_buffer(Some(flags::MEM_READ_ONLY),BufferCreateType::Region,BufferRegion::new(30 * std::mem::size_of::<f32>(), 20 * std::mem::size_of::<f32>())).unwrap();
+    let partitioner = Partitioner::new(&main_buffer, &dev)?.flags(flags::MEM_READ_ONLY);
+    println!("Base address alignment: {} bytes ({} floats)", partitioner.align_bytes(), partitioner.align_elems());
+
     let offset_elements = 30; // Offset in elements
     let size_elements = 20;   // Size in elements
-    // let sub_region = BufferRegion::new(
-    //     offset_elements * std::mem::size_of::<f32>(), // Offset in bytes
-    //     size_elements * std::mem::size_of::<f32>(),    // Size in bytes
-    // );
-    let sub_buffer = main_buffer.create_sub_buffer(
-        Some(flags::MEM_READ_ONLY),
-    //     BufferCreateType::Region,
-    //     sub_region,
-        offset_elements,
-        size_elements
-    )?;
-    println!("Main buffer size: {} bytes", main_buffer.len());
-    println!("Main buffer size: {} bytes", sub_buffer.len());
+    match partitioner.sub_buffer(offset_elements, size_elements, Rounding::Exact) {
+        Ok(sub_buffer) => println!("Sub-buffer at offset {}: {} floats", offset_elements, sub_buffer.len()),
+        Err(err) => println!("Exact sub-buffer rejected: {}", err),
+    }
+    let region = partitioner.region(offset_elements, size_elements, Rounding::Down)?;
+    let sub_buffer = partitioner.sub_buffer(offset_elements, size_elements, Rounding::Down)?;
+    println!("Rounded sub-buffer: offset {}, {} floats", region.offset, sub_buffer.len());
+
+    println!("Main buffer size: {} floats", main_buffer.len());
+    for chunk in partitioner.chunks(size_elements)? {
+        let chunk = chunk?;
+        println!("Chunk {}: offset {}, {} floats", chunk.region.index, chunk.region.offset, chunk.buffer.len());
+    }
 
     println!("Main array address: {:p}", main_data.as_ptr());
  
     Ok(())
-}
\ No newline at end of file
+}
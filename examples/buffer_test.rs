@@ -1,6 +1,7 @@
 use ocl::{Platform, Device, Context, Queue, Buffer, flags, DeviceType};
 
-fn main() -> ocl::Result<()> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
     let platform = Platform::list().into_iter().next().unwrap();
     let mut devices = Device::list(platform, Some(DeviceType::GPU)).unwrap_or_default();
     if devices.is_empty() {
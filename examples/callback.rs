@@ -1,8 +1,10 @@
 use ocl::builders::ProgramBuilder;
 use ocl::{ Context, Device, DeviceType,  Platform, Queue};
+use simple_gpu::future::{self, KernelCmdExt};
 use std::{fs};
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
 
     let platforms = Platform::list();
 
@@ -20,9 +22,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let program_con = ProgramBuilder::new()
         .src(&program_handle)
-        .devices(dev.clone())
+        .devices(dev)
         .build(&context)?;
-    let mut buffer = vec![0.0f32; 4096];
     let buffer_cl = ocl::Buffer::<f32>::builder()
         .queue(queue.clone())
         .flags(ocl::flags::MEM_WRITE_ONLY)
@@ -37,18 +38,24 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .global_work_size(1)
         .build()?;
 
-    unsafe {
+    let kernel_done = unsafe {
         kernel.cmd()
             .queue(&queue)
             .global_work_size(1)
-            .enq()?;
+            .enq_future()?
     }
+    .on_complete(|| println!("Kernel finished."))
+    .on_error(|status| println!("Kernel failed with status {}.", status));
 
-    buffer_cl.read(&mut buffer[..]).enq()?;
+    let read = future::read(&buffer_cl, vec![0.0f32; 4096])?;
+
+    let (kernel_result, buffer) = future::block_on(async { (kernel_done.await, read.await) });
+    kernel_result?;
+    let buffer = buffer?;
 
     let mut check = true;
-    for i in 0..4096 {
-        if buffer[i] != 5.0f32 {
+    for &value in &buffer {
+        if value != 5.0f32 {
             check = false;
             break;
         }
@@ -1,6 +1,7 @@
 use ocl::{Device, enums::DeviceInfo};
 
 fn main() {
+    simple_gpu::logging::init();
     let platforms = ocl::Platform::list();
     println!("Number of platforms: {}", platforms.len());
 
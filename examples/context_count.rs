@@ -1,9 +1,12 @@
 use ocl::ffi::cl_context;
 use ocl::{Device, Context, Platform, DeviceType};
 use ocl::enums::ContextInfo;
+use simple_gpu::tracker::{self, ObjectKind};
 use std::mem;
 
-fn main() {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
+    let _report = tracker::report_on_exit();
     let platforms = Platform::list();
 
     let platform = platforms.into_iter().next().expect("No platforms found");
@@ -21,6 +24,8 @@ fn main() {
 
     let cl_ctx: cl_context =  { context.as_core().as_ptr() };
 
+    // From here on the raw handle owns the context's only reference; the
+    // tracker recorded it when the builder created the context.
     std::mem::forget(context);
 
     let get_ref_count = || {
@@ -39,9 +44,12 @@ fn main() {
     };
 
     println!("Initial reference count: {}", get_ref_count());
-    unsafe { ocl::ffi::clRetainContext(cl_ctx) };
+    unsafe { tracker::retain(ObjectKind::Context, cl_ctx)? };
     println!("Reference count after retain: {}", get_ref_count());
-    unsafe { ocl::ffi::clReleaseContext(cl_ctx) };
+    unsafe { tracker::release(ObjectKind::Context, cl_ctx)? };
     println!("Reference count after first release: {}", get_ref_count());
-    unsafe { ocl::ffi::clReleaseContext(cl_ctx) };
+    unsafe { tracker::release(ObjectKind::Context, cl_ctx)? };
+
+    print!("{}", tracker::report());
+    Ok(())
 }
@@ -1,8 +1,28 @@
-use ocl::builders::ProgramBuilder;
+use ocl::enums::{DeviceInfo, DeviceInfoResult};
 use ocl::{Buffer, Context, Device, DeviceType, MemFlags, Platform, Queue};
-use std::fs;
+use simple_gpu::template::{KernelTemplate, Params};
 
-fn main() -> ocl::Result<()> {
+// Divides in double precision where the device supports it and multiplies
+// in single precision otherwise; FP_64 is defined only in the first case.
+const KERNEL_SRC: &str = r#"
+#ifdef FP_64
+#pragma OPENCL EXTENSION cl_khr_fp64 : enable
+#endif
+
+__kernel void double_test(
+        float a, float b,
+        __global float* out) {
+#ifdef FP_64
+    double c = (double)(a / b);
+    *out = c;
+#else
+    *out = a * b;
+#endif
+}
+"#;
+
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
 
     let platforms = Platform::list();
 
@@ -15,21 +35,21 @@ fn main() -> ocl::Result<()> {
     let dev = devices.into_iter().next().expect("No devices found");
 
     let context = Context::builder().platform(platform).devices(dev).build()?;
-    let program_handle = fs::read_to_string("hello_kernel.cl").unwrap_or_else(|_| panic!("Failed to read file: hello_kernel.cl"));
-    let queue = Queue::new(&context, (*dev).into(), None)?;
+    let queue = Queue::new(&context, dev, None)?;
 
-    let program_con = ProgramBuilder::new()
-        .src(&program_handle)
-        .devices(dev.clone())
-        .build(&context)?;
-    // program_con.build(Some("-D FP_64"))?;
-    // program_con.build(None::<&str>)?;
+    let fp64 = match dev.info(DeviceInfo::Extensions)? {
+        DeviceInfoResult::Extensions(extensions) => extensions.split_whitespace().any(|e| e == "cl_khr_fp64"),
+        _ => false,
+    };
+    println!("cl_khr_fp64 {}", if fp64 { "supported" } else { "not supported" });
+    let mut template = KernelTemplate::new(KERNEL_SRC)?;
+    let program = template.program(&context, dev, &Params::new().flag("FP_64", fp64))?;
 
     let mut out = [0.0f32; 1];
     let out_buffer = Buffer::<f32>::builder().queue(queue.clone()).flags(MemFlags::new().write_only()).len(1).build()?;
     let a: f32 = 6.0;
     let b: f32 = 5.0;
-    let kernel = ocl::Kernel::builder().program(&program_con).name("double_test").queue(queue.clone()).arg(a).arg(b).arg(&out_buffer).build()?;
+    let kernel = ocl::Kernel::builder().program(program).name("double_test").queue(queue.clone()).arg(a).arg(b).arg(&out_buffer).build()?;
 
     unsafe {kernel.cmd().queue(&queue).global_work_size(1).enq()?;}
     out_buffer.read(&mut out[..]).enq()?;
@@ -37,19 +57,3 @@ fn main() -> ocl::Result<()> {
 
     Ok(())
 }
-/*
-#ifdef FP_64
-#pragma OPENCL_EXTENSION cl_khr_fp64 : enable
-#endif
-
-__kernel void double_test(
-        float a, float b,
-        __global float* out) {
-#ifdef FP_64
-    double c = (double)(a / b);
-    *out = c;
-#else
-    *out = a * b;
-#endif
-}
- */
\ No newline at end of file
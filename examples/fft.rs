@@ -0,0 +1,141 @@
+use ocl::prm::Float2;
+use ocl::{Buffer, Context, Device, DeviceType, Platform, Queue};
+use rand::{Rng, SeedableRng};
+use simple_gpu::fft::{Direction, Fft};
+use simple_gpu::vector::flatten;
+use simple_gpu::{interpreter, reference};
+
+// Checks complex, real, batched and 2D transforms against a direct DFT,
+// then finds the two tones in a noisy signal.
+//
+// usage: fft [--interp]
+
+/// Where the transforms run.
+enum Runner {
+    Device(Queue),
+    Interpreter,
+}
+
+impl Runner {
+    fn complex(&self, fft: &Fft, direction: Direction, data: &[Float2]) -> simple_gpu::Result<Vec<Float2>> {
+        match self {
+            Runner::Device(queue) => {
+                let input = Buffer::builder().queue(queue.clone()).len(data.len()).copy_host_slice(data).build()?;
+                let output = Buffer::builder().queue(queue.clone()).len(data.len()).build()?;
+                fft.run(queue, direction, &input, &output)?;
+                let mut result = vec![Float2::default(); data.len()];
+                output.read(&mut result).enq()?;
+                Ok(result)
+            }
+            Runner::Interpreter => fft.run_interpreted(direction, data),
+        }
+    }
+
+    fn forward_real(&self, fft: &Fft, batch: usize, data: &[f32]) -> simple_gpu::Result<Vec<Float2>> {
+        match self {
+            Runner::Device(queue) => {
+                let input = Buffer::builder().queue(queue.clone()).len(data.len()).copy_host_slice(data).build()?;
+                let output = Buffer::builder().queue(queue.clone()).len(fft.real_spectrum_len() * batch).build()?;
+                fft.forward_real(queue, &input, &output)?;
+                let mut result = vec![Float2::default(); output.len()];
+                output.read(&mut result).enq()?;
+                Ok(result)
+            }
+            Runner::Interpreter => fft.forward_real_interpreted(data),
+        }
+    }
+
+    fn inverse_real(&self, fft: &Fft, batch: usize, data: &[Float2]) -> simple_gpu::Result<Vec<f32>> {
+        match self {
+            Runner::Device(queue) => {
+                let input = Buffer::builder().queue(queue.clone()).len(data.len()).copy_host_slice(data).build()?;
+                let output = Buffer::builder().queue(queue.clone()).len(fft.len() * batch).build()?;
+                fft.inverse_real(queue, &input, &output)?;
+                let mut result = vec![0.0; output.len()];
+                output.read(&mut result).enq()?;
+                Ok(result)
+            }
+            Runner::Interpreter => fft.inverse_real_interpreted(data),
+        }
+    }
+}
+
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
+    let runner = if std::env::args().any(|a| a == "--interp") || !interpreter::driver_available() {
+        println!("Running on the interpreter.");
+        Runner::Interpreter
+    } else {
+        Runner::Device(open_queue()?)
+    };
+    let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+    let mut failed = 0;
+    let mut check = |what: String, actual: &[f32], expected: &[f32], bound: f32| {
+        let error = reference::relative_error(actual, expected);
+        let ok = error <= bound as f64;
+        println!("{:<34} error {:.2e} (bound {:.2e}) {}", what, error, bound, if ok { "ok" } else { "FAILED" });
+        failed += !ok as usize;
+    };
+
+    for (len, batch) in [(1, 2), (8, 3), (12, 2), (64, 1), (105, 1), (360, 2), (1024, 1)] {
+        let fft = Fft::new(len)?.batch(batch);
+        let data: Vec<Float2> = (0..len * batch).map(|_| Float2::new(rng.r#gen(), rng.r#gen())).collect();
+        let spectrum = runner.complex(&fft, Direction::Forward, &data)?;
+        let expected = reference::dft(&data, len, false);
+        check(format!("complex {} x{}", len, batch), flatten(&spectrum), flatten(&expected), fft.error_bound());
+        let back = runner.complex(&fft, Direction::Inverse, &spectrum)?;
+        check(format!("complex {} x{} round trip", len, batch), flatten(&back), flatten(&data), fft.error_bound());
+    }
+
+    for (height, width, batch) in [(4, 8, 1), (6, 10, 2), (16, 15, 1)] {
+        let fft = Fft::new_2d(height, width)?.batch(batch);
+        let data: Vec<Float2> = (0..fft.len() * batch).map(|_| Float2::new(rng.r#gen(), rng.r#gen())).collect();
+        let spectrum = runner.complex(&fft, Direction::Forward, &data)?;
+        let expected = reference::dft_2d(&data, height, width, false);
+        check(format!("complex {}x{} x{}", height, width, batch), flatten(&spectrum), flatten(&expected), fft.error_bound());
+    }
+
+    for (height, width, batch) in [(1, 2, 1), (1, 16, 3), (1, 90, 1), (8, 12, 1), (5, 6, 2)] {
+        let fft = if height == 1 { Fft::new(width)? } else { Fft::new_2d(height, width)? }.batch(batch);
+        let data: Vec<f32> = (0..fft.len() * batch).map(|_| rng.r#gen()).collect();
+        let spectrum = runner.forward_real(&fft, batch, &data)?;
+        // The full complex transform, cut down to the bins a real one keeps.
+        let complex: Vec<Float2> = data.iter().map(|&x| Float2::new(x, 0.0)).collect();
+        let full = if height == 1 { reference::dft(&complex, width, false) } else { reference::dft_2d(&complex, height, width, false) };
+        let expected: Vec<Float2> = full.chunks(width).flat_map(|row| row[..width / 2 + 1].iter().copied()).collect();
+        check(format!("real {}x{} x{}", height, width, batch), flatten(&spectrum), flatten(&expected), fft.error_bound());
+        let back = runner.inverse_real(&fft, batch, &spectrum)?;
+        check(format!("real {}x{} x{} round trip", height, width, batch), &back, &data, fft.error_bound());
+    }
+
+    // Tones at 50 and 120 cycles per 1000 samples, plus noise.
+    let len = 1000;
+    let fft = Fft::new(len)?;
+    let signal: Vec<f32> = (0..len)
+        .map(|i| {
+            let t = i as f32 / len as f32;
+            (std::f32::consts::TAU * 50.0 * t).sin() + 0.5 * (std::f32::consts::TAU * 120.0 * t).sin() + rng.gen_range(-0.5..0.5)
+        })
+        .collect();
+    let spectrum = runner.forward_real(&fft, 1, &signal)?;
+    let mut peaks: Vec<(usize, f32)> = spectrum.iter().map(|c| (c[0] * c[0] + c[1] * c[1]).sqrt()).enumerate().collect();
+    peaks.sort_by(|a, b| b.1.total_cmp(&a.1));
+    println!("strongest bins: {} and {} (magnitudes {:.0} and {:.0})", peaks[0].0, peaks[1].0, peaks[0].1, peaks[1].1);
+
+    if failed > 0 {
+        eprintln!("{} checks exceeded their error bound", failed);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn open_queue() -> simple_gpu::Result<Queue> {
+    let platform = Platform::list().into_iter().next().expect("No platforms found");
+    let mut devices = Device::list(platform, Some(DeviceType::GPU)).unwrap_or_default();
+    if devices.is_empty() {
+        devices = Device::list(platform, Some(DeviceType::CPU)).unwrap_or_default();
+    }
+    let dev = devices.into_iter().next().expect("No devices found");
+    let context = Context::builder().platform(platform).devices(dev).build()?;
+    Ok(Queue::new(&context, dev, None)?)
+}
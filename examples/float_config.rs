@@ -3,6 +3,7 @@ use std::mem;
 use std::ptr;
 
 fn main() {
+    simple_gpu::logging::init();
     unsafe {
         let mut platform: cl_platform_id = ptr::null_mut();
         clGetPlatformIDs(1, &mut platform, ptr::null_mut());
@@ -4,6 +4,7 @@ use ocl::{ProQue, Buffer, MemFlags};
 
 
 fn main() -> Result<(), Box<dyn Error>> {
+    simple_gpu::logging::init();
     let kernel_src = r#"
         __kernel void add(
             __global float* c
@@ -0,0 +1,70 @@
+use ocl::{Buffer, Context, Device, DeviceType, Platform, Queue};
+use simple_gpu::histogram::{Bins, Counts, Histogram, PngImage, Sample, Samples};
+use simple_gpu::{interpreter, reference};
+
+// Per-channel histogram of a PNG: every level of an 8-bit image, or 256
+// bins of 256 levels each for a 16-bit one.
+//
+// usage: histogram [FILE.png] [--interp]
+
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
+    let interp = std::env::args().any(|a| a == "--interp") || !interpreter::driver_available();
+    let path = std::env::args().skip(1).find(|a| !a.starts_with("--")).unwrap_or_else(|| "input.png".into());
+    let image = PngImage::load(&path)?;
+    println!("{}: {}x{}, {} channels of {} bits", path, image.width, image.height, image.channels, image.bits());
+
+    let bins = Bins::levels(image.bits(), 256);
+    match &image.samples {
+        Samples::Eight(data) => show(Histogram::new(bins)?.channels(image.channels), data, interp),
+        Samples::Sixteen(data) => show(Histogram::new(bins)?.channels(image.channels), data, interp),
+    }
+}
+
+fn show<T: Sample + Sync>(hist: Histogram<T>, data: &[T], interp: bool) -> simple_gpu::Result<()> {
+    let counts = if interp {
+        println!("Running on the interpreter.");
+        hist.run_interpreted(data)?
+    } else {
+        on_device(&hist, data)?
+    };
+    if let Err(mismatch) = reference::diff_exact(&counts.counts, &reference::histogram(&hist, data).counts) {
+        eprintln!("Differs from the reference: {}", mismatch);
+        std::process::exit(1);
+    }
+
+    for channel in 0..counts.channels {
+        let bins = counts.channel(channel);
+        let (peak, peak_count) = bins.iter().enumerate().max_by_key(|&(_, &c)| c).expect("at least one bin");
+        println!(
+            "channel {}: {} samples, {} bins used, peak at bin {} ({} samples), mean bin {:.2}",
+            channel,
+            counts.total(channel),
+            bins.iter().filter(|&&c| c > 0).count(),
+            peak,
+            peak_count,
+            mean_bin(&counts, channel)
+        );
+    }
+    println!("Matches the host histogram.");
+    Ok(())
+}
+
+fn mean_bin(counts: &Counts, channel: usize) -> f64 {
+    let total = counts.total(channel).max(1) as f64;
+    counts.channel(channel).iter().enumerate().map(|(bin, &c)| bin as f64 * c as f64).sum::<f64>() / total
+}
+
+fn on_device<T: Sample>(hist: &Histogram<T>, data: &[T]) -> simple_gpu::Result<Counts> {
+    let platform = Platform::list().into_iter().next().expect("No platforms found");
+    let mut devices = Device::list(platform, Some(DeviceType::GPU)).unwrap_or_default();
+    if devices.is_empty() {
+        devices = Device::list(platform, Some(DeviceType::CPU)).unwrap_or_default();
+    }
+    let dev = devices.into_iter().next().expect("No devices found");
+
+    let context = Context::builder().platform(platform).devices(dev).build()?;
+    let queue = Queue::new(&context, dev, None)?;
+    let buffer = Buffer::<T>::builder().queue(queue.clone()).len(data.len()).copy_host_slice(data).build()?;
+    hist.run(&queue, &buffer)
+}
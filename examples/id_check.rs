@@ -1,8 +1,63 @@
 use ocl::builders::ProgramBuilder;
 use ocl::{Buffer, Context, Device, DeviceType, MemFlags, Platform, Queue};
-use std::{fs};
+use simple_gpu::interpreter;
 
-fn main() -> ocl::Result<()> {
+const KERNEL_SRC: &str = r#"
+__kernel void id_check(__global float *output) {
+
+   size_t global_id_0 = get_global_id(0);
+   size_t global_id_1 = get_global_id(1);
+   size_t global_size_0 = get_global_size(0);
+   size_t offset_0 = get_global_offset(0);
+   size_t offset_1 = get_global_offset(1);
+   size_t local_id_0 = get_local_id(0);
+   size_t local_id_1 = get_local_id(1);
+
+   int index_0 = global_id_0 - offset_0;
+   int index_1 = global_id_1 - offset_1;
+   int index = index_1 * global_size_0 + index_0;
+
+   float f = global_id_0 * 10.0f + global_id_1 * 1.0f;
+   f += local_id_0 * 0.1f + local_id_1 * 0.01f;
+
+   output[index] = f;
+}
+"#;
+
+const GLOBAL_OFFSET: [usize; 2] = [3, 5];
+const GLOBAL_SIZE: [usize; 2] = [6, 4];
+const LOCAL_SIZE: [usize; 2] = [3, 2];
+
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
+    let output_len = GLOBAL_SIZE[0] * GLOBAL_SIZE[1];
+
+    let msg = if std::env::args().any(|a| a == "--interp") || !interpreter::driver_available() {
+        println!("Running on the interpreter.");
+        let mut msg_buffer = interpreter::Buffer::new::<f32>(output_len);
+        interpreter::Program::build(KERNEL_SRC)?
+            .kernel("id_check")?
+            .arg_buf(&mut msg_buffer)
+            .global_work_size(GLOBAL_SIZE)
+            .global_work_offset(GLOBAL_OFFSET)
+            .local_work_size(LOCAL_SIZE)
+            .run()?;
+        msg_buffer.to_vec::<f32>()
+    } else {
+        on_device(output_len)?
+    };
+
+    for row in msg.chunks(GLOBAL_SIZE[0]) {
+        for value in row {
+            print!("{:6.2}", value);
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+fn on_device(output_len: usize) -> simple_gpu::Result<Vec<f32>> {
 
     let platforms = Platform::list();
 
@@ -15,54 +70,20 @@ fn main() -> ocl::Result<()> {
     let dev = devices.into_iter().next().expect("No devices found");
 
     let context = Context::builder().platform(platform).devices(dev).build()?;
-    let program_handle = fs::read_to_string("hello_kernel.cl").unwrap_or_else(|_| panic!("Failed to read file: hello_kernel.cl"));
     let queue = Queue::new(&context, (*dev).into(), None)?;
 
     let program_con = ProgramBuilder::new()
-        .src(&program_handle)
-        .devices(dev.clone())
+        .src(KERNEL_SRC)
+        .devices(dev)
         .build(&context)?;
 
-    let global_offset = [3, 5];
-    let global_size = [6, 4];
-    let local_size = [3, 2];
-    let output_len = global_size[0] * global_size[1];
-
     let mut msg = vec![0.0f32; output_len];
     let msg_buffer = Buffer::<f32>::builder().queue(queue.clone()).flags(MemFlags::new().write_only()).len(output_len).build()?;
-        
 
-    let kernel = ocl::Kernel::builder().program(&program_con).name("id_check").queue(queue.clone()).arg(&msg_buffer).global_work_size(global_size).build()?;
+    let kernel = ocl::Kernel::builder().program(&program_con).name("id_check").queue(queue.clone()).arg(&msg_buffer).global_work_size(GLOBAL_SIZE).build()?;
 
-    unsafe {kernel.cmd().queue(&queue).global_work_size(global_size).global_work_offset(global_offset).local_work_size(local_size).enq()?;}
+    unsafe {kernel.cmd().queue(&queue).global_work_size(GLOBAL_SIZE).global_work_offset(GLOBAL_OFFSET).local_work_size(LOCAL_SIZE).enq()?;}
     msg_buffer.read(&mut msg).enq()?;
-    for i in 0..global_size[1] {
-        for j in 0..global_size[0] {
-            print!("{:6.2}", msg[i * global_size[0] + j]);
-        }
-        println!();
-    }
 
-    Ok(())
-}
-/*
-__kernel void id_check(__global float *output) { 
-
-   size_t global_id_0 = get_global_id(0);
-   size_t global_id_1 = get_global_id(1);
-   size_t global_size_0 = get_global_size(0);
-   size_t offset_0 = get_global_offset(0);
-   size_t offset_1 = get_global_offset(1);
-   size_t local_id_0 = get_local_id(0);
-   size_t local_id_1 = get_local_id(1);
-
-   int index_0 = global_id_0 - offset_0;
-   int index_1 = global_id_1 - offset_1;
-   int index = index_1 * global_size_0 + index_0;
-   
-   float f = global_id_0 * 10.0f + global_id_1 * 1.0f;
-   f += local_id_0 * 0.1f + local_id_1 * 0.01f;
-
-   output[index] = f;
+    Ok(msg)
 }
-*/
\ No newline at end of file
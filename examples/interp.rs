@@ -1,13 +1,40 @@
-use ocl::builders::ProgramBuilder;
 use ocl::core::{MemObjectType};
 use ocl::{ Context, Device, DeviceType,  Platform, Queue, MemFlags, Image};
 use ocl::enums::{ ImageChannelDataType, ImageChannelOrder,  };
 use core::f32;
 use png::{BitDepth, ColorType, Decoder, Encoder};
+use simple_gpu::template::{KernelTemplate, Params};
 use std::{io::BufWriter};
 
 const SCALE: usize = 10;
+
+const KERNEL_SRC: &str = r#"
+constant sampler_t sampler = CLK_NORMALIZED_COORDS_FALSE
+   | CLK_ADDRESS_CLAMP | CLK_FILTER_NEAREST;
+   
+__kernel void interp(read_only image2d_t src_image,
+                     write_only image2d_t dst_image) {
+   float4 pixel;
+   float2 input_coord = (float2)
+      (get_global_id(0) + (1.0f/(SCALE*2)),
+       get_global_id(1) + (1.0f/(SCALE*2)));
+   int2 output_coord = (int2)
+      (SCALE*get_global_id(0),
+       SCALE*get_global_id(1));
+   for(int i=0; i<SCALE; i++) {
+      for(int j=0; j<SCALE; j++) {
+         pixel = read_imagef(src_image, sampler,
+           (float2)(input_coord + 
+           (float2)(1.0f*i/SCALE, 1.0f*j/SCALE)));
+         write_imagef(dst_image, output_coord + 
+                      (int2)(i, j), pixel);
+      } 
+   }
+}
+"#;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    simple_gpu::logging::init();
 
     let platforms = Platform::list();
 
@@ -21,37 +48,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let context = Context::builder().platform(platform).devices(dev).build()?;
     let queue = Queue::new(&context, (*dev).into(), None)?;
-    let kernel_source = format!(
-        r#"
-constant sampler_t sampler = CLK_NORMALIZED_COORDS_FALSE
-   | CLK_ADDRESS_CLAMP | CLK_FILTER_NEAREST;
-   
-__kernel void interp(read_only image2d_t src_image,
-                     write_only image2d_t dst_image) {{
-   float4 pixel;
-   float2 input_coord = (float2)
-      (get_global_id(0) + (1.0f/({SCALE}*2)),
-       get_global_id(1) + (1.0f/({SCALE}*2)));
-   int2 output_coord = (int2)
-      ({SCALE}*get_global_id(0),
-       {SCALE}*get_global_id(1));
-   for(int i=0; i<{SCALE}; i++) {{
-      for(int j=0; j<{SCALE}; j++) {{
-         pixel = read_imagef(src_image, sampler,
-           (float2)(input_coord + 
-           (float2)(1.0f*i/{SCALE}, 1.0f*j/{SCALE})));
-         write_imagef(dst_image, output_coord + 
-                      (int2)(i, j), pixel);
-      }} 
-   }}
-}}
-"#,
-    );
-
-    let program_con = ProgramBuilder::new()
-        .src(&kernel_source)
-        .devices(dev.clone())
-        .build(&context)?;
+    // SCALE reaches the kernel as a -D define.
+    let mut template = KernelTemplate::new(KERNEL_SRC)?;
+    let program_con = template.program(&context, dev, &Params::new().int("SCALE", SCALE as i64))?;
 
     let file = std::fs::File::open("input.png")?;
     let decoder = Decoder::new(file);
@@ -130,7 +129,7 @@ __kernel void interp(read_only image2d_t src_image,
         .build()?;
 
     let kernel = ocl::Kernel::builder()
-        .program(&program_con)
+        .program(program_con)
         .name("interp")
         .queue(queue.clone())
         .arg(&src_image)
@@ -154,7 +153,7 @@ __kernel void interp(read_only image2d_t src_image,
     }
     
     let file1 = std::fs::File::create("output.png")?;
-    let ref mut w = BufWriter::new(file1);
+    let w = &mut BufWriter::new(file1);
     
     let mut encoder = Encoder::new(w, dst_width as u32, dst_height as u32);
     encoder.set_color(ColorType::Grayscale);
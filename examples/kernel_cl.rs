@@ -3,7 +3,8 @@ use ocl::{Buffer, Context, Device, DeviceType, MemFlags, Platform, Queue};
 use std::fs;
 
 
-fn main() -> ocl::Result<()> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
 
     let platforms = Platform::list();
 
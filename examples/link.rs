@@ -0,0 +1,145 @@
+use ocl::{Buffer, Context, Device, DeviceType, Kernel, Platform, Queue};
+use rand::{Rng, SeedableRng};
+use simple_gpu::build_options::BuildOptions;
+use simple_gpu::interpreter;
+use simple_gpu::link::{self, Includes, Linker};
+
+// Two kernels in separate files share a statistics library through one
+// in-memory header. The library is compiled and linked on its own, then
+// linked into the program with both kernel files.
+//
+// usage: link [--interp]
+
+const CONFIG_H: &str = r#"
+#ifndef CONFIG_H
+#define CONFIG_H
+typedef float real;
+#endif
+"#;
+
+const STATS_H: &str = r#"
+#ifndef STATS_H
+#define STATS_H
+#include "config.h"
+real mean_of(__global const real* x, uint n);
+real variance_of(__global const real* x, uint n);
+#endif
+"#;
+
+const STATS_CL: &str = r#"
+#include "stats.h"
+
+real mean_of(__global const real* x, uint n) {
+    real sum = 0;
+    for (uint i = 0; i < n; i++) {
+        sum += x[i];
+    }
+    return sum / n;
+}
+
+real variance_of(__global const real* x, uint n) {
+    real mean = mean_of(x, n);
+    real sum = 0;
+    for (uint i = 0; i < n; i++) {
+        sum += (x[i] - mean) * (x[i] - mean);
+    }
+    return sum / n;
+}
+"#;
+
+const CENTER_CL: &str = r#"
+#include "stats.h"
+
+__kernel void center(__global const real* x, __global real* y, uint n) {
+    uint i = get_global_id(0);
+    y[i] = x[i] - mean_of(x, n);
+}
+"#;
+
+const STANDARDIZE_CL: &str = r#"
+#include "stats.h"
+
+__kernel void standardize(__global const real* x, __global real* y, uint n) {
+    uint i = get_global_id(0);
+    y[i] = (x[i] - mean_of(x, n)) / sqrt(variance_of(x, n));
+}
+"#;
+
+const UNITS: [(&str, &str); 3] = [("stats.cl", STATS_CL), ("center.cl", CENTER_CL), ("standardize.cl", STANDARDIZE_CL)];
+
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
+    let interp = std::env::args().any(|a| a == "--interp") || !interpreter::driver_available();
+    let includes = Includes::new().file("config.h", CONFIG_H).file("stats.h", STATS_H);
+    let options = BuildOptions::new();
+    let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+    let x: Vec<f32> = (0..256).map(|_| rng.gen_range(0.0..10.0)).collect();
+
+    let (centered, standardized) = if interp {
+        println!("Running on the interpreter.");
+        let program = link::link_interpreted(&UNITS, &includes, &options)?;
+        let run = |name: &str| -> simple_gpu::Result<Vec<f32>> {
+            let mut input = interpreter::Buffer::from_slice(&x);
+            let mut output = interpreter::Buffer::new::<f32>(x.len());
+            program.kernel(name)?.arg_buf(&mut input).arg_buf(&mut output).arg(x.len() as u32).global_work_size(x.len()).run()?;
+            Ok(output.to_vec())
+        };
+        (run("center")?, run("standardize")?)
+    } else {
+        let queue = open_queue()?;
+        let linker = Linker::new(&queue.context(), queue.device(), includes)?;
+        let [stats, center, standardize] = UNITS.map(|(name, src)| linker.compile(name, src, &options));
+        let library = linker.library("libstats", &[&stats?], &options)?;
+        let program = linker.executable(&[&center?, &standardize?, &library], &options)?;
+        let input = Buffer::builder().queue(queue.clone()).len(x.len()).copy_host_slice(&x).build()?;
+        let output = Buffer::<f32>::builder().queue(queue.clone()).len(x.len()).build()?;
+        let run = |name: &str| -> simple_gpu::Result<Vec<f32>> {
+            let kernel = Kernel::builder()
+                .program(&program)
+                .name(name)
+                .queue(queue.clone())
+                .arg(&input)
+                .arg(&output)
+                .arg(x.len() as u32)
+                .global_work_size(x.len())
+                .build()?;
+            unsafe { kernel.enq()? };
+            let mut result = vec![0.0; x.len()];
+            output.read(&mut result).enq()?;
+            Ok(result)
+        };
+        (run("center")?, run("standardize")?)
+    };
+
+    let n = x.len() as f64;
+    let mean = x.iter().map(|&v| v as f64).sum::<f64>() / n;
+    let deviation = (x.iter().map(|&v| (v as f64 - mean).powi(2)).sum::<f64>() / n).sqrt();
+    let worst = |actual: &[f32], expected: &dyn Fn(f64) -> f64| {
+        actual.iter().zip(&x).map(|(&a, &v)| (a as f64 - expected(v as f64)).abs()).fold(0.0, f64::max)
+    };
+    let mut ok = true;
+    for (name, error) in [
+        ("center", worst(&centered, &|v| v - mean)),
+        ("standardize", worst(&standardized, &|v| (v - mean) / deviation)),
+    ] {
+        let passed = error < 1e-4;
+        println!("{:<12} worst error {:.2e} {}", name, error, if passed { "ok" } else { "FAILED" });
+        ok &= passed;
+    }
+    if !ok {
+        eprintln!("a linked kernel did not match the host");
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn open_queue() -> simple_gpu::Result<Queue> {
+    let platform = Platform::list().into_iter().next().expect("No platforms found");
+    let mut devices = Device::list(platform, Some(DeviceType::GPU)).unwrap_or_default();
+    if devices.is_empty() {
+        devices = Device::list(platform, Some(DeviceType::CPU)).unwrap_or_default();
+    }
+    let dev = devices.into_iter().next().expect("No devices found");
+    let context = Context::builder().platform(platform).devices(dev).build()?;
+    Ok(Queue::new(&context, dev, None)?)
+}
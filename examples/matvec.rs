@@ -1,6 +1,8 @@
 use ocl::{ProQue, Buffer,Result};
+use simple_gpu::reference;
 
 fn main() -> Result<()> {
+    simple_gpu::logging::init();
     let kernel_src = r#"
         __kernel void matvec_mult(__global float* mat, __global float* vec, __global float* result) {
             int gid = get_global_id(0);
@@ -13,15 +15,14 @@ fn main() -> Result<()> {
     let mut mat = [0.0f32; 16];
     let mut vec = [0.0f32; 4];
     let mut result = [0.0f32; 4];
-    let mut correct = [0.0f32; 4];
     // Initialize data
-    for i in 0..16 {
-        mat[i] = i as f32 * 2.0;
+    for (i, m) in mat.iter_mut().enumerate() {
+        *m = i as f32 * 2.0;
     }
-    for i in 0..4 {
-        vec[i] = i as f32 * 3.0;
-        for j in 0..4 {correct[j] += mat[i+4*j] * vec[i];}
+    for (i, v) in vec.iter_mut().enumerate() {
+        *v = i as f32 * 3.0;
     }
+    let correct = reference::matvec(&mat, &vec);
     let pro_que = ProQue::builder().src(kernel_src).dims(4).build().unwrap();
     let mat_buf = Buffer::<f32>::builder().queue(pro_que.queue().clone()).flags(ocl::flags::MEM_READ_ONLY).len(16).copy_host_slice(&mat).build().unwrap();
     let vec_buf = Buffer::<f32>::builder().queue(pro_que.queue().clone()).flags(ocl::flags::MEM_READ_ONLY).len(4).copy_host_slice(&vec).build().unwrap();
@@ -31,25 +32,18 @@ fn main() -> Result<()> {
         use std::time::Instant;
         let now = Instant::now();
         unsafe {kernel.cmd().global_work_size(4).local_work_size(4).enq().unwrap();}
-        let _=pro_que.finish()?;
+        pro_que.finish()?;
         let elapsed = now.elapsed();
         println!("Elapsed: {:.2?}", elapsed);
         res_buf.read(&mut result[..]).enq().unwrap();
     }
-    let epsilon = 1e-5;
-    let mut success = true;
-    for i in 0..4 {
-        if (result[i] - correct[i]).abs() > epsilon {
-            success = false;
-            break;
+    match reference::diff(&result, &correct, 1e-5) {
+        Ok(()) => println!("Matrix-vector multiplication successful."),
+        Err(mismatch) => {
+            println!("Matrix-vector multiplication unsuccessful: {}", mismatch);
+            println!("Result:  {:?}", result);
+            println!("Correct: {:?}", correct);
         }
     }
-    if success {
-        println!("Matrix-vector multiplication successful.");
-    } else {
-        println!("Matrix-vector multiplication unsuccessful.");
-        println!("Result:  {:?}", result);
-        println!("Correct: {:?}", correct);
-    }
     Ok(())
 }
\ No newline at end of file
@@ -1,8 +1,10 @@
 use ocl::builders::ProgramBuilder;
 use ocl::{flags, Buffer, Context, Device, DeviceType, Platform, Queue};
-use std::{fs};
+use simple_gpu::reference;
+use std::fs;
 
-fn main() -> ocl::Result<()> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
 
     let platforms = Platform::list();
 
@@ -18,7 +20,7 @@ fn main() -> ocl::Result<()> {
     let program_handle = fs::read_to_string("hello_kernel.cl").unwrap_or_else(|_| panic!("Failed to read file: hello_kernel.cl"));
     let queue = Queue::new(&context, (*dev).into(), None)?;
 
-    let program_con = ProgramBuilder::new().src(&program_handle) .devices(dev.clone()) .build(&context)?;
+    let program_con = ProgramBuilder::new().src(&program_handle) .devices(dev) .build(&context)?;
 
     let mod_input = [317.0f32, 23.0f32];
     let round_input = [-6.5f32, -3.5f32, 3.5f32, 6.5f32];
@@ -47,22 +49,26 @@ fn main() -> ocl::Result<()> {
     mod_output_buf.read(&mut mod_output[..]).enq()?;
     let mut round_output_flat = [0.0f32; 20];
     round_output_buf.read(&mut round_output_flat[..]).enq()?;
-    for i in 0..5 {
-        for j in 0..4 {
-            round_output[i][j] = round_output_flat[i * 4 + j];
-        }
+    for (row, chunk) in round_output.iter_mut().zip(round_output_flat.chunks(4)) {
+        row.copy_from_slice(chunk);
     }
 
     println!("fmod({}, {}) = {}", mod_input[0], mod_input[1], mod_output[0]);
     println!("remainder({}, {}) = {}", mod_input[0], mod_input[1], mod_output[1]);
     println!("rounding input = ({:?})", round_input);
     let names = ["rint", "round", "ceil", "floor", "trunc"];
-    for i in 0..5 {
-        println!("{} = {:?}", names[i], round_output[i]);
+    for (name, row) in names.iter().zip(&round_output) {
+        println!("{} = {:?}", name, row);
     }
 
     println!();
 
+    let (mod_ref, round_ref) = reference::mod_round(mod_input, round_input);
+    match reference::diff(&mod_output, &mod_ref, 1e-6).and(reference::diff_exact(&round_output_flat, round_ref.as_flattened())) {
+        Ok(()) => println!("Matches the reference."),
+        Err(mismatch) => println!("Differs from the reference: {}", mismatch),
+    }
+
     Ok(())
 }
 /*
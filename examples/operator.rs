@@ -1,8 +1,45 @@
 use ocl::builders::ProgramBuilder;
 use ocl::{Buffer, Context, Device, DeviceType, MemFlags, Platform, Queue};
-use std::{fs};
+use simple_gpu::interpreter;
 
-fn main() -> ocl::Result<()> {
+const KERNEL_SRC: &str = r#"
+__kernel void op_test(__global int4 *output) {
+
+   int4 vec = (int4)(1, 2, 3, 4);
+   vec += 4;
+
+   if(vec.s2 == 7)
+      vec &= (int4)(-1, -1, 0, -1);
+
+   vec.s01 = vec.s23 < 7;
+
+   while(vec.s3 > 7 && (vec.s0 < 16 || vec.s1 < 16))
+      vec.s3 >>= 1;
+
+   *output = vec;
+}
+"#;
+
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
+    let msg = if std::env::args().any(|a| a == "--interp") || !interpreter::driver_available() {
+        println!("Running on the interpreter.");
+        let mut msg_buffer = interpreter::Buffer::new::<i32>(4);
+        interpreter::Program::build(KERNEL_SRC)?
+            .kernel("op_test")?
+            .arg_buf(&mut msg_buffer)
+            .global_work_size(1)
+            .run()?;
+        msg_buffer.to_vec::<i32>()
+    } else {
+        on_device()?
+    };
+    println!("Kernel output: {:?}", msg);
+
+    Ok(())
+}
+
+fn on_device() -> simple_gpu::Result<Vec<i32>> {
 
     let platforms = Platform::list();
 
@@ -15,40 +52,20 @@ fn main() -> ocl::Result<()> {
     let dev = devices.into_iter().next().expect("No devices found");
 
     let context = Context::builder().platform(platform).devices(dev).build()?;
-    let program_handle = fs::read_to_string("hello_kernel.cl").unwrap_or_else(|_| panic!("Failed to read file: hello_kernel.cl"));
     let queue = Queue::new(&context, (*dev).into(), None)?;
 
     let program_con = ProgramBuilder::new()
-        .src(&program_handle)
-        .devices(dev.clone())
+        .src(KERNEL_SRC)
+        .devices(dev)
         .build(&context)?;
 
     let mut msg = vec! [0; 4];
     let msg_buffer = Buffer::<i32>::builder().queue(queue.clone()).flags(MemFlags::new().write_only()).len(4).build()?;
-        
 
     let kernel = ocl::Kernel::builder().program(&program_con).name("op_test").queue(queue.clone()).arg(&msg_buffer).build()?;
 
     unsafe {kernel.cmd().queue(&queue).global_work_size(1).enq()?;}
     msg_buffer.read(&mut msg[..]).enq()?;
-    println!("Kernel output: {:?}", msg);
-
-    Ok(())
-}
-/*
-__kernel void op_test(__global int4 *output) {
 
-   int4 vec = (int4)(1, 2, 3, 4);
-   vec += 4;
-
-   if(vec.s2 == 7)
-      vec &= (int4)(-1, -1, 0, -1);
-   
-   vec.s01 = vec.s23 < 7; 
-   
-   while(vec.s3 > 7 && (vec.s0 < 16 || vec.s1 < 16))
-      vec.s3 >>= 1; 
-      
-   *output = vec;
+    Ok(msg)
 }
-*/
\ No newline at end of file
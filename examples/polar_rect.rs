@@ -1,10 +1,12 @@
 use ocl::builders::ProgramBuilder;
 use ocl::{flags, Buffer, Context, Device, DeviceType, Platform, Queue};
-use std::{fs};
+use simple_gpu::reference;
+use std::fs;
 
-const  M_PI:f32 = 3.14159265358979323846;
+use std::f32::consts::PI as M_PI;
 
-fn main() -> ocl::Result<()> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
 
     let platforms = Platform::list();
 
@@ -22,7 +24,7 @@ fn main() -> ocl::Result<()> {
 
     let program_con = ProgramBuilder::new()
         .src(&program_handle)
-        .devices(dev.clone())
+        .devices(dev)
         .build(&context)?;
 
     let r_coords = [2.0f32, 1.0f32, 3.0f32, 4.0f32];
@@ -57,11 +59,17 @@ fn main() -> ocl::Result<()> {
     x_coords_buf.read(&mut x_coords[..]).enq()?;
     y_coords_buf.read(&mut y_coords[..]).enq()?;
     println!("output");
-    for i in 0..4 {
-        println!("{:?} , {:?}", x_coords[i], y_coords[i]);
+    for (x, y) in x_coords.iter().zip(&y_coords) {
+        println!("{:?} , {:?}", x, y);
     }
     println!();
 
+    let (x_ref, y_ref) = reference::polar_rect(&r_coords, &angles);
+    match reference::diff(&x_coords, &x_ref, 1e-5).and(reference::diff(&y_coords, &y_ref, 1e-5)) {
+        Ok(()) => println!("Matches the reference."),
+        Err(mismatch) => println!("Differs from the reference: {}", mismatch),
+    }
+
     Ok(())
 }
 /*
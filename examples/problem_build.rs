@@ -1,53 +1,39 @@
-use ocl::{ enums::{ProgramBuildInfo, ProgramBuildInfoResult}};
-use ocl::builders::ProgramBuilder;
-use ocl::{Context, Device, DeviceType,  Platform};
-use std::fs;
-use std::ffi::CString;
-
-
+use simple_gpu::backend::{self, Backend, OclBackend, Status};
+use simple_gpu::build_options::BuildOptions;
+use simple_gpu::link::{Includes, Linker};
 
 const PROGRAM_FILE: &str = "good.cl";
 const PROGRAM_FILE_1: &str = "bad.cl";
-const OPTIONS: &str = "-cl-finite-math-only -cl-no-signed-zeros";
-
-fn main() -> ocl::Result<()> {
-
-    let platforms = Platform::list();
-
-    let platform = platforms.into_iter().next().expect("No platforms found");
 
-    let mut devices = Device::list(platform, Some(DeviceType::GPU)).unwrap_or_default();
-    if devices.is_empty() {
-        devices = Device::list(platform, Some(DeviceType::CPU)).unwrap_or_default();
+// Compiles each file on its own, so a failure names the file it came from,
+// and links the program once every file has compiled.
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
+
+    let (_platform, dev) = backend::default_device(&OclBackend)?;
+    let context = OclBackend.create_context(&[dev])?;
+    let options = BuildOptions::new().finite_math_only().no_signed_zeros();
+    let linker = Linker::new(&context, dev, Includes::new().dir("."))?;
+
+    let mut objects = Vec::new();
+    for file_name in [PROGRAM_FILE, PROGRAM_FILE_1] {
+        match linker.compile_file(file_name, &options) {
+            Ok(object) => {
+                println!("{} compiled.", file_name);
+                objects.push(object);
+            }
+            Err(err) if err.status() == Some(Status::CL_COMPILE_PROGRAM_FAILURE) => println!("{}", err),
+            Err(err) => return Err(err),
+        }
     }
-    let dev = devices.into_iter().next().expect("No devices found");
-
-    let context = Context::builder().platform(platform).devices(dev).build()?;
-    let file_names = [PROGRAM_FILE, PROGRAM_FILE_1];
-    let mut program_sources = Vec::<String>::new();
-    for file_name in &file_names {
-        let program_handle = fs::read_to_string(file_name).unwrap_or_else(|_| panic!("Failed to read file: {}", file_name));
-        program_sources.push(program_handle);
-    }
-
-    let program_con = ProgramBuilder::new()
-        .src(program_sources[0].as_str())
-        .src(program_sources[1].as_str())
-        .devices(dev.clone())
-        .cmplr_opt(&*OPTIONS)
-        .build(&context)
-        .unwrap(); 
-    let options_cstring = CString::new(OPTIONS).unwrap();
-    let build_result = ocl::core::build_program(&program_con, Some(&[dev.clone()]), &options_cstring,None,None);
-
-    if build_result.is_err() {
-        let log = match program_con.build_info(dev, ProgramBuildInfo::BuildLog) {
-            Ok(ProgramBuildInfoResult::BuildLog(s)) => s,
-            _ => "<No build log available>".to_string(),
-        };
-        println!("OpenCL build log:\n{}", log);
+    if objects.len() == 2 {
+        let objects: Vec<_> = objects.iter().collect();
+        match linker.executable(&objects, &options) {
+            Ok(_) => println!("Program built."),
+            Err(err) => println!("{}", err),
+        }
     }
 
     println!("Current directory: {:?}", std::env::current_dir());
     Ok(())
-}
\ No newline at end of file
+}
@@ -3,7 +3,8 @@ use ocl::{ Context, Device, DeviceType,  Platform, Queue};
 use std::{fs};
 const NUM:usize = 131072;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
 
     let platforms = Platform::list();
 
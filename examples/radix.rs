@@ -1,8 +1,9 @@
 use ocl::{builders::KernelBuilder, flags, Buffer, Context, Device, DeviceType, Platform, Program, Queue};
-use rand::Rng;
+use simple_gpu::datagen::DataGen;
 
 
-fn main() -> ocl::Result<()> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
     let src = std::fs::read_to_string("hello_kernel.cl").expect("Failed to read ");
 
     let platform = Platform::list().into_iter().next().unwrap();
@@ -18,11 +19,8 @@ fn main() -> ocl::Result<()> {
     for i in 0..8 {
        data[i] = i as u16;
     }
-    for i in 0..7 {
-      let j = i + rand::thread_rng().gen_range(0..(7-i));
-    //   data[i as usize] = data[i as usize] ^ data[j]; data[j] =  data[i as usize] ^ data[j]; data[i as usize] = data[i as usize] ^ data[j];
-        data.swap(i, j);
-   }
+    let mut datagen = DataGen::from_env();
+    datagen.shuffle(&mut data);
 
    println!("Input: \n");
     for i in 0..8 {
@@ -73,6 +71,7 @@ fn main() -> ocl::Result<()> {
       println!("The radix sort succeeded.\n");
    }else {
       println!("The radix sort failed.\n");
+      datagen.report_failure();
    }
 
     Ok(())
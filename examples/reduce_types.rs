@@ -0,0 +1,135 @@
+use ocl::{Buffer, Context, Device, DeviceType, Kernel, OclPrm, Platform, Queue};
+use rand::{Rng, SeedableRng};
+use simple_gpu::interpreter;
+use simple_gpu::template::{KernelTemplate, Params, ScalarType};
+
+// One work-group reduction source, built for float, double and int. The
+// element type is substituted into the text and the group size arrives as
+// a define; each parameter set is built once and then reused.
+//
+// usage: reduce_types [--interp]
+
+const KERNEL_SRC: &str = r#"
+__kernel void reduce(__global const ${T}* data,
+                     __local ${T}* scratch,
+                     __global ${T}* sums) {
+    uint lid = get_local_id(0);
+    scratch[lid] = data[get_global_id(0)];
+    barrier(CLK_LOCAL_MEM_FENCE);
+    for (uint stride = GROUP_SIZE / 2; stride > 0; stride /= 2) {
+        if (lid < stride) {
+            scratch[lid] += scratch[lid + stride];
+        }
+        barrier(CLK_LOCAL_MEM_FENCE);
+    }
+    if (lid == 0) {
+        sums[get_group_id(0)] = scratch[0];
+    }
+}
+"#;
+
+const GROUP_SIZE: usize = 64;
+const LEN: usize = 4096;
+
+/// Per-group sums of `data` for the element type `T`.
+fn reduce<T: OclPrm + 'static>(
+    template: &mut KernelTemplate,
+    queue: Option<&Queue>,
+    data: &[T],
+) -> simple_gpu::Result<Vec<T>> {
+    let ty = ScalarType::of::<T>().expect("not an OpenCL scalar");
+    let params = Params::new().ty("T", ty).int("GROUP_SIZE", GROUP_SIZE as i64);
+    let groups = data.len() / GROUP_SIZE;
+    let mut sums = vec![T::default(); groups];
+    match queue {
+        Some(queue) => {
+            let program = template.program(&queue.context(), queue.device(), &params)?;
+            let input = Buffer::builder().queue(queue.clone()).len(data.len()).copy_host_slice(data).build()?;
+            let output = Buffer::<T>::builder().queue(queue.clone()).len(groups).build()?;
+            let kernel = Kernel::builder()
+                .program(program)
+                .name("reduce")
+                .queue(queue.clone())
+                .arg(&input)
+                .arg_local::<T>(GROUP_SIZE)
+                .arg(&output)
+                .global_work_size(data.len())
+                .local_work_size(GROUP_SIZE)
+                .build()?;
+            unsafe { kernel.enq()? };
+            output.read(&mut sums).enq()?;
+        }
+        None => {
+            let program = template.interpreted(&params)?;
+            let mut input = interpreter::Buffer::from_slice(data);
+            let mut output = interpreter::Buffer::new::<T>(groups);
+            program
+                .kernel("reduce")?
+                .arg_buf(&mut input)
+                .arg_local::<T>(GROUP_SIZE)
+                .arg_buf(&mut output)
+                .global_work_size(data.len())
+                .local_work_size(GROUP_SIZE)
+                .run()?;
+            sums = output.to_vec();
+        }
+    }
+    Ok(sums)
+}
+
+/// Compares the group sums against the host, within `tolerance` relative
+/// to each sum's magnitude.
+fn check<T: Copy>(label: &str, sums: &[T], data: &[T], to_f64: impl Fn(T) -> f64, tolerance: f64) -> bool {
+    let worst = sums
+        .iter()
+        .zip(data.chunks(GROUP_SIZE))
+        .map(|(&sum, group)| {
+            let expected: f64 = group.iter().map(|&x| to_f64(x)).sum();
+            (to_f64(sum) - expected).abs() / expected.abs().max(1.0)
+        })
+        .fold(0.0, f64::max);
+    let ok = worst <= tolerance;
+    println!("{:<8} {} groups, worst relative error {:.2e} {}", label, sums.len(), worst, if ok { "ok" } else { "FAILED" });
+    ok
+}
+
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
+    let queue = if std::env::args().any(|a| a == "--interp") || !interpreter::driver_available() {
+        println!("Running on the interpreter.");
+        None
+    } else {
+        Some(open_queue()?)
+    };
+    let mut rng = rand::rngs::StdRng::seed_from_u64(11);
+    let mut template = KernelTemplate::new(KERNEL_SRC)?;
+    println!("placeholders: {:?}", template.placeholders());
+
+    let floats: Vec<f32> = (0..LEN).map(|_| rng.gen_range(-1.0..1.0)).collect();
+    let doubles: Vec<f64> = (0..LEN).map(|_| rng.gen_range(-1.0..1.0)).collect();
+    let ints: Vec<i32> = (0..LEN).map(|_| rng.gen_range(-1000..1000)).collect();
+
+    let mut ok = check("float", &reduce(&mut template, queue.as_ref(), &floats)?, &floats, f64::from, 1e-5);
+    ok &= check("double", &reduce(&mut template, queue.as_ref(), &doubles)?, &doubles, |x| x, 1e-12);
+    ok &= check("int", &reduce(&mut template, queue.as_ref(), &ints)?, &ints, f64::from, 0.0);
+    // Served from the cache.
+    ok &= check("float", &reduce(&mut template, queue.as_ref(), &floats)?, &floats, f64::from, 1e-5);
+    println!("{} programs built for 4 reductions", template.cached());
+
+    if !ok {
+        eprintln!("a reduction did not match the host");
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn open_queue() -> simple_gpu::Result<Queue> {
+    let platform = Platform::list().into_iter().next().expect("No platforms found");
+    let mut devices = Device::list(platform, Some(DeviceType::GPU)).unwrap_or_default();
+    if devices.is_empty() {
+        devices = Device::list(platform, Some(DeviceType::CPU)).unwrap_or_default();
+    }
+    let dev = devices.into_iter().next().expect("No devices found");
+    let context = Context::builder().platform(platform).devices(dev).build()?;
+    Ok(Queue::new(&context, dev, None)?)
+}
@@ -1,10 +1,12 @@
 use ocl::{Platform, Device, Context, Queue, Program, Buffer, flags, DeviceType, builders::KernelBuilder};
+use simple_gpu::reference;
 use std::time::Instant;
 
 const ARRAY_SIZE: usize = 65536;
 const NUM_KERNELS: usize = 2;
 
-fn main() -> ocl::Result<()> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
     let src = std::fs::read_to_string("hello_kernel.cl").expect("Failed to read reduction.cl");
 
     let platform = Platform::list().into_iter().next().unwrap();
@@ -14,17 +16,14 @@ fn main() -> ocl::Result<()> {
     }
     let device = devices.into_iter().next().unwrap();
 
-    let context = Context::builder().platform(platform).devices(device.clone()).build()?;
-    let queue = Queue::new(&context, device.clone(), Some(flags::QUEUE_PROFILING_ENABLE))?;
+    let context = Context::builder().platform(platform).devices(device).build()?;
+    let queue = Queue::new(&context, device, Some(flags::QUEUE_PROFILING_ENABLE))?;
 
     let local_size = 128usize;
     let global_size_scalar = ARRAY_SIZE;
     let global_size_vector = ARRAY_SIZE / 4;
 
-    let mut data = vec![0.0f32; ARRAY_SIZE];
-    for i in 0..ARRAY_SIZE {
-        data[i] = i as f32;
-    }
+    let data: Vec<f32> = (0..ARRAY_SIZE).map(|i| i as f32).collect();
 
     let data_buffer = Buffer::<f32>::builder()
         .queue(queue.clone())
@@ -35,12 +34,12 @@ fn main() -> ocl::Result<()> {
 
     let program = Program::builder()
         .src(src)
-        .devices(device.clone())
+        .devices(device)
         .build(&context)?;
 
     let kernel_names = ["reduction_scalar", "reduction_vector"];
 
-    for i in 0..NUM_KERNELS {
+    for (i, kernel_name) in kernel_names.iter().enumerate().take(NUM_KERNELS) {
         let start = Instant::now();
         let (global_size, local_mem_size, num_groups) = if i == 0 {
             let num_groups = global_size_scalar / local_size;
@@ -58,7 +57,7 @@ fn main() -> ocl::Result<()> {
 
         let kernel = KernelBuilder::new()
             .program(&program)
-            .name(kernel_names[i])
+            .name(*kernel_name)
             .queue(queue.clone())
             .arg(&data_buffer)
             .arg_local::<f32>(local_mem_size)
@@ -82,12 +81,11 @@ fn main() -> ocl::Result<()> {
         sums_buffer.read(&mut sums[..]).enq()?;
         let sum: f32 = sums.iter().sum();
 
-        println!("{} sum is: {}", kernel_names[i], sum);
-        let actual_sum = (ARRAY_SIZE as f32 / 2.0) * ((ARRAY_SIZE - 1) as f32);
-        if (sum - actual_sum).abs() > 0.01 * sum.abs() {
-            println!("Check failed.");
-        } else {
-            println!("Check passed.");
+        println!("{} sum is: {}", kernel_name, sum);
+        let expected = reference::partial_sums(&data, ARRAY_SIZE / num_groups);
+        match reference::diff(&sums, &expected, 1e-4) {
+            Ok(()) => println!("Check passed."),
+            Err(mismatch) => println!("Check failed: {}", mismatch),
         }
         let duration = start.elapsed();
         
@@ -4,7 +4,8 @@ use std::time::Instant;
 const ARRAY_SIZE: usize = 65536;
 const NUM_KERNELS: usize = 2;
 
-fn main() -> ocl::Result<()> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
     let src = std::fs::read_to_string("hello_kernel.cl").expect("Failed to read reduction.cl");
 
     let platform = Platform::list().into_iter().next().unwrap();
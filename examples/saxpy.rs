@@ -1,7 +1,10 @@
-use ocl::{ProQue, flags::MemFlags};
+use simple_gpu::multi_device::MultiDevice;
+use simple_gpu::reference;
 
-fn main() -> ocl::Result<()> {
-    let vector_size = 1024;
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
+    let vector_size = 1 << 20;
+    let local_size = 64;
     let alpha = 2.0f32;
 
     // Initialize vectors
@@ -20,34 +23,40 @@ fn main() -> ocl::Result<()> {
         }
     "#;
 
-    // Initialize OpenCL environment
-    let pro_que = ProQue::builder()
-        .src(kernel_source)
-        .dims(vector_size)
-        .build()?;
-
-    // Create buffers
-    let a_buffer = pro_que.buffer_builder().flags(MemFlags::READ_ONLY | MemFlags::COPY_HOST_PTR).copy_host_slice(&a).build()?;
-    let b_buffer = pro_que.buffer_builder().flags(MemFlags::READ_ONLY | MemFlags::COPY_HOST_PTR).copy_host_slice(&b).build()?;
-    let c_buffer = pro_que.buffer_builder().flags(MemFlags::WRITE_ONLY).len(vector_size).build()?;
-
-    // Create and execute kernel
-    let kernel = pro_que.kernel_builder("saxpy_kernel")
-        .arg(alpha)
-        .arg(&a_buffer)
-        .arg(&b_buffer)
-        .arg(&c_buffer)
-        .local_work_size(64)
-        .build()?;
+    // Build on every device of every platform
+    let mut md = MultiDevice::new(kernel_source)?;
+    for slot in md.devices() {
+        println!("Using {} (estimated weight {:.0})", slot.name, slot.weight);
+    }
+    for reason in md.skipped() {
+        println!("Skipped {}", reason);
+    }
 
-    let local_size = 64;
-    unsafe {kernel.cmd().global_work_size(vector_size).local_work_size(local_size).enq()?;}
+    // The first run splits by estimate, the second by measured throughput
+    for pass in 0..2 {
+        let reports = md
+            .launch::<f32>("saxpy_kernel")
+            .global_work_size(vector_size)
+            .local_work_size(local_size)
+            .scalar(alpha)
+            .split(&a, local_size)
+            .split(&b, local_size)
+            .output(&mut c, local_size)
+            .run()?;
+        for r in &reports {
+            println!("pass {}: {} ran {} work-groups in {:?}", pass, r.name, r.groups, r.kernel_time);
+        }
+        md.rebalance(&reports);
+    }
 
-    // Read results
-    c_buffer.read(&mut c).enq()?;
-    for i in 0..vector_size {
+    // Check results; devices may fuse the multiply-add, so allow rounding
+    for i in (0..vector_size).step_by(vector_size / 8) {
         println!("{} * {} + {} = {}", alpha, a[i], b[i], c[i]);
     }
+    match reference::diff(&c, &reference::saxpy(alpha, &a, &b), 1e-6) {
+        Ok(()) => println!("Matches the reference."),
+        Err(mismatch) => println!("Differs from the reference: {}", mismatch),
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
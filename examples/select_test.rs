@@ -2,7 +2,8 @@ use ocl::builders::ProgramBuilder;
 use ocl::{flags, Buffer, Context, Device, DeviceType, Platform, Queue};
 use std::{fs};
 
-fn main() -> ocl::Result<()> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
 
     let platforms = Platform::list();
 
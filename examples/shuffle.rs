@@ -1,8 +1,51 @@
 use ocl::builders::ProgramBuilder;
+use ocl::prm::{Char16, Float8};
 use ocl::{flags, Buffer, Context, Device, DeviceType, Platform, Queue};
-use std::{fs};
+use simple_gpu::interpreter;
+use simple_gpu::vector::{ByteVector, Vector};
 
-fn main() -> ocl::Result<()> {
+const KERNEL_SRC: &str = r#"
+__kernel void shuffle_test(__global float8 *s1,
+                           __global char16 *s2) {
+
+   /* Execute the first example */
+   uint8 mask1 = (uint8)(1, 2, 0, 1, 3, 1, 2, 3);
+   float4 input = (float4)(0.25f, 0.5f, 0.75f, 1.0f);
+   *s1 = shuffle(input, mask1);
+
+   /* Execute the second example */
+   uchar16 mask2 = (uchar16)(6, 10, 5, 2, 8, 0, 9, 14,
+                             7, 5, 12, 3, 11, 15, 1, 13);
+   char8 input1 = (char8)('l', 'o', 'f', 'c', 'a', 'u', 's', 'f');
+   char8 input2 = (char8)('f', 'e', 'h', 't', 'n', 'n', '2', 'i');
+   *s2 = shuffle2(input1, input2, mask2);
+}
+"#;
+
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
+    let (s1, s2) = if std::env::args().any(|a| a == "--interp") || !interpreter::driver_available() {
+        println!("Running on the interpreter.");
+        let mut s1_buffer = interpreter::Buffer::new::<Float8>(1);
+        let mut s2_buffer = interpreter::Buffer::new::<Char16>(1);
+        interpreter::Program::build(KERNEL_SRC)?
+            .kernel("shuffle_test")?
+            .arg_buf(&mut s1_buffer)
+            .arg_buf(&mut s2_buffer)
+            .global_work_size(1)
+            .run()?;
+        (s1_buffer.to_vec::<Float8>()[0], s2_buffer.to_vec::<Char16>()[0])
+    } else {
+        on_device()?
+    };
+
+    println!("s1 (float8): {:?}", s1.lanes());
+    println!("s2 (char16): {}", s2.to_string_lossy());
+
+    Ok(())
+}
+
+fn on_device() -> simple_gpu::Result<(Float8, Char16)> {
 
     let platforms = Platform::list();
 
@@ -15,15 +58,14 @@ fn main() -> ocl::Result<()> {
     let dev = devices.into_iter().next().expect("No devices found");
 
     let context = Context::builder().platform(platform).devices(dev).build()?;
-    let program_handle = fs::read_to_string("hello_kernel.cl").unwrap_or_else(|_| panic!("Failed to read file: hello_kernel.cl"));
     let queue = Queue::new(&context, (*dev).into(), None)?;
 
-    let program_con = ProgramBuilder::new() .src(&program_handle).devices(dev.clone()) .build(&context)?;
-    let mut s1 = [0.0f32; 8];
-    let mut s2 = [0u8; 16];
+    let program_con = ProgramBuilder::new() .src(KERNEL_SRC).devices(dev) .build(&context)?;
+    let mut s1 = [Float8::default()];
+    let mut s2 = [Char16::default()];
 
-    let s1_buffer = Buffer::<f32>::builder() .queue(queue.clone()).flags(flags::MEM_WRITE_ONLY) .len(8) .build()?;
-    let s2_buffer = Buffer::<u8>::builder() .queue(queue.clone()).flags(flags::MEM_WRITE_ONLY) .len(16) .build()?;
+    let s1_buffer = Buffer::<Float8>::builder() .queue(queue.clone()).flags(flags::MEM_WRITE_ONLY) .len(1) .build()?;
+    let s2_buffer = Buffer::<Char16>::builder() .queue(queue.clone()).flags(flags::MEM_WRITE_ONLY) .len(1) .build()?;
 
     let kernel = ocl::Kernel::builder().program(&program_con).name("shuffle_test").queue(queue.clone()).arg(&s1_buffer).arg(&s2_buffer).global_work_size(1).build()?;
 
@@ -31,12 +73,5 @@ fn main() -> ocl::Result<()> {
     s1_buffer.read(&mut s1[..]).enq()?;
     s2_buffer.read(&mut s2[..]).enq()?;
 
-    println!("s1 (float8): {:?}", s1);
-    print!("s2 (char16): ");
-    for &c in &s2 {
-        print!("{}", c as u8 as char);
-    }
-    println!();
-
-    Ok(())
-}
\ No newline at end of file
+    Ok((s1[0], s2[0]))
+}
@@ -4,7 +4,8 @@ use ocl::{ Context, Device, DeviceType,  Platform, Queue, MemFlags, Image};
 use ocl::enums::{ ImageChannelDataType, ImageChannelOrder,  };
 use std::{fs};
 
-fn main() -> ocl::Result<()> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
 
     let platforms = Platform::list();
 
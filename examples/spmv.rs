@@ -0,0 +1,132 @@
+use ocl::{Buffer, Context, Device, DeviceType, Platform, Queue};
+use rand::{Rng, SeedableRng};
+use simple_gpu::sparse::{Csr, Ell, Triplet, Variant};
+use simple_gpu::{interpreter, reference};
+
+// Sparse matrix-vector products in CSR (both kernels) and ELL form, checked
+// against the host. Without arguments it uses a 2D Laplacian, whose short
+// rows suit the scalar kernel, and a wide band matrix for the vector one.
+//
+// usage: spmv [MATRIX.mtx ...] [--interp]
+
+fn laplacian(n: usize) -> (usize, Vec<Triplet>) {
+    let mut triplets = Vec::new();
+    for y in 0..n {
+        for x in 0..n {
+            let row = y * n + x;
+            triplets.push((row, row, 4.0));
+            if x > 0 {
+                triplets.push((row, row - 1, -1.0));
+            }
+            if x + 1 < n {
+                triplets.push((row, row + 1, -1.0));
+            }
+            if y > 0 {
+                triplets.push((row, row - n, -1.0));
+            }
+            if y + 1 < n {
+                triplets.push((row, row + n, -1.0));
+            }
+        }
+    }
+    (n * n, triplets)
+}
+
+fn band(n: usize, half_width: usize, rng: &mut impl Rng) -> Vec<Triplet> {
+    (0..n)
+        .flat_map(|row| (row.saturating_sub(half_width)..(row + half_width + 1).min(n)).map(move |col| (row, col)))
+        .map(|(row, col)| (row, col, rng.gen_range(-1.0..1.0)))
+        .collect()
+}
+
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
+    let queue = if std::env::args().any(|a| a == "--interp") || !interpreter::driver_available() {
+        println!("Running on the interpreter.");
+        None
+    } else {
+        Some(open_queue()?)
+    };
+    let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+
+    let mut matrices = Vec::new();
+    for path in std::env::args().skip(1).filter(|a| !a.starts_with("--")) {
+        matrices.push((path.clone(), Csr::load_mtx(&path)?));
+    }
+    if matrices.is_empty() {
+        let (n, triplets) = laplacian(24);
+        let laplacian = Csr::from_triplets(n, n, &triplets)?;
+        // Through a Matrix Market file and back.
+        let path = std::env::temp_dir().join("spmv_laplacian.mtx");
+        laplacian.save_mtx(&path)?;
+        let reloaded = Csr::load_mtx(&path)?;
+        assert_eq!(reloaded, laplacian, "Matrix Market round trip changed the matrix");
+        matrices.push(("laplacian 24x24".into(), reloaded));
+        matrices.push(("band 300, 41 wide".into(), Csr::from_triplets(300, 300, &band(300, 20, &mut rng))?));
+    }
+
+    for (name, csr) in &matrices {
+        let ell = Ell::from_csr(csr);
+        println!(
+            "{}: {}x{}, {} entries, {:.1} per row (longest {}), ELL fill {:.0}%",
+            name,
+            csr.rows,
+            csr.cols,
+            csr.nnz(),
+            csr.mean_row_len(),
+            csr.max_row_len(),
+            ell.fill() * 100.0
+        );
+        let x: Vec<f32> = (0..csr.cols).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        let expected = reference::spmv(csr, &x);
+        let other = match csr.variant() {
+            Variant::Scalar => Variant::Vector { lanes: 4 },
+            Variant::Vector { .. } => Variant::Scalar,
+        };
+        for (label, variant) in [("CSR, chosen", csr.variant()), ("CSR, other", other)] {
+            let y = match &queue {
+                Some(queue) => on_device(queue, &x, csr.rows, |x, y| csr.upload(queue)?.spmv_with(variant, x, y))?,
+                None => csr.spmv_interpreted(&x, variant)?,
+            };
+            report(&format!("{} {}", label, variant), &y, &expected);
+        }
+        let y = match &queue {
+            Some(queue) => on_device(queue, &x, csr.rows, |x, y| ell.upload(queue)?.spmv(x, y))?,
+            None => ell.spmv_interpreted(&x)?,
+        };
+        report("ELL", &y, &expected);
+    }
+    Ok(())
+}
+
+fn report(label: &str, y: &[f32], expected: &[f32]) {
+    match reference::diff(y, expected, 1e-5) {
+        Ok(()) => println!("  {:<30} ok", label),
+        Err(mismatch) => println!("  {:<30} FAILED: {}", label, mismatch),
+    }
+}
+
+fn on_device(
+    queue: &Queue,
+    x: &[f32],
+    rows: usize,
+    spmv: impl FnOnce(&Buffer<f32>, &Buffer<f32>) -> simple_gpu::Result<()>,
+) -> simple_gpu::Result<Vec<f32>> {
+    let x = Buffer::builder().queue(queue.clone()).len(x.len()).copy_host_slice(x).build()?;
+    let y = Buffer::builder().queue(queue.clone()).len(rows).build()?;
+    spmv(&x, &y)?;
+    let mut result = vec![0.0; rows];
+    y.read(&mut result).enq()?;
+    Ok(result)
+}
+
+fn open_queue() -> simple_gpu::Result<Queue> {
+    let platform = Platform::list().into_iter().next().expect("No platforms found");
+    let mut devices = Device::list(platform, Some(DeviceType::GPU)).unwrap_or_default();
+    if devices.is_empty() {
+        devices = Device::list(platform, Some(DeviceType::CPU)).unwrap_or_default();
+    }
+    let dev = devices.into_iter().next().expect("No devices found");
+    let context = Context::builder().platform(platform).devices(dev).build()?;
+    Ok(Queue::new(&context, dev, None)?)
+}
@@ -1,8 +1,10 @@
 use ocl::{Platform, Device, Context, Queue, Program, Buffer, flags, DeviceType, builders::KernelBuilder};
 use ocl::prm::Char16;
+use simple_gpu::vector::ByteVector;
 const TEXT_FILE: &str = "kafka.txt";
 
-fn main() -> ocl::Result<()> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
     let src = std::fs::read_to_string("hello_kernel.cl").expect("Failed to read ");
     let text_file = std::fs::read_to_string(TEXT_FILE).expect("Failed to read ");
     let text_size = text_file.len();
@@ -14,8 +16,8 @@ fn main() -> ocl::Result<()> {
     }
     let device = devices.into_iter().next().unwrap();
 
-    let context = Context::builder().platform(platform).devices(device.clone()).build()?;
-    let queue = Queue::new(&context, device.clone(), Some(flags::QUEUE_PROFILING_ENABLE))?;
+    let context = Context::builder().platform(platform).devices(device).build()?;
+    let queue = Queue::new(&context, device, Some(flags::QUEUE_PROFILING_ENABLE))?;
 
     let mut result = [0i32; 4];
     let max_group_size = match device.info(ocl::core::DeviceInfo::MaxWorkGroupSize)? {
@@ -26,27 +28,16 @@ fn main() -> ocl::Result<()> {
     ocl::enums::DeviceInfoResult::MaxComputeUnits(units) => units,
     _ => 0,
     };
-    let global_size = (max_group_size as usize) * (max_compute_units as usize) ;
-    let local_size = max_group_size as usize;
+    let global_size = max_group_size * (max_compute_units as usize) ;
+    let local_size = max_group_size;
     
-    let chars_per_item = (text_size as usize / global_size as usize) + 1;
+    let chars_per_item = (text_size / global_size) + 1;
     
-    let pattern = "thatwithhavefrom";
-    let mut pattern_array = [0i8; 16];
-    for (i, &b) in pattern.as_bytes().iter().enumerate() {
-        pattern_array[i] = b as i8;
-    }
-
-    let pattern_vec = Char16::new(
-    pattern_array[0], pattern_array[1], pattern_array[2], pattern_array[3],
-    pattern_array[4], pattern_array[5], pattern_array[6], pattern_array[7],
-    pattern_array[8], pattern_array[9], pattern_array[10], pattern_array[11],
-    pattern_array[12], pattern_array[13], pattern_array[14], pattern_array[15],
-    );
+    let pattern_vec = Char16::from_str_padded("thatwithhavefrom").expect("pattern fits a char16");
 
     let program = Program::builder()
         .src(src)
-        .devices(device.clone())
+        .devices(device)
         .build(&context)?;
 
     let text_buffer = Buffer::<u8>::builder()
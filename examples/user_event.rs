@@ -1,8 +1,10 @@
 use ocl::builders::ProgramBuilder;
 use ocl::{ Context, Device, DeviceType,  Platform, Queue};
+use simple_gpu::graph::CommandGraph;
 use std::{fs};
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
 
     let platforms = Platform::list();
 
@@ -20,14 +22,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let program_con = ProgramBuilder::new()
         .src(&program_handle)
-        .devices(dev.clone())
+        .devices(dev)
         .build(&context)?;
-    let mut buffer = [1.0f32,2.0,-3.5,-6.7];
+    let buffer = [1.0f32,2.0,-3.5,-6.7];
     let buffer_cl = ocl::Buffer::<f32>::builder()
         .queue(queue.clone())
-        .flags(ocl::flags::MEM_WRITE_ONLY)
+        .flags(ocl::flags::MEM_READ_WRITE)
         .len(4)
-        .copy_host_slice(&buffer)
         .build()?;
 
     let kernel = ocl::Kernel::builder()
@@ -38,14 +39,20 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .global_work_size(1)
         .build()?;
 
-    unsafe {
-        kernel.cmd()
-            .queue(&queue)
-            .global_work_size(1)
-            .enq()?;
-    }
-
-    buffer_cl.read(&mut buffer[..]).enq()?;
+    let mut graph = CommandGraph::new();
+    let gate = graph.gate("host ready");
+    let upload = graph.write("upload", &buffer_cl, buffer.to_vec());
+    let run = graph.kernel("user_event", kernel);
+    let download = graph.read::<f32>("download", &buffer_cl);
+    graph.after(upload, &[gate])?;
+    graph.chain(&[upload, run, download])?;
+    println!("{}", graph.to_dot());
+
+    let mut pending = graph.run(&queue)?;
+    println!("Commands enqueued, waiting on the user event.");
+    pending.trigger(gate)?;
+    pending.wait()?;
+    let buffer = graph.read_data::<f32>(download).expect("download is a read node");
 
     println!("Output vector: {:?}", buffer);
 
@@ -1,8 +1,48 @@
 use ocl::builders::ProgramBuilder;
+use ocl::prm::Uchar16;
 use ocl::{Buffer, Context, Device, DeviceType, MemFlags, Platform, Queue};
-use std::{fs};
+use simple_gpu::interpreter;
+use simple_gpu::layout::{self, Layout};
+use simple_gpu::vector::{self, ByteVector};
 
-fn main() -> ocl::Result<()> {
+const KERNEL_SRC: &str = r#"
+__kernel void vector_bytes(__global uchar16 *test) {
+
+   /* Initialize a vector of four integers */
+   uint4 vec = {0x00010203, 0x04050607,
+      0x08090A0B, 0x0C0D0E0F};
+
+   /* Convert the uint4 to a uchar16 byte-by-byte */
+   uchar *p = &vec;
+   *test = (uchar16)(*p, *(p+1), *(p+2), *(p+3), *(p+4), *(p+5),
+      *(p+6), *(p+7), *(p+8), *(p+9), *(p+10), *(p+11), *(p+12),
+      *(p+13), *(p+14), *(p+15));
+}
+"#;
+
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
+    let msg = if std::env::args().any(|a| a == "--interp") || !interpreter::driver_available() {
+        println!("Running on the interpreter.");
+        let mut msg_buffer = interpreter::Buffer::new::<Uchar16>(1);
+        interpreter::Program::build(KERNEL_SRC)?
+            .kernel("vector_bytes")?
+            .arg_buf(&mut msg_buffer)
+            .global_work_size(1)
+            .run()?;
+        msg_buffer.to_vec::<Uchar16>()[0].to_bytes()
+    } else {
+        on_device()?
+    };
+    for b in &msg {
+        print!("{:02X} ", b);
+    }
+    println!();
+
+    Ok(())
+}
+
+fn on_device() -> simple_gpu::Result<Vec<u8>> {
 
     let platforms = Platform::list();
 
@@ -15,39 +55,20 @@ fn main() -> ocl::Result<()> {
     let dev = devices.into_iter().next().expect("No devices found");
 
     let context = Context::builder().platform(platform).devices(dev).build()?;
-    let program_handle = fs::read_to_string("hello_kernel.cl").unwrap_or_else(|_| panic!("Failed to read file: hello_kernel.cl"));
     let queue = Queue::new(&context, (*dev).into(), None)?;
 
     let program_con = ProgramBuilder::new()
-        .src(&program_handle)
-        .devices(dev.clone())
+        .src(KERNEL_SRC)
+        .devices(dev)
         .build(&context)?;
 
-    let mut msg = vec! [0u8; 16];
-    let msg_buffer = Buffer::<u8>::builder().queue(queue.clone()).flags(MemFlags::new().write_only()).len(16).build()?;
-        
+    let msg_buffer = Buffer::<Uchar16>::builder().queue(queue.clone()).flags(MemFlags::new().write_only()).len(1).build()?;
+
     let kernel = ocl::Kernel::builder().program(&program_con).name("vector_bytes").queue(queue.clone()).arg(&msg_buffer).build()?;
 
     unsafe {kernel.cmd().queue(&queue).global_work_size(1).enq()?;}
-    msg_buffer.read(&mut msg[..]).enq()?;
-    for b in &msg {
-        print!("{:02X} ", b);
-    }
-    println!();
 
-    Ok(())
-}
-/*
-__kernel void vector_bytes(__global uchar16 *test) {
-
-   /* Initialize a vector of four integers */
-   uint4 vec = {0x00010203, 0x04050607, 
-      0x08090A0B, 0x0C0D0E0F}; 
-
-   /* Convert the uint4 to a uchar16 byte-by-byte */
-   uchar *p = &vec;
-   *test = (uchar16)(*p, *(p+1), *(p+2), *(p+3), *(p+4), *(p+5), 
-      *(p+6), *(p+7), *(p+8), *(p+9), *(p+10), *(p+11), *(p+12), 
-      *(p+13), *(p+14), *(p+15));
+    let layout = Layout::probe(&queue)?;
+    println!("Device is {} (reports {}), host is {}.", layout.measured, layout.reported, layout::ByteOrder::host());
+    vector::read_flat(&msg_buffer)
 }
-*/
\ No newline at end of file
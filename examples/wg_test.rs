@@ -4,7 +4,8 @@ use ocl::{Buffer, Context, Device, DeviceType, MemFlags, Platform, Queue, core};
 use std::fs;
 
 
-fn main() -> ocl::Result<()> {
+fn main() -> simple_gpu::Result<()> {
+    simple_gpu::logging::init();
 
     let platforms = Platform::list();
 
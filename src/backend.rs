@@ -0,0 +1,638 @@
+//! The OpenCL calls made by host-side setup code, behind a trait.
+//!
+//! `OclBackend` forwards to the driver. `MockBackend` is scripted instead:
+//! its devices are plain `DeviceProps`, chosen calls can be made to fail
+//! with a given status, and every call is recorded, so error handling can be
+//! exercised deterministically on machines without a driver. The helpers at
+//! the end (`default_device`, `build_program`, `find_kernel`) are the logic
+//! the examples share, written once against the trait.
+
+use crate::build_options::ClVersion;
+use crate::interpreter;
+use crate::logging::span;
+use crate::{Error, Result};
+use log::Level;
+use ocl::core::{self, BufferRegion, ProgramBuildInfo, ProgramBuildInfoResult, ProgramInfo, ProgramInfoResult};
+use ocl::enums::{DeviceInfo, DeviceInfoResult};
+use ocl::flags::{DeviceType, MemFlags};
+use ocl::{Context, Device, Platform};
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::fmt;
+
+pub use ocl::core::Status;
+
+/// The API calls a backend makes, named after the OpenCL entry points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Call {
+    GetPlatformIds,
+    GetDeviceIds,
+    GetDeviceInfo,
+    CreateContext,
+    CreateProgramWithSource,
+    BuildProgram,
+    GetProgramBuildInfo,
+    GetProgramInfo,
+    CreateBuffer,
+    CreateSubBuffer,
+}
+
+impl Call {
+    /// The OpenCL entry point, e.g. `clGetDeviceIDs`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Call::GetPlatformIds => "clGetPlatformIDs",
+            Call::GetDeviceIds => "clGetDeviceIDs",
+            Call::GetDeviceInfo => "clGetDeviceInfo",
+            Call::CreateContext => "clCreateContext",
+            Call::CreateProgramWithSource => "clCreateProgramWithSource",
+            Call::BuildProgram => "clBuildProgram",
+            Call::GetProgramBuildInfo => "clGetProgramBuildInfo",
+            Call::GetProgramInfo => "clGetProgramInfo",
+            Call::CreateBuffer => "clCreateBuffer",
+            Call::CreateSubBuffer => "clCreateSubBuffer",
+        }
+    }
+
+    /// `err` as a failure of this call.
+    fn error(self, err: impl Into<Error>) -> Error {
+        err.into().call(self.name())
+    }
+}
+
+/// The device properties host code commonly branches on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceProps {
+    pub name: String,
+    pub vendor: String,
+    pub device_type: DeviceType,
+    pub compute_units: u32,
+    pub max_work_group_size: usize,
+    pub global_mem_size: u64,
+    pub local_mem_size: u64,
+    /// `CL_DEVICE_MEM_BASE_ADDR_ALIGN`, in bits.
+    pub mem_base_addr_align: u32,
+    /// `CL_DEVICE_OPENCL_C_VERSION`.
+    pub c_version: ClVersion,
+}
+
+impl Default for DeviceProps {
+    /// A modest discrete GPU.
+    fn default() -> Self {
+        DeviceProps {
+            name: "Mock GPU".into(),
+            vendor: "Mock".into(),
+            device_type: DeviceType::GPU,
+            compute_units: 16,
+            max_work_group_size: 256,
+            global_mem_size: 1 << 30,
+            local_mem_size: 32 << 10,
+            mem_base_addr_align: 1024,
+            c_version: ClVersion::V1_2,
+        }
+    }
+}
+
+pub trait Backend {
+    type Platform: Copy + fmt::Debug;
+    type Device: Copy + fmt::Debug + PartialEq;
+    type Context;
+    type Program;
+    type Buffer;
+
+    fn platforms(&self) -> Result<Vec<Self::Platform>>;
+    /// Fails with `CL_DEVICE_NOT_FOUND` when no device matches, as OpenCL
+    /// does.
+    fn devices(&self, platform: Self::Platform, device_type: DeviceType) -> Result<Vec<Self::Device>>;
+    fn device_props(&self, device: Self::Device) -> Result<DeviceProps>;
+    fn create_context(&self, devices: &[Self::Device]) -> Result<Self::Context>;
+    fn create_program(&self, context: &Self::Context, sources: &[&str]) -> Result<Self::Program>;
+    fn build_program(&self, program: &Self::Program, device: Self::Device, options: &str) -> Result<()>;
+    fn build_log(&self, program: &Self::Program, device: Self::Device) -> Result<String>;
+    /// Names of the kernels in a built program, in program order.
+    fn kernel_names(&self, program: &Self::Program) -> Result<Vec<String>>;
+    /// A buffer of `size` bytes.
+    fn create_buffer(&self, context: &Self::Context, flags: MemFlags, size: usize) -> Result<Self::Buffer>;
+    /// A sub-buffer of `size` bytes starting `origin` bytes into `buffer`.
+    fn create_sub_buffer(
+        &self,
+        buffer: &Self::Buffer,
+        flags: MemFlags,
+        origin: usize,
+        size: usize,
+    ) -> Result<Self::Buffer>;
+}
+
+/// The installed OpenCL driver.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OclBackend;
+
+impl Backend for OclBackend {
+    type Platform = Platform;
+    type Device = Device;
+    type Context = Context;
+    type Program = core::Program;
+    type Buffer = core::Mem;
+
+    fn platforms(&self) -> Result<Vec<Platform>> {
+        // `Platform::list` panics when no ICD is installed.
+        let ids = core::get_platform_ids().map_err(|err| Call::GetPlatformIds.error(err))?;
+        Ok(Platform::list_from_core(ids))
+    }
+
+    fn devices(&self, platform: Platform, device_type: DeviceType) -> Result<Vec<Device>> {
+        let ids = core::get_device_ids(platform, Some(device_type), None)
+            .map_err(|err| Call::GetDeviceIds.error(err))?;
+        Ok(ids.into_iter().map(Device::from).collect())
+    }
+
+    fn device_props(&self, device: Device) -> Result<DeviceProps> {
+        let info = |kind| device.info(kind).map_err(|err| Call::GetDeviceInfo.error(err));
+        let mut props = DeviceProps {
+            name: device.name().map_err(|err| Call::GetDeviceInfo.error(err))?,
+            vendor: device.vendor().map_err(|err| Call::GetDeviceInfo.error(err))?,
+            ..DeviceProps::default()
+        };
+        if let DeviceInfoResult::Type(device_type) = info(DeviceInfo::Type)? {
+            props.device_type = device_type;
+        }
+        if let DeviceInfoResult::MaxComputeUnits(units) = info(DeviceInfo::MaxComputeUnits)? {
+            props.compute_units = units;
+        }
+        if let DeviceInfoResult::MaxWorkGroupSize(size) = info(DeviceInfo::MaxWorkGroupSize)? {
+            props.max_work_group_size = size;
+        }
+        if let DeviceInfoResult::GlobalMemSize(size) = info(DeviceInfo::GlobalMemSize)? {
+            props.global_mem_size = size;
+        }
+        if let DeviceInfoResult::LocalMemSize(size) = info(DeviceInfo::LocalMemSize)? {
+            props.local_mem_size = size;
+        }
+        if let DeviceInfoResult::MemBaseAddrAlign(bits) = info(DeviceInfo::MemBaseAddrAlign)? {
+            props.mem_base_addr_align = bits;
+        }
+        if let DeviceInfoResult::OpenclCVersion(text) = info(DeviceInfo::OpenclCVersion)? {
+            props.c_version = ClVersion::parse(&text)?;
+        }
+        Ok(props)
+    }
+
+    fn create_context(&self, devices: &[Device]) -> Result<Context> {
+        let context = Context::builder().devices(devices).build().map_err(|err| Call::CreateContext.error(err))?;
+        log::debug!("context {:?} for {} device(s)", context.as_ptr(), devices.len());
+        Ok(context)
+    }
+
+    fn create_program(&self, context: &Context, sources: &[&str]) -> Result<core::Program> {
+        let sources = sources
+            .iter()
+            .map(|s| CString::new(*s))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|err| Call::CreateProgramWithSource.error(Error::ocl(err.to_string())))?;
+        core::create_program_with_source(context, &sources)
+            .map_err(|err| Call::CreateProgramWithSource.error(err))
+    }
+
+    fn build_program(&self, program: &core::Program, device: Device, options: &str) -> Result<()> {
+        let options =
+            CString::new(options).map_err(|_| Call::BuildProgram.error(Error::cl(Status::CL_INVALID_BUILD_OPTIONS)))?;
+        core::build_program(program, Some(&[device]), &options, None, None)
+            .map_err(|err| Call::BuildProgram.error(err))
+    }
+
+    fn build_log(&self, program: &core::Program, device: Device) -> Result<String> {
+        match core::get_program_build_info(program, device, ProgramBuildInfo::BuildLog) {
+            Ok(ProgramBuildInfoResult::BuildLog(log)) => Ok(log),
+            Ok(_) => Ok(String::new()),
+            Err(err) => Err(Call::GetProgramBuildInfo.error(err)),
+        }
+    }
+
+    fn kernel_names(&self, program: &core::Program) -> Result<Vec<String>> {
+        match core::get_program_info(program, ProgramInfo::KernelNames) {
+            Ok(ProgramInfoResult::KernelNames(names)) => {
+                Ok(names.split(';').filter(|n| !n.is_empty()).map(str::to_string).collect())
+            }
+            Ok(_) => Ok(Vec::new()),
+            Err(err) => Err(Call::GetProgramInfo.error(err)),
+        }
+    }
+
+    fn create_buffer(&self, context: &Context, flags: MemFlags, size: usize) -> Result<core::Mem> {
+        // SAFETY: no host pointer is passed, so the driver allocates the
+        // storage itself.
+        let buffer = unsafe { core::create_buffer::<_, u8>(context, flags, size, None) }
+            .map_err(|err| Call::CreateBuffer.error(err))?;
+        log::debug!("buffer {:?}: {} bytes, {:?}", buffer.as_ptr(), size, flags);
+        Ok(buffer)
+    }
+
+    fn create_sub_buffer(&self, buffer: &core::Mem, flags: MemFlags, origin: usize, size: usize) -> Result<core::Mem> {
+        let sub_buffer = core::create_sub_buffer(buffer, flags, &BufferRegion::<u8>::new(origin, size))
+            .map_err(|err| Call::CreateSubBuffer.error(err))?;
+        log::debug!("sub-buffer {:?}: bytes {}..{} of {:?}", sub_buffer.as_ptr(), origin, origin + size, buffer.as_ptr());
+        Ok(sub_buffer)
+    }
+}
+
+/// One call made to a `MockBackend` and the status it returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    pub call: Call,
+    pub status: Status,
+}
+
+#[derive(Debug, Clone)]
+struct Fault {
+    call: Call,
+    /// Fail only the n-th call (counting from 0), or every call.
+    nth: Option<usize>,
+    status: Status,
+}
+
+#[derive(Debug, Clone)]
+struct MockProgram {
+    devices: Vec<usize>,
+    source: String,
+    /// Per device in `devices`: the build log, once a build has run.
+    logs: Vec<Option<String>>,
+    kernels: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    records: Vec<Record>,
+    programs: Vec<MockProgram>,
+}
+
+/// A context of the mock backend: the indices of its devices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockContext {
+    pub devices: Vec<usize>,
+}
+
+/// A buffer of the mock backend. No memory is allocated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockBuffer {
+    pub flags: MemFlags,
+    pub origin: usize,
+    pub size: usize,
+    pub is_sub_buffer: bool,
+    /// Smallest base address alignment among the context's devices, in
+    /// bytes.
+    align_bytes: usize,
+}
+
+/// A backend whose devices and failures are scripted.
+///
+/// It has one platform holding the devices added with `device`. Programs
+/// are checked with the interpreter's parser, so a source it rejects fails
+/// to build with `CL_BUILD_PROGRAM_FAILURE` and the parse error as its log.
+/// Sub-buffers are validated against the devices' base address alignment.
+#[derive(Debug, Default)]
+pub struct MockBackend {
+    devices: Vec<DeviceProps>,
+    faults: Vec<Fault>,
+    build_log: Option<String>,
+    state: RefCell<MockState>,
+}
+
+impl MockBackend {
+    pub fn new() -> MockBackend {
+        MockBackend::default()
+    }
+
+    pub fn device(mut self, props: DeviceProps) -> Self {
+        self.devices.push(props);
+        self
+    }
+
+    /// Makes every `call` fail with `status`.
+    pub fn fail(mut self, call: Call, status: Status) -> Self {
+        self.faults.push(Fault { call, nth: None, status });
+        self
+    }
+
+    /// Makes only the `nth` `call` (counting from 0) fail with `status`.
+    pub fn fail_nth(mut self, call: Call, nth: usize, status: Status) -> Self {
+        self.faults.push(Fault { call, nth: Some(nth), status });
+        self
+    }
+
+    /// Replaces the build log of every build, e.g. to pair with an injected
+    /// `CL_BUILD_PROGRAM_FAILURE`.
+    pub fn build_log(mut self, log: &str) -> Self {
+        self.build_log = Some(log.to_string());
+        self
+    }
+
+    /// Every call made so far, in order.
+    pub fn records(&self) -> Vec<Record> {
+        self.state.borrow().records.clone()
+    }
+
+    /// The calls made so far, in order.
+    pub fn calls(&self) -> Vec<Call> {
+        self.state.borrow().records.iter().map(|r| r.call).collect()
+    }
+
+    pub fn call_count(&self, call: Call) -> usize {
+        self.state.borrow().records.iter().filter(|r| r.call == call).count()
+    }
+
+    pub fn clear_records(&self) {
+        self.state.borrow_mut().records.clear();
+    }
+
+    /// Runs `body` unless a fault is scripted for this call, and records the
+    /// outcome.
+    fn call<T>(&self, call: Call, body: impl FnOnce(&mut MockState) -> std::result::Result<T, Status>) -> Result<T> {
+        let mut state = self.state.borrow_mut();
+        let nth = state.records.iter().filter(|r| r.call == call).count();
+        let fault = self.faults.iter().find(|f| f.call == call && f.nth.is_none_or(|n| n == nth));
+        let result = match fault {
+            Some(fault) => Err(call.error(Error::cl(fault.status))),
+            None => body(&mut state).map_err(|status| call.error(Error::cl(status))),
+        };
+        let status = result.as_ref().err().and_then(Error::status).unwrap_or(Status::CL_SUCCESS);
+        log::trace!("mock {} -> {:?}", call.name(), status);
+        state.records.push(Record { call, status });
+        result
+    }
+
+    fn props(&self, device: usize) -> std::result::Result<&DeviceProps, Status> {
+        self.devices.get(device).ok_or(Status::CL_INVALID_DEVICE)
+    }
+}
+
+impl Backend for MockBackend {
+    type Platform = usize;
+    type Device = usize;
+    type Context = MockContext;
+    type Program = usize;
+    type Buffer = MockBuffer;
+
+    fn platforms(&self) -> Result<Vec<usize>> {
+        self.call(Call::GetPlatformIds, |_| Ok(vec![0]))
+    }
+
+    fn devices(&self, platform: usize, device_type: DeviceType) -> Result<Vec<usize>> {
+        self.call(Call::GetDeviceIds, |_| {
+            if platform != 0 {
+                return Err(Status::CL_INVALID_PLATFORM);
+            }
+            let found: Vec<usize> = (0..self.devices.len())
+                .filter(|&i| device_type == DeviceType::ALL || self.devices[i].device_type.intersects(device_type))
+                .collect();
+            if found.is_empty() {
+                return Err(Status::CL_DEVICE_NOT_FOUND);
+            }
+            Ok(found)
+        })
+    }
+
+    fn device_props(&self, device: usize) -> Result<DeviceProps> {
+        self.call(Call::GetDeviceInfo, |_| self.props(device).cloned())
+    }
+
+    fn create_context(&self, devices: &[usize]) -> Result<MockContext> {
+        self.call(Call::CreateContext, |_| {
+            if devices.is_empty() {
+                return Err(Status::CL_INVALID_VALUE);
+            }
+            for &device in devices {
+                self.props(device)?;
+            }
+            Ok(MockContext { devices: devices.to_vec() })
+        })
+    }
+
+    fn create_program(&self, context: &MockContext, sources: &[&str]) -> Result<usize> {
+        self.call(Call::CreateProgramWithSource, |state| {
+            if sources.is_empty() {
+                return Err(Status::CL_INVALID_VALUE);
+            }
+            state.programs.push(MockProgram {
+                devices: context.devices.clone(),
+                source: sources.concat(),
+                logs: vec![None; context.devices.len()],
+                kernels: None,
+            });
+            Ok(state.programs.len() - 1)
+        })
+    }
+
+    fn build_program(&self, program: &usize, device: usize, options: &str) -> Result<()> {
+        let log = self.build_log.clone();
+        self.call(Call::BuildProgram, |state| {
+            let program = state.programs.get_mut(*program).ok_or(Status::CL_INVALID_PROGRAM)?;
+            let slot = program.devices.iter().position(|&d| d == device).ok_or(Status::CL_INVALID_DEVICE)?;
+            match interpreter::Program::build_with_options(&program.source, options) {
+                Ok(built) => {
+                    program.kernels = Some(built.kernel_names().into_iter().map(str::to_string).collect());
+                    program.logs[slot] = Some(log.unwrap_or_default());
+                    Ok(())
+                }
+                Err(err) => {
+                    program.logs[slot] = Some(log.unwrap_or_else(|| err.to_string()));
+                    Err(Status::CL_BUILD_PROGRAM_FAILURE)
+                }
+            }
+        })
+    }
+
+    fn build_log(&self, program: &usize, device: usize) -> Result<String> {
+        self.call(Call::GetProgramBuildInfo, |state| {
+            let program = state.programs.get(*program).ok_or(Status::CL_INVALID_PROGRAM)?;
+            let slot = program.devices.iter().position(|&d| d == device).ok_or(Status::CL_INVALID_DEVICE)?;
+            // A build stopped by an injected failure still has the scripted log.
+            Ok(program.logs[slot].clone().or_else(|| self.build_log.clone()).unwrap_or_default())
+        })
+    }
+
+    fn kernel_names(&self, program: &usize) -> Result<Vec<String>> {
+        self.call(Call::GetProgramInfo, |state| {
+            let program = state.programs.get(*program).ok_or(Status::CL_INVALID_PROGRAM)?;
+            program.kernels.clone().ok_or(Status::CL_INVALID_PROGRAM_EXECUTABLE)
+        })
+    }
+
+    fn create_buffer(&self, context: &MockContext, flags: MemFlags, size: usize) -> Result<MockBuffer> {
+        self.call(Call::CreateBuffer, |_| {
+            if size == 0 {
+                return Err(Status::CL_INVALID_BUFFER_SIZE);
+            }
+            let mut align_bits = u32::MAX;
+            for &device in &context.devices {
+                let props = self.props(device)?;
+                if size as u64 > props.global_mem_size {
+                    return Err(Status::CL_INVALID_BUFFER_SIZE);
+                }
+                align_bits = align_bits.min(props.mem_base_addr_align);
+            }
+            Ok(MockBuffer { flags, origin: 0, size, is_sub_buffer: false, align_bytes: (align_bits / 8).max(1) as usize })
+        })
+    }
+
+    fn create_sub_buffer(&self, buffer: &MockBuffer, flags: MemFlags, origin: usize, size: usize) -> Result<MockBuffer> {
+        self.call(Call::CreateSubBuffer, |_| {
+            if buffer.is_sub_buffer {
+                return Err(Status::CL_INVALID_MEM_OBJECT);
+            }
+            if size == 0 {
+                return Err(Status::CL_INVALID_BUFFER_SIZE);
+            }
+            if origin.checked_add(size).is_none_or(|end| end > buffer.size) {
+                return Err(Status::CL_INVALID_VALUE);
+            }
+            if !origin.is_multiple_of(buffer.align_bytes) {
+                return Err(Status::CL_MISALIGNED_SUB_BUFFER_OFFSET);
+            }
+            Ok(MockBuffer { flags, origin, size, is_sub_buffer: true, ..buffer.clone() })
+        })
+    }
+}
+
+/// Picks the first GPU of the first platform, falling back to its first
+/// CPU.
+pub fn default_device<B: Backend>(backend: &B) -> Result<(B::Platform, B::Device)> {
+    let platform = backend
+        .platforms()?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Call::GetPlatformIds.error(Error::cl(Status::CL_PLATFORM_NOT_FOUND_KHR)))?;
+    let devices = match backend.devices(platform, DeviceType::GPU) {
+        Err(err) if err.status() == Some(Status::CL_DEVICE_NOT_FOUND) => {
+            log::info!("no GPU on platform {:?}; falling back to a CPU", platform);
+            backend.devices(platform, DeviceType::CPU)?
+        }
+        result => result?,
+    };
+    let device =
+        devices.into_iter().next().ok_or_else(|| Call::GetDeviceIds.error(Error::cl(Status::CL_DEVICE_NOT_FOUND)))?;
+    match backend.device_props(device) {
+        Ok(props) => log::info!("selected {} ({}, {:?}) on platform {:?}", props.name, props.vendor, props.device_type, platform),
+        Err(_) => log::info!("selected device {:?} on platform {:?}", device, platform),
+    }
+    Ok((platform, device))
+}
+
+/// Creates and builds a program for `device`, attaching the build log if
+/// the build fails.
+pub fn build_program<B: Backend>(
+    backend: &B,
+    context: &B::Context,
+    device: B::Device,
+    sources: &[&str],
+    options: &str,
+) -> Result<B::Program> {
+    let bytes: usize = sources.iter().map(|s| s.len()).sum();
+    let program = backend.create_program(context, sources)?;
+    let span = span!(Level::Debug, "build {} bytes for {:?} with `{}`", bytes, device, options);
+    let result = match backend.build_program(&program, device, options) {
+        Ok(()) => Ok(program),
+        Err(err) => match backend.build_log(&program, device) {
+            Ok(log) => Err(err.log(&log)),
+            Err(_) => Err(err),
+        },
+    };
+    span.finish(result)
+}
+
+/// The index of kernel `name` within a built program.
+pub fn find_kernel<B: Backend>(backend: &B, program: &B::Program, name: &str) -> Result<Option<usize>> {
+    Ok(backend.kernel_names(program)?.iter().position(|k| k == name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpu() -> DeviceProps {
+        DeviceProps { name: "Mock CPU".into(), device_type: DeviceType::CPU, ..DeviceProps::default() }
+    }
+
+    #[test]
+    fn default_device_falls_back_to_a_cpu() {
+        let mock = MockBackend::new().device(cpu());
+        assert_eq!(default_device(&mock).unwrap(), (0, 0));
+        assert_eq!(
+            mock.records(),
+            [
+                Record { call: Call::GetPlatformIds, status: Status::CL_SUCCESS },
+                Record { call: Call::GetDeviceIds, status: Status::CL_DEVICE_NOT_FOUND },
+                Record { call: Call::GetDeviceIds, status: Status::CL_SUCCESS },
+                Record { call: Call::GetDeviceInfo, status: Status::CL_SUCCESS },
+            ]
+        );
+
+        let mock = MockBackend::new().device(cpu()).device(DeviceProps::default());
+        assert_eq!(default_device(&mock).unwrap(), (0, 1));
+        assert_eq!(mock.call_count(Call::GetDeviceIds), 1);
+
+        let err = default_device(&MockBackend::new()).unwrap_err();
+        assert_eq!(err.status(), Some(Status::CL_DEVICE_NOT_FOUND));
+    }
+
+    #[test]
+    fn failed_builds_carry_the_build_log() {
+        let mock = MockBackend::new()
+            .device(DeviceProps::default())
+            .fail(Call::BuildProgram, Status::CL_BUILD_PROGRAM_FAILURE)
+            .build_log("error: expected ';'");
+        let context = mock.create_context(&[0]).unwrap();
+        let err = build_program(&mock, &context, 0, &["__kernel void k() {}"], "").unwrap_err();
+        assert_eq!(err.status(), Some(Status::CL_BUILD_PROGRAM_FAILURE));
+        let context = err.context().unwrap();
+        assert_eq!(context.call.as_deref(), Some("clBuildProgram"));
+        assert_eq!(context.log.as_deref(), Some("error: expected ';'"));
+
+        // Without a scripted log, a source the parser rejects logs the parse
+        // error.
+        let mock = MockBackend::new().device(DeviceProps::default());
+        let context = mock.create_context(&[0]).unwrap();
+        let err = build_program(&mock, &context, 0, &["__kernel void k( {"], "").unwrap_err();
+        assert!(err.context().unwrap().log.as_deref().is_some_and(|log| log.contains("line 1: ")));
+
+        let program = build_program(&mock, &context, 0, &["__kernel void a() {} __kernel void b() {}"], "").unwrap();
+        assert_eq!(find_kernel(&mock, &program, "b").unwrap(), Some(1));
+    }
+
+    #[test]
+    fn fail_nth_fails_only_that_call() {
+        let mock = MockBackend::new().device(DeviceProps::default()).fail_nth(
+            Call::CreateSubBuffer,
+            1,
+            Status::CL_MISALIGNED_SUB_BUFFER_OFFSET,
+        );
+        let context = mock.create_context(&[0]).unwrap();
+        let buffer = mock.create_buffer(&context, MemFlags::READ_WRITE, 4096).unwrap();
+        assert!(mock.create_sub_buffer(&buffer, MemFlags::READ_WRITE, 0, 1024).is_ok());
+        let err = mock.create_sub_buffer(&buffer, MemFlags::READ_WRITE, 1024, 1024).unwrap_err();
+        assert_eq!(err.status(), Some(Status::CL_MISALIGNED_SUB_BUFFER_OFFSET));
+        assert_eq!(err.context().unwrap().call.as_deref(), Some("clCreateSubBuffer"));
+        let sub_buffer = mock.create_sub_buffer(&buffer, MemFlags::READ_WRITE, 2048, 1024).unwrap();
+        assert_eq!((sub_buffer.origin, sub_buffer.size, sub_buffer.is_sub_buffer), (2048, 1024, true));
+
+        // The default device aligns to 1024 bits, i.e. 128 bytes.
+        let err = mock.create_sub_buffer(&buffer, MemFlags::READ_WRITE, 64, 64).unwrap_err();
+        assert_eq!(err.status(), Some(Status::CL_MISALIGNED_SUB_BUFFER_OFFSET));
+    }
+
+    #[test]
+    fn records_every_call_in_order() {
+        let mock = MockBackend::new().device(DeviceProps::default());
+        let (_, device) = default_device(&mock).unwrap();
+        mock.clear_records();
+        let context = mock.create_context(&[device]).unwrap();
+        let program = build_program(&mock, &context, device, &["__kernel void k() {}"], "-D N=4").unwrap();
+        find_kernel(&mock, &program, "k").unwrap();
+        mock.create_buffer(&context, MemFlags::READ_ONLY, 0).unwrap_err();
+        assert_eq!(
+            mock.calls(),
+            [Call::CreateContext, Call::CreateProgramWithSource, Call::BuildProgram, Call::GetProgramInfo, Call::CreateBuffer]
+        );
+        assert_eq!(mock.records().last().unwrap().status, Status::CL_INVALID_BUFFER_SIZE);
+        assert_eq!(mock.call_count(Call::GetProgramBuildInfo), 0);
+    }
+}
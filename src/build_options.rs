@@ -0,0 +1,369 @@
+//! Compiler options for program builds.
+//!
+//! `BuildOptions` assembles the option string from typed setters instead of
+//! hand-written text. Several options only exist from a given OpenCL C
+//! version on (`-cl-kernel-arg-info` from 1.2, `-cl-uniform-work-group-size`
+//! from 2.0, and `-cl-std` itself from 1.1), so `check` compares them with
+//! the version a device reports before anything reaches its compiler, which
+//! otherwise fails with an unhelpful `CL_INVALID_BUILD_OPTIONS`.
+
+use crate::Result;
+use crate::logging::span;
+use log::Level;
+use ocl::enums::{DeviceInfo, DeviceInfoResult};
+use ocl::{Context, Device, Program};
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum BuildOptionsError {
+    /// An option the device's OpenCL C version does not have.
+    Unsupported { option: String, required: ClVersion, device: ClVersion },
+    /// A `-D` name that is not a C identifier.
+    Define(String),
+    /// Text that would be split into several options.
+    Whitespace(String),
+    /// A version string that is not `OpenCL C <major>.<minor>`.
+    Version(String),
+    /// A compiler option given to the linker.
+    NotForLinking(String),
+}
+
+impl fmt::Display for BuildOptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildOptionsError::Unsupported { option, required, device } => {
+                write!(f, "{} needs OpenCL C {}, the device has {}", option, required, device)
+            }
+            BuildOptionsError::Define(name) => write!(f, "-D {}: not a valid macro name", name),
+            BuildOptionsError::Whitespace(text) => write!(f, "`{}` contains whitespace", text),
+            BuildOptionsError::Version(text) => write!(f, "unrecognized OpenCL C version `{}`", text),
+            BuildOptionsError::NotForLinking(option) => write!(f, "{} is a compiler option, not a linker option", option),
+        }
+    }
+}
+
+impl std::error::Error for BuildOptionsError {}
+
+/// An OpenCL C language version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ClVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl ClVersion {
+    pub const V1_0: ClVersion = ClVersion::new(1, 0);
+    pub const V1_1: ClVersion = ClVersion::new(1, 1);
+    pub const V1_2: ClVersion = ClVersion::new(1, 2);
+    pub const V2_0: ClVersion = ClVersion::new(2, 0);
+    pub const V3_0: ClVersion = ClVersion::new(3, 0);
+
+    pub const fn new(major: u8, minor: u8) -> ClVersion {
+        ClVersion { major, minor }
+    }
+
+    /// Parses `CL_DEVICE_OPENCL_C_VERSION`, e.g. `OpenCL C 1.2 pocl`.
+    pub fn parse(text: &str) -> Result<ClVersion> {
+        let bad = || BuildOptionsError::Version(text.to_string());
+        let number = text.trim().strip_prefix("OpenCL C ").ok_or_else(bad)?;
+        let number = number.split_whitespace().next().ok_or_else(bad)?;
+        let (major, minor) = number.split_once('.').ok_or_else(bad)?;
+        Ok(ClVersion::new(major.parse().map_err(|_| bad())?, minor.parse().map_err(|_| bad())?))
+    }
+
+    /// The OpenCL C version `device` compiles.
+    pub fn of_device(device: Device) -> Result<ClVersion> {
+        match device.info(DeviceInfo::OpenclCVersion)? {
+            DeviceInfoResult::OpenclCVersion(text) => ClVersion::parse(&text),
+            other => Err(BuildOptionsError::Version(other.to_string()).into()),
+        }
+    }
+}
+
+impl fmt::Display for ClVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Compiler options; `Display` gives the option string.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildOptions {
+    std: Option<ClVersion>,
+    flags: Vec<&'static str>,
+    defines: Vec<(String, Option<String>)>,
+    includes: Vec<PathBuf>,
+}
+
+/// The version each flag first appears in.
+const FLAG_VERSIONS: &[(&str, ClVersion)] = &[
+    ("-cl-fp32-correctly-rounded-divide-sqrt", ClVersion::V1_2),
+    ("-cl-kernel-arg-info", ClVersion::V1_2),
+    ("-cl-uniform-work-group-size", ClVersion::V2_0),
+];
+
+/// The flags `clLinkProgram` accepts.
+const LINK_FLAGS: &[&str] = &[
+    "-cl-denorms-are-zero",
+    "-cl-no-signed-zeros",
+    "-cl-unsafe-math-optimizations",
+    "-cl-finite-math-only",
+    "-cl-fast-relaxed-math",
+];
+
+impl BuildOptions {
+    pub fn new() -> BuildOptions {
+        BuildOptions::default()
+    }
+
+    fn flag(mut self, flag: &'static str) -> Self {
+        if !self.flags.contains(&flag) {
+            self.flags.push(flag);
+        }
+        self
+    }
+
+    /// `-cl-std=CL<version>`: compile as that OpenCL C version.
+    pub fn std(mut self, version: ClVersion) -> Self {
+        self.std = Some(version);
+        self
+    }
+
+    pub fn single_precision_constant(self) -> Self {
+        self.flag("-cl-single-precision-constant")
+    }
+
+    pub fn denorms_are_zero(self) -> Self {
+        self.flag("-cl-denorms-are-zero")
+    }
+
+    pub fn fp32_correctly_rounded_divide_sqrt(self) -> Self {
+        self.flag("-cl-fp32-correctly-rounded-divide-sqrt")
+    }
+
+    pub fn opt_disable(self) -> Self {
+        self.flag("-cl-opt-disable")
+    }
+
+    /// `-cl-mad-enable`: allow `a * b + c` to be fused with reduced accuracy.
+    pub fn mad_enable(self) -> Self {
+        self.flag("-cl-mad-enable")
+    }
+
+    pub fn no_signed_zeros(self) -> Self {
+        self.flag("-cl-no-signed-zeros")
+    }
+
+    pub fn unsafe_math_optimizations(self) -> Self {
+        self.flag("-cl-unsafe-math-optimizations")
+    }
+
+    pub fn finite_math_only(self) -> Self {
+        self.flag("-cl-finite-math-only")
+    }
+
+    /// `-cl-fast-relaxed-math`: `-cl-finite-math-only` and
+    /// `-cl-unsafe-math-optimizations` together.
+    pub fn fast_relaxed_math(self) -> Self {
+        self.flag("-cl-fast-relaxed-math")
+    }
+
+    pub fn uniform_work_group_size(self) -> Self {
+        self.flag("-cl-uniform-work-group-size")
+    }
+
+    /// `-cl-kernel-arg-info`, which `program_info` needs for argument names.
+    pub fn kernel_arg_info(self) -> Self {
+        self.flag("-cl-kernel-arg-info")
+    }
+
+    /// `-Werror`: turn warnings into errors.
+    pub fn werror(self) -> Self {
+        self.flag("-Werror")
+    }
+
+    /// `-w`: silence warnings.
+    pub fn no_warnings(self) -> Self {
+        self.flag("-w")
+    }
+
+    /// `-D name`.
+    pub fn define(mut self, name: &str) -> Self {
+        self.defines.push((name.to_string(), None));
+        self
+    }
+
+    /// `-D name=value`.
+    pub fn define_value(mut self, name: &str, value: impl fmt::Display) -> Self {
+        self.defines.push((name.to_string(), Some(value.to_string())));
+        self
+    }
+
+    /// `-I dir`.
+    pub fn include(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.includes.push(dir.into());
+        self
+    }
+
+    /// Checks the options against a device compiling OpenCL C `device`.
+    pub fn check(&self, device: ClVersion) -> Result<()> {
+        let require = |option: String, required: ClVersion| {
+            if device < required {
+                return Err(BuildOptionsError::Unsupported { option, required, device });
+            }
+            Ok(())
+        };
+        if let Some(std) = self.std {
+            // `-cl-std` itself is new in 1.1.
+            require(format!("-cl-std=CL{}", std), std.max(ClVersion::V1_1))?;
+        }
+        for flag in &self.flags {
+            if let Some(&(_, required)) = FLAG_VERSIONS.iter().find(|(f, _)| f == flag) {
+                require(flag.to_string(), required)?;
+            }
+        }
+        for (name, value) in &self.defines {
+            let mut chars = name.chars();
+            let identifier = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !identifier {
+                return Err(BuildOptionsError::Define(name.clone()).into());
+            }
+            if let Some(value) = value.as_ref().filter(|v| v.is_empty() || v.contains(char::is_whitespace)) {
+                return Err(BuildOptionsError::Whitespace(format!("-D {}={}", name, value)).into());
+            }
+        }
+        for dir in &self.includes {
+            let dir = dir.display().to_string();
+            if dir.is_empty() || dir.contains(char::is_whitespace) {
+                return Err(BuildOptionsError::Whitespace(format!("-I {}", dir)).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the options against `device`'s reported OpenCL C version.
+    pub fn check_device(&self, device: Device) -> Result<()> {
+        self.check(ClVersion::of_device(device)?)
+    }
+
+    /// The option string for `clLinkProgram`, which takes only a few of the
+    /// math flags.
+    pub fn link_options(&self) -> Result<String> {
+        if let Some(std) = self.std {
+            return Err(BuildOptionsError::NotForLinking(format!("-cl-std=CL{}", std)).into());
+        }
+        if let Some(flag) = self.flags.iter().find(|flag| !LINK_FLAGS.contains(flag)) {
+            return Err(BuildOptionsError::NotForLinking(flag.to_string()).into());
+        }
+        if let Some((name, _)) = self.defines.first() {
+            return Err(BuildOptionsError::NotForLinking(format!("-D {}", name)).into());
+        }
+        if let Some(dir) = self.includes.first() {
+            return Err(BuildOptionsError::NotForLinking(format!("-I {}", dir.display())).into());
+        }
+        Ok(self.to_string())
+    }
+
+    /// Checks the options and builds `src` for `device` with them.
+    pub fn build(&self, context: &Context, device: Device, src: &str) -> Result<Program> {
+        self.check_device(device)?;
+        let span = span!(Level::Debug, "build {} bytes for {:?} with `{}`", src.len(), device, self);
+        Ok(span.finish(Program::builder().src(src).devices(device).cmplr_opt(self.to_string()).build(context))?)
+    }
+}
+
+impl fmt::Display for BuildOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(std) = self.std {
+            parts.push(format!("-cl-std=CL{}", std));
+        }
+        parts.extend(self.flags.iter().map(|flag| flag.to_string()));
+        parts.extend(self.defines.iter().map(|(name, value)| match value {
+            Some(value) => format!("-D {}={}", name, value),
+            None => format!("-D {}", name),
+        }));
+        parts.extend(self.includes.iter().map(|dir| format!("-I {}", dir.display())));
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    fn options_error(result: Result<impl fmt::Debug>) -> BuildOptionsError {
+        match result.unwrap_err() {
+            Error::BuildOptions(err) => err,
+            err => panic!("expected a build options error, got {:?}", err),
+        }
+    }
+
+    fn unsupported(options: &BuildOptions, device: ClVersion) -> (String, ClVersion) {
+        match options_error(options.check(device)) {
+            BuildOptionsError::Unsupported { option, required, device: found } => {
+                assert_eq!(found, device);
+                (option, required)
+            }
+            err => panic!("expected an unsupported option, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn options_newer_than_the_device_are_rejected() {
+        let arg_info = BuildOptions::new().kernel_arg_info();
+        arg_info.check(ClVersion::V1_2).unwrap();
+        assert_eq!(unsupported(&arg_info, ClVersion::V1_1), ("-cl-kernel-arg-info".into(), ClVersion::V1_2));
+
+        let uniform = BuildOptions::new().mad_enable().uniform_work_group_size();
+        uniform.check(ClVersion::V3_0).unwrap();
+        assert_eq!(unsupported(&uniform, ClVersion::V1_2), ("-cl-uniform-work-group-size".into(), ClVersion::V2_0));
+
+        let std = BuildOptions::new().std(ClVersion::V2_0);
+        std.check(ClVersion::V2_0).unwrap();
+        assert_eq!(unsupported(&std, ClVersion::V1_2), ("-cl-std=CL2.0".into(), ClVersion::V2_0));
+        // `-cl-std` itself needs 1.1, even to ask for 1.0.
+        let std = BuildOptions::new().std(ClVersion::V1_0);
+        assert_eq!(unsupported(&std, ClVersion::V1_0), ("-cl-std=CL1.0".into(), ClVersion::V1_1));
+
+        // Flags with no version requirement pass everywhere.
+        BuildOptions::new().mad_enable().fast_relaxed_math().werror().check(ClVersion::V1_0).unwrap();
+    }
+
+    #[test]
+    fn device_versions_are_parsed() {
+        assert_eq!(ClVersion::parse("OpenCL C 1.2 pocl").unwrap(), ClVersion::V1_2);
+        assert_eq!(ClVersion::parse("OpenCL C 3.0 ").unwrap(), ClVersion::V3_0);
+        for bad in ["OpenCL 1.2", "OpenCL C", "OpenCL C 12", "OpenCL C x.y"] {
+            assert!(matches!(options_error(ClVersion::parse(bad)), BuildOptionsError::Version(_)), "{}", bad);
+        }
+        assert!(ClVersion::V1_2 < ClVersion::V2_0);
+    }
+
+    #[test]
+    fn options_render_in_order_and_are_checked() {
+        let options = BuildOptions::new()
+            .std(ClVersion::V1_2)
+            .mad_enable()
+            .mad_enable()
+            .define("DEBUG")
+            .define_value("N", 16)
+            .include("kernels/include");
+        assert_eq!(options.to_string(), "-cl-std=CL1.2 -cl-mad-enable -D DEBUG -D N=16 -I kernels/include");
+        options.check(ClVersion::V1_2).unwrap();
+
+        let check = |options: BuildOptions| options_error(options.check(ClVersion::V3_0));
+        assert!(matches!(check(BuildOptions::new().define("2FAST")), BuildOptionsError::Define(_)));
+        assert!(matches!(check(BuildOptions::new().define_value("N", "1 2")), BuildOptionsError::Whitespace(_)));
+        assert!(matches!(check(BuildOptions::new().include("my dir")), BuildOptionsError::Whitespace(_)));
+
+        assert_eq!(BuildOptions::new().fast_relaxed_math().link_options().unwrap(), "-cl-fast-relaxed-math");
+        assert!(matches!(
+            options_error(BuildOptions::new().mad_enable().link_options()),
+            BuildOptionsError::NotForLinking(option) if option == "-cl-mad-enable"
+        ));
+    }
+}
@@ -0,0 +1,303 @@
+//! The `simple_gpu` command line: the example workloads with their sizes,
+//! files, device and output format given as flags.
+
+mod args;
+mod bench;
+mod info;
+mod reduce;
+mod resize;
+mod run;
+mod search;
+mod sort;
+mod target;
+
+use args::Args;
+use simple_gpu::datagen::{self, DataGen, Distribution};
+use simple_gpu::program_info::json_str;
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+const USAGE: &str = "\
+usage: simple_gpu <command> [options]
+
+commands:
+  info      list platforms and devices
+  sort      bitonic sort of floats
+  reduce    sum of floats
+  search    count words in a text file
+  resize    nearest-neighbour upscaling of an image
+  bench     time a kernel over several sizes
+  run       build and launch a kernel from a .cl file
+
+device options (all commands but info):
+  --platform N        platform index (default 0)
+  --device N          device index within the platform (default 0)
+  --device-type T     gpu, cpu, accelerator or all (default: gpu, else cpu)
+  --interp            run on the built-in interpreter instead of a device
+
+output options:
+  --format F          text or json; bench also accepts csv
+
+environment:
+  SIMPLE_GPU_LOG      log level (error, warn, info, debug, trace) and/or
+                      target=level overrides, e.g. info,simple_gpu::backend=trace
+  SIMPLE_GPU_LOG_FILE append log records to this file instead of stderr
+  SIMPLE_GPU_SEED     seed for random input when --seed is not given
+  SIMPLE_GPU_OPENCL   OpenCL library to load instead of the system's
+
+Run `simple_gpu <command> --help` for the command's own options.";
+
+#[derive(Debug)]
+pub enum CliError {
+    /// Bad command line; the process exits with status 2.
+    Usage(String),
+    Io { path: String, err: std::io::Error },
+    Gpu(simple_gpu::Error),
+    Image(image::ImageError),
+    /// The result differs from the host reference.
+    Check(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Usage(msg) => write!(f, "{}", msg),
+            CliError::Io { path, err } => write!(f, "{}: {}", path, err),
+            CliError::Gpu(err) => write!(f, "{}", err),
+            CliError::Image(err) => write!(f, "{}", err),
+            CliError::Check(msg) => write!(f, "check failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl From<simple_gpu::Error> for CliError {
+    fn from(err: simple_gpu::Error) -> Self {
+        CliError::Gpu(err)
+    }
+}
+
+impl From<ocl::Error> for CliError {
+    fn from(err: ocl::Error) -> Self {
+        CliError::Gpu(err.into())
+    }
+}
+
+impl From<ocl::core::Error> for CliError {
+    fn from(err: ocl::core::Error) -> Self {
+        CliError::Gpu(err.into())
+    }
+}
+
+impl From<image::ImageError> for CliError {
+    fn from(err: image::ImageError) -> Self {
+        CliError::Image(err)
+    }
+}
+
+pub fn run(args: Vec<String>) -> Result<(), CliError> {
+    let mut args = Args::new(args);
+    let Some(command) = args.command() else {
+        println!("{}", USAGE);
+        return Ok(());
+    };
+    if args.flag("help") {
+        println!("{}", command_usage(&command).unwrap_or(USAGE));
+        return Ok(());
+    }
+    match command.as_str() {
+        "info" => info::run(args),
+        "sort" => sort::run(args),
+        "reduce" => reduce::run(args),
+        "search" => search::run(args),
+        "resize" => resize::run(args),
+        "bench" => bench::run(args),
+        "run" => run::run(args),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        other => Err(CliError::Usage(format!("unknown command `{}`\n\n{}", other, USAGE))),
+    }
+}
+
+fn command_usage(command: &str) -> Option<&'static str> {
+    match command {
+        "info" => Some(info::USAGE),
+        "sort" => Some(sort::USAGE),
+        "reduce" => Some(reduce::USAGE),
+        "search" => Some(search::USAGE),
+        "resize" => Some(resize::USAGE),
+        "bench" => Some(bench::USAGE),
+        "run" => Some(run::USAGE),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("unknown format `{}` (expected text, json or csv)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Format::Text => "text",
+            Format::Json => "json",
+            Format::Csv => "csv",
+        })
+    }
+}
+
+/// Reads `--format`, rejecting formats the command does not offer.
+fn format_option(args: &mut Args, allowed: &[Format]) -> Result<Format, CliError> {
+    let format = args.value("format", Format::Text)?;
+    if !allowed.contains(&format) {
+        return Err(CliError::Usage(format!("--format {} is not supported by this command", format)));
+    }
+    Ok(format)
+}
+
+/// Named results of one run, printed as `key: value` lines, a JSON object
+/// or a CSV row.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    fields: Vec<(String, String, String)>,
+}
+
+impl Report {
+    pub fn new() -> Report {
+        Report::default()
+    }
+
+    pub fn num(mut self, key: impl Into<String>, value: impl fmt::Display) -> Self {
+        let value = value.to_string();
+        self.fields.push((key.into(), value.clone(), value));
+        self
+    }
+
+    pub fn text(mut self, key: impl Into<String>, value: &str) -> Self {
+        self.fields.push((key.into(), value.to_string(), json_str(value)));
+        self
+    }
+
+    pub fn print(&self, format: Format) {
+        match format {
+            Format::Json => println!("{}", self.to_json()),
+            _ => Report::print_all(std::slice::from_ref(self), format),
+        }
+    }
+
+    /// Prints several reports with the same keys: text blocks, a JSON
+    /// array or one CSV table.
+    pub fn print_all(reports: &[Report], format: Format) {
+        let mut out = String::new();
+        match format {
+            Format::Text => {
+                let width = reports.iter().flat_map(|r| &r.fields).map(|f| f.0.len()).max().unwrap_or(0);
+                for (i, report) in reports.iter().enumerate() {
+                    if i > 0 {
+                        out.push('\n');
+                    }
+                    for (key, text, _) in &report.fields {
+                        let _ = writeln!(out, "{:width$}  {}", format!("{}:", key), text, width = width + 1);
+                    }
+                }
+            }
+            Format::Json => {
+                let objects: Vec<String> = reports.iter().map(Report::to_json).collect();
+                let _ = writeln!(out, "[{}]", objects.join(","));
+            }
+            Format::Csv => {
+                if let Some(first) = reports.first() {
+                    let header: Vec<&str> = first.fields.iter().map(|f| f.0.as_str()).collect();
+                    let _ = writeln!(out, "{}", header.join(","));
+                }
+                for report in reports {
+                    let row: Vec<String> = report.fields.iter().map(|f| csv_field(&f.1)).collect();
+                    let _ = writeln!(out, "{}", row.join(","));
+                }
+            }
+        }
+        print!("{}", out);
+    }
+
+    fn to_json(&self) -> String {
+        let fields: Vec<String> = self.fields.iter().map(|(key, _, json)| format!("{}:{}", json_str(key), json)).collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) { format!("\"{}\"", value.replace('"', "\"\"")) } else { value.to_string() }
+}
+
+/// `skipped`, `passed` or `failed`, for a check that may not have run.
+fn check_status<E>(checked: &Option<Result<(), E>>) -> &'static str {
+    match checked {
+        None => "skipped",
+        Some(Ok(())) => "passed",
+        Some(Err(_)) => "failed",
+    }
+}
+
+/// Turns a failed check into the command's error, after its report has
+/// been printed.
+fn check_result<E: fmt::Display>(checked: Option<Result<(), E>>) -> Result<(), CliError> {
+    match checked {
+        Some(Err(err)) => Err(CliError::Check(err.to_string())),
+        _ => Ok(()),
+    }
+}
+
+/// Reads `--seed`, else `SIMPLE_GPU_SEED`, else picks one. Either way it is
+/// reported so a run can be repeated.
+fn seed_option(args: &mut Args) -> Result<u64, CliError> {
+    Ok(args.opt("seed")?.unwrap_or_else(datagen::seed_from_env))
+}
+
+fn random_floats(len: usize, seed: u64, distribution: Distribution, scale: f32) -> Vec<f32> {
+    DataGen::new(seed).values(distribution, len, scale as f64)
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, CliError> {
+    std::fs::read(path).map_err(|err| CliError::Io { path: path.to_string(), err })
+}
+
+fn write_file(path: &str, data: &[u8]) -> Result<(), CliError> {
+    std::fs::write(path, data).map_err(|err| CliError::Io { path: path.to_string(), err })
+}
+
+/// Parses whitespace- or comma-separated numbers.
+fn read_floats(path: &str) -> Result<Vec<f32>, CliError> {
+    let text = String::from_utf8_lossy(&read_file(path)?).into_owned();
+    text.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().map_err(|_| CliError::Usage(format!("{}: `{}` is not a number", path, s))))
+        .collect()
+}
+
+/// Writes one number per line.
+fn write_floats(path: &str, data: &[f32]) -> Result<(), CliError> {
+    let mut text = String::with_capacity(data.len() * 12);
+    for value in data {
+        let _ = writeln!(text, "{}", value);
+    }
+    write_file(path, text.as_bytes())
+}
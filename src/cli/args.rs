@@ -0,0 +1,116 @@
+//! A small command-line parser. Options are looked up by name in any order
+//! (`--name value`, `--name=value` or a bare `--flag`) and removed as they
+//! are read; anything left over is reported by `finish`, or returned by
+//! `positional` for commands that take operands. Either way every option
+//! must be read first.
+
+use super::CliError;
+use std::fmt;
+use std::str::FromStr;
+
+pub struct Args {
+    items: Vec<String>,
+}
+
+impl Args {
+    pub fn new(items: Vec<String>) -> Args {
+        Args { items }
+    }
+
+    /// Removes the first argument, the subcommand.
+    pub fn command(&mut self) -> Option<String> {
+        (!self.items.is_empty()).then(|| self.items.remove(0))
+    }
+
+    pub fn flag(&mut self, name: &str) -> bool {
+        let flag = format!("--{}", name);
+        match self.items.iter().position(|item| *item == flag) {
+            Some(i) => {
+                self.items.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The raw value of the first `--name`, if given.
+    fn raw(&mut self, name: &str) -> Result<Option<String>, CliError> {
+        let flag = format!("--{}", name);
+        let prefix = format!("--{}=", name);
+        for i in 0..self.items.len() {
+            if let Some(value) = self.items[i].strip_prefix(&prefix) {
+                let value = value.to_string();
+                self.items.remove(i);
+                return Ok(Some(value));
+            }
+            if self.items[i] == flag {
+                if i + 1 == self.items.len() {
+                    return Err(CliError::Usage(format!("{} needs a value", flag)));
+                }
+                self.items.remove(i);
+                return Ok(Some(self.items.remove(i)));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn opt<T: FromStr>(&mut self, name: &str) -> Result<Option<T>, CliError>
+    where
+        T::Err: fmt::Display,
+    {
+        match self.raw(name)? {
+            Some(value) => parse(name, &value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn value<T: FromStr>(&mut self, name: &str, default: T) -> Result<T, CliError>
+    where
+        T::Err: fmt::Display,
+    {
+        Ok(self.opt(name)?.unwrap_or(default))
+    }
+
+    pub fn required<T: FromStr>(&mut self, name: &str) -> Result<T, CliError>
+    where
+        T::Err: fmt::Display,
+    {
+        self.opt(name)?.ok_or_else(|| CliError::Usage(format!("--{} is required", name)))
+    }
+
+    /// A comma-separated list.
+    pub fn list<T: FromStr>(&mut self, name: &str) -> Result<Option<Vec<T>>, CliError>
+    where
+        T::Err: fmt::Display,
+    {
+        match self.raw(name)? {
+            Some(value) => value.split(',').map(|item| parse(name, item.trim())).collect::<Result<_, _>>().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The arguments left once every option has been read; fails on an
+    /// unknown option.
+    pub fn positional(self) -> Result<Vec<String>, CliError> {
+        match self.items.iter().find(|item| item.starts_with("--")) {
+            Some(item) => Err(CliError::Usage(format!("unknown option `{}`", item))),
+            None => Ok(self.items),
+        }
+    }
+
+    /// Fails on anything not read by the command.
+    pub fn finish(self) -> Result<(), CliError> {
+        match self.items.first() {
+            Some(item) if item.starts_with("--") => Err(CliError::Usage(format!("unknown option `{}`", item))),
+            Some(item) => Err(CliError::Usage(format!("unexpected argument `{}`", item))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T, CliError>
+where
+    T::Err: fmt::Display,
+{
+    value.parse().map_err(|err| CliError::Usage(format!("--{} `{}`: {}", name, value, err)))
+}
@@ -0,0 +1,142 @@
+//! `simple_gpu bench`: times a simple kernel over several input sizes.
+
+use super::args::Args;
+use super::target::{Arg, DeviceOptions, Program, Target};
+use super::{CliError, Format, Report, format_option, reduce};
+use ocl::SpatialDims;
+use simple_gpu::interpreter::Buffer;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+usage: simple_gpu bench [options]
+
+  --kernel K        copy, saxpy or reduce (default saxpy)
+  --sizes LIST      comma-separated element counts (default 65536,1048576,
+                    16777216; 256,1024,4096 on the interpreter)
+  --iterations N    timed runs per size (default 10)
+  --warmup N        untimed runs per size (default 1)
+  --format F        text, json or csv
+
+Device options as for every command; see `simple_gpu --help`.";
+
+const KERNEL_SRC: &str = r#"
+__kernel void copy(__global const float *src, __global float *dst) {
+   size_t i = get_global_id(0);
+   dst[i] = src[i];
+}
+
+__kernel void saxpy(__global const float *x, __global float *y, float alpha) {
+   size_t i = get_global_id(0);
+   y[i] = alpha * x[i] + y[i];
+}
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kernel {
+    Copy,
+    Saxpy,
+    Reduce,
+}
+
+impl FromStr for Kernel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "copy" => Ok(Kernel::Copy),
+            "saxpy" => Ok(Kernel::Saxpy),
+            "reduce" => Ok(Kernel::Reduce),
+            _ => Err(format!("unknown kernel `{}` (expected copy, saxpy or reduce)", s)),
+        }
+    }
+}
+
+impl Kernel {
+    fn name(self) -> &'static str {
+        match self {
+            Kernel::Copy => "copy",
+            Kernel::Saxpy => "saxpy",
+            Kernel::Reduce => "reduction_scalar",
+        }
+    }
+
+    /// Global memory traffic of one run, in bytes.
+    fn bytes(self, len: usize) -> usize {
+        match self {
+            Kernel::Copy => 8 * len,
+            Kernel::Saxpy => 12 * len,
+            Kernel::Reduce => 4 * len,
+        }
+    }
+}
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let kernel: Kernel = args.value("kernel", Kernel::Saxpy)?;
+    let sizes: Option<Vec<usize>> = args.list("sizes")?;
+    let iterations: usize = args.value("iterations", 10)?;
+    let warmup: usize = args.value("warmup", 1)?;
+    let format = format_option(&mut args, &[Format::Text, Format::Json, Format::Csv])?;
+    let device = DeviceOptions::parse(&mut args)?;
+    args.finish()?;
+
+    if iterations == 0 {
+        return Err(CliError::Usage("--iterations must be at least 1".into()));
+    }
+    let target = Target::select(&device)?;
+    let sizes = sizes.unwrap_or_else(|| [1 << 16, 1 << 20, 1 << 24].iter().zip([256, 1024, 4096]).map(|(&d, i)| target.default_len(d, i)).collect());
+    if sizes.contains(&0) {
+        return Err(CliError::Usage("--sizes must not contain 0".into()));
+    }
+    let program = target.build(if kernel == Kernel::Reduce { reduce::KERNEL_SRC } else { KERNEL_SRC }, "")?;
+    let local = target.group_size(256);
+
+    let mut reports = Vec::with_capacity(sizes.len());
+    for &len in &sizes {
+        let mut times = Vec::with_capacity(iterations);
+        for i in 0..warmup + iterations {
+            let elapsed = run_once(&target, &program, kernel, len, local)?;
+            if i >= warmup {
+                times.push(elapsed);
+            }
+        }
+        let mean = times.iter().sum::<Duration>() / iterations as u32;
+        let min = times.iter().min().copied().unwrap_or_default();
+        reports.push(
+            Report::new()
+                .text("device", &target.name())
+                .text("kernel", kernel.name())
+                .num("elements", len)
+                .num("iterations", iterations)
+                .num("min_ms", format!("{:.4}", min.as_secs_f64() * 1e3))
+                .num("mean_ms", format!("{:.4}", mean.as_secs_f64() * 1e3))
+                .num("gb_per_s", format!("{:.3}", kernel.bytes(len) as f64 / mean.as_secs_f64().max(1e-12) / 1e9)),
+        );
+    }
+    Report::print_all(&reports, format);
+    Ok(())
+}
+
+fn run_once(target: &Target, program: &Program, kernel: Kernel, len: usize, local: usize) -> Result<Duration, CliError> {
+    let data: Vec<f32> = (0..len).map(|i| i as f32).collect();
+    match kernel {
+        Kernel::Copy | Kernel::Saxpy => {
+            let mut x = Buffer::from_slice(&data);
+            let mut y = Buffer::from_slice(&data);
+            let mut kernel_args = vec![Arg::Buffer(&mut x), Arg::Buffer(&mut y)];
+            if kernel == Kernel::Saxpy {
+                kernel_args.push(Arg::scalar(2.0f32));
+            }
+            target.run(program, kernel.name(), &mut kernel_args, len.into(), SpatialDims::Unspecified)
+        }
+        Kernel::Reduce => {
+            // Whole work-groups only; the tail beyond them is not timed.
+            let local = local.min(len);
+            let global = len / local * local;
+            let mut input = Buffer::from_slice(&data[..global]);
+            let mut sums = Buffer::new::<f32>(global / local);
+            let mut kernel_args = [Arg::Buffer(&mut input), Arg::local::<f32>(local), Arg::Buffer(&mut sums)];
+            target.run(program, kernel.name(), &mut kernel_args, global.into(), SpatialDims::One(local))
+        }
+    }
+}
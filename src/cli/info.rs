@@ -0,0 +1,114 @@
+//! `simple_gpu info`: the platforms and devices the other commands can use.
+
+use super::args::Args;
+use super::{CliError, Format, format_option};
+use ocl::Platform;
+use ocl::flags::DeviceType;
+use simple_gpu::backend::{Backend, DeviceProps, OclBackend};
+use simple_gpu::interpreter;
+use simple_gpu::program_info::json_str;
+
+pub const USAGE: &str = "\
+usage: simple_gpu info [options]
+
+  --format F        text or json";
+
+struct PlatformEntry {
+    name: String,
+    vendor: String,
+    version: String,
+    devices: Vec<DeviceProps>,
+}
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let format = format_option(&mut args, &[Format::Text, Format::Json])?;
+    args.finish()?;
+
+    let mut platforms = Vec::new();
+    if interpreter::driver_available() {
+        for platform in Platform::list_from_core(ocl::core::get_platform_ids()?) {
+            let devices = OclBackend
+                .devices(platform, DeviceType::ALL)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|device| OclBackend.device_props(device))
+                .collect::<Result<Vec<_>, _>>()?;
+            platforms.push(PlatformEntry {
+                name: platform.name()?,
+                vendor: platform.vendor()?,
+                version: platform.version()?,
+                devices,
+            });
+        }
+    }
+
+    match format {
+        Format::Json => println!("{}", to_json(&platforms)),
+        _ => print_text(&platforms),
+    }
+    Ok(())
+}
+
+fn print_text(platforms: &[PlatformEntry]) {
+    if platforms.is_empty() {
+        println!("No OpenCL platforms found; commands run on the built-in interpreter.");
+        return;
+    }
+    for (p, platform) in platforms.iter().enumerate() {
+        println!("platform {}: {} ({}, {})", p, platform.name, platform.vendor, platform.version);
+        for (d, device) in platform.devices.iter().enumerate() {
+            println!("  device {}: {} ({})", d, device.name, device.vendor);
+            println!("    type:                 {}", device_type_name(device.device_type));
+            println!("    compute units:        {}", device.compute_units);
+            println!("    max work-group size:  {}", device.max_work_group_size);
+            println!("    global memory:        {} MiB", device.global_mem_size >> 20);
+            println!("    local memory:         {} KiB", device.local_mem_size >> 10);
+            println!("    base address align:   {} bytes", device.mem_base_addr_align / 8);
+        }
+    }
+}
+
+fn to_json(platforms: &[PlatformEntry]) -> String {
+    let platforms: Vec<String> = platforms
+        .iter()
+        .map(|platform| {
+            let devices: Vec<String> = platform
+                .devices
+                .iter()
+                .map(|d| {
+                    format!(
+                        "{{\"name\":{},\"vendor\":{},\"type\":{},\"compute_units\":{},\"max_work_group_size\":{},\"global_mem_size\":{},\"local_mem_size\":{},\"mem_base_addr_align\":{}}}",
+                        json_str(&d.name),
+                        json_str(&d.vendor),
+                        json_str(device_type_name(d.device_type)),
+                        d.compute_units,
+                        d.max_work_group_size,
+                        d.global_mem_size,
+                        d.local_mem_size,
+                        d.mem_base_addr_align
+                    )
+                })
+                .collect();
+            format!(
+                "{{\"name\":{},\"vendor\":{},\"version\":{},\"devices\":[{}]}}",
+                json_str(&platform.name),
+                json_str(&platform.vendor),
+                json_str(&platform.version),
+                devices.join(",")
+            )
+        })
+        .collect();
+    format!("{{\"platforms\":[{}],\"interpreter\":true}}", platforms.join(","))
+}
+
+fn device_type_name(device_type: DeviceType) -> &'static str {
+    if device_type.contains(DeviceType::GPU) {
+        "gpu"
+    } else if device_type.contains(DeviceType::CPU) {
+        "cpu"
+    } else if device_type.contains(DeviceType::ACCELERATOR) {
+        "accelerator"
+    } else {
+        "other"
+    }
+}
@@ -0,0 +1,135 @@
+//! `simple_gpu reduce`: work-group tree reduction; the per-group sums are
+//! added on the host.
+
+use super::args::Args;
+use super::target::{Arg, DeviceOptions, Target};
+use super::{CliError, Format, Report, check_result, check_status, format_option, random_floats, read_floats, seed_option};
+use ocl::SpatialDims;
+use simple_gpu::datagen::Distribution;
+use simple_gpu::interpreter::Buffer;
+use simple_gpu::reference;
+
+pub const USAGE: &str = "\
+usage: simple_gpu reduce [options]
+
+  --size N          number of random floats to add (default 65536; 4096 on
+                    the interpreter)
+  --input FILE      add the numbers in FILE instead
+  --local-size L    work-group size, a power of two (default: up to 256)
+  --vector          use the float4 kernel
+  --seed S          seed for the random input
+  --no-check        skip the comparison with a host sum
+  --format F        text or json
+
+Device options as for every command; see `simple_gpu --help`.";
+
+pub(super) const KERNEL_SRC: &str = r#"
+__kernel void reduction_scalar(__global float *data,
+                               __local float *partial_sums,
+                               __global float *output) {
+   int lid = get_local_id(0);
+   int group_size = get_local_size(0);
+
+   partial_sums[lid] = data[get_global_id(0)];
+   barrier(CLK_LOCAL_MEM_FENCE);
+
+   for(int i = group_size/2; i > 0; i >>= 1) {
+      if(lid < i)
+         partial_sums[lid] += partial_sums[lid + i];
+      barrier(CLK_LOCAL_MEM_FENCE);
+   }
+
+   if(lid == 0)
+      output[get_group_id(0)] = partial_sums[0];
+}
+
+__kernel void reduction_vector(__global float4 *data,
+                               __local float4 *partial_sums,
+                               __global float *output) {
+   int lid = get_local_id(0);
+   int group_size = get_local_size(0);
+
+   partial_sums[lid] = data[get_global_id(0)];
+   barrier(CLK_LOCAL_MEM_FENCE);
+
+   for(int i = group_size/2; i > 0; i >>= 1) {
+      if(lid < i)
+         partial_sums[lid] += partial_sums[lid + i];
+      barrier(CLK_LOCAL_MEM_FENCE);
+   }
+
+   if(lid == 0)
+      output[get_group_id(0)] = dot(partial_sums[0], (float4)(1.0f));
+}
+"#;
+
+/// Relative difference allowed between the device and host sums.
+const TOLERANCE: f64 = 1e-4;
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let size: Option<usize> = args.opt("size")?;
+    let input: Option<String> = args.opt("input")?;
+    let local_size: Option<usize> = args.opt("local-size")?;
+    let vector = args.flag("vector");
+    let seed = seed_option(&mut args)?;
+    let check = !args.flag("no-check");
+    let format = format_option(&mut args, &[Format::Text, Format::Json])?;
+    let device = DeviceOptions::parse(&mut args)?;
+    args.finish()?;
+
+    if local_size.is_some_and(|l| !l.is_power_of_two()) {
+        return Err(CliError::Usage("--local-size must be a power of two".into()));
+    }
+    let target = Target::select(&device)?;
+    let data = match &input {
+        Some(path) => read_floats(path)?,
+        None => random_floats(size.unwrap_or(target.default_len(65536, 4096)), seed, Distribution::Uniform, 1.0),
+    };
+    let program = target.build(KERNEL_SRC, "")?;
+
+    let local = local_size.unwrap_or_else(|| target.group_size(256));
+    let width = if vector { 4 } else { 1 };
+    // Zeros pad the input to whole work-groups without changing the sum.
+    let mut padded = data.clone();
+    padded.resize(data.len().max(1).next_multiple_of(local * width), 0.0);
+    let global = padded.len() / width;
+    let groups = global / local;
+
+    let mut data_buffer = Buffer::from_slice(&padded);
+    let mut sums_buffer = Buffer::new::<f32>(groups);
+    let (kernel, scratch) = if vector {
+        ("reduction_vector", Arg::local::<ocl::prm::Float4>(local))
+    } else {
+        ("reduction_scalar", Arg::local::<f32>(local))
+    };
+    let elapsed = target.run(
+        &program,
+        kernel,
+        &mut [Arg::Buffer(&mut data_buffer), scratch, Arg::Buffer(&mut sums_buffer)],
+        global.into(),
+        SpatialDims::One(local),
+    )?;
+    let sum: f64 = sums_buffer.to_vec::<f32>().iter().map(|&s| s as f64).sum();
+
+    let checked = check.then(|| {
+        let expected = reference::sum(&data) as f64;
+        if (sum - expected).abs() <= TOLERANCE * expected.abs().max(1.0) {
+            Ok(())
+        } else {
+            Err(format!("sum {} differs from the host sum {}", sum, expected))
+        }
+    });
+
+    let mut report = Report::new().text("device", &target.name()).text("kernel", kernel).num("elements", data.len());
+    if input.is_none() {
+        report = report.num("seed", seed);
+    }
+    report
+        .num("local_size", local)
+        .num("groups", groups)
+        .num("sum", sum as f32)
+        .num("kernel_ms", format!("{:.3}", elapsed.as_secs_f64() * 1e3))
+        .text("check", check_status(&checked))
+        .print(format);
+    check_result(checked)
+}
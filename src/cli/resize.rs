@@ -0,0 +1,75 @@
+//! `simple_gpu resize`: nearest-neighbour upscaling of an RGBA image, one
+//! work-item per output pixel.
+
+use super::args::Args;
+use super::target::{Arg, DeviceOptions, Target};
+use super::{CliError, Format, Report, check_result, check_status, format_option};
+use ocl::SpatialDims;
+use simple_gpu::interpreter::Buffer;
+use simple_gpu::reference;
+
+pub const USAGE: &str = "\
+usage: simple_gpu resize --input FILE --output FILE [options]
+
+  --input FILE      image to read (PNG, JPEG, BMP, ...)
+  --output FILE     image to write; the format follows the extension
+  --scale N         integer scale factor (default 10)
+  --no-check        skip the comparison with a host resize
+  --format F        text or json
+
+Device options as for every command; see `simple_gpu --help`.";
+
+const KERNEL_SRC: &str = r#"
+__kernel void resize_nearest(__global const uchar4 *src,
+                             __global uchar4 *dst,
+                             uint src_width, uint scale) {
+   uint x = get_global_id(0);
+   uint y = get_global_id(1);
+   uint dst_width = get_global_size(0);
+   dst[y * dst_width + x] = src[(y / scale) * src_width + x / scale];
+}
+"#;
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let input: String = args.required("input")?;
+    let output: String = args.required("output")?;
+    let scale: usize = args.value("scale", 10)?;
+    let check = !args.flag("no-check");
+    let format = format_option(&mut args, &[Format::Text, Format::Json])?;
+    let device = DeviceOptions::parse(&mut args)?;
+    args.finish()?;
+
+    if scale == 0 {
+        return Err(CliError::Usage("--scale must be at least 1".into()));
+    }
+    let src = image::open(&input)?.to_rgba8();
+    let (width, height) = (src.width() as usize, src.height() as usize);
+    let (dst_width, dst_height) = (width * scale, height * scale);
+    let target = Target::select(&device)?;
+    let program = target.build(KERNEL_SRC, "")?;
+
+    let mut src_buffer = Buffer::from_slice(src.as_raw());
+    let mut dst_buffer = Buffer::new::<u8>(dst_width * dst_height * 4);
+    let elapsed = target.run(
+        &program,
+        "resize_nearest",
+        &mut [Arg::Buffer(&mut src_buffer), Arg::Buffer(&mut dst_buffer), Arg::scalar(width as u32), Arg::scalar(scale as u32)],
+        [dst_width, dst_height].into(),
+        SpatialDims::Unspecified,
+    )?;
+    let pixels = dst_buffer.to_vec::<u8>();
+
+    let checked = check.then(|| reference::diff_exact(&pixels, &reference::interp(src.as_raw(), width, height, scale)));
+    let dst = image::RgbaImage::from_raw(dst_width as u32, dst_height as u32, pixels)
+        .expect("output buffer holds the whole image");
+    dst.save(&output)?;
+
+    Report::new()
+        .text("device", &target.name())
+        .text("input", &format!("{} ({}x{})", input, width, height))
+        .text("output", &format!("{} ({}x{})", output, dst_width, dst_height))
+        .num("kernel_ms", format!("{:.3}", elapsed.as_secs_f64() * 1e3))
+        .text("check", check_status(&checked))
+        .print(format);
+    check_result(checked)
+}
@@ -0,0 +1,468 @@
+//! `simple_gpu run`: builds a kernel from a .cl file and launches it with
+//! arguments described on the command line, so trying a kernel needs no
+//! Rust.
+
+use super::args::Args;
+use super::target::{Arg, DeviceOptions, Target};
+use super::{CliError, Format, Report, format_option, read_file, write_file};
+use ocl::SpatialDims;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use simple_gpu::interpreter::Buffer;
+use simple_gpu::npy::RawArray;
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+pub const USAGE: &str = "\
+usage: simple_gpu run FILE KERNEL --global SIZE [options] ARG...
+
+  --global SIZE     global work size: N, NxM or NxMxK
+  --local SIZE      work-group size (default: left to the device)
+  --build-options S compiler options, e.g. \"-D WIDTH=64\"
+  --seed N          seed for rand data (default: $SIMPLE_GPU_SEED, else
+                    random)
+  --format F        text or json
+
+Each ARG describes one kernel argument, in order:
+  buf:TYPE:LEN[=DATA]          a __global buffer
+  out:TYPE:LEN[=DATA][>FILE]   a __global buffer shown after the run, or
+                               written to FILE (.npy, else one value a line)
+  local:TYPE:LEN               __local memory for LEN elements
+  scalar:TYPE=V[,V...]         a value; several make a vector (float4, ...)
+
+TYPE is f32, f64, i8, i16, i32, i64, u8, u16, u32 or u64. LEN is a count or
+a shape such as 64x64. DATA is zero (the default), rand ([0, 1) for floats,
+[0, 100) for integers), iota (0, 1, 2, ...), a constant, or @FILE: a .npy
+array of TYPE or whitespace- or comma-separated values, in which case LEN
+may be left out.
+Quote arguments holding `>` from the shell.
+
+Device options as for every command; see `simple_gpu --help`.";
+
+/// Values shown at each end of an output buffer.
+const PREVIEW: usize = 4;
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let global: WorkSize = args.required("global")?;
+    let local: Option<WorkSize> = args.opt("local")?;
+    let build_options: String = args.value("build-options", String::new())?;
+    let seed: Option<u64> = args.opt("seed")?;
+    let format = format_option(&mut args, &[Format::Text, Format::Json])?;
+    let device = DeviceOptions::parse(&mut args)?;
+    let mut operands = args.positional()?.into_iter();
+    let (Some(file), Some(kernel)) = (operands.next(), operands.next()) else {
+        return Err(CliError::Usage(format!("run needs a .cl file and a kernel name\n\n{}", USAGE)));
+    };
+    let mut specs = operands.map(|spec| parse_spec(&spec)).collect::<Result<Vec<_>, _>>()?;
+    if let Some(local) = &local
+        && local.0.dim_count() != global.0.dim_count()
+    {
+        return Err(CliError::Usage("--local needs as many dimensions as --global".into()));
+    }
+
+    let uses_rand = specs.iter().any(|spec| matches!(spec, Spec::Buffer { data: Data::Rand, .. }));
+    let seed = seed.unwrap_or_else(simple_gpu::datagen::seed_from_env);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut buffers = Vec::new();
+    for spec in &mut specs {
+        if let Spec::Buffer { elem, shape, data, .. } = spec {
+            buffers.push(fill(*elem, shape, data, &mut rng)?);
+        }
+    }
+
+    let src = String::from_utf8(read_file(&file)?)
+        .map_err(|_| CliError::Usage(format!("{} is not UTF-8 text", file)))?;
+    let target = Target::select(&device)?;
+    let program = target.build(&src, &build_options)?;
+    let mut next_buffer = buffers.iter_mut();
+    let mut kernel_args = Vec::with_capacity(specs.len());
+    for spec in &specs {
+        kernel_args.push(match spec {
+            Spec::Buffer { .. } => Arg::Buffer(next_buffer.next().expect("one buffer per buffer spec")),
+            Spec::Local { elem, len } => Arg::Local(elem.size * len),
+            Spec::Scalar(bytes) => Arg::Scalar(bytes.clone()),
+        });
+    }
+    let elapsed = target.run(
+        &program,
+        &kernel,
+        &mut kernel_args,
+        global.0,
+        local.as_ref().map_or(SpatialDims::Unspecified, |l| l.0),
+    )?;
+
+    let mut report = Report::new()
+        .text("device", &target.name())
+        .text("kernel", &kernel)
+        .text("global", &global.to_string())
+        .text("local", &local.as_ref().map_or("auto".into(), WorkSize::to_string));
+    if uses_rand {
+        report = report.num("seed", seed);
+    }
+    report = report.num("kernel_ms", format!("{:.3}", elapsed.as_secs_f64() * 1e3));
+    let mut next_buffer = buffers.iter();
+    for (i, spec) in specs.iter().enumerate() {
+        let Spec::Buffer { elem, shape, out, .. } = spec else {
+            continue;
+        };
+        let buffer = next_buffer.next().expect("one buffer per buffer spec");
+        let Some(dest) = out else {
+            continue;
+        };
+        let value = match dest {
+            Some(path) => {
+                save(*elem, shape, buffer.as_bytes(), path)?;
+                format!("wrote {}", path)
+            }
+            None => preview(*elem, buffer.as_bytes()),
+        };
+        report = report.text(format!("arg{}", i), &value);
+    }
+    report.print(format);
+    Ok(())
+}
+
+/// A work size given as `N`, `NxM` or `NxMxK`.
+#[derive(Debug, Clone, Copy)]
+struct WorkSize(SpatialDims);
+
+impl FromStr for WorkSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let dims = parse_shape(s)?;
+        Ok(WorkSize(match dims[..] {
+            [x] => SpatialDims::One(x),
+            [x, y] => SpatialDims::Two(x, y),
+            [x, y, z] => SpatialDims::Three(x, y, z),
+            _ => return Err("at most three dimensions".into()),
+        }))
+    }
+}
+
+impl fmt::Display for WorkSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dims: Vec<String> = (0..self.0.dim_count() as usize).map(|d| self.0[d].to_string()).collect();
+        f.write_str(&dims.join("x"))
+    }
+}
+
+/// `64`, `64x64`, ...: every extent at least 1.
+fn parse_shape(s: &str) -> Result<Vec<usize>, String> {
+    s.split('x')
+        .map(|d| match d.parse() {
+            Ok(0) => Err("sizes must be at least 1".to_string()),
+            Ok(n) => Ok(n),
+            Err(_) => Err(format!("`{}` is not a size", s)),
+        })
+        .collect()
+}
+
+/// The number of elements in `shape`, or `None` if they or their bytes
+/// overflow.
+fn element_count(elem: Elem, shape: &[usize]) -> Option<usize> {
+    let len = shape.iter().try_fold(1usize, |len, &d| len.checked_mul(d))?;
+    len.checked_mul(elem.size)?;
+    Some(len)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Float,
+    Signed,
+    Unsigned,
+}
+
+/// An element type; every type is stored little-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Elem {
+    kind: Kind,
+    size: usize,
+}
+
+impl FromStr for Elem {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, bits) = match s.split_at_checked(1) {
+            Some(("f", bits)) => (Kind::Float, bits),
+            Some(("i", bits)) => (Kind::Signed, bits),
+            Some(("u", bits)) => (Kind::Unsigned, bits),
+            _ => return Err(format!("unknown type `{}`", s)),
+        };
+        match (kind, bits) {
+            (Kind::Float, "32" | "64") | (Kind::Signed | Kind::Unsigned, "8" | "16" | "32" | "64") => {
+                Ok(Elem { kind, size: bits.parse::<usize>().expect("checked above") / 8 })
+            }
+            _ => Err(format!("unknown type `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for Elem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.letter(), self.size * 8)
+    }
+}
+
+impl Elem {
+    fn letter(self) -> char {
+        match self.kind {
+            Kind::Float => 'f',
+            Kind::Signed => 'i',
+            Kind::Unsigned => 'u',
+        }
+    }
+
+    /// Appends `value`, parsed as this type.
+    fn encode(self, value: &str, out: &mut Vec<u8>) -> Result<(), CliError> {
+        let invalid = || CliError::Usage(format!("`{}` is not a valid {}", value, self));
+        if self.kind == Kind::Float {
+            self.push_float(value.parse().map_err(|_| invalid())?, out);
+            return Ok(());
+        }
+        let v: i128 = value.parse().map_err(|_| invalid())?;
+        let bits = self.size as u32 * 8;
+        let (min, max) = match self.kind {
+            Kind::Signed => (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1),
+            _ => (0, (1i128 << bits) - 1),
+        };
+        if v < min || v > max {
+            return Err(invalid());
+        }
+        self.push_int(v, out);
+        Ok(())
+    }
+
+    fn push_float(self, v: f64, out: &mut Vec<u8>) {
+        match self.size {
+            4 => out.extend_from_slice(&(v as f32).to_le_bytes()),
+            _ => out.extend_from_slice(&v.to_le_bytes()),
+        }
+    }
+
+    /// Appends the low bytes of `v`, so out-of-range values wrap.
+    fn push_int(self, v: i128, out: &mut Vec<u8>) {
+        out.extend_from_slice(&v.to_le_bytes()[..self.size]);
+    }
+
+    fn decode(self, bytes: &[u8]) -> String {
+        match (self.kind, self.size) {
+            (Kind::Float, 4) => f32::from_le_bytes(bytes.try_into().expect("4 bytes")).to_string(),
+            (Kind::Float, _) => f64::from_le_bytes(bytes.try_into().expect("8 bytes")).to_string(),
+            (kind, size) => {
+                let mut wide = [0u8; 16];
+                wide[..size].copy_from_slice(bytes);
+                let unsigned = u128::from_le_bytes(wide);
+                if kind == Kind::Signed {
+                    let shift = 128 - size as u32 * 8;
+                    (((unsigned << shift) as i128) >> shift).to_string()
+                } else {
+                    unsigned.to_string()
+                }
+            }
+        }
+    }
+
+    /// The NumPy dtype string.
+    fn descr(self) -> String {
+        format!("{}{}{}", if self.size == 1 { '|' } else { '<' }, self.letter(), self.size)
+    }
+}
+
+enum Data {
+    Zero,
+    Rand,
+    Iota,
+    Value(String),
+    File(String),
+}
+
+enum Spec {
+    Buffer {
+        elem: Elem,
+        /// Empty when the length comes from the data file.
+        shape: Vec<usize>,
+        data: Data,
+        /// For `out`: shown after the run, or written to the file.
+        out: Option<Option<String>>,
+    },
+    Local {
+        elem: Elem,
+        len: usize,
+    },
+    /// The encoded value.
+    Scalar(Vec<u8>),
+}
+
+fn parse_spec(spec: &str) -> Result<Spec, CliError> {
+    let bad = |why: &str| CliError::Usage(format!("argument `{}`: {}", spec, why));
+    let (kind, rest) = spec.split_once(':').ok_or_else(|| bad("expected KIND:TYPE..."))?;
+    let (rest, file) = match rest.rsplit_once('>') {
+        Some(_) if kind != "out" => return Err(bad("only out arguments are written to a file")),
+        Some((_, "")) => return Err(bad("needs a file name after `>`")),
+        Some((rest, file)) => (rest, Some(file.to_string())),
+        None => (rest, None),
+    };
+    let (rest, data) = match rest.split_once('=') {
+        Some((rest, data)) => (rest, Some(data)),
+        None => (rest, None),
+    };
+    let (elem, len) = match rest.split_once(':') {
+        Some((elem, len)) => (elem, Some(len)),
+        None => (rest, None),
+    };
+    let elem: Elem = elem.parse().map_err(|e: String| bad(&e))?;
+    let shape = len.map(parse_shape).transpose().map_err(|e| bad(&e))?;
+    if let Some(shape) = &shape
+        && element_count(elem, shape).is_none()
+    {
+        return Err(bad("the size is too large"));
+    }
+
+    match kind {
+        "buf" | "out" => {
+            let data = match data {
+                None | Some("zero") => Data::Zero,
+                Some("rand") => Data::Rand,
+                Some("iota") => Data::Iota,
+                Some(path) if path.starts_with('@') => Data::File(path[1..].to_string()),
+                Some(value) => {
+                    elem.encode(value, &mut Vec::new()).map_err(|err| bad(&err.to_string()))?;
+                    Data::Value(value.to_string())
+                }
+            };
+            let shape = match shape {
+                Some(shape) => shape,
+                None if matches!(data, Data::File(_)) => Vec::new(),
+                None => return Err(bad("needs a length")),
+            };
+            Ok(Spec::Buffer { elem, shape, data, out: (kind == "out").then_some(file) })
+        }
+        "local" => match (shape, data) {
+            (Some(shape), None) => Ok(Spec::Local { elem, len: shape.iter().product() }),
+            (None, _) => Err(bad("needs a length")),
+            (_, Some(_)) => Err(bad("local memory takes no data")),
+        },
+        "scalar" => match (shape, data) {
+            (None, Some(values)) => {
+                let mut bytes = Vec::new();
+                for value in values.split(',') {
+                    elem.encode(value, &mut bytes).map_err(|err| bad(&err.to_string()))?;
+                }
+                Ok(Spec::Scalar(bytes))
+            }
+            (Some(_), _) => Err(bad("a scalar takes no length")),
+            (_, None) => Err(bad("needs a value")),
+        },
+        _ => Err(bad("the kind must be buf, out, local or scalar")),
+    }
+}
+
+/// A buffer holding `data`; an empty `shape` becomes the file's length.
+fn fill(elem: Elem, shape: &mut Vec<usize>, data: &Data, rng: &mut StdRng) -> Result<Buffer, CliError> {
+    let len = element_count(elem, shape).ok_or_else(|| CliError::Usage(format!("shape {:?} is too large", shape)))?;
+    let mut bytes = Vec::with_capacity(len * elem.size);
+    match data {
+        Data::Zero => bytes.resize(len * elem.size, 0),
+        Data::Rand => {
+            for _ in 0..len {
+                match elem.kind {
+                    Kind::Float => elem.push_float(rng.r#gen(), &mut bytes),
+                    _ => elem.push_int(rng.gen_range(0..100), &mut bytes),
+                }
+            }
+        }
+        Data::Iota => {
+            for i in 0..len {
+                match elem.kind {
+                    Kind::Float => elem.push_float(i as f64, &mut bytes),
+                    _ => elem.push_int(i as i128, &mut bytes),
+                }
+            }
+        }
+        Data::Value(value) => {
+            for _ in 0..len {
+                elem.encode(value, &mut bytes)?;
+            }
+        }
+        Data::File(path) if path.ends_with(".npy") => {
+            let raw = RawArray::load(path).map_err(|err| npy_error(path, err))?.into_little_endian();
+            if raw.descr != elem.descr() {
+                return Err(CliError::Usage(format!("{} holds {} data, the argument {}", path, raw.descr, elem.descr())));
+            }
+            if shape.is_empty() {
+                *shape = raw.shape;
+            } else if raw.shape.iter().product::<usize>() != len {
+                return Err(CliError::Usage(format!("{} has shape {:?}, the argument {:?}", path, raw.shape, shape)));
+            }
+            bytes = raw.data;
+        }
+        Data::File(path) => {
+            let text = String::from_utf8_lossy(&read_file(path)?).into_owned();
+            let mut count = 0;
+            for value in text.split(|c: char| c.is_whitespace() || c == ',').filter(|s| !s.is_empty()) {
+                elem.encode(value, &mut bytes).map_err(|err| CliError::Usage(format!("{}: {}", path, err)))?;
+                count += 1;
+            }
+            if shape.is_empty() {
+                shape.push(count);
+            } else if count != len {
+                return Err(CliError::Usage(format!("{} holds {} values, the argument {}", path, count, len)));
+            }
+        }
+    }
+    if bytes.is_empty() {
+        return Err(CliError::Usage("buffers need at least one element".into()));
+    }
+    Ok(Buffer::from_slice(&bytes))
+}
+
+/// The first and last few values, e.g. `[0, 1, 2, 3, ..., 1023] (1024 values)`.
+fn preview(elem: Elem, bytes: &[u8]) -> String {
+    let values: Vec<String> = bytes.chunks_exact(elem.size).map(|b| elem.decode(b)).collect();
+    if values.len() <= 2 * PREVIEW {
+        return format!("[{}]", values.join(", "));
+    }
+    format!(
+        "[{}, ..., {}] ({} values)",
+        values[..PREVIEW].join(", "),
+        values[values.len() - PREVIEW..].join(", "),
+        values.len()
+    )
+}
+
+/// Writes a `.npy` array of `shape`, or one value per line for any other
+/// extension.
+fn save(elem: Elem, shape: &[usize], bytes: &[u8], path: &str) -> Result<(), CliError> {
+    if !path.ends_with(".npy") {
+        let mut text = String::with_capacity(bytes.len() * 3);
+        for value in bytes.chunks_exact(elem.size) {
+            let _ = writeln!(text, "{}", elem.decode(value));
+        }
+        return write_file(path, text.as_bytes());
+    }
+    let raw = RawArray { descr: elem.descr(), shape: shape.to_vec(), data: bytes.to_vec() };
+    raw.save(path).map_err(|err| npy_error(path, err))
+}
+
+/// Names the file in I/O errors, as `read_file` does.
+fn npy_error(path: &str, err: simple_gpu::Error) -> CliError {
+    match err {
+        simple_gpu::Error::Io(err) => CliError::Io { path: path.to_string(), err },
+        err => CliError::Usage(format!("{}: {}", path, err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oversized_shapes_are_usage_errors() {
+        for spec in ["buf:f64:4611686018427387904", "out:u8:4294967296x4294967296", "local:f32:4611686018427387904"] {
+            assert!(matches!(parse_spec(spec), Err(CliError::Usage(_))), "{}", spec);
+        }
+        assert!(matches!(parse_spec("local:f32:4x1024"), Ok(Spec::Local { len: 4096, .. })));
+    }
+}
@@ -0,0 +1,100 @@
+//! `simple_gpu search`: counts occurrences of words in a text file, one
+//! work-item per starting position.
+
+use super::args::Args;
+use super::target::{Arg, DeviceOptions, Target};
+use super::{CliError, Format, Report, check_result, check_status, format_option, read_file};
+use ocl::SpatialDims;
+use simple_gpu::interpreter::Buffer;
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+usage: simple_gpu search --input FILE [options]
+
+  --input FILE      text to search
+  --words LIST      comma-separated words (default that,with,have,from)
+  --local-size L    work-group size (default: up to 256)
+  --no-check        skip the comparison with a host count
+  --format F        text or json
+
+Device options as for every command; see `simple_gpu --help`.";
+
+const KERNEL_SRC: &str = r#"
+__kernel void count_word(__global const uchar *text, uint text_len,
+                         __global const uchar *word, uint word_len,
+                         __global int *count) {
+   uint start = get_global_id(0);
+   if (start + word_len > text_len)
+      return;
+   for (uint i = 0; i < word_len; i++) {
+      if (text[start + i] != word[i])
+         return;
+   }
+   atomic_inc(count);
+}
+"#;
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let input: String = args.required("input")?;
+    let words: Vec<String> =
+        args.list("words")?.unwrap_or_else(|| ["that", "with", "have", "from"].map(String::from).to_vec());
+    let local_size: Option<usize> = args.opt("local-size")?;
+    let check = !args.flag("no-check");
+    let format = format_option(&mut args, &[Format::Text, Format::Json])?;
+    let device = DeviceOptions::parse(&mut args)?;
+    args.finish()?;
+
+    if words.iter().any(String::is_empty) {
+        return Err(CliError::Usage("--words must not contain empty words".into()));
+    }
+    let text = read_file(&input)?;
+    if text.is_empty() {
+        return Err(CliError::Usage(format!("{} is empty", input)));
+    }
+    let target = Target::select(&device)?;
+    let program = target.build(KERNEL_SRC, "")?;
+
+    let local = local_size.unwrap_or_else(|| target.group_size(256));
+    let global = text.len().next_multiple_of(local);
+    let mut text_buffer = Buffer::from_slice(&text);
+    let mut counts = Vec::with_capacity(words.len());
+    let mut elapsed = Duration::ZERO;
+    for word in &words {
+        let mut word_buffer = Buffer::from_slice(word.as_bytes());
+        let mut count_buffer = Buffer::new::<i32>(1);
+        elapsed += target.run(
+            &program,
+            "count_word",
+            &mut [
+                Arg::Buffer(&mut text_buffer),
+                Arg::scalar(text.len() as u32),
+                Arg::Buffer(&mut word_buffer),
+                Arg::scalar(word.len() as u32),
+                Arg::Buffer(&mut count_buffer),
+            ],
+            global.into(),
+            SpatialDims::One(local),
+        )?;
+        counts.push(count_buffer.to_vec::<i32>()[0]);
+    }
+
+    let checked = check.then(|| {
+        for (word, &count) in words.iter().zip(&counts) {
+            let expected = text.windows(word.len()).filter(|w| *w == word.as_bytes()).count();
+            if count as usize != expected {
+                return Err(format!("`{}` counted {} times, the host counts {}", word, count, expected));
+            }
+        }
+        Ok(())
+    });
+
+    let mut report = Report::new().text("device", &target.name()).text("input", &input).num("bytes", text.len());
+    for (word, count) in words.iter().zip(&counts) {
+        report = report.num(format!("count_{}", word), count);
+    }
+    report
+        .num("kernel_ms", format!("{:.3}", elapsed.as_secs_f64() * 1e3))
+        .text("check", check_status(&checked))
+        .print(format);
+    check_result(checked)
+}
@@ -0,0 +1,121 @@
+//! `simple_gpu sort`: bitonic sort, one launch per compare-exchange stage.
+
+use super::args::Args;
+use super::target::{Arg, DeviceOptions, Program, Target};
+use super::{
+    CliError, Format, Report, check_result, check_status, format_option, random_floats, read_floats, seed_option,
+    write_floats,
+};
+use ocl::SpatialDims;
+use simple_gpu::datagen::Distribution;
+use simple_gpu::interpreter::Buffer;
+use simple_gpu::reference;
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+usage: simple_gpu sort [options]
+
+  --size N          number of random floats to sort (default 32768; 1024 on
+                    the interpreter)
+  --input FILE      sort the numbers in FILE instead
+  --distribution D  arrangement of the random input: uniform (default),
+                    normal, sorted, reverse-sorted, nearly-sorted, all-equal
+                    or adversarial
+  --output FILE     write the sorted numbers to FILE, one per line
+  --descending      largest first
+  --seed S          seed for the random input
+  --no-check        skip the comparison with a host sort
+  --format F        text or json
+
+Device options as for every command; see `simple_gpu --help`.";
+
+const KERNEL_SRC: &str = r#"
+__kernel void bitonic_step(__global float *data, uint j, uint k, int descending) {
+   uint i = get_global_id(0);
+   uint partner = i ^ j;
+   if (partner > i) {
+      int ascending = ((i & k) == 0) != descending;
+      float a = data[i];
+      float b = data[partner];
+      if ((a > b) == ascending) {
+         data[i] = b;
+         data[partner] = a;
+      }
+   }
+}
+"#;
+
+pub fn run(mut args: Args) -> Result<(), CliError> {
+    let size: Option<usize> = args.opt("size")?;
+    let input: Option<String> = args.opt("input")?;
+    let distribution = args.value("distribution", Distribution::Uniform)?;
+    let output: Option<String> = args.opt("output")?;
+    let descending = args.flag("descending");
+    let seed = seed_option(&mut args)?;
+    let check = !args.flag("no-check");
+    let format = format_option(&mut args, &[Format::Text, Format::Json])?;
+    let device = DeviceOptions::parse(&mut args)?;
+    args.finish()?;
+
+    let target = Target::select(&device)?;
+    let data = match &input {
+        Some(path) => read_floats(path)?,
+        None => random_floats(size.unwrap_or(target.default_len(32768, 1024)), seed, distribution, 10000.0),
+    };
+    if data.is_empty() {
+        return Err(CliError::Usage("nothing to sort".into()));
+    }
+    let program = target.build(KERNEL_SRC, "")?;
+
+    // The network needs a power-of-two length; the padding sorts to the end.
+    let len = data.len().next_power_of_two();
+    let mut padded = data.clone();
+    padded.resize(len, if descending { f32::NEG_INFINITY } else { f32::INFINITY });
+    let mut buffer = Buffer::from_slice(&padded);
+    let (elapsed, launches) = bitonic_sort(&target, &program, &mut buffer, descending)?;
+    let mut sorted = buffer.to_vec::<f32>();
+    sorted.truncate(data.len());
+
+    let checked = check.then(|| {
+        let mut expected = data;
+        reference::bitonic_sort(&mut expected, descending);
+        reference::diff_exact(&sorted, &expected)
+    });
+
+    let mut report = Report::new().text("device", &target.name()).num("elements", sorted.len());
+    if input.is_none() {
+        report = report.text("distribution", distribution.name()).num("seed", seed);
+    }
+    report = report
+        .num("launches", launches)
+        .num("kernel_ms", format!("{:.3}", elapsed.as_secs_f64() * 1e3))
+        .text("first", &format!("{:?}", &sorted[..sorted.len().min(4)]))
+        .text("last", &format!("{:?}", &sorted[sorted.len().saturating_sub(4)..]))
+        .text("check", check_status(&checked));
+    report.print(format);
+
+    if let Some(path) = &output {
+        write_floats(path, &sorted)?;
+    }
+    check_result(checked)
+}
+
+/// Sorts `buffer`, a power-of-two number of floats, with one launch per
+/// stage; returns the kernel time and the number of launches.
+fn bitonic_sort(target: &Target, program: &Program, buffer: &mut Buffer, descending: bool) -> Result<(Duration, usize), CliError> {
+    let len = buffer.len() / std::mem::size_of::<f32>();
+    let (mut elapsed, mut launches) = (Duration::ZERO, 0);
+    let mut k = 2;
+    while k <= len {
+        let mut j = k / 2;
+        while j > 0 {
+            let mut kernel_args =
+                [Arg::Buffer(&mut *buffer), Arg::scalar(j as u32), Arg::scalar(k as u32), Arg::scalar(descending as i32)];
+            elapsed += target.run(program, "bitonic_step", &mut kernel_args, len.into(), SpatialDims::Unspecified)?;
+            launches += 1;
+            j /= 2;
+        }
+        k *= 2;
+    }
+    Ok((elapsed, launches))
+}
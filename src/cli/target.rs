@@ -0,0 +1,245 @@
+//! Where the commands' kernels run: the OpenCL device picked by the device
+//! options, or the interpreter. Buffers live on the host and are copied to
+//! the device and back around each launch, which keeps the commands simple;
+//! the returned duration covers the kernel alone.
+
+use super::CliError;
+use super::args::Args;
+use ocl::core::{self, ArgVal, ProgramBuildInfo, ProgramBuildInfoResult};
+use ocl::flags::{DeviceType, MemFlags};
+use ocl::{Context, Device, OclPrm, Platform, Queue, SpatialDims};
+use simple_gpu::interpreter::{self, Buffer};
+use std::ffi::CString;
+use std::time::{Duration, Instant};
+
+pub struct DeviceOptions {
+    platform: usize,
+    device: usize,
+    device_type: Option<DeviceType>,
+    interp: bool,
+}
+
+impl DeviceOptions {
+    pub fn parse(args: &mut Args) -> Result<DeviceOptions, CliError> {
+        let device_type = match args.opt::<String>("device-type")?.as_deref() {
+            None => None,
+            Some("gpu") => Some(DeviceType::GPU),
+            Some("cpu") => Some(DeviceType::CPU),
+            Some("accelerator") => Some(DeviceType::ACCELERATOR),
+            Some("all") => Some(DeviceType::ALL),
+            Some(other) => return Err(CliError::Usage(format!("unknown device type `{}`", other))),
+        };
+        Ok(DeviceOptions {
+            platform: args.value("platform", 0)?,
+            device: args.value("device", 0)?,
+            device_type,
+            interp: args.flag("interp"),
+        })
+    }
+}
+
+pub enum Target {
+    Device { device: Device, context: Context, queue: Queue },
+    Interpreter,
+}
+
+pub enum Program {
+    Device(core::Program),
+    Interpreter(interpreter::Program),
+}
+
+/// A kernel argument.
+pub enum Arg<'a> {
+    Buffer(&'a mut Buffer),
+    /// Bytes of `__local` memory.
+    Local(usize),
+    /// A scalar or vector value as raw bytes.
+    Scalar(Vec<u8>),
+}
+
+impl Arg<'_> {
+    pub fn scalar<T: OclPrm>(value: T) -> Self {
+        Arg::Scalar(Buffer::from_slice(&[value]).as_bytes().to_vec())
+    }
+
+    pub fn local<T: OclPrm>(len: usize) -> Self {
+        Arg::Local(len * std::mem::size_of::<T>())
+    }
+}
+
+impl Target {
+    /// Opens the selected device. Without an OpenCL driver the interpreter
+    /// is used, with a note on stderr.
+    pub fn select(options: &DeviceOptions) -> Result<Target, CliError> {
+        if options.interp {
+            return Ok(Target::Interpreter);
+        }
+        if !interpreter::driver_available() {
+            eprintln!("No OpenCL platform found; running on the interpreter.");
+            return Ok(Target::Interpreter);
+        }
+        let platforms = Platform::list_from_core(core::get_platform_ids()?);
+        let platform = *platforms.get(options.platform).ok_or_else(|| {
+            CliError::Usage(format!("no platform {} ({} found)", options.platform, platforms.len()))
+        })?;
+        let devices = match options.device_type {
+            Some(device_type) => Device::list(platform, Some(device_type)).unwrap_or_default(),
+            None => {
+                let gpus = Device::list(platform, Some(DeviceType::GPU)).unwrap_or_default();
+                if gpus.is_empty() { Device::list(platform, Some(DeviceType::CPU)).unwrap_or_default() } else { gpus }
+            }
+        };
+        let device = *devices.get(options.device).ok_or_else(|| {
+            CliError::Usage(format!("no device {} on platform {} ({} found)", options.device, options.platform, devices.len()))
+        })?;
+        let context = Context::builder().platform(platform).devices(device).build()?;
+        let queue = Queue::new(&context, device, None)?;
+        Ok(Target::Device { device, context, queue })
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Target::Device { device, .. } => device.name().unwrap_or_else(|_| "unknown device".into()),
+            Target::Interpreter => "interpreter".into(),
+        }
+    }
+
+    pub fn max_work_group_size(&self) -> usize {
+        match self {
+            Target::Device { device, .. } => device.max_wg_size().unwrap_or(1),
+            Target::Interpreter => 256,
+        }
+    }
+
+    /// The largest power-of-two work-group size the device allows, up to
+    /// `limit`.
+    pub fn group_size(&self, limit: usize) -> usize {
+        let max = limit.min(self.max_work_group_size()).max(1);
+        1 << max.ilog2()
+    }
+
+    /// A default problem size: `device` on a driver, the smaller `interpreter`
+    /// on the much slower interpreter.
+    pub fn default_len(&self, device: usize, interpreter: usize) -> usize {
+        match self {
+            Target::Device { .. } => device,
+            Target::Interpreter => interpreter,
+        }
+    }
+
+    /// Builds `src`; a failed device build reports the build log.
+    pub fn build(&self, src: &str, options: &str) -> Result<Program, CliError> {
+        match self {
+            Target::Device { device, context, .. } => {
+                let nul = |_| CliError::Usage("source or build options contain a NUL byte".into());
+                let program = core::create_program_with_source(context, &[CString::new(src).map_err(nul)?])?;
+                let options_c = CString::new(options).map_err(nul)?;
+                if let Err(err) = core::build_program(&program, Some(&[*device]), &options_c, None, None) {
+                    let err = simple_gpu::Error::from(err).call("clBuildProgram");
+                    return Err(match core::get_program_build_info(&program, device, ProgramBuildInfo::BuildLog) {
+                        Ok(ProgramBuildInfoResult::BuildLog(log)) => err.log(&log),
+                        _ => err,
+                    }
+                    .into());
+                }
+                Ok(Program::Device(program))
+            }
+            Target::Interpreter => Ok(Program::Interpreter(interpreter::Program::build_with_options(src, options)?)),
+        }
+    }
+
+    /// Runs `kernel` over `global` work-items (in groups of `local`, unless
+    /// unspecified) and copies the buffers back.
+    pub fn run(
+        &self,
+        program: &Program,
+        kernel: &str,
+        args: &mut [Arg],
+        global: SpatialDims,
+        local: SpatialDims,
+    ) -> Result<Duration, CliError> {
+        match (self, program) {
+            (Target::Device { context, queue, .. }, Program::Device(program)) => {
+                let kernel = core::create_kernel(program, kernel)?;
+                let mut mems = Vec::new();
+                for arg in args.iter() {
+                    if let Arg::Buffer(buffer) = arg {
+                        let flags = MemFlags::new().read_write().copy_host_ptr();
+                        // SAFETY: the host data is copied during the call.
+                        mems.push(unsafe { core::create_buffer(context, flags, buffer.len(), Some(buffer.as_bytes())) }?);
+                    }
+                }
+                let mut next_mem = mems.iter();
+                for (i, arg) in args.iter().enumerate() {
+                    let value = match arg {
+                        Arg::Buffer(_) => ArgVal::mem(next_mem.next().expect("one mem per buffer argument")),
+                        Arg::Local(bytes) => ArgVal::local::<u8>(bytes),
+                        // SAFETY: `bytes` outlives the `set_kernel_arg` call
+                        // and its length is the argument size.
+                        Arg::Scalar(bytes) => unsafe { ArgVal::from_raw(bytes.len(), bytes.as_ptr().cast(), false) },
+                    };
+                    core::set_kernel_arg(&kernel, i as u32, value)?;
+                }
+
+                let unset = |_| CliError::Usage("work size not set".into());
+                let global_lens = global.to_lens().map_err(unset)?;
+                let local = if local.is_unspecified() { None } else { Some(local.to_lens().map_err(unset)?) };
+                let start = Instant::now();
+                // SAFETY: every argument is set, and the buffers stay alive
+                // until `finish` returns.
+                unsafe {
+                    core::enqueue_kernel(
+                        queue,
+                        &kernel,
+                        global.dim_count(),
+                        None,
+                        &global_lens,
+                        local,
+                        None::<core::Event>,
+                        None::<&mut core::Event>,
+                    )?;
+                }
+                core::finish(queue)?;
+                let elapsed = start.elapsed();
+
+                let mut next_mem = mems.iter();
+                for arg in args.iter_mut() {
+                    if let Arg::Buffer(buffer) = arg {
+                        let mem = next_mem.next().expect("one mem per buffer argument");
+                        // SAFETY: blocking read into a host slice of the
+                        // buffer's size.
+                        unsafe {
+                            core::enqueue_read_buffer(
+                                queue,
+                                mem,
+                                true,
+                                0,
+                                buffer.as_bytes_mut(),
+                                None::<core::Event>,
+                                None::<&mut core::Event>,
+                            )?;
+                        }
+                    }
+                }
+                Ok(elapsed)
+            }
+            (Target::Interpreter, Program::Interpreter(program)) => {
+                let mut launch = program.kernel(kernel)?.global_work_size(global);
+                if !local.is_unspecified() {
+                    launch = launch.local_work_size(local);
+                }
+                for arg in args.iter_mut() {
+                    launch = match arg {
+                        Arg::Buffer(buffer) => launch.arg_buf(buffer),
+                        Arg::Local(bytes) => launch.arg_local::<u8>(*bytes),
+                        Arg::Scalar(bytes) => launch.arg_bytes(bytes),
+                    };
+                }
+                let start = Instant::now();
+                launch.run()?;
+                Ok(start.elapsed())
+            }
+            _ => panic!("program built for a different target"),
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Seeded input generators, so a failing run can be repeated exactly.
+//!
+//! A `DataGen` draws everything from one seed: `SIMPLE_GPU_SEED` if it is
+//! set, else a fresh random one. Programs print the seed when a check fails
+//! (`report_failure`), and a generator dropped during a panic prints it too;
+//! setting the variable to that seed reproduces the input.
+//!
+//! Values come in the `Distribution`s sorts are usually tested with, plus
+//! random text and noise images.
+
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::fmt;
+use std::str::FromStr;
+
+pub const SEED_VAR: &str = "SIMPLE_GPU_SEED";
+
+/// The seed in `SIMPLE_GPU_SEED`, or a random one if it is unset or not a
+/// number.
+pub fn seed_from_env() -> u64 {
+    match std::env::var(SEED_VAR) {
+        Ok(text) => parse_seed(&text).unwrap_or_else(|| {
+            eprintln!("{}: `{}` is not a u64; using a random seed", SEED_VAR, text);
+            rand::thread_rng().r#gen()
+        }),
+        Err(_) => rand::thread_rng().r#gen(),
+    }
+}
+
+/// A seed as written in `SIMPLE_GPU_SEED`: a decimal `u64`, surrounding
+/// whitespace allowed.
+fn parse_seed(text: &str) -> Option<u64> {
+    text.trim().parse().ok()
+}
+
+/// How generated values are arranged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Distribution {
+    Uniform,
+    /// Centred on half the range with a standard deviation of an eighth of
+    /// it, clamped to the range.
+    Normal,
+    Sorted,
+    ReverseSorted,
+    /// Sorted, then about one element in a hundred swapped with a neighbour
+    /// at most 8 places away.
+    NearlySorted,
+    AllEqual,
+    /// An organ pipe of equal pairs: rising to the middle, then falling. It
+    /// defeats sorts that look for runs or assume distinct keys.
+    Adversarial,
+}
+
+impl Distribution {
+    pub const ALL: [Distribution; 7] = [
+        Distribution::Uniform,
+        Distribution::Normal,
+        Distribution::Sorted,
+        Distribution::ReverseSorted,
+        Distribution::NearlySorted,
+        Distribution::AllEqual,
+        Distribution::Adversarial,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Distribution::Uniform => "uniform",
+            Distribution::Normal => "normal",
+            Distribution::Sorted => "sorted",
+            Distribution::ReverseSorted => "reverse-sorted",
+            Distribution::NearlySorted => "nearly-sorted",
+            Distribution::AllEqual => "all-equal",
+            Distribution::Adversarial => "adversarial",
+        }
+    }
+}
+
+impl FromStr for Distribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Distribution::ALL.into_iter().find(|d| d.name() == s).ok_or_else(|| {
+            let names: Vec<&str> = Distribution::ALL.iter().map(|d| d.name()).collect();
+            format!("unknown distribution `{}` (expected {})", s, names.join(", "))
+        })
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Element types values can be generated as.
+pub trait Value: Copy {
+    /// `x` converted with `as`: integers truncate.
+    fn from_f64(x: f64) -> Self;
+}
+
+macro_rules! value {
+    ($($t:ty),*) => {
+        $(impl Value for $t {
+            fn from_f64(x: f64) -> Self {
+                x as $t
+            }
+        })*
+    };
+}
+
+value!(u8, u16, u32, u64, i8, i16, i32, i64, usize, f32, f64);
+
+/// A random number generator that remembers its seed.
+#[derive(Debug, Clone)]
+pub struct DataGen {
+    seed: u64,
+    rng: StdRng,
+}
+
+impl DataGen {
+    pub fn new(seed: u64) -> DataGen {
+        log::info!("data seed {}", seed);
+        DataGen { seed, rng: StdRng::seed_from_u64(seed) }
+    }
+
+    /// Seeded from `SIMPLE_GPU_SEED`, or randomly.
+    pub fn from_env() -> DataGen {
+        DataGen::new(seed_from_env())
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The underlying generator, for draws not covered here.
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    /// Prints the seed and how to rerun with it to stderr.
+    pub fn report_failure(&self) {
+        eprintln!("input seed {}; rerun with {}={} to reproduce", self.seed, SEED_VAR, self.seed);
+    }
+
+    /// `len` values between 0 and `max`, arranged as `dist` says.
+    pub fn values<T: Value>(&mut self, dist: Distribution, len: usize, max: f64) -> Vec<T> {
+        let values = match dist {
+            Distribution::Uniform => (0..len).map(|_| self.rng.r#gen::<f64>() * max).collect(),
+            Distribution::Normal => (0..len).map(|_| (max / 2.0 + self.normal() * max / 8.0).clamp(0.0, max)).collect(),
+            Distribution::Sorted | Distribution::ReverseSorted | Distribution::NearlySorted => {
+                let mut values: Vec<f64> = (0..len).map(|_| self.rng.r#gen::<f64>() * max).collect();
+                values.sort_by(f64::total_cmp);
+                if dist == Distribution::ReverseSorted {
+                    values.reverse();
+                }
+                if dist == Distribution::NearlySorted && len > 1 {
+                    for _ in 0..len.div_ceil(100) {
+                        let i = self.rng.gen_range(0..len - 1);
+                        let j = self.rng.gen_range(i + 1..len.min(i + 9));
+                        values.swap(i, j);
+                    }
+                }
+                values
+            }
+            Distribution::AllEqual => vec![max / 2.0; len],
+            Distribution::Adversarial => {
+                let steps = len.div_ceil(4).max(1) as f64;
+                (0..len).map(|i| (i.min(len - 1 - i) / 2) as f64 / steps * max).collect()
+            }
+        };
+        values.into_iter().map(T::from_f64).collect()
+    }
+
+    /// A standard normal sample (Box-Muller).
+    fn normal(&mut self) -> f64 {
+        let u: f64 = 1.0 - self.rng.r#gen::<f64>();
+        let v: f64 = self.rng.r#gen();
+        (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+    }
+
+    pub fn shuffle<T>(&mut self, data: &mut [T]) {
+        data.shuffle(&mut self.rng);
+    }
+
+    /// About `len` bytes of lowercase words of 1 to 10 letters separated by
+    /// spaces, with a newline every dozen words or so. One word in eight is
+    /// taken from `planted`, if it is not empty, so searches have matches.
+    pub fn text(&mut self, len: usize, planted: &[&str]) -> String {
+        let mut text = String::with_capacity(len + 10);
+        while text.len() < len {
+            if !text.is_empty() {
+                text.push(if self.rng.gen_ratio(1, 12) { '\n' } else { ' ' });
+            }
+            if !planted.is_empty() && self.rng.gen_ratio(1, 8) {
+                text.push_str(planted[self.rng.gen_range(0..planted.len())]);
+            } else {
+                for _ in 0..self.rng.gen_range(1..=10) {
+                    text.push(self.rng.gen_range('a'..='z'));
+                }
+            }
+        }
+        text
+    }
+
+    /// Uniform noise in every channel; alpha included.
+    pub fn rgba_image(&mut self, width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |_, _| Rgba(self.rng.r#gen()))
+    }
+
+    pub fn gray_image(&mut self, width: u32, height: u32) -> GrayImage {
+        GrayImage::from_fn(width, height, |_, _| Luma([self.rng.r#gen()]))
+    }
+}
+
+impl Drop for DataGen {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.report_failure();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_sorted(values: &[f64]) -> bool {
+        values.windows(2).all(|w| w[0] <= w[1])
+    }
+
+    #[test]
+    fn distributions_have_their_shape() {
+        let mut data = DataGen::new(1);
+        for dist in Distribution::ALL {
+            let values: Vec<f64> = data.values(dist, 1000, 50.0);
+            assert_eq!(values.len(), 1000, "{}", dist);
+            assert!(values.iter().all(|v| (0.0..=50.0).contains(v)), "{} out of range", dist);
+            assert_eq!(dist.name().parse::<Distribution>(), Ok(dist));
+        }
+
+        assert!(is_sorted(&data.values::<f64>(Distribution::Sorted, 1000, 50.0)));
+        let mut reverse: Vec<f64> = data.values(Distribution::ReverseSorted, 1000, 50.0);
+        reverse.reverse();
+        assert!(is_sorted(&reverse));
+        assert_eq!(data.values::<f64>(Distribution::AllEqual, 5, 50.0), [25.0; 5]);
+
+        // Ten swaps of nearby elements leave at most 20 elements out of
+        // place, none by more than 8 positions.
+        let nearly: Vec<f64> = data.values(Distribution::NearlySorted, 1000, 50.0);
+        let mut sorted = nearly.clone();
+        sorted.sort_by(f64::total_cmp);
+        let moved: Vec<usize> = (0..nearly.len()).filter(|&i| nearly[i] != sorted[i]).collect();
+        assert!(!moved.is_empty() && moved.len() <= 20, "{} moved", moved.len());
+        for i in moved {
+            let home = sorted.iter().position(|&v| v == nearly[i]).unwrap();
+            assert!(i.abs_diff(home) <= 8, "element {} is {} places from home", i, i.abs_diff(home));
+        }
+
+        // Equal pairs rising to the middle, then falling.
+        let pipe: Vec<u32> = data.values(Distribution::Adversarial, 8, 8.0);
+        assert_eq!(pipe, [0, 0, 4, 4, 4, 4, 0, 0]);
+        let pipe: Vec<f64> = data.values(Distribution::Adversarial, 101, 100.0);
+        assert!(is_sorted(&pipe[..51]));
+        assert!(pipe.iter().eq(pipe.iter().rev()));
+    }
+
+    #[test]
+    fn normal_values_centre_on_half_the_range() {
+        let values: Vec<f64> = DataGen::new(2).values(Distribution::Normal, 10_000, 80.0);
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let sd = (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64).sqrt();
+        assert!((mean - 40.0).abs() < 0.5, "mean {}", mean);
+        assert!((sd - 10.0).abs() < 0.5, "standard deviation {}", sd);
+    }
+
+    #[test]
+    fn seeds_are_parsed_from_the_variable() {
+        assert_eq!(parse_seed("42"), Some(42));
+        assert_eq!(parse_seed(" 18446744073709551615\n"), Some(u64::MAX));
+        assert_eq!(parse_seed(""), None);
+        assert_eq!(parse_seed("-1"), None);
+        assert_eq!(parse_seed("0x10"), None);
+        assert_eq!(parse_seed("18446744073709551616"), None);
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_data() {
+        let draw = |seed| {
+            let mut data = DataGen::new(seed);
+            let values: Vec<Vec<f32>> = Distribution::ALL.iter().map(|&d| data.values(d, 100, 10.0)).collect();
+            let mut order: Vec<usize> = (0..20).collect();
+            data.shuffle(&mut order);
+            (values, order, data.text(200, &["word"]), data.rgba_image(4, 3))
+        };
+        assert_eq!(draw(7), draw(7));
+        assert_ne!(draw(7), draw(8));
+        assert_eq!(DataGen::new(7).seed(), 7);
+    }
+}
@@ -0,0 +1,431 @@
+//! The crate's error type.
+//!
+//! Every fallible API returns `Error`. OpenCL failures, whether they come
+//! from `ocl`, a raw status code or the mock backend, become `Error::Cl`:
+//! the named status plus whatever was known where it happened, i.e. the API
+//! call, kernel, argument index, program and build log. The modules' own
+//! errors are wrapped unchanged. `hint` suggests a fix for the statuses that
+//! usually have one obvious cause.
+
+use crate::build_options::BuildOptionsError;
+use crate::fft::FftError;
+use crate::graph::GraphError;
+use crate::histogram::HistogramError;
+use crate::interpreter::InterpError;
+use crate::layout::LayoutError;
+use crate::link::LinkError;
+use crate::multi_device::MultiDeviceError;
+use crate::npy::NpyError;
+use crate::partition::PartitionError;
+use crate::sparse::SparseError;
+use crate::template::TemplateError;
+use crate::vector::VectorError;
+use ocl::core::Status;
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Where an OpenCL failure happened, as far as it is known.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// The API entry point, e.g. `clEnqueueNDRangeKernel`.
+    pub call: Option<String>,
+    pub kernel: Option<String>,
+    pub arg: Option<u32>,
+    /// The program or source file being built.
+    pub program: Option<String>,
+    pub log: Option<String>,
+}
+
+impl fmt::Display for ErrorContext {
+    /// The location, e.g. ` in kernel `reduce`, argument 2`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(program) = &self.program {
+            parts.push(format!("program `{}`", program));
+        }
+        if let Some(kernel) = &self.kernel {
+            parts.push(format!("kernel `{}`", kernel));
+        }
+        if let Some(arg) = self.arg {
+            parts.push(format!("argument {}", arg));
+        }
+        if !parts.is_empty() {
+            write!(f, " in {}", parts.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+pub enum Error {
+    /// An OpenCL call failed with `status`.
+    Cl { status: Status, context: Box<ErrorContext> },
+    /// A failure `ocl` reports without a status, such as a kernel argument
+    /// of the wrong type.
+    Ocl { message: String, context: Box<ErrorContext> },
+    Interp(InterpError),
+    Io(std::io::Error),
+    Png(png::DecodingError),
+    BuildOptions(BuildOptionsError),
+    Fft(FftError),
+    Graph(GraphError),
+    Histogram(HistogramError),
+    Layout(LayoutError),
+    Link(LinkError),
+    MultiDevice(MultiDeviceError),
+    Npy(NpyError),
+    Partition(PartitionError),
+    Sparse(SparseError),
+    Template(TemplateError),
+    Vector(VectorError),
+}
+
+/// Every status OpenCL defines, for naming raw codes.
+const STATUSES: &[Status] = &[
+    Status::CL_SUCCESS,
+    Status::CL_DEVICE_NOT_FOUND,
+    Status::CL_DEVICE_NOT_AVAILABLE,
+    Status::CL_COMPILER_NOT_AVAILABLE,
+    Status::CL_MEM_OBJECT_ALLOCATION_FAILURE,
+    Status::CL_OUT_OF_RESOURCES,
+    Status::CL_OUT_OF_HOST_MEMORY,
+    Status::CL_PROFILING_INFO_NOT_AVAILABLE,
+    Status::CL_MEM_COPY_OVERLAP,
+    Status::CL_IMAGE_FORMAT_MISMATCH,
+    Status::CL_IMAGE_FORMAT_NOT_SUPPORTED,
+    Status::CL_BUILD_PROGRAM_FAILURE,
+    Status::CL_MAP_FAILURE,
+    Status::CL_MISALIGNED_SUB_BUFFER_OFFSET,
+    Status::CL_EXEC_STATUS_ERROR_FOR_EVENTS_IN_WAIT_LIST,
+    Status::CL_COMPILE_PROGRAM_FAILURE,
+    Status::CL_LINKER_NOT_AVAILABLE,
+    Status::CL_LINK_PROGRAM_FAILURE,
+    Status::CL_DEVICE_PARTITION_FAILED,
+    Status::CL_KERNEL_ARG_INFO_NOT_AVAILABLE,
+    Status::CL_INVALID_VALUE,
+    Status::CL_INVALID_DEVICE_TYPE,
+    Status::CL_INVALID_PLATFORM,
+    Status::CL_INVALID_DEVICE,
+    Status::CL_INVALID_CONTEXT,
+    Status::CL_INVALID_QUEUE_PROPERTIES,
+    Status::CL_INVALID_COMMAND_QUEUE,
+    Status::CL_INVALID_HOST_PTR,
+    Status::CL_INVALID_MEM_OBJECT,
+    Status::CL_INVALID_IMAGE_FORMAT_DESCRIPTOR,
+    Status::CL_INVALID_IMAGE_SIZE,
+    Status::CL_INVALID_SAMPLER,
+    Status::CL_INVALID_BINARY,
+    Status::CL_INVALID_BUILD_OPTIONS,
+    Status::CL_INVALID_PROGRAM,
+    Status::CL_INVALID_PROGRAM_EXECUTABLE,
+    Status::CL_INVALID_KERNEL_NAME,
+    Status::CL_INVALID_KERNEL_DEFINITION,
+    Status::CL_INVALID_KERNEL,
+    Status::CL_INVALID_ARG_INDEX,
+    Status::CL_INVALID_ARG_VALUE,
+    Status::CL_INVALID_ARG_SIZE,
+    Status::CL_INVALID_KERNEL_ARGS,
+    Status::CL_INVALID_WORK_DIMENSION,
+    Status::CL_INVALID_WORK_GROUP_SIZE,
+    Status::CL_INVALID_WORK_ITEM_SIZE,
+    Status::CL_INVALID_GLOBAL_OFFSET,
+    Status::CL_INVALID_EVENT_WAIT_LIST,
+    Status::CL_INVALID_EVENT,
+    Status::CL_INVALID_OPERATION,
+    Status::CL_INVALID_GL_OBJECT,
+    Status::CL_INVALID_BUFFER_SIZE,
+    Status::CL_INVALID_MIP_LEVEL,
+    Status::CL_INVALID_GLOBAL_WORK_SIZE,
+    Status::CL_INVALID_PROPERTY,
+    Status::CL_INVALID_IMAGE_DESCRIPTOR,
+    Status::CL_INVALID_COMPILER_OPTIONS,
+    Status::CL_INVALID_LINKER_OPTIONS,
+    Status::CL_INVALID_DEVICE_PARTITION_COUNT,
+    Status::CL_INVALID_PIPE_SIZE,
+    Status::CL_INVALID_DEVICE_QUEUE,
+    Status::CL_INVALID_GL_SHAREGROUP_REFERENCE_KHR,
+    Status::CL_PLATFORM_NOT_FOUND_KHR,
+    Status::CL_NV_INVALID_MEM_ACCESS,
+];
+
+/// What usually causes `status`, and what to do about it.
+pub fn hint(status: Status) -> Option<&'static str> {
+    Some(match status {
+        Status::CL_DEVICE_NOT_FOUND => "no device of the requested type; try DeviceType::CPU or ALL, or run with --interp",
+        Status::CL_PLATFORM_NOT_FOUND_KHR => "no OpenCL driver (ICD) is installed; run with --interp to use the interpreter",
+        Status::CL_COMPILER_NOT_AVAILABLE | Status::CL_LINKER_NOT_AVAILABLE => {
+            "this device cannot compile from source; build from a binary instead"
+        }
+        Status::CL_BUILD_PROGRAM_FAILURE | Status::CL_COMPILE_PROGRAM_FAILURE | Status::CL_LINK_PROGRAM_FAILURE => {
+            "the build log above holds the compiler's messages"
+        }
+        Status::CL_INVALID_BUILD_OPTIONS | Status::CL_INVALID_COMPILER_OPTIONS | Status::CL_INVALID_LINKER_OPTIONS => {
+            "assemble options with build_options::BuildOptions, which checks them against the device's OpenCL C version"
+        }
+        Status::CL_INVALID_WORK_GROUP_SIZE => {
+            "the global size must be a multiple of the local size in every dimension, and the local size at most \
+             CL_DEVICE_MAX_WORK_GROUP_SIZE and the kernel's CL_KERNEL_WORK_GROUP_SIZE; pad the global size or pass \
+             no local size to let the driver choose"
+        }
+        Status::CL_INVALID_WORK_ITEM_SIZE => "a local size exceeds CL_DEVICE_MAX_WORK_ITEM_SIZES in that dimension",
+        Status::CL_INVALID_GLOBAL_WORK_SIZE => "a global size is zero or does not fit in the device's size_t",
+        Status::CL_INVALID_WORK_DIMENSION => "work sizes must have 1 to 3 dimensions",
+        Status::CL_INVALID_KERNEL_ARGS => "not every kernel argument has been set before the launch",
+        Status::CL_INVALID_ARG_SIZE | Status::CL_INVALID_ARG_VALUE => {
+            "the host value's type differs from the kernel parameter's, e.g. usize for a uint or a scalar for a buffer"
+        }
+        Status::CL_INVALID_ARG_INDEX => "the kernel has fewer parameters than this index",
+        Status::CL_INVALID_KERNEL_NAME => "the program has no __kernel of this name; check the spelling and that it was built",
+        Status::CL_INVALID_PROGRAM_EXECUTABLE => "the program has not been built successfully for this device",
+        Status::CL_OUT_OF_RESOURCES => {
+            "the kernel needs more registers, local or private memory than the device has at this work-group size; \
+             try a smaller local size. On some drivers it also reports an out-of-bounds access"
+        }
+        Status::CL_MEM_OBJECT_ALLOCATION_FAILURE | Status::CL_INVALID_BUFFER_SIZE => {
+            "buffers must be non-empty and at most CL_DEVICE_MAX_MEM_ALLOC_SIZE bytes"
+        }
+        Status::CL_MISALIGNED_SUB_BUFFER_OFFSET => {
+            "sub-buffer origins must be multiples of CL_DEVICE_MEM_BASE_ADDR_ALIGN; partition::Partitioner rounds them"
+        }
+        Status::CL_INVALID_MEM_OBJECT => "the buffer belongs to another context or has been released",
+        Status::CL_INVALID_COMMAND_QUEUE => "often the aftermath of an earlier kernel that crashed, e.g. by writing out of bounds",
+        Status::CL_EXEC_STATUS_ERROR_FOR_EVENTS_IN_WAIT_LIST => "a command this one waited on failed; look for its error first",
+        Status::CL_IMAGE_FORMAT_NOT_SUPPORTED => "query the context's supported image formats and pick one of them",
+        Status::CL_KERNEL_ARG_INFO_NOT_AVAILABLE => "build the program with -cl-kernel-arg-info",
+        _ => return None,
+    })
+}
+
+/// The entry point named in `ocl`'s message ("Error executing function:
+/// clName("info")"), with its info string.
+fn api_call(message: &str) -> Option<(String, Option<String>)> {
+    let rest = message.split_once("Error executing function: ")?.1;
+    let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+    let info = rest[end..].strip_prefix("(\"").and_then(|info| info.split_once("\")")).map(|(info, _)| info.to_string());
+    Some((rest[..end].to_string(), info))
+}
+
+/// The kernel and argument index in `ocl`'s kernel argument messages
+/// ("(kernel: name, index: 3)", "at index: [3]"); its `KernelError` is not
+/// exported.
+fn kernel_arg(message: &str) -> (Option<String>, Option<u32>) {
+    if let Some((_, rest)) = message.split_once("(kernel: ")
+        && let Some((kernel, rest)) = rest.split_once(", index: ")
+    {
+        let index = rest.split(')').next().and_then(|i| i.trim().parse().ok());
+        return (Some(kernel.to_string()), index);
+    }
+    let index = message.split_once("at index: [").and_then(|(_, rest)| rest.split(']').next()?.parse().ok());
+    (None, index)
+}
+
+/// The build log in `ocl`'s build failure message.
+fn build_log(message: &str) -> Option<String> {
+    let rest = message.split_once("PROGRAM BUILD DEBUG OUTPUT")?.1;
+    let rest = rest.trim_start_matches(|c: char| c == '#' || c.is_whitespace());
+    let end = rest.rfind("\n#").unwrap_or(rest.len());
+    Some(rest[..end].trim().to_string())
+}
+
+impl Error {
+    pub fn cl(status: Status) -> Error {
+        Error::Cl { status, context: Box::default() }
+    }
+
+    /// A failure without a status.
+    pub fn ocl(message: impl Into<String>) -> Error {
+        Error::Ocl { message: message.into(), context: Box::default() }
+    }
+
+    /// The error for a raw status code, e.g. from a callback or `cl-sys`.
+    pub fn from_code(code: i32) -> Error {
+        match STATUSES.iter().find(|&&s| s as i32 == code) {
+            Some(&status) => Error::cl(status),
+            None => Error::ocl(format!("unknown OpenCL status {}", code)),
+        }
+    }
+
+    /// `Ok` for `CL_SUCCESS` and other non-negative codes.
+    pub fn check(code: i32) -> Result<()> {
+        if code >= 0 { Ok(()) } else { Err(Error::from_code(code)) }
+    }
+
+    pub fn status(&self) -> Option<Status> {
+        match self {
+            Error::Cl { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Error::Cl { context, .. } | Error::Ocl { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    pub fn hint(&self) -> Option<&'static str> {
+        self.status().and_then(hint)
+    }
+
+    fn with(mut self, set: impl FnOnce(&mut ErrorContext)) -> Self {
+        if let Error::Cl { context, .. } | Error::Ocl { context, .. } = &mut self {
+            set(context);
+        }
+        self
+    }
+
+    /// Records the API call; the other variants are returned unchanged, as
+    /// by the other context setters.
+    pub fn call(self, call: &str) -> Self {
+        self.with(|c| c.call = Some(call.to_string()))
+    }
+
+    pub fn kernel(self, kernel: &str) -> Self {
+        self.with(|c| c.kernel = Some(kernel.to_string()))
+    }
+
+    pub fn arg(self, index: u32) -> Self {
+        self.with(|c| c.arg = Some(index))
+    }
+
+    pub fn program(self, program: &str) -> Self {
+        self.with(|c| c.program = Some(program.to_string()))
+    }
+
+    pub fn log(self, log: &str) -> Self {
+        self.with(|c| c.log = Some(log.to_string()))
+    }
+}
+
+/// Context setters on results, so a call site can say
+/// `kernel.enq().kernel("reduce")?`.
+pub trait ResultExt<T> {
+    fn call(self, call: &str) -> Result<T>;
+    fn kernel(self, kernel: &str) -> Result<T>;
+    fn arg(self, index: u32) -> Result<T>;
+    fn program(self, program: &str) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for std::result::Result<T, E> {
+    fn call(self, call: &str) -> Result<T> {
+        self.map_err(|err| err.into().call(call))
+    }
+
+    fn kernel(self, kernel: &str) -> Result<T> {
+        self.map_err(|err| err.into().kernel(kernel))
+    }
+
+    fn arg(self, index: u32) -> Result<T> {
+        self.map_err(|err| err.into().arg(index))
+    }
+
+    fn program(self, program: &str) -> Result<T> {
+        self.map_err(|err| err.into().program(program))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (message, context) = match self {
+            Error::Cl { status, context } => (format!("{:?} ({})", status, *status as i32), context),
+            Error::Ocl { message, context } => (message.clone(), context),
+            Error::Interp(err) => return write!(f, "interpreter: {}", err),
+            Error::Io(err) => return write!(f, "{}", err),
+            Error::Png(err) => return write!(f, "{}", err),
+            Error::BuildOptions(err) => return write!(f, "{}", err),
+            Error::Fft(err) => return write!(f, "{}", err),
+            Error::Graph(err) => return write!(f, "{}", err),
+            Error::Histogram(err) => return write!(f, "{}", err),
+            Error::Layout(err) => return write!(f, "{}", err),
+            Error::Link(err) => return write!(f, "{}", err),
+            Error::MultiDevice(err) => return write!(f, "{}", err),
+            Error::Npy(err) => return write!(f, "{}", err),
+            Error::Partition(err) => return write!(f, "{}", err),
+            Error::Sparse(err) => return write!(f, "{}", err),
+            Error::Template(err) => return write!(f, "{}", err),
+            Error::Vector(err) => return write!(f, "{}", err),
+        };
+        match &context.call {
+            Some(call) => write!(f, "{} failed: {}{}", call, message, context)?,
+            None => write!(f, "{}{}", message, context)?,
+        }
+        if let Some(log) = context.log.as_deref().map(str::trim).filter(|l| !l.is_empty()) {
+            write!(f, "\nbuild log:\n{}", log)?;
+        }
+        if let Some(hint) = self.hint() {
+            write!(f, "\nhint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Error {
+    /// Same as `Display`, so an error returned from `main` is printed with
+    /// its context, build log and hint.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ocl::Error> for Error {
+    fn from(err: ocl::Error) -> Self {
+        let message = err.to_string();
+        if let Some(status) = err.api_status() {
+            let mut context = Box::<ErrorContext>::default();
+            if let Some((call, info)) = api_call(&message) {
+                // The info string of kernel calls is the kernel's name.
+                if call.contains("Kernel") {
+                    context.kernel = info;
+                }
+                context.call = Some(call);
+            }
+            return Error::Cl { status, context };
+        }
+        if let Some(log) = build_log(&message) {
+            let context = ErrorContext { call: Some("clBuildProgram".into()), log: Some(log), ..ErrorContext::default() };
+            return Error::Cl { status: Status::CL_BUILD_PROGRAM_FAILURE, context: Box::new(context) };
+        }
+        let mut context = Box::<ErrorContext>::default();
+        if let ocl::Error::Kernel(_) = &err {
+            (context.kernel, context.arg) = kernel_arg(&message);
+        }
+        Error::Ocl { message: message.trim().to_string(), context }
+    }
+}
+
+impl From<ocl::core::Error> for Error {
+    fn from(err: ocl::core::Error) -> Self {
+        ocl::Error::from(err).into()
+    }
+}
+
+macro_rules! wrap {
+    ($($variant:ident($ty:ty)),* $(,)?) => {
+        $(impl From<$ty> for Error {
+            fn from(err: $ty) -> Self {
+                Error::$variant(err)
+            }
+        })*
+    };
+}
+
+wrap! {
+    Interp(InterpError),
+    Io(std::io::Error),
+    Png(png::DecodingError),
+    BuildOptions(BuildOptionsError),
+    Fft(FftError),
+    Graph(GraphError),
+    Histogram(HistogramError),
+    Layout(LayoutError),
+    Link(LinkError),
+    MultiDevice(MultiDeviceError),
+    Npy(NpyError),
+    Partition(PartitionError),
+    Sparse(SparseError),
+    Template(TemplateError),
+    Vector(VectorError),
+}
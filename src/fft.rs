@@ -0,0 +1,465 @@
+//! Fast Fourier transforms of `Float2` (complex) and `f32` (real) buffers.
+//!
+//! Lengths may have the prime factors 2, 3, 5 and 7. Each transform is a
+//! sequence of Stockham passes of radix 4, 2, 3, 5 or 7, one launch each,
+//! ping-ponging between two scratch buffers so the input is never
+//! overwritten. Two-dimensional transforms run the rows, then the columns.
+//!
+//! Real transforms use the usual packing trick: the `n` reals are read as
+//! `n / 2` complex values, transformed at half length, and untangled into
+//! the `n / 2 + 1` non-redundant bins (the rest are their conjugates). The
+//! inverse does the reverse, so real lengths must be even.
+//!
+//! Forward transforms are unscaled, inverse ones divide by the length, so
+//! a round trip returns the input. Results agree with a double-precision
+//! DFT to within `Fft::error_bound`, relative to the RMS of the spectrum.
+
+use crate::Result;
+use crate::interpreter;
+use crate::logging::span;
+use log::Level;
+use ocl::core::{self, ArgVal};
+use ocl::prm::Float2;
+use ocl::{Buffer, OclPrm, Program, Queue};
+use std::fmt;
+
+const KERNEL_SRC: &str = r#"
+#define MAX_RADIX 7
+#define TWO_PI 6.283185307179586f
+
+float2 cmul(float2 a, float2 b) {
+   return (float2)(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+}
+
+/* e^(i 2 pi sign num / den) */
+float2 unit(float sign, uint num, uint den) {
+   float angle = sign * TWO_PI * (float)num / (float)den;
+   return (float2)(cos(angle), sin(angle));
+}
+
+/* One Stockham pass over `n`-point sequences: work-item (i, row, batch)
+   takes the `radix` inputs i, i + n/radix, ..., twiddles them for the `p`
+   points already combined and writes their DFT `p` apart. Element j of a
+   sequence is at `j * stride`; sequences start `dist` apart within a batch
+   and batches `batch_dist` apart. */
+__kernel void fft_pass(__global const float2 *x, __global float2 *y, uint n, uint radix, uint p,
+                       uint stride, uint dist, uint batch_dist, float sign, float scale) {
+   uint i = get_global_id(0);
+   uint base = get_global_id(1) * dist + get_global_id(2) * batch_dist;
+   uint m = n / radix;
+   uint k = i % p;
+   float2 u[MAX_RADIX];
+   for (uint j = 0; j < radix; j++)
+      u[j] = cmul(x[base + (i + j * m) * stride], unit(sign, j * k, p * radix));
+   uint out = (i - k) * radix + k;
+   for (uint q = 0; q < radix; q++) {
+      float2 sum = (float2)(0.0f, 0.0f);
+      for (uint j = 0; j < radix; j++)
+         sum += cmul(u[j], unit(sign, (j * q) % radix, radix));
+      y[base + (out + q * p) * stride] = sum * scale;
+   }
+}
+
+/* Bins 0..=m of the real FFT of each row, from the half-length FFT `z`
+   of the row's reals read as complex pairs. */
+__kernel void real_forward(__global const float2 *z, __global float2 *x, uint m) {
+   uint k = get_global_id(0);
+   __global const float2 *row = z + get_global_id(1) * m;
+   float2 a = row[k % m];
+   float2 c = row[(m - k) % m];
+   c.y = -c.y;
+   float2 even = 0.5f * (a + c);
+   float2 d = 0.5f * (a - c);
+   float2 odd = (float2)(d.y, -d.x);
+   x[get_global_id(1) * (m + 1) + k] = even + cmul(unit(-1.0f, k, 2 * m), odd);
+}
+
+/* The reverse of `real_forward`: the half-length spectrum whose inverse
+   holds each row's reals as complex pairs. */
+__kernel void real_inverse(__global const float2 *x, __global float2 *z, uint m) {
+   uint k = get_global_id(0);
+   __global const float2 *row = x + get_global_id(1) * (m + 1);
+   float2 a = row[k];
+   float2 c = row[m - k];
+   c.y = -c.y;
+   float2 even = 0.5f * (a + c);
+   float2 odd = cmul(0.5f * (a - c), unit(1.0f, k, 2 * m));
+   z[get_global_id(1) * m + k] = (float2)(even.x - odd.y, even.y + odd.x);
+}
+"#;
+
+const RADICES: [usize; 5] = [4, 2, 3, 5, 7];
+
+#[derive(Debug)]
+pub enum FftError {
+    /// A length with a prime factor above 7, zero, or odd for a real
+    /// transform.
+    Len(usize),
+    /// A buffer of the wrong length; `what` names it.
+    Size { what: &'static str, expected: usize, found: usize },
+}
+
+impl fmt::Display for FftError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FftError::Len(len) => {
+                write!(f, "unsupported FFT length {}: lengths need prime factors of at most 7, real ones must be even", len)
+            }
+            FftError::Size { what, expected, found } => {
+                write!(f, "{} buffer holds {} elements, the transform needs {}", what, found, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FftError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    /// Scaled by one over the length.
+    Inverse,
+}
+
+impl Direction {
+    fn sign(self) -> f32 {
+        match self {
+            Direction::Forward => -1.0,
+            Direction::Inverse => 1.0,
+        }
+    }
+}
+
+/// One launch.
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    Pass { n: usize, radix: usize, p: usize, stride: usize, dist: usize, rows: usize, batch_dist: usize, batch: usize, sign: f32, scale: f32 },
+    RealForward { half: usize, rows: usize },
+    RealInverse { half: usize, rows: usize },
+}
+
+/// A scalar kernel argument.
+#[derive(Debug, Clone, Copy)]
+enum Scalar {
+    Uint(u32),
+    Float(f32),
+}
+
+impl Step {
+    fn kernel(&self) -> &'static str {
+        match self {
+            Step::Pass { .. } => "fft_pass",
+            Step::RealForward { .. } => "real_forward",
+            Step::RealInverse { .. } => "real_inverse",
+        }
+    }
+
+    fn global(&self) -> [usize; 3] {
+        match *self {
+            Step::Pass { n, radix, rows, batch, .. } => [n / radix, rows, batch],
+            Step::RealForward { half, rows } => [half + 1, rows, 1],
+            Step::RealInverse { half, rows } => [half, rows, 1],
+        }
+    }
+
+    /// Elements written.
+    fn output_len(&self) -> usize {
+        let [items, rows, batch] = self.global();
+        match *self {
+            Step::Pass { radix, .. } => items * radix * rows * batch,
+            Step::RealForward { .. } | Step::RealInverse { .. } => items * rows,
+        }
+    }
+
+    fn scalars(&self) -> Vec<Scalar> {
+        let uint = |x: usize| Scalar::Uint(x as u32);
+        match *self {
+            Step::Pass { n, radix, p, stride, dist, batch_dist, sign, scale, .. } => vec![
+                uint(n),
+                uint(radix),
+                uint(p),
+                uint(stride),
+                uint(dist),
+                uint(batch_dist),
+                Scalar::Float(sign),
+                Scalar::Float(scale),
+            ],
+            Step::RealForward { half, .. } | Step::RealInverse { half, .. } => vec![uint(half)],
+        }
+    }
+}
+
+/// The radices of the passes for length `n`; a single copying pass of
+/// radix 1 for `n == 1`.
+fn factor(n: usize) -> Result<Vec<usize>> {
+    if n == 0 {
+        return Err(FftError::Len(n).into());
+    }
+    let mut radices = Vec::new();
+    let mut rest = n;
+    for radix in RADICES {
+        while rest.is_multiple_of(radix) {
+            radices.push(radix);
+            rest /= radix;
+        }
+    }
+    if rest != 1 {
+        return Err(FftError::Len(n).into());
+    }
+    if radices.is_empty() {
+        radices.push(1);
+    }
+    Ok(radices)
+}
+
+/// A transform plan: the shape of one transform and how many to do at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fft {
+    height: usize,
+    width: usize,
+    two_d: bool,
+    batch: usize,
+}
+
+impl Fft {
+    /// One-dimensional transforms of `len` points.
+    pub fn new(len: usize) -> Result<Fft> {
+        factor(len)?;
+        Ok(Fft { height: 1, width: len, two_d: false, batch: 1 })
+    }
+
+    /// Two-dimensional transforms of row-major `height` × `width` arrays.
+    pub fn new_2d(height: usize, width: usize) -> Result<Fft> {
+        factor(height)?;
+        factor(width)?;
+        Ok(Fft { height, width, two_d: true, batch: 1 })
+    }
+
+    /// `batch` transforms stored one after another.
+    pub fn batch(mut self, batch: usize) -> Self {
+        self.batch = batch.max(1);
+        self
+    }
+
+    /// Points per transform.
+    pub fn len(&self) -> usize {
+        self.height * self.width
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bins per transform of real data: `width / 2 + 1` per row.
+    pub fn real_spectrum_len(&self) -> usize {
+        self.height * (self.width / 2 + 1)
+    }
+
+    /// The relative RMS error, against an exact DFT, that results stay
+    /// within: a few ulps per pass, weighted by the pass radix.
+    pub fn error_bound(&self) -> f32 {
+        let radices: usize = [self.width, self.height]
+            .into_iter()
+            .map(|n| factor(n).map(|r| r.iter().sum::<usize>()).unwrap_or(0))
+            .sum();
+        2.0 * f32::EPSILON * (radices + 2) as f32
+    }
+
+    fn real_half(&self) -> Result<usize> {
+        if !self.width.is_multiple_of(2) {
+            return Err(FftError::Len(self.width).into());
+        }
+        factor(self.width / 2)?;
+        Ok(self.width / 2)
+    }
+
+    /// Passes over `n`-point sequences laid out as described at `fft_pass`.
+    fn passes(
+        steps: &mut Vec<Step>,
+        n: usize,
+        (stride, dist, rows): (usize, usize, usize),
+        (batch_dist, batch): (usize, usize),
+        direction: Direction,
+    ) -> Result<()> {
+        let radices = factor(n)?;
+        let mut p = 1;
+        for (i, &radix) in radices.iter().enumerate() {
+            let last = i + 1 == radices.len();
+            let scale = if last && direction == Direction::Inverse { 1.0 / n as f32 } else { 1.0 };
+            steps.push(Step::Pass { n, radix, p, stride, dist, rows, batch_dist, batch, sign: direction.sign(), scale });
+            p *= radix;
+        }
+        Ok(())
+    }
+
+    fn complex_steps(&self, direction: Direction) -> Result<Vec<Step>> {
+        let (h, w) = (self.height, self.width);
+        let mut steps = Vec::new();
+        Fft::passes(&mut steps, w, (1, w, h * self.batch), (0, 1), direction)?;
+        if self.two_d {
+            Fft::passes(&mut steps, h, (w, 1, w), (h * w, self.batch), direction)?;
+        }
+        Ok(steps)
+    }
+
+    fn real_forward_steps(&self) -> Result<Vec<Step>> {
+        let (h, half) = (self.height, self.real_half()?);
+        let rows = h * self.batch;
+        let mut steps = Vec::new();
+        Fft::passes(&mut steps, half, (1, half, rows), (0, 1), Direction::Forward)?;
+        steps.push(Step::RealForward { half, rows });
+        if self.two_d {
+            Fft::passes(&mut steps, h, (half + 1, 1, half + 1), (h * (half + 1), self.batch), Direction::Forward)?;
+        }
+        Ok(steps)
+    }
+
+    fn real_inverse_steps(&self) -> Result<Vec<Step>> {
+        let (h, half) = (self.height, self.real_half()?);
+        let rows = h * self.batch;
+        let mut steps = Vec::new();
+        if self.two_d {
+            Fft::passes(&mut steps, h, (half + 1, 1, half + 1), (h * (half + 1), self.batch), Direction::Inverse)?;
+        }
+        steps.push(Step::RealInverse { half, rows });
+        Fft::passes(&mut steps, half, (1, half, rows), (0, 1), Direction::Inverse)?;
+        Ok(steps)
+    }
+
+    fn check_len(what: &'static str, expected: usize, found: usize) -> Result<()> {
+        if expected != found {
+            return Err(FftError::Size { what, expected, found }.into());
+        }
+        Ok(())
+    }
+
+    /// Complex-to-complex transform of `input` into `output`, on the
+    /// buffers' device. The program is built on every call.
+    pub fn run(&self, queue: &Queue, direction: Direction, input: &Buffer<Float2>, output: &Buffer<Float2>) -> Result<()> {
+        let n = self.len() * self.batch;
+        Fft::check_len("input", n, input.len())?;
+        Fft::check_len("output", n, output.len())?;
+        run_device(queue, &self.complex_steps(direction)?, input, output)
+    }
+
+    /// Real-to-complex transform: `len()` reals per transform in,
+    /// `real_spectrum_len()` bins out.
+    pub fn forward_real(&self, queue: &Queue, input: &Buffer<f32>, output: &Buffer<Float2>) -> Result<()> {
+        let steps = self.real_forward_steps()?;
+        Fft::check_len("input", self.len() * self.batch, input.len())?;
+        Fft::check_len("output", self.real_spectrum_len() * self.batch, output.len())?;
+        run_device(queue, &steps, input, output)
+    }
+
+    /// Complex-to-real transform, the inverse of `forward_real`. Only the
+    /// bins `forward_real` produces are read.
+    pub fn inverse_real(&self, queue: &Queue, input: &Buffer<Float2>, output: &Buffer<f32>) -> Result<()> {
+        let steps = self.real_inverse_steps()?;
+        Fft::check_len("input", self.real_spectrum_len() * self.batch, input.len())?;
+        Fft::check_len("output", self.len() * self.batch, output.len())?;
+        run_device(queue, &steps, input, output)
+    }
+
+    /// `run` on the interpreter.
+    pub fn run_interpreted(&self, direction: Direction, input: &[Float2]) -> Result<Vec<Float2>> {
+        let n = self.len() * self.batch;
+        Fft::check_len("input", n, input.len())?;
+        run_interpreted(&self.complex_steps(direction)?, input, n)
+    }
+
+    /// `forward_real` on the interpreter.
+    pub fn forward_real_interpreted(&self, input: &[f32]) -> Result<Vec<Float2>> {
+        let steps = self.real_forward_steps()?;
+        Fft::check_len("input", self.len() * self.batch, input.len())?;
+        run_interpreted(&steps, input, self.real_spectrum_len() * self.batch)
+    }
+
+    /// `inverse_real` on the interpreter.
+    pub fn inverse_real_interpreted(&self, input: &[Float2]) -> Result<Vec<f32>> {
+        let steps = self.real_inverse_steps()?;
+        Fft::check_len("input", self.real_spectrum_len() * self.batch, input.len())?;
+        run_interpreted(&steps, input, self.len() * self.batch)
+    }
+}
+
+/// Which buffer each step reads and writes: the input first, the output
+/// last, alternating scratch buffers 2 and 3 in between.
+fn slots(count: usize) -> impl Iterator<Item = (usize, usize)> {
+    (0..count).map(move |i| {
+        let src = if i == 0 { 0 } else { 2 + (i - 1) % 2 };
+        let dst = if i + 1 == count { 1 } else { 2 + i % 2 };
+        (src, dst)
+    })
+}
+
+fn scratch_len(steps: &[Step]) -> usize {
+    steps.iter().map(Step::output_len).max().unwrap_or(0)
+}
+
+fn run_device<I: OclPrm, O: OclPrm>(queue: &Queue, steps: &[Step], input: &Buffer<I>, output: &Buffer<O>) -> Result<()> {
+    let span = span!(Level::Debug, "FFT of {} steps on {:?}", steps.len(), queue.device());
+    span.finish(enqueue_steps(queue, steps, input, output))
+}
+
+/// Builds the kernels and runs `steps` through the scratch buffers,
+/// waiting for the last.
+fn enqueue_steps<I: OclPrm, O: OclPrm>(queue: &Queue, steps: &[Step], input: &Buffer<I>, output: &Buffer<O>) -> Result<()> {
+    let program = Program::builder().src(KERNEL_SRC).devices(queue.device()).build(&queue.context())?;
+    let scratch = || Buffer::<Float2>::builder().queue(queue.clone()).len(scratch_len(steps).max(1)).build();
+    let (a, b) = (scratch()?, scratch()?);
+    let mems = [input.as_core(), output.as_core(), a.as_core(), b.as_core()];
+    for (step, (src, dst)) in steps.iter().zip(slots(steps.len())) {
+        let kernel = core::create_kernel(program.as_core(), step.kernel())?;
+        core::set_kernel_arg(&kernel, 0, ArgVal::mem(mems[src]))?;
+        core::set_kernel_arg(&kernel, 1, ArgVal::mem(mems[dst]))?;
+        for (i, scalar) in step.scalars().iter().enumerate() {
+            let value = match scalar {
+                Scalar::Uint(x) => ArgVal::scalar(x),
+                Scalar::Float(x) => ArgVal::scalar(x),
+            };
+            core::set_kernel_arg(&kernel, 2 + i as u32, value)?;
+        }
+        log::trace!("enqueue {} with global size {:?}", step.kernel(), step.global());
+        // SAFETY: every argument is set and the buffers outlive `finish`.
+        unsafe {
+            core::enqueue_kernel(queue, &kernel, 3, None, &step.global(), None, None::<core::Event>, None::<&mut core::Event>)?;
+        }
+    }
+    core::finish(queue)?;
+    Ok(())
+}
+
+fn run_interpreted<I: OclPrm, O: OclPrm>(steps: &[Step], input: &[I], output_len: usize) -> Result<Vec<O>> {
+    let program = interpreter::Program::build(KERNEL_SRC)?;
+    let scratch = scratch_len(steps);
+    let mut buffers = [
+        interpreter::Buffer::from_slice(input),
+        interpreter::Buffer::new::<O>(output_len),
+        interpreter::Buffer::new::<Float2>(scratch),
+        interpreter::Buffer::new::<Float2>(scratch),
+    ];
+    for (step, (src, dst)) in steps.iter().zip(slots(steps.len())) {
+        let (src, dst) = pair_mut(&mut buffers, src, dst);
+        let mut launch = program.kernel(step.kernel())?.arg_buf(src).arg_buf(dst);
+        for scalar in step.scalars() {
+            launch = match scalar {
+                Scalar::Uint(x) => launch.arg(x),
+                Scalar::Float(x) => launch.arg(x),
+            };
+        }
+        launch.global_work_size(step.global()).run()?;
+    }
+    Ok(buffers[1].to_vec())
+}
+
+/// Mutable borrows of two different elements.
+fn pair_mut<T>(items: &mut [T], a: usize, b: usize) -> (&mut T, &mut T) {
+    assert_ne!(a, b, "pair_mut needs two different elements");
+    if a < b {
+        let (left, right) = items.split_at_mut(b);
+        (&mut left[a], &mut right[0])
+    } else {
+        let (left, right) = items.split_at_mut(a);
+        (&mut right[0], &mut left[b])
+    }
+}
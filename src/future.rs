@@ -0,0 +1,308 @@
+//! `std::future::Future`s for enqueued commands, resolved through
+//! `clSetEventCallback` so they work with any executor.
+
+use crate::{Error, Result};
+use ocl::builders::KernelCmd;
+use ocl::ffi::{self, cl_event, cl_int};
+use ocl::{Buffer, Event, OclPrm};
+use std::ffi::c_void;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+type Handler = Box<dyn FnOnce(cl_int) + Send>;
+
+#[derive(Default)]
+struct State {
+    status: Option<cl_int>,
+    waker: Option<Waker>,
+    handlers: Vec<Handler>,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn complete(&self, status: cl_int) {
+        let (waker, handlers) = {
+            let mut state = self.lock();
+            state.status = Some(status);
+            (state.waker.take(), std::mem::take(&mut state.handlers))
+        };
+        for handler in handlers {
+            handler(status);
+        }
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+extern "C" fn event_callback(_event: cl_event, status: cl_int, user_data: *mut c_void) {
+    let shared = unsafe { Arc::from_raw(user_data as *const Shared) };
+    shared.complete(status);
+}
+
+/// The command's execution status; negative when it terminated abnormally.
+fn status_result(status: cl_int) -> Result<()> {
+    Error::check(status).map_err(|err| err.call("command"))
+}
+
+/// Resolves when the command behind an event reaches `CL_COMPLETE`, or fails
+/// if it terminates with an error status.
+pub struct CommandFuture {
+    event: Event,
+    shared: Arc<Shared>,
+}
+
+impl CommandFuture {
+    /// Registers a completion callback on `event` and flushes the event's
+    /// queue, as callbacks only fire once their command has been submitted.
+    pub fn new(event: Event) -> Result<CommandFuture> {
+        if event.is_empty() {
+            return Err(Error::ocl("CommandFuture::new: event is empty; was it passed to `enew`?"));
+        }
+        let shared = Arc::new(Shared::default());
+        let user_data = Arc::into_raw(shared.clone()) as *mut c_void;
+        let ptr = unsafe { *event.as_core().as_ptr_ref() };
+        let status = unsafe { ffi::clSetEventCallback(ptr, ffi::CL_COMPLETE, Some(event_callback), user_data) };
+        if status != ffi::CL_SUCCESS {
+            // The callback will never run, so reclaim its reference.
+            unsafe { drop(Arc::from_raw(user_data as *const Shared)) };
+            return Err(Error::from_code(status).call("clSetEventCallback"));
+        }
+        flush_queue_of(ptr)?;
+        Ok(CommandFuture { event, shared })
+    }
+
+    pub fn event(&self) -> &Event {
+        &self.event
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.shared.lock().status.is_some()
+    }
+
+    /// Runs `f` on successful completion, possibly on a driver thread.
+    pub fn on_complete(self, f: impl FnOnce() + Send + 'static) -> Self {
+        self.on_status(move |status| {
+            if status >= 0 {
+                f()
+            }
+        })
+    }
+
+    /// Runs `f` with the error status if the command terminates abnormally.
+    pub fn on_error(self, f: impl FnOnce(i32) + Send + 'static) -> Self {
+        self.on_status(move |status| {
+            if status < 0 {
+                f(status)
+            }
+        })
+    }
+
+    fn on_status(self, f: impl FnOnce(cl_int) + Send + 'static) -> Self {
+        let mut state = self.shared.lock();
+        match state.status {
+            Some(status) => {
+                drop(state);
+                f(status);
+            }
+            None => {
+                state.handlers.push(Box::new(f));
+                drop(state);
+            }
+        }
+        self
+    }
+
+    /// Blocks until the command has finished, regardless of the callback.
+    pub fn wait(&self) -> Result<()> {
+        Ok(self.event.wait_for()?)
+    }
+}
+
+impl Future for CommandFuture {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.lock();
+        match state.status {
+            Some(status) => Poll::Ready(status_result(status)),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// A command future that owns host memory the device is reading or writing.
+/// The data is handed back once the command has completed. Dropping it early
+/// blocks until the device is done with the memory.
+pub struct DataFuture<D> {
+    data: Option<D>,
+    command: CommandFuture,
+}
+
+impl<D> DataFuture<D> {
+    pub fn command(&self) -> &CommandFuture {
+        &self.command
+    }
+}
+
+impl<D: Unpin> Future for DataFuture<D> {
+    type Output = Result<D>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.command).poll(cx) {
+            Poll::Ready(result) => {
+                let data = self.data.take().expect("DataFuture polled after completion");
+                Poll::Ready(result.map(|()| data))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<D> Drop for DataFuture<D> {
+    fn drop(&mut self) {
+        if self.data.is_some() && !self.command.is_complete() {
+            self.command.wait().ok();
+        }
+    }
+}
+
+/// Flushes the queue `event` was enqueued on; user events have none.
+fn flush_queue_of(event: cl_event) -> Result<()> {
+    let mut queue: ffi::cl_command_queue = std::ptr::null_mut();
+    let status = unsafe {
+        ffi::clGetEventInfo(
+            event,
+            ffi::CL_EVENT_COMMAND_QUEUE,
+            std::mem::size_of::<ffi::cl_command_queue>(),
+            (&mut queue as *mut ffi::cl_command_queue).cast(),
+            std::ptr::null_mut(),
+        )
+    };
+    Error::check(status).map_err(|err| err.call("clGetEventInfo"))?;
+    if queue.is_null() {
+        return Ok(());
+    }
+    Error::check(unsafe { ffi::clFlush(queue) }).map_err(|err| err.call("clFlush"))
+}
+
+/// A future for a transfer into or out of `data`; if it cannot be created,
+/// waits for the transfer so `data` is not dropped while in use.
+fn data_future<T>(event: Event, data: Vec<T>) -> Result<DataFuture<Vec<T>>> {
+    match CommandFuture::new(event.clone()) {
+        Ok(command) => Ok(DataFuture { data: Some(data), command }),
+        Err(err) => {
+            event.wait_for().ok();
+            Err(err)
+        }
+    }
+}
+
+/// Enqueues a non-blocking read of the whole buffer into `data`.
+pub fn read<T: OclPrm>(buffer: &Buffer<T>, mut data: Vec<T>) -> Result<DataFuture<Vec<T>>> {
+    let mut event = Event::empty();
+    // The heap allocation behind `data` does not move when the vec is moved
+    // into the future, which keeps it alive until the read completes.
+    unsafe { buffer.read(&mut data[..]).block(false).enew(&mut event).enq()? };
+    log::trace!("read {} bytes from {:?}", std::mem::size_of_val(&data[..]), buffer.as_core().as_ptr());
+    data_future(event, data)
+}
+
+/// Enqueues a non-blocking write of `data` to the start of the buffer.
+pub fn write<T: OclPrm>(buffer: &Buffer<T>, data: Vec<T>) -> Result<DataFuture<Vec<T>>> {
+    let mut event = Event::empty();
+    unsafe { buffer.write(&data[..]).block(false).enew(&mut event).enq()? };
+    log::trace!("write {} bytes to {:?}", std::mem::size_of_val(&data[..]), buffer.as_core().as_ptr());
+    data_future(event, data)
+}
+
+/// Future-returning enqueue for kernel commands.
+pub trait KernelCmdExt {
+    /// # Safety
+    ///
+    /// Same contract as `KernelCmd::enq`: kernel code is untrusted.
+    unsafe fn enq_future(self) -> Result<CommandFuture>;
+}
+
+impl KernelCmdExt for KernelCmd<'_> {
+    unsafe fn enq_future(self) -> Result<CommandFuture> {
+        let mut event = Event::empty();
+        unsafe { self.enew(&mut event).enq()? };
+        CommandFuture::new(event)
+    }
+}
+
+/// Resolves once every future has, yielding their outputs in order.
+pub struct JoinAll<F: Future> {
+    pending: Vec<Option<F>>,
+    outputs: Vec<Option<F::Output>>,
+}
+
+pub fn join_all<F: Future + Unpin>(futures: impl IntoIterator<Item = F>) -> JoinAll<F> {
+    let pending: Vec<Option<F>> = futures.into_iter().map(Some).collect();
+    let outputs = pending.iter().map(|_| None).collect();
+    JoinAll { pending, outputs }
+}
+
+impl<F: Future + Unpin> Future for JoinAll<F>
+where
+    F::Output: Unpin,
+{
+    type Output = Vec<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let mut done = true;
+        for (slot, out) in this.pending.iter_mut().zip(this.outputs.iter_mut()) {
+            if let Some(fut) = slot {
+                match Pin::new(fut).poll(cx) {
+                    Poll::Ready(v) => {
+                        *out = Some(v);
+                        *slot = None;
+                    }
+                    Poll::Pending => done = false,
+                }
+            }
+        }
+        if done {
+            Poll::Ready(this.outputs.iter_mut().map(|o| o.take().expect("JoinAll polled after completion")).collect())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Minimal executor for host code that has no async runtime of its own.
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = std::pin::pin!(fut);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(v) => return v,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
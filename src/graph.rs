@@ -0,0 +1,444 @@
+//! Explicit command dependency graphs.
+//!
+//! Nodes are kernels, transfers and user-event gates. Edges become event wait
+//! lists when the graph is enqueued, so one declaration can be replayed with
+//! `run` as often as needed.
+
+use crate::Result;
+use ocl::ffi;
+use ocl::{Buffer, Event, EventList, Kernel, OclPrm, Queue, SpatialDims};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+#[derive(Debug)]
+pub enum GraphError {
+    UnknownNode(NodeId),
+    /// Names of the nodes that take part in (or wait on) a cycle.
+    Cycle(Vec<String>),
+    NotAGate(String),
+    UntriggeredGates(Vec<String>),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::UnknownNode(id) => write!(f, "node {} does not belong to this graph", id.0),
+            GraphError::Cycle(names) => write!(f, "command graph has a cycle through: {}", names.join(", ")),
+            GraphError::NotAGate(name) => write!(f, "node '{}' is not a gate", name),
+            GraphError::UntriggeredGates(names) => {
+                write!(f, "waiting on a run whose gates were never triggered: {}", names.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+/// A command a graph node enqueues.
+pub trait Command: Any {
+    fn enqueue(&mut self, queue: &Queue, ewait: &EventList, enew: &mut Event) -> Result<()>;
+
+    /// Short description used in DOT output.
+    fn describe(&self) -> String;
+}
+
+struct KernelCommand {
+    kernel: Kernel,
+    gws: Option<SpatialDims>,
+    lws: Option<SpatialDims>,
+}
+
+impl Command for KernelCommand {
+    fn enqueue(&mut self, queue: &Queue, ewait: &EventList, enew: &mut Event) -> Result<()> {
+        let mut cmd = self.kernel.cmd().queue(queue).ewait(ewait).enew(enew);
+        if let Some(gws) = self.gws {
+            cmd = cmd.global_work_size(gws);
+        }
+        if let Some(lws) = self.lws {
+            cmd = cmd.local_work_size(lws);
+        }
+        unsafe { Ok(cmd.enq()?) }
+    }
+
+    fn describe(&self) -> String {
+        let name = self.kernel.name().unwrap_or_else(|_| "?".into());
+        match self.gws {
+            Some(gws) => format!("kernel {} {:?}", name, gws.to_lens().unwrap_or([0; 3])),
+            None => format!("kernel {}", name),
+        }
+    }
+}
+
+struct WriteCommand<T: OclPrm> {
+    buffer: Buffer<T>,
+    data: Vec<T>,
+}
+
+impl<T: OclPrm> Command for WriteCommand<T> {
+    fn enqueue(&mut self, queue: &Queue, ewait: &EventList, enew: &mut Event) -> Result<()> {
+        // `data` stays owned by the graph, which a run borrows mutably until
+        // it has completed.
+        unsafe { Ok(self.buffer.write(&self.data[..]).queue(queue).block(false).ewait(ewait).enew(enew).enq()?) }
+    }
+
+    fn describe(&self) -> String {
+        format!("write {} x {}", self.data.len(), std::any::type_name::<T>())
+    }
+}
+
+struct ReadCommand<T: OclPrm> {
+    buffer: Buffer<T>,
+    data: Vec<T>,
+}
+
+impl<T: OclPrm> Command for ReadCommand<T> {
+    fn enqueue(&mut self, queue: &Queue, ewait: &EventList, enew: &mut Event) -> Result<()> {
+        unsafe { Ok(self.buffer.read(&mut self.data[..]).queue(queue).block(false).ewait(ewait).enew(enew).enq()?) }
+    }
+
+    fn describe(&self) -> String {
+        format!("read {} x {}", self.data.len(), std::any::type_name::<T>())
+    }
+}
+
+struct CopyCommand<T: OclPrm> {
+    src: Buffer<T>,
+    dst: Buffer<T>,
+}
+
+impl<T: OclPrm> Command for CopyCommand<T> {
+    fn enqueue(&mut self, queue: &Queue, ewait: &EventList, enew: &mut Event) -> Result<()> {
+        Ok(self.src.copy(&self.dst, None, None).queue(queue).ewait(ewait).enew(enew).enq()?)
+    }
+
+    fn describe(&self) -> String {
+        format!("copy {} x {}", self.src.len(), std::any::type_name::<T>())
+    }
+}
+
+enum Op {
+    Command(Box<dyn Command>),
+    Gate,
+}
+
+struct Node {
+    name: String,
+    op: Op,
+    deps: Vec<usize>,
+}
+
+#[derive(Default)]
+pub struct CommandGraph {
+    nodes: Vec<Node>,
+}
+
+impl CommandGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&mut self, name: &str, op: Op) -> NodeId {
+        self.nodes.push(Node { name: name.to_string(), op, deps: Vec::new() });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Adds a kernel launched with the work sizes it was built with.
+    pub fn kernel(&mut self, name: &str, kernel: Kernel) -> NodeId {
+        self.push(name, Op::Command(Box::new(KernelCommand { kernel, gws: None, lws: None })))
+    }
+
+    pub fn kernel_sized<G: Into<SpatialDims>>(&mut self, name: &str, kernel: Kernel, gws: G, lws: Option<SpatialDims>) -> NodeId {
+        self.push(name, Op::Command(Box::new(KernelCommand { kernel, gws: Some(gws.into()), lws })))
+    }
+
+    /// Writes `data` (owned by the graph, see `write_data_mut`) to `buffer`.
+    pub fn write<T: OclPrm>(&mut self, name: &str, buffer: &Buffer<T>, data: Vec<T>) -> NodeId {
+        self.push(name, Op::Command(Box::new(WriteCommand { buffer: buffer.clone(), data })))
+    }
+
+    /// Reads the whole of `buffer`; the result is available from `read_data`
+    /// once a run has completed.
+    pub fn read<T: OclPrm>(&mut self, name: &str, buffer: &Buffer<T>) -> NodeId {
+        let data = vec![T::default(); buffer.len()];
+        self.push(name, Op::Command(Box::new(ReadCommand { buffer: buffer.clone(), data })))
+    }
+
+    pub fn copy<T: OclPrm>(&mut self, name: &str, src: &Buffer<T>, dst: &Buffer<T>) -> NodeId {
+        self.push(name, Op::Command(Box::new(CopyCommand { src: src.clone(), dst: dst.clone() })))
+    }
+
+    pub fn command(&mut self, name: &str, command: impl Command) -> NodeId {
+        self.push(name, Op::Command(Box::new(command)))
+    }
+
+    /// Adds a user event that holds back everything depending on it until
+    /// `GraphRun::trigger` is called.
+    pub fn gate(&mut self, name: &str) -> NodeId {
+        self.push(name, Op::Gate)
+    }
+
+    /// Makes `node` wait for every node in `deps`.
+    pub fn after(&mut self, node: NodeId, deps: &[NodeId]) -> Result<()> {
+        self.check(node)?;
+        for &dep in deps {
+            self.check(dep)?;
+            if !self.nodes[node.0].deps.contains(&dep.0) {
+                self.nodes[node.0].deps.push(dep.0);
+            }
+        }
+        Ok(())
+    }
+
+    /// Chains nodes so each waits for the previous one.
+    pub fn chain(&mut self, nodes: &[NodeId]) -> Result<()> {
+        for pair in nodes.windows(2) {
+            self.after(pair[1], &[pair[0]])?;
+        }
+        Ok(())
+    }
+
+    fn check(&self, id: NodeId) -> Result<()> {
+        if id.0 < self.nodes.len() { Ok(()) } else { Err(GraphError::UnknownNode(id).into()) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn name(&self, id: NodeId) -> Option<&str> {
+        self.nodes.get(id.0).map(|n| n.name.as_str())
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.nodes.iter().position(|n| n.name == name).map(NodeId)
+    }
+
+    /// The kernel behind a kernel node, e.g. to change scalar arguments
+    /// between runs.
+    pub fn kernel_of(&self, id: NodeId) -> Option<&Kernel> {
+        match &self.nodes.get(id.0)?.op {
+            Op::Command(cmd) => {
+                let any: &dyn Any = &**cmd;
+                any.downcast_ref::<KernelCommand>().map(|k| &k.kernel)
+            }
+            Op::Gate => None,
+        }
+    }
+
+    /// Data produced by a read node during the last completed run.
+    pub fn read_data<T: OclPrm>(&self, id: NodeId) -> Option<&[T]> {
+        match &self.nodes.get(id.0)?.op {
+            Op::Command(cmd) => {
+                let any: &dyn Any = &**cmd;
+                any.downcast_ref::<ReadCommand<T>>().map(|r| &r.data[..])
+            }
+            Op::Gate => None,
+        }
+    }
+
+    /// Host data uploaded by a write node, for changing it between runs.
+    pub fn write_data_mut<T: OclPrm>(&mut self, id: NodeId) -> Option<&mut Vec<T>> {
+        match &mut self.nodes.get_mut(id.0)?.op {
+            Op::Command(cmd) => {
+                let any: &mut dyn Any = &mut **cmd;
+                any.downcast_mut::<WriteCommand<T>>().map(|w| &mut w.data)
+            }
+            Op::Gate => None,
+        }
+    }
+
+    /// Topological order of the nodes, or the nodes left over by a cycle.
+    pub fn validate(&self) -> Result<Vec<NodeId>> {
+        let n = self.nodes.len();
+        let mut indegree: Vec<usize> = self.nodes.iter().map(|node| node.deps.len()).collect();
+        let mut dependents = vec![Vec::new(); n];
+        for (i, node) in self.nodes.iter().enumerate() {
+            for &d in &node.deps {
+                dependents[d].push(i);
+            }
+        }
+        let mut ready: VecDeque<usize> = (0..n).filter(|&i| indegree[i] == 0).collect();
+        let mut order = Vec::with_capacity(n);
+        while let Some(i) = ready.pop_front() {
+            order.push(NodeId(i));
+            for &j in &dependents[i] {
+                indegree[j] -= 1;
+                if indegree[j] == 0 {
+                    ready.push_back(j);
+                }
+            }
+        }
+        if order.len() < n {
+            let stuck = (0..n).filter(|&i| indegree[i] > 0).map(|i| self.nodes[i].name.clone()).collect();
+            return Err(GraphError::Cycle(stuck).into());
+        }
+        Ok(order)
+    }
+
+    /// Enqueues every node on `queue` in dependency order. Gates start
+    /// untriggered; the returned run borrows the graph until it is dropped.
+    pub fn run(&mut self, queue: &Queue) -> Result<GraphRun<'_>> {
+        let order = self.validate()?;
+        log::debug!("run graph of {} nodes on queue {:?}", order.len(), queue.as_ptr());
+        let mut events: Vec<Option<Event>> = (0..self.nodes.len()).map(|_| None).collect();
+        let mut gates = Vec::new();
+        if let Err(err) = self.enqueue(queue, order, &mut events, &mut gates) {
+            // Commands already enqueued may read or write host data owned by
+            // the graph: fail the gates so nothing waits on them forever,
+            // then drain what was enqueued.
+            for (_, event) in &gates {
+                abandon(event);
+            }
+            queue.flush().ok();
+            for event in events.iter().flatten() {
+                event.wait_for().ok();
+            }
+            return Err(err);
+        }
+        Ok(GraphRun { graph: self, events, gates })
+    }
+
+    fn enqueue(
+        &mut self,
+        queue: &Queue,
+        order: Vec<NodeId>,
+        events: &mut [Option<Event>],
+        gates: &mut Vec<(usize, Event)>,
+    ) -> Result<()> {
+        let context = queue.context();
+        for id in order {
+            let node = &mut self.nodes[id.0];
+            let event = match &mut node.op {
+                Op::Gate => {
+                    let event = Event::user(&context)?;
+                    gates.push((id.0, event.clone()));
+                    event
+                }
+                Op::Command(cmd) => {
+                    let mut ewait = EventList::new();
+                    for &d in &node.deps {
+                        if let Some(event) = &events[d] {
+                            ewait.push(event.clone());
+                        }
+                    }
+                    let mut event = Event::empty();
+                    log::trace!("node {}: enqueue {} after {} event(s)", id.0, cmd.describe(), ewait.len());
+                    cmd.enqueue(queue, &ewait, &mut event)?;
+                    event
+                }
+            };
+            events[id.0] = Some(event);
+        }
+        queue.flush()?;
+        Ok(())
+    }
+
+    /// Graphviz DOT rendering; edges point from a dependency to its dependent.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph commands {\n    rankdir=LR;\n");
+        for (i, node) in self.nodes.iter().enumerate() {
+            let (shape, detail) = match &node.op {
+                Op::Gate => ("diamond", "user event".to_string()),
+                Op::Command(cmd) => ("box", cmd.describe()),
+            };
+            let _ = writeln!(
+                out,
+                "    n{} [shape={}, label=\"{}\\n{}\"];",
+                i,
+                shape,
+                node.name.replace('"', "\\\""),
+                detail.replace('"', "\\\"")
+            );
+        }
+        for (i, node) in self.nodes.iter().enumerate() {
+            for d in &node.deps {
+                let _ = writeln!(out, "    n{} -> n{};", d, i);
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// An enqueued execution of a graph.
+pub struct GraphRun<'g> {
+    graph: &'g mut CommandGraph,
+    events: Vec<Option<Event>>,
+    gates: Vec<(usize, Event)>,
+}
+
+impl GraphRun<'_> {
+    /// Releases the commands waiting on `gate`.
+    pub fn trigger(&mut self, gate: NodeId) -> Result<()> {
+        self.graph.check(gate)?;
+        match self.gates.iter().find(|(i, _)| *i == gate.0) {
+            Some((_, event)) => {
+                if !event.is_complete()? {
+                    event.set_complete()?;
+                }
+                Ok(())
+            }
+            None => Err(GraphError::NotAGate(self.graph.nodes[gate.0].name.clone()).into()),
+        }
+    }
+
+    pub fn trigger_all(&mut self) -> Result<()> {
+        for (_, event) in &self.gates {
+            if !event.is_complete()? {
+                event.set_complete()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn event(&self, id: NodeId) -> Option<&Event> {
+        self.events.get(id.0)?.as_ref()
+    }
+
+    /// Blocks until every node has completed. Fails instead of deadlocking
+    /// if a gate has not been triggered.
+    pub fn wait(self) -> Result<()> {
+        let mut untriggered = Vec::new();
+        for (i, event) in &self.gates {
+            if !event.is_complete()? {
+                untriggered.push(self.graph.nodes[*i].name.clone());
+            }
+        }
+        if !untriggered.is_empty() {
+            return Err(GraphError::UntriggeredGates(untriggered).into());
+        }
+        for event in self.events.iter().flatten() {
+            event.wait_for()?;
+        }
+        Ok(())
+    }
+}
+
+/// Ends a gate in error, which terminates the commands waiting on it.
+fn abandon(gate: &Event) {
+    if gate.is_complete().unwrap_or(true) {
+        return;
+    }
+    // Any negative status marks a user event as failed.
+    let status = ocl::core::Status::CL_INVALID_EVENT as ffi::cl_int;
+    unsafe { ffi::clSetUserEventStatus(*gate.as_core().as_ptr_ref(), status) };
+}
+
+impl Drop for GraphRun<'_> {
+    fn drop(&mut self) {
+        // Host data owned by the graph must not be touched while commands
+        // are still in flight, so open every gate and drain the run.
+        self.trigger_all().ok();
+        for event in self.events.iter().flatten() {
+            event.wait_for().ok();
+        }
+    }
+}
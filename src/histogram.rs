@@ -0,0 +1,488 @@
+//! Histograms computed with atomics.
+//!
+//! Each work-group counts its share of the data into a private copy of the
+//! histogram in local memory, then adds that copy into the global one, so
+//! most atomics hit fast local memory and only one per bin and group goes
+//! to global memory. When the bins do not fit in local memory, every count
+//! is a global atomic instead.
+//!
+//! Bins are uniform over `[min, max)` or given by their edges; either way
+//! each bin is half-open and values outside all of them are not counted.
+//! Interleaved data (the channels of an image) gets one histogram per
+//! channel. `PngImage` loads the 8- and 16-bit PNGs the image examples use.
+
+use crate::Result;
+use crate::interpreter;
+use crate::logging::span;
+use log::Level;
+use ocl::enums::{DeviceInfo, DeviceInfoResult};
+use ocl::{Buffer, Kernel, OclPrm, Program, Queue};
+use std::fmt;
+use std::marker::PhantomData;
+use std::path::Path;
+
+const KERNEL_SRC: &str = r#"
+/* T: sample type; E: edge type; BINS, CHANNELS; UNIFORM with LO, HI (and
+   SCALE for floats) or edges; INTEGER; PRIVATIZE to count in local memory. */
+
+uint bin_of(T x, __global const E *edges) {
+#ifdef UNIFORM
+#ifdef INTEGER
+   long v = x;
+   if (v < LO || v >= HI) return BINS;
+   return (uint)((v - LO) * BINS / (HI - LO));
+#else
+   if (!(x >= LO && x < HI)) return BINS;
+   return min((uint)((x - LO) * SCALE), (uint)(BINS - 1));
+#endif
+#else
+   E v = x;
+   if (!(v >= edges[0] && v < edges[BINS])) return BINS;
+   uint lo = 0, hi = BINS;
+   while (hi - lo > 1) {
+      uint mid = (lo + hi) / 2;
+      if (v >= edges[mid]) lo = mid; else hi = mid;
+   }
+   return lo;
+#endif
+}
+
+__kernel void histogram(__global const T *data, uint len, __global uint *hist,
+                        __local uint *local_hist, __global const E *edges) {
+#ifdef PRIVATIZE
+   for (uint i = get_local_id(0); i < BINS * CHANNELS; i += get_local_size(0))
+      local_hist[i] = 0;
+   barrier(CLK_LOCAL_MEM_FENCE);
+   for (uint i = get_global_id(0); i < len; i += get_global_size(0)) {
+      uint bin = bin_of(data[i], edges);
+      if (bin < BINS) atomic_inc(&local_hist[(i % CHANNELS) * BINS + bin]);
+   }
+   barrier(CLK_LOCAL_MEM_FENCE);
+   for (uint i = get_local_id(0); i < BINS * CHANNELS; i += get_local_size(0))
+      if (local_hist[i] != 0) atomic_add(&hist[i], local_hist[i]);
+#else
+   for (uint i = get_global_id(0); i < len; i += get_global_size(0)) {
+      uint bin = bin_of(data[i], edges);
+      if (bin < BINS) atomic_inc(&hist[(i % CHANNELS) * BINS + bin]);
+   }
+#endif
+}
+"#;
+
+/// Work-group size used when the device allows it.
+const GROUP_SIZE: usize = 256;
+/// Local memory assumed for the interpreter, which has no limit of its own.
+const INTERPRETER_LOCAL_MEM: usize = 32 * 1024;
+/// Bin positions are computed in `long` as `(x - min) * bins`, so integer
+/// ranges are limited to `i64::MAX / bins`.
+const MAX_BINS: usize = 1 << 24;
+
+#[derive(Debug)]
+pub enum HistogramError {
+    /// Unusable bins, with the reason.
+    Bins(String),
+    /// Data that is not a whole number of pixels, or too long for the
+    /// kernel's `uint` indices.
+    Len { len: usize, channels: usize },
+}
+
+impl fmt::Display for HistogramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistogramError::Bins(why) => write!(f, "bad bins: {}", why),
+            HistogramError::Len { len, channels } => {
+                write!(f, "{} samples cannot be binned as {}-channel data", len, channels)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HistogramError {}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A type that can be binned: 8- to 32-bit integers and `f32`.
+pub trait Sample: OclPrm + sealed::Sealed {
+    const CL_TYPE: &'static str;
+    const INTEGER: bool;
+
+    fn to_i64(self) -> i64;
+    fn to_f32(self) -> f32;
+}
+
+macro_rules! samples {
+    ($($ty:ty: $cl:expr, $integer:expr);+) => {
+        $(
+            impl sealed::Sealed for $ty {}
+            impl Sample for $ty {
+                const CL_TYPE: &'static str = $cl;
+                const INTEGER: bool = $integer;
+
+                fn to_i64(self) -> i64 {
+                    self as i64
+                }
+
+                fn to_f32(self) -> f32 {
+                    self as f32
+                }
+            }
+        )+
+    };
+}
+
+samples!(i8: "char", true; u8: "uchar", true; i16: "short", true; u16: "ushort", true;
+    i32: "int", true; u32: "uint", true; f32: "float", false);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bins {
+    /// `count` bins of equal width over `[min, max)`. Integer data needs
+    /// whole-number bounds.
+    Uniform { count: usize, min: f64, max: f64 },
+    /// Bin `i` is `[edges[i], edges[i + 1])`. Integer data compares against
+    /// the edges rounded up, float data against the edges as `f32`.
+    Edges(Vec<f64>),
+}
+
+impl Bins {
+    pub fn uniform(count: usize, min: f64, max: f64) -> Bins {
+        Bins::Uniform { count, min, max }
+    }
+
+    /// One bin per value of a `bits`-bit image sample, `count` of them:
+    /// 256 bins over 8-bit samples count every level.
+    pub fn levels(bits: u32, count: usize) -> Bins {
+        Bins::Uniform { count, min: 0.0, max: (1u64 << bits) as f64 }
+    }
+
+    pub fn count(&self) -> usize {
+        match self {
+            Bins::Uniform { count, .. } => *count,
+            Bins::Edges(edges) => edges.len().saturating_sub(1),
+        }
+    }
+}
+
+/// The bins as the kernel sees them for a given sample type.
+#[derive(Debug, Clone, PartialEq)]
+enum Rule {
+    IntUniform { lo: i64, hi: i64 },
+    FloatUniform { lo: f32, hi: f32, scale: f32 },
+    IntEdges(Vec<i64>),
+    FloatEdges(Vec<f32>),
+}
+
+/// Counts per bin and channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Counts {
+    pub bins: usize,
+    pub channels: usize,
+    /// Channel-major: the bins of channel 0, then channel 1, ...
+    pub counts: Vec<u32>,
+}
+
+impl Counts {
+    pub fn channel(&self, channel: usize) -> &[u32] {
+        &self.counts[channel * self.bins..(channel + 1) * self.bins]
+    }
+
+    /// Samples counted in `channel`.
+    pub fn total(&self, channel: usize) -> u64 {
+        self.channel(channel).iter().map(|&c| c as u64).sum()
+    }
+}
+
+/// A histogram of `T` samples.
+#[derive(Debug, Clone)]
+pub struct Histogram<T: Sample> {
+    bins: usize,
+    channels: usize,
+    rule: Rule,
+    sample: PhantomData<T>,
+}
+
+impl<T: Sample> Histogram<T> {
+    pub fn new(bins: Bins) -> Result<Histogram<T>> {
+        let bad = |why: &str| Err(HistogramError::Bins(why.into()).into());
+        let count = bins.count();
+        if count == 0 || count > MAX_BINS {
+            return bad(&format!("need between 1 and {} bins, found {}", MAX_BINS, count));
+        }
+        let rule = match bins {
+            Bins::Uniform { min, max, .. } => {
+                if !(min.is_finite() && max.is_finite() && min < max) {
+                    return bad(&format!("[{}, {}) is not a finite, non-empty range", min, max));
+                }
+                if T::INTEGER {
+                    let whole = |x: f64| x.fract() == 0.0 && x.abs() < (1u64 << 62) as f64;
+                    if !(whole(min) && whole(max)) {
+                        return bad(&format!("{} data needs whole-number bounds, found [{}, {})", T::CL_TYPE, min, max));
+                    }
+                    let (lo, hi) = (min as i64, max as i64);
+                    if (hi - lo).checked_mul(count as i64).is_none() {
+                        return bad(&format!("[{}, {}) is too wide to split into {} bins", min, max, count));
+                    }
+                    Rule::IntUniform { lo, hi }
+                } else {
+                    let (lo, hi) = (min as f32, max as f32);
+                    Rule::FloatUniform { lo, hi, scale: count as f32 / (hi - lo) }
+                }
+            }
+            Bins::Edges(edges) => {
+                if edges.iter().any(|e| !e.is_finite()) || edges.windows(2).any(|w| w[0] >= w[1]) {
+                    return bad("edges must be finite and strictly increasing");
+                }
+                if T::INTEGER {
+                    Rule::IntEdges(edges.iter().map(|e| e.ceil() as i64).collect())
+                } else {
+                    Rule::FloatEdges(edges.iter().map(|&e| e as f32).collect())
+                }
+            }
+        };
+        Ok(Histogram { bins: count, channels: 1, rule, sample: PhantomData })
+    }
+
+    /// Treats the data as interleaved samples of `channels` channels, such
+    /// as RGBA pixels, with one histogram each.
+    pub fn channels(mut self, channels: usize) -> Self {
+        self.channels = channels.max(1);
+        self
+    }
+
+    pub fn bin_count(&self) -> usize {
+        self.bins
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels
+    }
+
+    /// The bin of `x`, computed exactly as the kernel does; `None` outside
+    /// all bins.
+    pub fn bin_of(&self, x: T) -> Option<usize> {
+        let bin = match &self.rule {
+            Rule::IntUniform { lo, hi } => {
+                let v = x.to_i64();
+                if v < *lo || v >= *hi {
+                    return None;
+                }
+                ((v - lo) * self.bins as i64 / (hi - lo)) as usize
+            }
+            Rule::FloatUniform { lo, hi, scale } => {
+                let v = x.to_f32();
+                if !(v >= *lo && v < *hi) {
+                    return None;
+                }
+                (((v - lo) * scale) as usize).min(self.bins - 1)
+            }
+            Rule::IntEdges(edges) => search(edges, x.to_i64())?,
+            Rule::FloatEdges(edges) => search(edges, x.to_f32())?,
+        };
+        Some(bin)
+    }
+
+    /// Compiler options selecting the sample type and bins. The kernel
+    /// counts in local memory if `private_bytes` fit in `local_mem`.
+    fn build_options(&self, local_mem: usize) -> String {
+        let mut options = format!("-D T={} -D BINS={} -D CHANNELS={}", T::CL_TYPE, self.bins, self.channels);
+        let edge_type = if T::INTEGER { "long" } else { "float" };
+        options.push_str(&format!(" -D E={}", edge_type));
+        if T::INTEGER {
+            options.push_str(" -D INTEGER");
+        }
+        match &self.rule {
+            Rule::IntUniform { lo, hi } => options.push_str(&format!(" -D UNIFORM -D LO=({}L) -D HI=({}L)", lo, hi)),
+            Rule::FloatUniform { lo, hi, scale } => options.push_str(&format!(
+                " -D UNIFORM -D LO=({}) -D HI=({}) -D SCALE=({})",
+                float_literal(*lo),
+                float_literal(*hi),
+                float_literal(*scale)
+            )),
+            Rule::IntEdges(_) | Rule::FloatEdges(_) => {}
+        }
+        if self.private_bytes() <= local_mem {
+            options.push_str(" -D PRIVATIZE");
+        }
+        options
+    }
+
+    fn private_bytes(&self) -> usize {
+        self.bins * self.channels * std::mem::size_of::<u32>()
+    }
+
+    /// The edges as the kernel reads them, as raw bytes; a single unused
+    /// element for uniform bins.
+    fn edge_bytes(&self) -> Vec<u8> {
+        match &self.rule {
+            Rule::IntEdges(edges) => edges.iter().flat_map(|e| e.to_ne_bytes()).collect(),
+            Rule::FloatEdges(edges) => edges.iter().flat_map(|e| e.to_ne_bytes()).collect(),
+            Rule::IntUniform { .. } => vec![0; 8],
+            Rule::FloatUniform { .. } => vec![0; 4],
+        }
+    }
+
+    fn check_len(&self, len: usize) -> Result<u32> {
+        if !len.is_multiple_of(self.channels) {
+            return Err(HistogramError::Len { len, channels: self.channels }.into());
+        }
+        Ok(u32::try_from(len).map_err(|_| HistogramError::Len { len, channels: self.channels })?)
+    }
+
+    /// Counts `data` on the device of `queue`. The program is built on
+    /// every call.
+    pub fn run(&self, queue: &Queue, data: &Buffer<T>) -> Result<Counts> {
+        let len = self.check_len(data.len())?;
+        let device = queue.device();
+        let span = span!(Level::Debug, "histogram of {} values into {} bins on {:?}", len, self.bins, device);
+        span.finish(self.count(queue, data, len))
+    }
+
+    /// `run` after the length check.
+    fn count(&self, queue: &Queue, data: &Buffer<T>, len: u32) -> Result<Counts> {
+        let device = queue.device();
+        let local_mem = match device.info(DeviceInfo::LocalMemSize)? {
+            DeviceInfoResult::LocalMemSize(bytes) => bytes as usize,
+            _ => 0,
+        };
+        let units = match device.info(DeviceInfo::MaxComputeUnits)? {
+            DeviceInfoResult::MaxComputeUnits(units) => units.max(1) as usize,
+            _ => 1,
+        };
+        let group = GROUP_SIZE.min(device.max_wg_size()?).max(1);
+        let program = Program::builder()
+            .src(KERNEL_SRC)
+            .devices(device)
+            .cmplr_opt(self.build_options(local_mem))
+            .build(&queue.context())?;
+
+        let edge_bytes = self.edge_bytes();
+        let edges = Buffer::<u8>::builder().queue(queue.clone()).len(edge_bytes.len()).copy_host_slice(&edge_bytes).build()?;
+        let hist = Buffer::<u32>::builder().queue(queue.clone()).len(self.bins * self.channels).fill_val(0).build()?;
+        let kernel = Kernel::builder()
+            .program(&program)
+            .name("histogram")
+            .queue(queue.clone())
+            .global_work_size(global_size(data.len(), group, units * 8))
+            .local_work_size(group)
+            .arg(data)
+            .arg(len)
+            .arg(&hist)
+            .arg_local::<u32>(if self.private_bytes() <= local_mem { self.bins * self.channels } else { 1 })
+            .arg(&edges)
+            .build()?;
+        log::trace!("enqueue histogram with work group {}", group);
+        unsafe {
+            kernel.enq()?;
+        }
+        let mut counts = vec![0; hist.len()];
+        hist.read(&mut counts).enq()?;
+        log::trace!("read {} bytes from {:?}", std::mem::size_of_val(&counts[..]), hist.as_core().as_ptr());
+        Ok(Counts { bins: self.bins, channels: self.channels, counts })
+    }
+
+    /// Counts `data` with the same kernel on the interpreter.
+    pub fn run_interpreted(&self, data: &[T]) -> Result<Counts> {
+        let len = self.check_len(data.len())?;
+        let program = interpreter::Program::build_with_options(KERNEL_SRC, &self.build_options(INTERPRETER_LOCAL_MEM))?;
+        let mut data = interpreter::Buffer::from_slice(data);
+        let mut hist = interpreter::Buffer::new::<u32>(self.bins * self.channels);
+        let mut edges = interpreter::Buffer::from_slice(&self.edge_bytes());
+        let local = if self.private_bytes() <= INTERPRETER_LOCAL_MEM { self.bins * self.channels } else { 1 };
+        program
+            .kernel("histogram")?
+            .arg_buf(&mut data)
+            .arg(len)
+            .arg_buf(&mut hist)
+            .arg_local::<u32>(local)
+            .arg_buf(&mut edges)
+            .global_work_size(global_size(len as usize, GROUP_SIZE, 4))
+            .local_work_size(GROUP_SIZE)
+            .run()?;
+        Ok(Counts { bins: self.bins, channels: self.channels, counts: hist.to_vec() })
+    }
+}
+
+/// The index of the bin `[edges[i], edges[i + 1])` holding `x`.
+fn search<E: PartialOrd + Copy>(edges: &[E], x: E) -> Option<usize> {
+    let last = *edges.last()?;
+    if !(x >= edges[0] && x < last) {
+        return None;
+    }
+    Some(edges.partition_point(|&e| e <= x) - 1)
+}
+
+/// Whole groups covering `len` items, at most `max_groups` of them; the
+/// kernel strides over whatever is left.
+fn global_size(len: usize, group: usize, max_groups: usize) -> usize {
+    len.div_ceil(group).clamp(1, max_groups) * group
+}
+
+/// An OpenCL C literal that reads back as exactly `x`.
+fn float_literal(x: f32) -> String {
+    format!("{:e}f", x)
+}
+
+/// Samples of a PNG, 8 or 16 bits each.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Samples {
+    Eight(Vec<u8>),
+    Sixteen(Vec<u16>),
+}
+
+/// A decoded PNG with its samples interleaved by channel. Palette images
+/// are expanded to RGB(A) and 1-, 2- and 4-bit ones to 8 bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PngImage {
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pub samples: Samples,
+}
+
+impl PngImage {
+    pub fn load(path: impl AsRef<Path>) -> Result<PngImage> {
+        let file = std::fs::File::open(path).map_err(png::DecodingError::IoError)?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        buf.truncate(info.buffer_size());
+        let samples = match info.bit_depth {
+            png::BitDepth::Sixteen => {
+                Samples::Sixteen(buf.chunks_exact(2).map(|b| u16::from_be_bytes([b[0], b[1]])).collect())
+            }
+            _ => Samples::Eight(buf),
+        };
+        Ok(PngImage {
+            width: info.width as usize,
+            height: info.height as usize,
+            channels: info.color_type.samples(),
+            samples,
+        })
+    }
+
+    pub fn bits(&self) -> u32 {
+        match self.samples {
+            Samples::Eight(_) => 8,
+            Samples::Sixteen(_) => 16,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_ranges_that_overflow_are_rejected() {
+        let err = Histogram::<i32>::new(Bins::uniform(1000, -1e18, 1e18)).unwrap_err();
+        assert!(matches!(err, crate::Error::Histogram(HistogramError::Bins(_))), "{}", err);
+
+        let hist = Histogram::<i32>::new(Bins::uniform(4, -1e18, 1e18)).unwrap();
+        assert_eq!(hist.bin_of(0), Some(2));
+        assert_eq!(hist.bin_of(i32::MIN), Some(1));
+        assert_eq!(hist.run_interpreted(&[0, i32::MIN, i32::MAX]).unwrap().counts, [0, 1, 2, 0]);
+    }
+}
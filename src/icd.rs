@@ -0,0 +1,995 @@
+//! Runtime loading of the OpenCL ICD loader.
+//!
+//! `ocl` calls the OpenCL API through `cl-sys`, which links `libOpenCL`, so
+//! every binary would need the library just to start. This module defines
+//! each entry point itself and forwards it to the library opened with
+//! `dlopen` on first use; the build script satisfies `-lOpenCL` with an
+//! empty archive. Without a library every call fails as the ICD loader
+//! does without platforms, with `CL_PLATFORM_NOT_FOUND_KHR`, and the
+//! interpreter takes over.
+//!
+//! `SIMPLE_GPU_OPENCL` names the library to open instead of the platform's
+//! usual one; a path that does not exist runs as if there were no driver.
+
+use crate::tracker::ObjectKind;
+use libloading::Library;
+use ocl::core::Status;
+use ocl::ffi::*;
+use std::sync::OnceLock;
+
+pub const LIBRARY_VAR: &str = "SIMPLE_GPU_OPENCL";
+
+/// Names tried, in order, when `SIMPLE_GPU_OPENCL` is unset.
+#[cfg(target_os = "macos")]
+const LIBRARY_NAMES: &[&str] = &["/System/Library/Frameworks/OpenCL.framework/OpenCL"];
+#[cfg(target_os = "windows")]
+const LIBRARY_NAMES: &[&str] = &["OpenCL.dll"];
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+const LIBRARY_NAMES: &[&str] = &["libOpenCL.so.1", "libOpenCL.so"];
+
+/// What every entry point returns when there is no library.
+const NO_PLATFORM: cl_int = Status::CL_PLATFORM_NOT_FOUND_KHR as cl_int;
+
+/// The ICD loader, opened on first use.
+pub(crate) fn library() -> Option<&'static Library> {
+    static LIBRARY: OnceLock<Option<Library>> = OnceLock::new();
+    LIBRARY
+        .get_or_init(|| {
+            let names = match std::env::var(LIBRARY_VAR) {
+                Ok(name) => vec![name],
+                Err(_) => LIBRARY_NAMES.iter().map(|name| name.to_string()).collect(),
+            };
+            for name in &names {
+                // SAFETY: the ICD loader has no initialisers with
+                // preconditions.
+                match unsafe { Library::new(name) } {
+                    Ok(library) => {
+                        log::debug!("loaded OpenCL from {}", name);
+                        return Some(library);
+                    }
+                    Err(err) => log::debug!("cannot load {}: {}", name, err),
+                }
+            }
+            log::info!("no OpenCL library found; running without a driver");
+            None
+        })
+        .as_ref()
+}
+
+/// The return value of an entry point that could not be called.
+trait Unavailable {
+    fn unavailable() -> Self;
+}
+
+impl Unavailable for cl_int {
+    fn unavailable() -> Self {
+        NO_PLATFORM
+    }
+}
+
+impl<T> Unavailable for *mut T {
+    fn unavailable() -> Self {
+        std::ptr::null_mut()
+    }
+}
+
+impl Unavailable for () {
+    fn unavailable() -> Self {}
+}
+
+/// Defines each entry point as a forward to the loaded library. Entry
+/// points that create an object name their `errcode_ret` parameter, which
+/// is set when there is no library, and entry points that create, retain or
+/// release an object name the hook below that records it with the tracker.
+macro_rules! entry_points {
+    ($(
+        fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty $(, $errcode:ident)? $(=> $hook:ident($($hook_arg:expr),*))?;
+    )*) => {
+        $(
+            /// Forwards to the OpenCL library.
+            ///
+            /// # Safety
+            ///
+            /// As for the OpenCL function of the same name.
+            #[unsafe(no_mangle)]
+            pub unsafe extern "system" fn $name($($arg: $ty),*) -> $ret {
+                type Entry = unsafe extern "system" fn($($ty),*) -> $ret;
+                static ENTRY: OnceLock<Option<Entry>> = OnceLock::new();
+                let entry = ENTRY.get_or_init(|| {
+                    // SAFETY: `Entry` is the function's signature in the
+                    // OpenCL headers.
+                    unsafe { library()?.get::<Entry>(concat!(stringify!($name), "\0").as_bytes()) }.ok().map(|f| *f)
+                });
+                match entry {
+                    Some(entry) => {
+                        // SAFETY: the caller upholds the function's contract.
+                        let call = || unsafe { entry($($arg),*) };
+                        $(let call = || track::$hook($($hook_arg,)* call);)?
+                        call()
+                    }
+                    None => {
+                        $(if !$errcode.is_null() {
+                            // SAFETY: a non-null `errcode_ret` points to a
+                            // `cl_int`.
+                            unsafe { *$errcode = NO_PLATFORM };
+                        })?
+                        Unavailable::unavailable()
+                    }
+                }
+            }
+        )*
+    };
+}
+
+/// The hooks that record objects with the tracker as the library creates,
+/// retains and releases them.
+mod track {
+    use crate::tracker::{self, ObjectKind};
+    use ocl::ffi::*;
+
+    /// Whether an entry point's return value means it succeeded.
+    pub(super) trait Succeeded {
+        fn succeeded(&self) -> bool;
+    }
+
+    impl Succeeded for cl_int {
+        fn succeeded(&self) -> bool {
+            *self == CL_SUCCESS
+        }
+    }
+
+    impl<T> Succeeded for *mut T {
+        fn succeeded(&self) -> bool {
+            !self.is_null()
+        }
+    }
+
+    pub(super) fn create<T>(kind: ObjectKind, call: impl FnOnce() -> *mut T) -> *mut T {
+        let object = call();
+        if !object.is_null() {
+            tracker::record_create(kind, object as usize);
+        }
+        object
+    }
+
+    pub(super) fn retain(kind: ObjectKind, object: *mut c_void, call: impl FnOnce() -> cl_int) -> cl_int {
+        let status = call();
+        if status == CL_SUCCESS {
+            tracker::record_retain(kind, object as usize);
+        }
+        status
+    }
+
+    /// Recorded first: once released, the handle may be reused by an object
+    /// another thread creates.
+    pub(super) fn release(kind: ObjectKind, object: *mut c_void, call: impl FnOnce() -> cl_int) -> cl_int {
+        tracker::record_release(kind, object as usize);
+        call()
+    }
+
+    /// Commands that return an event through `event`.
+    pub(super) fn event<R: Succeeded>(event: *mut cl_event, call: impl FnOnce() -> R) -> R {
+        let ret = call();
+        if ret.succeeded() && !event.is_null() {
+            // SAFETY: the command succeeded, so it wrote the event.
+            tracker::record_create(ObjectKind::Event, unsafe { *event } as usize);
+        }
+        ret
+    }
+
+    pub(super) fn kernels(kernels: *mut cl_kernel, num_kernels: cl_uint, num_kernels_ret: *mut cl_uint, call: impl FnOnce() -> cl_int) -> cl_int {
+        let status = call();
+        if status == CL_SUCCESS && !kernels.is_null() {
+            // SAFETY: on success the library wrote one kernel per kernel in the
+            // program, which is at most `num_kernels`.
+            let count = if num_kernels_ret.is_null() { num_kernels } else { unsafe { *num_kernels_ret }.min(num_kernels) };
+            for i in 0..count as usize {
+                tracker::record_create(ObjectKind::Kernel, unsafe { *kernels.add(i) } as usize);
+            }
+        }
+        status
+    }
+}
+
+entry_points! {
+    fn clGetPlatformIDs(num_entries: cl_uint, platforms: *mut cl_platform_id, num_platforms: *mut cl_uint) -> cl_int;
+    fn clGetPlatformInfo(
+        platform: cl_platform_id,
+        param_name: cl_platform_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clGetDeviceIDs(
+        platform: cl_platform_id,
+        device_type: cl_device_type,
+        num_entries: cl_uint,
+        devices: *mut cl_device_id,
+        num_devices: *mut cl_uint,
+    ) -> cl_int;
+    fn clGetDeviceInfo(
+        device: cl_device_id,
+        param_name: cl_device_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clCreateSubDevices(
+        in_device: cl_device_id,
+        properties: *const cl_device_partition_property,
+        num_devices: cl_uint,
+        out_devices: *mut cl_device_id,
+        num_devices_ret: *mut cl_uint,
+    ) -> cl_int;
+    fn clRetainDevice(device: cl_device_id) -> cl_int;
+    fn clReleaseDevice(device: cl_device_id) -> cl_int;
+    fn clSetDefaultDeviceCommandQueue(
+        context: cl_context,
+        device: cl_device_id,
+        command_queue: cl_command_queue,
+    ) -> cl_int;
+    fn clGetDeviceAndHostTimer(
+        device: cl_device_id,
+        device_timestamp: *mut cl_ulong,
+        host_timestamp: *mut cl_ulong,
+    ) -> cl_int;
+    fn clGetHostTimer(device: cl_device_id, host_timestamp: *mut cl_ulong) -> cl_int;
+    fn clCreateContext(
+        properties: *const cl_context_properties,
+        num_devices: cl_uint,
+        devices: *const cl_device_id,
+        pfn_notify: Option<extern "C" fn(*const c_char, *const c_void, size_t, *mut c_void)>,
+        user_data: *mut c_void,
+        errcode_ret: *mut cl_int,
+    ) -> cl_context, errcode_ret => create(ObjectKind::Context);
+    fn clCreateContextFromType(
+        properties: *const cl_context_properties,
+        device_type: cl_device_type,
+        pfn_notify: Option<extern "C" fn(*const c_char, *const c_void, size_t, *mut c_void)>,
+        user_data: *mut c_void,
+        errcode_ret: *mut cl_int,
+    ) -> cl_context, errcode_ret => create(ObjectKind::Context);
+    fn clRetainContext(context: cl_context) -> cl_int => retain(ObjectKind::Context, context);
+    fn clReleaseContext(context: cl_context) -> cl_int => release(ObjectKind::Context, context);
+    fn clGetContextInfo(
+        context: cl_context,
+        param_name: cl_context_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clCreateCommandQueue(
+        context: cl_context,
+        device: cl_device_id,
+        properties: cl_command_queue_properties,
+        errcode_ret: *mut cl_int,
+    ) -> cl_command_queue, errcode_ret => create(ObjectKind::Queue);
+    fn clCreateCommandQueueWithProperties(
+        context: cl_context,
+        device: cl_device_id,
+        properties: *const cl_queue_properties,
+        errcode_ret: *mut cl_int,
+    ) -> cl_command_queue, errcode_ret => create(ObjectKind::Queue);
+    fn clRetainCommandQueue(command_queue: cl_command_queue) -> cl_int => retain(ObjectKind::Queue, command_queue);
+    fn clReleaseCommandQueue(command_queue: cl_command_queue) -> cl_int => release(ObjectKind::Queue, command_queue);
+    fn clGetCommandQueueInfo(
+        command_queue: cl_command_queue,
+        param_name: cl_command_queue_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clCreateBuffer(
+        context: cl_context,
+        flags: cl_mem_flags,
+        size: size_t,
+        host_ptr: *mut c_void,
+        errcode_ret: *mut cl_int,
+    ) -> cl_mem, errcode_ret => create(ObjectKind::Buffer);
+    fn clCreateSubBuffer(
+        buffer: cl_mem,
+        flags: cl_mem_flags,
+        buffer_create_type: cl_buffer_create_type,
+        buffer_create_info: *const c_void,
+        errcode_ret: *mut cl_int,
+    ) -> cl_mem, errcode_ret => create(ObjectKind::Buffer);
+    fn clCreateImage2D(
+        context: cl_context,
+        flags: cl_mem_flags,
+        image_format: *mut cl_image_format,
+        image_width: size_t,
+        image_depth: size_t,
+        image_slc_pitch: size_t,
+        host_ptr: *mut c_void,
+        errcode_ret: *mut cl_int,
+    ) -> cl_mem, errcode_ret => create(ObjectKind::Image);
+    fn clCreateImage3D(
+        context: cl_context,
+        flags: cl_mem_flags,
+        image_format: *mut cl_image_format,
+        image_width: size_t,
+        image_height: size_t,
+        image_depth: size_t,
+        image_row_pitch: size_t,
+        image_slc_pitch: size_t,
+        host_ptr: *mut c_void,
+        errcode_ret: *mut cl_int,
+    ) -> cl_mem, errcode_ret => create(ObjectKind::Image);
+    fn clCreateImage(
+        context: cl_context,
+        flags: cl_mem_flags,
+        image_format: *const cl_image_format,
+        image_desc: *const cl_image_desc,
+        host_ptr: *mut c_void,
+        errcode_ret: *mut cl_int,
+    ) -> cl_mem, errcode_ret => create(ObjectKind::Image);
+    fn clCreatePipe(
+        context: cl_context,
+        flags: cl_mem_flags,
+        pipe_packet_size: cl_uint,
+        pipe_max_packets: cl_uint,
+        properties: *const cl_pipe_properties,
+        errcode_ret: *mut cl_int,
+    ) -> cl_mem, errcode_ret => create(ObjectKind::Buffer);
+    fn clRetainMemObject(memobj: cl_mem) -> cl_int => retain(ObjectKind::Buffer, memobj);
+    fn clReleaseMemObject(memobj: cl_mem) -> cl_int => release(ObjectKind::Buffer, memobj);
+    fn clGetSupportedImageFormats(
+        context: cl_context,
+        flags: cl_mem_flags,
+        image_type: cl_mem_object_type,
+        num_entries: cl_uint,
+        image_formats: *mut cl_image_format,
+        num_image_formats: *mut cl_uint,
+    ) -> cl_int;
+    fn clGetMemObjectInfo(
+        memobj: cl_mem,
+        param_name: cl_mem_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clGetImageInfo(
+        image: cl_mem,
+        param_name: cl_image_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clGetPipeInfo(
+        pipe: cl_mem,
+        param_name: cl_pipe_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clSetMemObjectDestructorCallback(
+        memobj: cl_mem,
+        pfn_notify: Option<extern "C" fn(cl_mem, *mut c_void)>,
+        user_data: *mut c_void,
+    ) -> cl_int;
+    fn clSVMAlloc(context: cl_context, flags: cl_svm_mem_flags, size: size_t, alignment: cl_uint) -> *mut c_void;
+    fn clSVMFree(context: cl_context, svm_pointer: *mut c_void) -> ();
+    fn clCreateSampler(
+        context: cl_context,
+        normalize_coords: cl_bool,
+        addressing_mode: cl_addressing_mode,
+        filter_mode: cl_filter_mode,
+        errcode_ret: *mut cl_int,
+    ) -> cl_sampler, errcode_ret;
+    fn clCreateSamplerWithProperties(
+        context: cl_context,
+        normalized_coords: *const cl_sampler_properties,
+        errcode_ret: *mut cl_int,
+    ) -> cl_sampler, errcode_ret;
+    fn clRetainSampler(sampler: cl_sampler) -> cl_int;
+    fn clReleaseSampler(sampler: cl_sampler) -> cl_int;
+    fn clGetSamplerInfo(
+        sampler: cl_sampler,
+        param_name: cl_sampler_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clCreateProgramWithSource(
+        context: cl_context,
+        count: cl_uint,
+        strings: *const *const c_char,
+        lengths: *const size_t,
+        errcode_ret: *mut cl_int,
+    ) -> cl_program, errcode_ret => create(ObjectKind::Program);
+    fn clCreateProgramWithBinary(
+        context: cl_context,
+        num_devices: cl_uint,
+        device_list: *const cl_device_id,
+        lengths: *const size_t,
+        binaries: *const *const c_uchar,
+        binary_status: *mut cl_int,
+        errcode_ret: *mut cl_int,
+    ) -> cl_program, errcode_ret => create(ObjectKind::Program);
+    fn clCreateProgramWithBuiltInKernels(
+        context: cl_context,
+        num_devices: cl_uint,
+        device_list: *const cl_device_id,
+        kernel_names: *const char,
+        errcode_ret: *mut cl_int,
+    ) -> cl_program, errcode_ret => create(ObjectKind::Program);
+    fn clCreateProgramWithIL(
+        context: cl_context,
+        il: *const c_void,
+        length: size_t,
+        errcode_ret: *mut cl_int,
+    ) -> cl_program, errcode_ret => create(ObjectKind::Program);
+    fn clRetainProgram(program: cl_program) -> cl_int => retain(ObjectKind::Program, program);
+    fn clReleaseProgram(program: cl_program) -> cl_int => release(ObjectKind::Program, program);
+    fn clBuildProgram(
+        program: cl_program,
+        num_devices: cl_uint,
+        device_list: *const cl_device_id,
+        options: *const c_char,
+        pfn_notify: Option<extern "C" fn(cl_program, *mut c_void)>,
+        user_data: *mut c_void,
+    ) -> cl_int;
+    fn clUnloadCompiler() -> cl_int;
+    fn clCompileProgram(
+        program: cl_program,
+        num_devices: cl_uint,
+        device_list: *const cl_device_id,
+        options: *const c_char,
+        num_input_headers: cl_uint,
+        input_headers: *const cl_program,
+        header_include_names: *const *const c_char,
+        pfn_notify: Option<extern "C" fn(program: cl_program, user_data: *mut c_void)>,
+        user_data: *mut c_void,
+    ) -> cl_int;
+    fn clLinkProgram(
+        context: cl_context,
+        num_devices: cl_uint,
+        device_list: *const cl_device_id,
+        options: *const c_char,
+        num_input_programs: cl_uint,
+        input_programs: *const cl_program,
+        pfn_notify: Option<extern "C" fn(program: cl_program, user_data: *mut c_void)>,
+        user_data: *mut c_void,
+        errcode_ret: *mut cl_int,
+    ) -> cl_program, errcode_ret => create(ObjectKind::Program);
+    fn clUnloadPlatformCompiler(platform: cl_platform_id) -> cl_int;
+    fn clGetProgramInfo(
+        program: cl_program,
+        param_name: cl_program_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clGetProgramBuildInfo(
+        program: cl_program,
+        device: cl_device_id,
+        param_name: cl_program_build_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clCreateKernel(
+        program: cl_program,
+        kernel_name: *const c_char,
+        errcode_ret: *mut cl_int,
+    ) -> cl_kernel, errcode_ret => create(ObjectKind::Kernel);
+    fn clCreateKernelsInProgram(
+        program: cl_program,
+        num_kernels: cl_uint,
+        kernels: *mut cl_kernel,
+        num_kernels_ret: *mut cl_uint,
+    ) -> cl_int => kernels(kernels, num_kernels, num_kernels_ret);
+    fn clCloneKernel(source_kernel: cl_kernel, errcode_ret: *mut cl_int) -> cl_kernel, errcode_ret => create(ObjectKind::Kernel);
+    fn clRetainKernel(kernel: cl_kernel) -> cl_int => retain(ObjectKind::Kernel, kernel);
+    fn clReleaseKernel(kernel: cl_kernel) -> cl_int => release(ObjectKind::Kernel, kernel);
+    fn clSetKernelArg(kernel: cl_kernel, arg_index: cl_uint, arg_size: size_t, arg_value: *const c_void) -> cl_int;
+    fn clSetKernelArgSVMPointer(kernel: cl_kernel, arg_index: cl_uint, arg_value: *const c_void) -> cl_int;
+    fn clSetKernelExecInfo(
+        kernel: cl_kernel,
+        param_name: cl_kernel_exec_info,
+        param_value_size: size_t,
+        param_value: *const c_void,
+    ) -> cl_int;
+    fn clGetKernelInfo(
+        kernel: cl_kernel,
+        param_name: cl_kernel_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clGetKernelArgInfo(
+        kernel: cl_kernel,
+        arg_indx: cl_uint,
+        param_name: cl_kernel_arg_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clGetKernelWorkGroupInfo(
+        kernel: cl_kernel,
+        device: cl_device_id,
+        param_name: cl_kernel_work_group_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clGetKernelSubGroupInfo(
+        kernel: cl_kernel,
+        device: cl_device_id,
+        param_name: cl_kernel_sub_group_info,
+        input_value_size: size_t,
+        input_value: *const c_void,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clWaitForEvents(num_events: cl_uint, event_list: *const cl_event) -> cl_int;
+    fn clGetEventInfo(
+        event: cl_event,
+        param_name: cl_event_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clCreateUserEvent(context: cl_context, errcode_ret: *mut cl_int) -> cl_event, errcode_ret => create(ObjectKind::Event);
+    fn clRetainEvent(event: cl_event) -> cl_int => retain(ObjectKind::Event, event);
+    fn clReleaseEvent(event: cl_event) -> cl_int => release(ObjectKind::Event, event);
+    fn clSetUserEventStatus(event: cl_event, execution_status: cl_int) -> cl_int;
+    fn clSetEventCallback(
+        event: cl_event,
+        command_exec_callback_type: cl_int,
+        pfn_notify: Option<extern "C" fn(cl_event, cl_int, *mut c_void)>,
+        user_data: *mut c_void,
+    ) -> cl_int;
+    fn clGetEventProfilingInfo(
+        event: cl_event,
+        param_name: cl_profiling_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clFlush(command_queue: cl_command_queue) -> cl_int;
+    fn clFinish(command_queue: cl_command_queue) -> cl_int;
+    fn clEnqueueReadBuffer(
+        command_queue: cl_command_queue,
+        buffer: cl_mem,
+        blocking_read: cl_bool,
+        offset: size_t,
+        cb: size_t,
+        ptr: *mut c_void,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueReadBufferRect(
+        command_queue: cl_command_queue,
+        buffer: cl_mem,
+        blocking_read: cl_bool,
+        buffer_origin: *const size_t,
+        host_origin: *const size_t,
+        region: *const size_t,
+        buffer_row_pitch: size_t,
+        buffer_slc_pitch: size_t,
+        host_row_pitch: size_t,
+        host_slc_pitch: size_t,
+        ptr: *mut c_void,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueWriteBuffer(
+        command_queue: cl_command_queue,
+        buffer: cl_mem,
+        blocking_write: cl_bool,
+        offset: size_t,
+        cb: size_t,
+        ptr: *const c_void,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueWriteBufferRect(
+        command_queue: cl_command_queue,
+        buffer: cl_mem,
+        blocking_write: cl_bool,
+        buffer_origin: *const size_t,
+        host_origin: *const size_t,
+        region: *const size_t,
+        buffer_row_pitch: size_t,
+        buffer_slc_pitch: size_t,
+        host_row_pitch: size_t,
+        host_slc_pitch: size_t,
+        ptr: *const c_void,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueFillBuffer(
+        command_queue: cl_command_queue,
+        buffer: cl_mem,
+        pattern: *const c_void,
+        pattern_size: size_t,
+        offset: size_t,
+        size: size_t,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueCopyBuffer(
+        command_queue: cl_command_queue,
+        src_buffer: cl_mem,
+        dst_buffer: cl_mem,
+        src_offset: size_t,
+        dst_offset: size_t,
+        cb: size_t,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueCopyBufferRect(
+        command_queue: cl_command_queue,
+        src_buffer: cl_mem,
+        dst_buffer: cl_mem,
+        src_origin: *const size_t,
+        dst_origin: *const size_t,
+        region: *const size_t,
+        src_row_pitch: size_t,
+        src_slc_pitch: size_t,
+        dst_row_pitch: size_t,
+        dst_slc_pitch: size_t,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueReadImage(
+        command_queue: cl_command_queue,
+        image: cl_mem,
+        blocking_read: cl_bool,
+        origin: *const size_t,
+        region: *const size_t,
+        row_pitch: size_t,
+        slc_pitch: size_t,
+        ptr: *mut c_void,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueWriteImage(
+        command_queue: cl_command_queue,
+        image: cl_mem,
+        blocking_write: cl_bool,
+        origin: *const size_t,
+        region: *const size_t,
+        input_row_pitch: size_t,
+        input_slc_pitch: size_t,
+        ptr: *const c_void,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueFillImage(
+        command_queue: cl_command_queue,
+        image: cl_mem,
+        fill_color: *const c_void,
+        origin: *const size_t,
+        region: *const size_t,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueCopyImage(
+        command_queue: cl_command_queue,
+        src_image: cl_mem,
+        dst_image: cl_mem,
+        src_origin: *const size_t,
+        dst_origin: *const size_t,
+        region: *const size_t,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueCopyImageToBuffer(
+        command_queue: cl_command_queue,
+        src_image: cl_mem,
+        dst_buffer: cl_mem,
+        src_origin: *const size_t,
+        region: *const size_t,
+        dst_offset: size_t,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueCopyBufferToImage(
+        command_queue: cl_command_queue,
+        src_buffer: cl_mem,
+        dst_image: cl_mem,
+        src_offset: size_t,
+        dst_origin: *const size_t,
+        region: *const size_t,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueMapBuffer(
+        command_queue: cl_command_queue,
+        buffer: cl_mem,
+        blocking_map: cl_bool,
+        map_flags: cl_map_flags,
+        offset: size_t,
+        size: size_t,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+        errorcode_ret: *mut cl_int,
+    ) -> *mut c_void, errorcode_ret => event(event);
+    fn clEnqueueMapImage(
+        command_queue: cl_command_queue,
+        image: cl_mem,
+        blocking_map: cl_bool,
+        map_flags: cl_map_flags,
+        origin: *const size_t,
+        region: *const size_t,
+        image_row_pitch: *mut size_t,
+        image_slc_pitch: *mut size_t,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+        errorcode_ret: *mut cl_int,
+    ) -> *mut c_void, errorcode_ret => event(event);
+    fn clEnqueueUnmapMemObject(
+        command_queue: cl_command_queue,
+        memobj: cl_mem,
+        mapped_ptr: *mut c_void,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueMigrateMemObjects(
+        command_queue: cl_command_queue,
+        num_mem_objects: cl_uint,
+        mem_objects: *const cl_mem,
+        flags: cl_mem_migration_flags,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueNDRangeKernel(
+        command_queue: cl_command_queue,
+        kernel: cl_kernel,
+        work_dim: cl_uint,
+        global_work_offset: *const size_t,
+        global_work_dims: *const size_t,
+        local_work_dims: *const size_t,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueTask(
+        command_queue: cl_command_queue,
+        kernel: cl_kernel,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueNativeKernel(
+        command_queue: cl_command_queue,
+        user_func: Option<extern "C" fn(*mut c_void)>,
+        args: *mut c_void,
+        cb_args: size_t,
+        num_mem_objects: cl_uint,
+        mem_list: *const cl_mem,
+        args_mem_loc: *const *const c_void,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueMarker(command_queue: cl_command_queue, event: *mut cl_event) -> cl_int => event(event);
+    fn clEnqueueMarkerWithWaitList(
+        command_queue: cl_command_queue,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueWaitForEvents(
+        command_queue: cl_command_queue,
+        num_events: cl_uint,
+        event_list: *mut cl_event,
+    ) -> cl_int;
+    fn clEnqueueBarrierWithWaitList(
+        command_queue: cl_command_queue,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueSVMFree(
+        command_queue: cl_command_queue,
+        num_svm_pointers: cl_uint,
+        svm_pointers: *const *const c_void,
+        pfn_free_func: Option< extern "C" fn( queue: cl_command_queue, num_svm_pointers: cl_uint, svm_pointers: *const *const c_void, user_data: *mut c_void, ), >,
+        user_data: *mut c_void,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueSVMMemcpy(
+        command_queue: cl_command_queue,
+        blocking_copy: cl_bool,
+        dst_ptr: *mut c_void,
+        src_ptr: *const c_void,
+        size: size_t,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueSVMMemFill(
+        command_queue: cl_command_queue,
+        svm_ptr: *mut c_void,
+        pattern: *const c_void,
+        pattern_size: size_t,
+        size: size_t,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueSVMMap(
+        command_queue: cl_command_queue,
+        blocking_map: cl_bool,
+        flags: cl_map_flags,
+        svm_ptr: *mut c_void,
+        size: size_t,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueSVMUnmap(
+        command_queue: cl_command_queue,
+        svm_ptr: *mut c_void,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueSVMMigrateMem(
+        command_queue: cl_command_queue,
+        num_svm_pointers: cl_uint,
+        svm_pointers: *const *const c_void,
+        sizes: *const size_t,
+        flags: cl_mem_migration_flags,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueBarrier(command_queue: cl_command_queue) -> cl_int;
+    fn clGetExtensionFunctionAddress(func_name: *mut c_char) -> ();
+    fn clGetExtensionFunctionAddressForPlatform(platform: cl_platform_id, func_name: *const c_char) -> *mut c_void;
+    fn clCreateFromGLBuffer(
+        context: cl_context,
+        flags: cl_mem_flags,
+        bufobj: cl_GLuint,
+        errcode_ret: *mut cl_int,
+    ) -> cl_mem, errcode_ret => create(ObjectKind::Buffer);
+    fn clCreateFromGLTexture(
+        context: cl_context,
+        flags: cl_mem_flags,
+        texture_target: cl_GLenum,
+        miplevel: cl_GLint,
+        texture: cl_GLuint,
+        errcode_ret: *mut cl_int,
+    ) -> cl_mem, errcode_ret => create(ObjectKind::Image);
+    fn clGetGLObjectInfo(
+        memobj: cl_mem,
+        gl_object_type: *mut cl_gl_object_type,
+        gl_object_name: *mut cl_GLuint,
+    ) -> cl_int;
+    fn clGetGLTextureInfo(
+        memobj: cl_mem,
+        param_name: cl_gl_texture_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clCreateFromGLRenderbuffer(
+        context: cl_context,
+        flags: cl_mem_flags,
+        renderbuffer: cl_GLuint,
+        errcode_ret: *mut cl_int,
+    ) -> cl_mem, errcode_ret => create(ObjectKind::Image);
+    fn clEnqueueAcquireGLObjects(
+        command_queue: cl_command_queue,
+        num_objects: cl_uint,
+        mem_objects: *const cl_mem,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clEnqueueReleaseGLObjects(
+        command_queue: cl_command_queue,
+        num_objects: cl_uint,
+        mem_objects: *const cl_mem,
+        num_events_in_wait_list: cl_uint,
+        event_wait_list: *const cl_event,
+        event: *mut cl_event,
+    ) -> cl_int => event(event);
+    fn clGetGLContextInfoKHR(
+        properties: *const cl_context_properties,
+        param_name: cl_gl_context_info,
+        param_value_size: size_t,
+        param_value: *mut c_void,
+        param_value_size_ret: *mut size_t,
+    ) -> cl_int;
+    fn clCreateFromGLTexture2D(
+        context: cl_context,
+        flags: cl_mem_flags,
+        texture_target: cl_GLenum,
+        miplevel: cl_GLint,
+        texture: cl_GLuint,
+        errcode_ret: *mut cl_int,
+    ) -> cl_mem, errcode_ret => create(ObjectKind::Image);
+    fn clCreateFromGLTexture3D(
+        context: cl_context,
+        flags: cl_mem_flags,
+        texture_target: cl_GLenum,
+        miplevel: cl_GLint,
+        texture: cl_GLuint,
+        errcode_ret: *mut cl_int,
+    ) -> cl_mem, errcode_ret => create(ObjectKind::Image);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker;
+
+    fn refs(kind: ObjectKind, handle: usize) -> Option<usize> {
+        tracker::report().leaked.iter().find(|l| l.kind == kind && l.handle == handle).map(|l| l.refs)
+    }
+
+    #[test]
+    fn hooks_record_objects_the_library_creates() {
+        if !tracker::enabled() {
+            return;
+        }
+        let kernel = 0x1cd_0001 as *mut c_void;
+        assert_eq!(track::create(ObjectKind::Kernel, || kernel), kernel);
+        assert_eq!(track::retain(ObjectKind::Kernel, kernel, || CL_SUCCESS), CL_SUCCESS);
+        // A failed retain takes no reference.
+        track::retain(ObjectKind::Kernel, kernel, || CL_INVALID_KERNEL);
+        assert_eq!(refs(ObjectKind::Kernel, kernel as usize), Some(2));
+        track::release(ObjectKind::Kernel, kernel, || CL_SUCCESS);
+        track::release(ObjectKind::Kernel, kernel, || CL_SUCCESS);
+        assert_eq!(refs(ObjectKind::Kernel, kernel as usize), None);
+
+        // Failed creations record nothing.
+        track::create(ObjectKind::Buffer, std::ptr::null_mut::<c_void>);
+        let mut event = 0x1cd_0002 as cl_event;
+        track::event(&mut event, || CL_OUT_OF_RESOURCES);
+        assert_eq!(refs(ObjectKind::Event, event as usize), None);
+    }
+
+    #[test]
+    fn commands_record_the_events_they_return() {
+        if !tracker::enabled() {
+            return;
+        }
+        let mut event: cl_event = std::ptr::null_mut();
+        let out: *mut cl_event = &mut event;
+        let status = track::event(out, || {
+            // SAFETY: `out` points to `event`, which outlives the call.
+            unsafe { *out = 0x1cd_0003 as cl_event };
+            CL_SUCCESS
+        });
+        assert_eq!(status, CL_SUCCESS);
+        assert_eq!(refs(ObjectKind::Event, event as usize), Some(1));
+        // No event requested.
+        track::event(std::ptr::null_mut(), || CL_SUCCESS);
+        track::release(ObjectKind::Event, event, || CL_SUCCESS);
+        assert_eq!(refs(ObjectKind::Event, event as usize), None);
+
+        let mut kernels = [0x1cd_0004 as cl_kernel, 0x1cd_0005 as cl_kernel, std::ptr::null_mut()];
+        let mut count = 2;
+        track::kernels(kernels.as_mut_ptr(), 3, &mut count, || CL_SUCCESS);
+        for kernel in &kernels[..2] {
+            assert_eq!(refs(ObjectKind::Kernel, *kernel as usize), Some(1));
+            track::release(ObjectKind::Kernel, *kernel, || CL_SUCCESS);
+        }
+    }
+}
@@ -0,0 +1,510 @@
+//! Software interpreter for a practical subset of OpenCL C.
+//!
+//! Runs kernels on the CPU without an OpenCL driver, so the examples work
+//! (and can be checked) anywhere. Supported: scalar and vector types with
+//! swizzles and vector literals, pointers into global, constant, local and
+//! private memory, helper functions, the usual control flow, `barrier`,
+//! the work-item functions and the common math, integer, geometric,
+//! relational, conversion, `vload`/`vstore`, `shuffle`, atomic and `printf`
+//! built-ins. Work-groups, and work-items within a group, run in a fixed
+//! order, so results are deterministic. Structs, images, `half` and
+//! `#include` are not supported.
+
+mod ast;
+mod builtins;
+mod exec;
+mod lexer;
+mod parser;
+mod types;
+mod value;
+
+use crate::logging::span;
+use log::Level;
+use ocl::{OclPrm, SpatialDims};
+use std::collections::HashMap;
+use std::fmt;
+use types::{Space, Type};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InterpError {
+    Parse { line: u32, message: String },
+    Runtime { line: u32, message: String },
+    /// Bad kernel name, arguments or work sizes.
+    Launch(String),
+}
+
+impl fmt::Display for InterpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            InterpError::Runtime { line, message } => write!(f, "line {}: {}", line, message),
+            InterpError::Launch(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for InterpError {}
+
+/// Whether an OpenCL platform is installed. Asks the ICD loader directly,
+/// which avoids `ocl`'s retries when there is none.
+pub fn driver_available() -> bool {
+    let mut count = 0;
+    // SAFETY: only the platform count is requested; no array is written.
+    let status = unsafe { cl_sys::clGetPlatformIDs(0, std::ptr::null_mut(), &mut count) };
+    status == cl_sys::CL_SUCCESS && count > 0
+}
+
+/// A parsed program.
+#[derive(Debug, Clone)]
+pub struct Program {
+    unit: ast::Unit,
+    uses_barrier: bool,
+}
+
+impl Program {
+    pub fn build(src: &str) -> crate::Result<Program> {
+        Program::build_with_options(src, "")
+    }
+
+    /// Builds with compiler options. `-D NAME` and `-D NAME=VALUE` are
+    /// honoured; other options (`-cl-*`, `-w`, `-I dir`) are accepted and
+    /// ignored.
+    pub fn build_with_options(src: &str, options: &str) -> crate::Result<Program> {
+        let mut defines = Vec::new();
+        let mut words = options.split_whitespace();
+        while let Some(word) = words.next() {
+            let define = match word {
+                "-D" => words.next(),
+                "-I" => {
+                    words.next();
+                    None
+                }
+                _ => word.strip_prefix("-D"),
+            };
+            if let Some(define) = define {
+                let (name, value) = define.split_once('=').unwrap_or((define, "1"));
+                defines.push((name.to_string(), value.to_string()));
+            }
+        }
+        let tokens = lexer::tokenize(src, &defines)?;
+        let uses_barrier = tokens
+            .iter()
+            .any(|t| matches!(&t.tok, lexer::Tok::Ident(n) if n == "barrier" || n == "work_group_barrier"));
+        let unit = parser::parse(tokens)?;
+        Ok(Program { unit, uses_barrier })
+    }
+
+    pub fn kernel_names(&self) -> Vec<&str> {
+        self.unit.functions.iter().filter(|f| f.is_kernel).map(|f| f.name.as_str()).collect()
+    }
+
+    /// Starts a launch of kernel `name`. Arguments are given in order.
+    pub fn kernel<'b>(&self, name: &str) -> crate::Result<Kernel<'_, 'b>> {
+        let func = self
+            .unit
+            .functions
+            .iter()
+            .find(|f| f.is_kernel && f.name == name)
+            .ok_or_else(|| InterpError::Launch(format!("no kernel named {}", name)))?;
+        Ok(Kernel {
+            program: self,
+            func,
+            args: Vec::new(),
+            global_work_size: SpatialDims::Unspecified,
+            global_work_offset: SpatialDims::Unspecified,
+            local_work_size: SpatialDims::Unspecified,
+        })
+    }
+}
+
+/// Memory for the interpreter: plain bytes, in the device's (little-endian)
+/// layout.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Buffer {
+    bytes: Vec<u8>,
+}
+
+fn bytes_of<T: OclPrm>(data: &[T]) -> &[u8] {
+    // SAFETY: `OclPrm` types are plain data without padding invariants.
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data)) }
+}
+
+impl Buffer {
+    /// A zeroed buffer of `len` elements of `T`.
+    pub fn new<T: OclPrm>(len: usize) -> Buffer {
+        Buffer { bytes: vec![0; len * std::mem::size_of::<T>()] }
+    }
+
+    pub fn from_slice<T: OclPrm>(data: &[T]) -> Buffer {
+        Buffer { bytes: bytes_of(data).to_vec() }
+    }
+
+    /// The contents as elements of `T`; trailing bytes that do not fill a
+    /// whole element are ignored.
+    pub fn to_vec<T: OclPrm>(&self) -> Vec<T> {
+        let len = self.bytes.len() / std::mem::size_of::<T>().max(1);
+        let mut out = vec![T::default(); len];
+        // SAFETY: `out` has room for `len` elements, and any bit pattern is
+        // a valid `OclPrm`.
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                self.bytes.as_ptr(),
+                out.as_mut_ptr() as *mut u8,
+                len * std::mem::size_of::<T>(),
+            )
+        };
+        out
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+
+    /// Size in bytes.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+enum Arg<'b> {
+    Buffer(&'b mut Buffer),
+    Scalar(Vec<u8>),
+    /// Bytes of `__local` memory.
+    Local(usize),
+}
+
+/// A kernel launch being configured. Buffers are borrowed until `run`.
+pub struct Kernel<'p, 'b> {
+    program: &'p Program,
+    func: &'p ast::Function,
+    args: Vec<Arg<'b>>,
+    global_work_size: SpatialDims,
+    global_work_offset: SpatialDims,
+    local_work_size: SpatialDims,
+}
+
+type WorkSize = [usize; 3];
+
+/// Picks the largest work-group of at most 256 items that divides the
+/// global size.
+fn default_local_size(global: [usize; 3]) -> [usize; 3] {
+    let mut budget = 256;
+    global.map(|len| {
+        let size = (1..=budget.min(len)).rev().find(|d| len % d == 0).unwrap_or(1);
+        budget /= size;
+        size
+    })
+}
+
+impl<'b> Kernel<'_, 'b> {
+    pub fn name(&self) -> &str {
+        &self.func.name
+    }
+
+    pub fn arg_buf(mut self, buffer: &'b mut Buffer) -> Self {
+        self.args.push(Arg::Buffer(buffer));
+        self
+    }
+
+    pub fn arg<T: OclPrm>(mut self, value: T) -> Self {
+        self.args.push(Arg::Scalar(bytes_of(&[value]).to_vec()));
+        self
+    }
+
+    /// A scalar or vector argument given as its raw bytes.
+    pub fn arg_bytes(mut self, bytes: &[u8]) -> Self {
+        self.args.push(Arg::Scalar(bytes.to_vec()));
+        self
+    }
+
+    /// A `__local` argument of `len` elements of `T`.
+    pub fn arg_local<T: OclPrm>(mut self, len: usize) -> Self {
+        self.args.push(Arg::Local(len * std::mem::size_of::<T>()));
+        self
+    }
+
+    pub fn global_work_size<D: Into<SpatialDims>>(mut self, dims: D) -> Self {
+        self.global_work_size = dims.into();
+        self
+    }
+
+    pub fn global_work_offset<D: Into<SpatialDims>>(mut self, dims: D) -> Self {
+        self.global_work_offset = dims.into();
+        self
+    }
+
+    pub fn local_work_size<D: Into<SpatialDims>>(mut self, dims: D) -> Self {
+        self.local_work_size = dims.into();
+        self
+    }
+
+    /// Returns the dimension count and the global size, offset and local
+    /// size.
+    fn work_sizes(&self) -> Result<(u32, WorkSize, WorkSize, WorkSize), InterpError> {
+        let launch_error = |m: String| InterpError::Launch(format!("{}: {}", self.func.name, m));
+        let dims = self.global_work_size.dim_count();
+        let global = self.global_work_size.to_lens().map_err(|_| launch_error("global work size not set".into()))?;
+        let offset = self.global_work_offset.to_offset().unwrap_or([0; 3]);
+        let local = match self.local_work_size.to_lens() {
+            Ok(_) if self.local_work_size.dim_count() != dims => {
+                return Err(launch_error(format!(
+                    "local work size has {} dimensions, global {}",
+                    self.local_work_size.dim_count(),
+                    dims
+                )));
+            }
+            Ok(local) => local,
+            Err(_) => default_local_size(global),
+        };
+        for d in 0..3 {
+            if global[d] == 0 || local[d] == 0 {
+                return Err(launch_error(format!("work size is zero in dimension {}", d)));
+            }
+            if global[d] % local[d] != 0 {
+                return Err(launch_error(format!(
+                    "global size {} is not a multiple of local size {} in dimension {}",
+                    global[d], local[d], d
+                )));
+            }
+        }
+        Ok((dims, global, offset, local))
+    }
+
+    /// Runs the whole NDRange and writes results back into the buffers.
+    pub fn run(mut self) -> crate::Result<()> {
+        let func = self.func;
+        let launch_error = |m: String| InterpError::Launch(format!("{}: {}", func.name, m));
+        if self.args.len() != func.params.len() {
+            return Err(launch_error(format!("takes {} arguments, {} given", func.params.len(), self.args.len())).into());
+        }
+        let (dims, global_size, offset, local_size) = self.work_sizes()?;
+
+        let mut slots = Vec::with_capacity(self.args.len());
+        let (mut buffers, mut local_bytes) = (0u16, 0usize);
+        for (i, (param, arg)) in func.params.iter().zip(&self.args).enumerate() {
+            let slot = match (&param.ty, arg) {
+                (Type::Ptr(_, Space::Global | Space::Constant), Arg::Buffer(_)) => {
+                    buffers += 1;
+                    exec::ArgSlot::Buffer(buffers - 1)
+                }
+                (Type::Ptr(_, Space::Local), Arg::Local(len)) => {
+                    let offset = local_bytes.next_multiple_of(16);
+                    local_bytes = offset + len;
+                    exec::ArgSlot::Local(offset)
+                }
+                (Type::Num(s, n), Arg::Scalar(bytes))
+                    if bytes.len() == param.ty.size() || (*n == 3 && bytes.len() == 3 * s.size()) =>
+                {
+                    exec::ArgSlot::Bytes(bytes.clone())
+                }
+                (ty, arg) => {
+                    let given = match arg {
+                        Arg::Buffer(_) => "a buffer".to_string(),
+                        Arg::Scalar(bytes) => format!("a {}-byte scalar", bytes.len()),
+                        Arg::Local(_) => "local memory".to_string(),
+                    };
+                    return Err(launch_error(format!("argument {} ({} {}) given {}", i, ty, param.name, given)).into());
+                }
+            };
+            slots.push(slot);
+        }
+
+        let mut launch = exec::Launch {
+            unit: &self.program.unit,
+            kernel: func,
+            dims,
+            global_size,
+            local_size,
+            offset,
+            args: slots,
+            locals: HashMap::new(),
+            local_bytes: 0,
+            constant: Vec::new(),
+            constant_vars: Vec::new(),
+            uses_barrier: self.program.uses_barrier,
+        };
+        launch.prepare(local_bytes)?;
+
+        let mut shared = exec::Shared::default();
+        for arg in &mut self.args {
+            if let Arg::Buffer(buffer) = arg {
+                shared.buffers.push(std::mem::take(&mut buffer.bytes));
+            }
+        }
+        let (global, local) = (&global_size[..dims as usize], &local_size[..dims as usize]);
+        let span = span!(Level::Debug, "interpret {} over {:?} in groups of {:?}", func.name, global, local);
+        let result = span.finish(launch.run(&mut shared));
+        let mut returned = shared.buffers.into_iter();
+        for arg in &mut self.args {
+            if let Arg::Buffer(buffer) = arg {
+                buffer.bytes = returned.next().expect("one entry per buffer argument");
+            }
+        }
+        Ok(result?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    /// Runs kernel `k` with a single output buffer of `len` elements.
+    fn run<T: OclPrm>(src: &str, global: usize, len: usize) -> crate::Result<Vec<T>> {
+        let program = Program::build(src)?;
+        let mut out = Buffer::new::<T>(len);
+        program.kernel("k")?.arg_buf(&mut out).global_work_size(global).run()?;
+        Ok(out.to_vec())
+    }
+
+    fn runtime_error(result: crate::Result<Vec<i32>>) -> String {
+        match result {
+            Err(Error::Interp(InterpError::Runtime { message, .. })) => message,
+            other => panic!("expected a runtime error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn swizzles_read_and_write_components() {
+        let src = r#"
+            __kernel void k(__global float *out) {
+                float4 v = (float4)(1.0f, 2.0f, 3.0f, 4.0f);
+                float2 m = v.s23;
+                out[0] = m.x;
+                out[1] = m.y;
+                out[2] = v.hi.y;
+                out[3] = v.lo.x;
+                v.hi = v.lo;
+                v.s10 = (float2)(7.0f, 8.0f);
+                vstore4(v, 1, out);
+                float8 w = (float8)(v, v.wzyx);
+                vstore4(w.odd, 2, out);
+            }
+        "#;
+        let out = run::<f32>(src, 1, 12).unwrap();
+        assert_eq!(out, [3.0, 4.0, 4.0, 1.0, 8.0, 7.0, 1.0, 2.0, 7.0, 2.0, 1.0, 8.0]);
+    }
+
+    #[test]
+    fn vector_arithmetic_and_conversions() {
+        let src = r#"
+            __kernel void k(__global int *out) {
+                int4 a = (int4)(1, -2, 3, 7);
+                int4 b = a * 2 + (int4)(1);
+                vstore4(b, 0, out);
+                vstore4(b / 2, 1, out);
+                vstore4(a > (int4)(2), 2, out);
+                float4 f = convert_float4(b) * 0.5f;
+                vstore4(convert_int4_rte(f), 3, out);
+                vstore4(convert_int4(f), 4, out);
+                vstore4(convert_int4(convert_uchar4_sat((int4)(-5, 100, 300, 255))), 5, out);
+                out[24] = as_int(1.0f);
+            }
+        "#;
+        let out = run::<i32>(src, 1, 25).unwrap();
+        assert_eq!(out[0..4], [3, -3, 7, 15]);
+        assert_eq!(out[4..8], [1, -1, 3, 7]);
+        // Vector comparisons are -1 where true.
+        assert_eq!(out[8..12], [0, 0, -1, -1]);
+        assert_eq!(out[12..16], [2, -2, 4, 8]);
+        assert_eq!(out[16..20], [1, -1, 3, 7]);
+        assert_eq!(out[20..24], [0, 100, 255, 255]);
+        assert_eq!(out[24], 0x3f80_0000);
+    }
+
+    #[test]
+    fn barriers_order_local_memory_across_work_items() {
+        let src = r#"
+            __kernel void k(__global int *out, __local int *tmp) {
+                int l = get_local_id(0), n = get_local_size(0);
+                tmp[l] = get_global_id(0);
+                barrier(CLK_LOCAL_MEM_FENCE);
+                out[get_global_id(0)] = tmp[n - 1 - l];
+                barrier(CLK_LOCAL_MEM_FENCE);
+                for (int s = n / 2; s > 0; s >>= 1) {
+                    if (l < s) {
+                        tmp[l] += tmp[l + s];
+                    }
+                    barrier(CLK_LOCAL_MEM_FENCE);
+                }
+                if (l == 0) {
+                    out[get_global_size(0) + get_group_id(0)] = tmp[0];
+                }
+            }
+        "#;
+        let program = Program::build(src).unwrap();
+        let mut out = Buffer::new::<i32>(34);
+        program
+            .kernel("k")
+            .unwrap()
+            .arg_buf(&mut out)
+            .arg_local::<i32>(16)
+            .global_work_size(32)
+            .local_work_size(16)
+            .run()
+            .unwrap();
+        let out = out.to_vec::<i32>();
+        let reversed: Vec<i32> = (0..2).flat_map(|g| (0..16).rev().map(move |l| g * 16 + l)).collect();
+        assert_eq!(out[..32], reversed[..]);
+        assert_eq!(out[32..], [(0..16).sum::<i32>(), (16..32).sum::<i32>()]);
+    }
+
+    #[test]
+    fn out_of_bounds_accesses_fail() {
+        let write = "__kernel void k(__global int *out) { out[get_global_id(0) + 1] = 1; }";
+        let message = runtime_error(run::<i32>(write, 4, 4));
+        assert!(message.contains("out-of-bounds __global write"), "{}", message);
+
+        let read = "__kernel void k(__global int *out) { out[0] = out[get_global_id(0) + 4]; }";
+        let message = runtime_error(run::<i32>(read, 1, 4));
+        assert!(message.contains("out-of-bounds __global read"), "{}", message);
+
+        let private = r#"
+            __kernel void k(__global int *out) {
+                int a[4];
+                a[get_global_id(0) + 1] = 1;
+                out[get_global_id(0)] = a[1];
+            }
+        "#;
+        let message = runtime_error(run::<i32>(private, 4, 4));
+        assert!(message.contains("out of bounds for array of 4"), "{}", message);
+    }
+
+    #[test]
+    fn common_builtins() {
+        let src = r#"
+            __kernel void k(__global float *out) {
+                out[0] = clamp(5.0f, 0.0f, 2.0f);
+                out[1] = mad(2.0f, 3.0f, 1.0f);
+                out[2] = fmin(-1.0f, 4.0f) + fmax(-1.0f, 4.0f);
+                out[3] = sqrt(16.0f) + fabs(-0.5f) + floor(-1.5f);
+                out[4] = dot((float4)(1.0f, 2.0f, 3.0f, 4.0f), (float4)(1.0f));
+                out[5] = length((float2)(3.0f, 4.0f));
+                out[6] = select(1.0f, 2.0f, 1);
+                out[7] = mix(0.0f, 10.0f, 0.25f);
+                out[8] = popcount(0xf0f0u) + clz(1u) + rotate(1u, 4u) + min(3, -3) + max(2u, 9u);
+                out[9] = get_global_id(1) * 100 + get_num_groups(0) * 10 + get_group_id(0);
+            }
+        "#;
+        let program = Program::build(src).unwrap();
+        let mut out = Buffer::new::<f32>(10);
+        program
+            .kernel("k")
+            .unwrap()
+            .arg_buf(&mut out)
+            .global_work_size([1, 1])
+            .run()
+            .unwrap();
+        let out = out.to_vec::<f32>();
+        assert_eq!(out[..9], [2.0, 7.0, 3.0, 2.5, 10.0, 5.0, 2.0, 2.5, 8.0 + 31.0 + 16.0 - 3.0 + 9.0]);
+        assert_eq!(out[9], 10.0);
+    }
+}
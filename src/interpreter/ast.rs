@@ -0,0 +1,175 @@
+//! Syntax tree produced by the parser.
+
+use super::types::{Scalar, Space, Type};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+    And,
+    Or,
+    Xor,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    LogAnd,
+    LogOr,
+}
+
+impl BinOp {
+    pub fn is_comparison(self) -> bool {
+        matches!(self, BinOp::Lt | BinOp::Gt | BinOp::Le | BinOp::Ge | BinOp::Eq | BinOp::Ne)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnOp {
+    Neg,
+    Plus,
+    Not,
+    BitNot,
+}
+
+/// Vector component selection after `.`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Swizzle {
+    Lanes(Vec<u8>),
+    Lo,
+    Hi,
+    Even,
+    Odd,
+}
+
+impl Swizzle {
+    /// Lane indices for a vector of `len` components.
+    pub fn lanes(&self, len: u8) -> Result<Vec<u8>, String> {
+        // Three-component vectors behave like four for lo/hi/even/odd.
+        let n = if len == 3 { 4 } else { len };
+        let lanes: Vec<u8> = match self {
+            Swizzle::Lanes(lanes) => lanes.clone(),
+            Swizzle::Lo => (0..n / 2).collect(),
+            Swizzle::Hi => (n / 2..n).collect(),
+            Swizzle::Even => (0..n).step_by(2).collect(),
+            Swizzle::Odd => (1..n).step_by(2).collect(),
+        };
+        if len == 1 {
+            return Err("swizzle applied to a scalar".into());
+        }
+        let limit = if matches!(self, Swizzle::Lanes(_)) { len } else { n };
+        match lanes.iter().find(|&&l| l >= limit) {
+            Some(l) => Err(format!("component {} out of range for a {}-component vector", l, len)),
+            None => Ok(lanes),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Int(u64, Scalar),
+    Float(f64, Scalar),
+    Str(String),
+    Var(String),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    /// `=` when the operator is `None`, otherwise a compound assignment.
+    Assign(Option<BinOp>, Box<Expr>, Box<Expr>),
+    /// `++` / `--`, prefix or postfix.
+    Step { expr: Box<Expr>, pre: bool, inc: bool },
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Swizzle(Box<Expr>, Swizzle),
+    Cast(Type, Box<Expr>),
+    /// Vector literal such as `(float4)(a, b.xy, 1.0f)`.
+    Vector(Type, Vec<Expr>),
+    /// Brace initializer; only valid in declarations.
+    Init(Vec<Expr>),
+    Deref(Box<Expr>),
+    AddrOf(Box<Expr>),
+    SizeOf(Type),
+    SizeOfValue(Box<Expr>),
+    Comma(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Whether the expression names storage (and so can be assigned to).
+    pub fn is_place(&self) -> bool {
+        match self {
+            Expr::Var(_) | Expr::Deref(_) | Expr::Index(..) => true,
+            Expr::Swizzle(inner, _) => inner.is_place(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decl {
+    /// Unique within the program; identifies `__local` allocations.
+    pub id: usize,
+    pub name: String,
+    pub ty: Type,
+    pub space: Space,
+    pub init: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    Empty,
+    Expr(Expr),
+    Decls(Vec<Decl>),
+    Block(Vec<Stmt>),
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    While(Expr, Box<Stmt>),
+    DoWhile(Box<Stmt>, Expr),
+    For(Option<Box<Stmt>>, Option<Expr>, Option<Expr>, Box<Stmt>),
+    /// Body statements with `Case` / `Default` labels inline.
+    Switch(Expr, Vec<Stmt>),
+    Case(Expr),
+    Default,
+    Break,
+    Continue,
+    Return(Option<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub line: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub ty: Type,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub ret: Type,
+    pub params: Vec<Param>,
+    pub body: Vec<Stmt>,
+    pub is_kernel: bool,
+    pub line: u32,
+}
+
+/// A parsed program: functions and program-scope `__constant` data.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Unit {
+    pub functions: Vec<Function>,
+    pub constants: Vec<Decl>,
+}
+
+impl Unit {
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+}
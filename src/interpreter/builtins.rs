@@ -0,0 +1,884 @@
+//! Built-in functions and constants.
+
+use super::ast::Expr;
+use super::exec::{Fault, Item, Res};
+use super::types::{Scalar, Type, parse_num_type};
+use super::value::{Num, Value};
+use std::io::Write;
+
+/// Values of the predefined macros the interpreter knows.
+pub fn constant(name: &str) -> Option<Value> {
+    let float = |x: f64| Some(Value::Num(Num::from_f64(Scalar::Float, x)));
+    let double = |x: f64| Some(Value::Num(Num::from_f64(Scalar::Double, x)));
+    let int = |ty: Scalar, x: i64| Some(Value::Num(Num::from_i64(ty, x)));
+    use std::f64::consts;
+    match name {
+        "CLK_LOCAL_MEM_FENCE" => int(Scalar::UInt, 1),
+        "CLK_GLOBAL_MEM_FENCE" => int(Scalar::UInt, 2),
+        "CLK_IMAGE_MEM_FENCE" => int(Scalar::UInt, 4),
+        "M_PI_F" => float(consts::PI),
+        "M_PI_2_F" => float(consts::FRAC_PI_2),
+        "M_PI_4_F" => float(consts::FRAC_PI_4),
+        "M_1_PI_F" => float(consts::FRAC_1_PI),
+        "M_2_PI_F" => float(consts::FRAC_2_PI),
+        "M_E_F" => float(consts::E),
+        "M_LN2_F" => float(consts::LN_2),
+        "M_LN10_F" => float(consts::LN_10),
+        "M_LOG2E_F" => float(consts::LOG2_E),
+        "M_LOG10E_F" => float(consts::LOG10_E),
+        "M_SQRT2_F" => float(consts::SQRT_2),
+        "M_SQRT1_2_F" => float(consts::FRAC_1_SQRT_2),
+        "M_PI" => double(consts::PI),
+        "M_PI_2" => double(consts::FRAC_PI_2),
+        "M_PI_4" => double(consts::FRAC_PI_4),
+        "M_E" => double(consts::E),
+        "M_LN2" => double(consts::LN_2),
+        "M_SQRT2" => double(consts::SQRT_2),
+        "MAXFLOAT" | "FLT_MAX" => float(f32::MAX as f64),
+        "FLT_MIN" => float(f32::MIN_POSITIVE as f64),
+        "FLT_EPSILON" => float(f32::EPSILON as f64),
+        "HUGE_VALF" | "INFINITY" => float(f64::INFINITY),
+        "NAN" => float(f64::NAN),
+        "DBL_MAX" => double(f64::MAX),
+        "DBL_MIN" => double(f64::MIN_POSITIVE),
+        "DBL_EPSILON" => double(f64::EPSILON),
+        "HUGE_VAL" => double(f64::INFINITY),
+        "CHAR_BIT" => int(Scalar::Int, 8),
+        "CHAR_MAX" | "SCHAR_MAX" => int(Scalar::Int, i8::MAX as i64),
+        "CHAR_MIN" | "SCHAR_MIN" => int(Scalar::Int, i8::MIN as i64),
+        "UCHAR_MAX" => int(Scalar::Int, u8::MAX as i64),
+        "SHRT_MAX" => int(Scalar::Int, i16::MAX as i64),
+        "SHRT_MIN" => int(Scalar::Int, i16::MIN as i64),
+        "USHRT_MAX" => int(Scalar::Int, u16::MAX as i64),
+        "INT_MAX" => int(Scalar::Int, i32::MAX as i64),
+        "INT_MIN" => int(Scalar::Int, i32::MIN as i64),
+        "UINT_MAX" => int(Scalar::UInt, u32::MAX as i64),
+        "LONG_MAX" => int(Scalar::Long, i64::MAX),
+        "LONG_MIN" => int(Scalar::Long, i64::MIN),
+        "ULONG_MAX" => int(Scalar::ULong, -1),
+        _ => None,
+    }
+}
+
+fn arity(name: &str, args: &[Value], n: usize) -> Res<()> {
+    if args.len() != n {
+        return Err(format!("{} takes {} arguments, {} given", name, n, args.len()).into());
+    }
+    Ok(())
+}
+
+fn num(v: &Value) -> Res<Num> {
+    Ok(v.clone().num()?)
+}
+
+/// The argument as a floating-point number (integers become `float`).
+fn float_arg(v: &Value) -> Res<Num> {
+    let n = num(v)?;
+    Ok(if n.ty.is_float() { n } else { n.convert(Scalar::Float) })
+}
+
+fn map1(a: Num, f: impl Fn(f64) -> f64) -> Num {
+    Num::from_fn_f64(a.ty, a.len, |i| f(a.f(i)))
+}
+
+/// Widens a scalar operand to the other's vector length; the result type is
+/// the vector's (or the first operand's) element type.
+fn pair(a: Num, b: Num) -> Res<(Num, Num)> {
+    let len = match (a.len, b.len) {
+        (x, y) if x == y => x,
+        (1, y) => y,
+        (x, 1) => x,
+        (x, y) => return Err(format!("arguments have different vector lengths ({} and {})", x, y).into()),
+    };
+    let ty = if a.len >= b.len { a.ty } else { b.ty };
+    Ok((a.convert(ty).splat(len), b.convert(ty).splat(len)))
+}
+
+fn map2(a: Num, b: Num, f: impl Fn(f64, f64) -> f64) -> Res<Num> {
+    let (a, b) = pair(a, b)?;
+    Ok(Num::from_fn_f64(a.ty, a.len, |i| f(a.f(i), b.f(i))))
+}
+
+fn map3(a: Num, b: Num, c: Num, f: impl Fn(f64, f64, f64) -> f64) -> Res<Num> {
+    let (a, b) = pair(a, b)?;
+    let (a, c) = pair(a, c)?;
+    let (b, _) = pair(b, c)?;
+    Ok(Num::from_fn_f64(a.ty, a.len, |i| f(a.f(i), b.f(i), c.f(i))))
+}
+
+/// Integer lane as a wide signed value, respecting the type's signedness.
+fn wide(n: &Num, i: usize) -> i128 {
+    if n.ty.is_signed() { n.lanes[i] as i64 as i128 } else { n.lanes[i] as i128 }
+}
+
+fn range(ty: Scalar) -> (i128, i128) {
+    let bits = ty.bits();
+    match ty {
+        Scalar::Bool => (0, 1),
+        _ if ty.is_signed() => (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1),
+        _ => (0, (1i128 << bits) - 1),
+    }
+}
+
+fn int_map(a: Num, f: impl Fn(i128) -> i128) -> Num {
+    Num::from_fn_u64(a.ty, a.len, |i| f(wide(&a, i)) as u64)
+}
+
+fn int_map2(a: Num, b: Num, f: impl Fn(i128, i128) -> i128) -> Res<Num> {
+    let (a, b) = pair(a, b)?;
+    Ok(Num::from_fn_u64(a.ty, a.len, |i| f(wide(&a, i), wide(&b, i)) as u64))
+}
+
+/// Relational results: `0`/`1` for scalars, `0`/`-1` for vectors.
+fn relation(a: &Num, ty: Scalar, test: impl Fn(usize) -> bool) -> Num {
+    if a.len == 1 {
+        Num::int(test(0) as i64)
+    } else {
+        let out = if ty == Scalar::Double { Scalar::Long } else { Scalar::Int };
+        Num::from_fn_u64(out, a.len, |i| if test(i) { u64::MAX } else { 0 })
+    }
+}
+
+fn round_mode(x: f64, mode: &str) -> f64 {
+    match mode {
+        "rte" => x.round_ties_even(),
+        "rtp" => x.ceil(),
+        "rtn" => x.floor(),
+        _ => x.trunc(),
+    }
+}
+
+fn convert(name: &str, rest: &str, v: Num) -> Res<Num> {
+    let mut parts = rest.split('_');
+    let type_name = parts.next().unwrap_or_default();
+    let (to, len) = parse_num_type(type_name).ok_or_else(|| format!("unknown function {}", name))?;
+    let (mut sat, mut mode) = (false, "");
+    for part in parts {
+        match part {
+            "sat" => sat = true,
+            "rte" | "rtz" | "rtp" | "rtn" => mode = part,
+            _ => return Err(format!("unknown function {}", name).into()),
+        }
+    }
+    if v.len != len {
+        return Err(format!("{} needs a {}-component argument, got {}", name, len, v.len).into());
+    }
+    if to.is_float() || (!v.ty.is_float() && !sat) {
+        return Ok(v.convert(to));
+    }
+    let (lo, hi) = range(to);
+    Ok(Num::from_fn_u64(to, len, |i| {
+        let x = if v.ty.is_float() {
+            let x = round_mode(v.f(i), mode);
+            if x.is_nan() {
+                0
+            } else if sat {
+                (x.clamp(lo as f64, hi as f64)) as i128
+            } else {
+                x as i128
+            }
+        } else {
+            wide(&v, i).clamp(lo, hi)
+        };
+        x as u64
+    }))
+}
+
+fn reinterpret(name: &str, to: Scalar, len: u8, v: Num) -> Res<Num> {
+    let mut bytes = Vec::with_capacity(128);
+    v.write_bytes(&mut bytes);
+    let from_size = Type::Num(v.ty, v.len).size();
+    if from_size != Type::Num(to, len).size() {
+        return Err(format!("{} needs an argument of {} bytes, got {}", name, Type::Num(to, len).size(), from_size).into());
+    }
+    bytes.resize(from_size, 0);
+    Ok(Num::read_bytes(to, len, &bytes))
+}
+
+fn pointer(name: &str, v: &Value) -> Res<(Type, super::value::Addr)> {
+    match v {
+        Value::Ptr(ty, addr) => Ok((ty.clone(), *addr)),
+        _ => Err(format!("{} needs a pointer argument", name).into()),
+    }
+}
+
+fn work_dim_arg(args: &[Value]) -> Res<usize> {
+    let d = num(&args[0])?.u(0);
+    Ok(d as usize)
+}
+
+/// Calls the built-in `name`, or returns `None` if there is no such
+/// function.
+pub fn call(item: &mut Item, name: &str, args: Vec<Value>) -> Res<Option<Value>> {
+    let ulong = |x: usize| Value::Num(Num::from_u64(Scalar::ULong, x as u64));
+    let launch = item.launch;
+    let dim_value = |args: &[Value], of: &dyn Fn(usize) -> usize, default: usize| -> Res<Value> {
+        arity(name, args, 1)?;
+        let d = work_dim_arg(args)?;
+        Ok(ulong(if d < launch.dims as usize { of(d) } else { default }))
+    };
+    let v = match name {
+        "get_work_dim" => Value::Num(Num::from_u64(Scalar::UInt, launch.dims as u64)),
+        "get_global_size" => dim_value(&args, &|d| launch.global_size[d], 1)?,
+        "get_local_size" | "get_enqueued_local_size" => dim_value(&args, &|d| launch.local_size[d], 1)?,
+        "get_num_groups" => dim_value(&args, &|d| launch.global_size[d] / launch.local_size[d], 1)?,
+        "get_global_offset" => dim_value(&args, &|d| launch.offset[d], 0)?,
+        "get_global_id" => {
+            let id = item.global_id;
+            dim_value(&args, &|d| id[d], 0)?
+        }
+        "get_local_id" => {
+            let id = item.local_id;
+            dim_value(&args, &|d| id[d], 0)?
+        }
+        "get_group_id" => {
+            let id = item.group_id;
+            dim_value(&args, &|d| id[d], 0)?
+        }
+        "get_global_linear_id" => {
+            let [gx, gy, _] = launch.global_size;
+            let [x, y, z] = [0, 1, 2].map(|d| item.global_id[d] - launch.offset[d]);
+            ulong((z * gy + y) * gx + x)
+        }
+        "get_local_linear_id" => {
+            let [lx, ly, _] = launch.local_size;
+            let [x, y, z] = item.local_id;
+            ulong((z * ly + y) * lx + x)
+        }
+        "barrier" | "work_group_barrier" => {
+            item.barrier()?;
+            Value::Void
+        }
+        "mem_fence" | "read_mem_fence" | "write_mem_fence" => Value::Void,
+        _ => return call_math(item, name, args),
+    };
+    Ok(Some(v))
+}
+
+fn unary_float(name: &str) -> Option<fn(f64) -> f64> {
+    let name = name.strip_prefix("native_").or_else(|| name.strip_prefix("half_")).unwrap_or(name);
+    Some(match name {
+        "acos" => f64::acos,
+        "acosh" => f64::acosh,
+        "acospi" => |x| x.acos() / std::f64::consts::PI,
+        "asin" => f64::asin,
+        "asinh" => f64::asinh,
+        "asinpi" => |x| x.asin() / std::f64::consts::PI,
+        "atan" => f64::atan,
+        "atanh" => f64::atanh,
+        "atanpi" => |x| x.atan() / std::f64::consts::PI,
+        "cbrt" => f64::cbrt,
+        "ceil" => f64::ceil,
+        "cos" => f64::cos,
+        "cosh" => f64::cosh,
+        "cospi" => |x| (x * std::f64::consts::PI).cos(),
+        "exp" => f64::exp,
+        "exp2" => f64::exp2,
+        "exp10" => |x| 10f64.powf(x),
+        "expm1" => f64::exp_m1,
+        "fabs" => f64::abs,
+        "floor" => f64::floor,
+        "log" => f64::ln,
+        "log2" => f64::log2,
+        "log10" => f64::log10,
+        "log1p" => f64::ln_1p,
+        "logb" => |x| x.abs().log2().floor(),
+        "rint" => f64::round_ties_even,
+        "round" => f64::round,
+        "rsqrt" => |x| 1.0 / x.sqrt(),
+        "recip" => |x| 1.0 / x,
+        "sin" => f64::sin,
+        "sinh" => f64::sinh,
+        "sinpi" => |x| (x * std::f64::consts::PI).sin(),
+        "sqrt" => f64::sqrt,
+        "tan" => f64::tan,
+        "tanh" => f64::tanh,
+        "tanpi" => |x| (x * std::f64::consts::PI).tan(),
+        "trunc" => f64::trunc,
+        "degrees" => f64::to_degrees,
+        "radians" => f64::to_radians,
+        "sign" => |x| if x.is_nan() { 0.0 } else if x == 0.0 { x } else { x.signum() },
+        _ => return None,
+    })
+}
+
+fn binary_float(name: &str) -> Option<fn(f64, f64) -> f64> {
+    let name = name.strip_prefix("native_").or_else(|| name.strip_prefix("half_")).unwrap_or(name);
+    Some(match name {
+        "atan2" => f64::atan2,
+        "copysign" => f64::copysign,
+        "fdim" => |x, y| if x > y { x - y } else { 0.0 },
+        "fmod" => |x, y| x % y,
+        "hypot" => f64::hypot,
+        "pow" | "powr" => f64::powf,
+        "divide" => |x, y| x / y,
+        "remainder" => |x, y| x - (x / y).round_ties_even() * y,
+        "step" => |edge, x| if x < edge { 0.0 } else { 1.0 },
+        "maxmag" => |x, y| if x.abs() > y.abs() { x } else if y.abs() > x.abs() { y } else { x.max(y) },
+        "minmag" => |x, y| if x.abs() < y.abs() { x } else if y.abs() < x.abs() { y } else { x.min(y) },
+        _ => return None,
+    })
+}
+
+fn geometric(name: &str, args: &[Value]) -> Res<Option<Value>> {
+    let length = |v: &Num| (0..v.len as usize).map(|i| v.f(i) * v.f(i)).sum::<f64>().sqrt();
+    let scalar = |ty: Scalar, x: f64| Value::Num(Num::from_f64(ty, x));
+    let name = name.strip_prefix("fast_").unwrap_or(name);
+    Ok(Some(match name {
+        "dot" => {
+            arity(name, args, 2)?;
+            let (a, b) = pair(float_arg(&args[0])?, float_arg(&args[1])?)?;
+            scalar(a.ty, (0..a.len as usize).map(|i| a.f(i) * b.f(i)).sum())
+        }
+        "cross" => {
+            arity(name, args, 2)?;
+            let (a, b) = (float_arg(&args[0])?, float_arg(&args[1])?);
+            if !matches!(a.len, 3 | 4) || a.len != b.len {
+                return Err("cross needs two 3- or 4-component vectors".into());
+            }
+            let c = [
+                a.f(1) * b.f(2) - a.f(2) * b.f(1),
+                a.f(2) * b.f(0) - a.f(0) * b.f(2),
+                a.f(0) * b.f(1) - a.f(1) * b.f(0),
+                0.0,
+            ];
+            Value::Num(Num::from_fn_f64(a.ty, a.len, |i| c[i]))
+        }
+        "length" => {
+            arity(name, args, 1)?;
+            let a = float_arg(&args[0])?;
+            scalar(a.ty, length(&a))
+        }
+        "distance" => {
+            arity(name, args, 2)?;
+            let (a, b) = pair(float_arg(&args[0])?, float_arg(&args[1])?)?;
+            let d = Num::from_fn_f64(a.ty, a.len, |i| a.f(i) - b.f(i));
+            scalar(a.ty, length(&d))
+        }
+        "normalize" => {
+            arity(name, args, 1)?;
+            let a = float_arg(&args[0])?;
+            let len = length(&a);
+            Value::Num(map1(a, |x| if len == 0.0 { x } else { x / len }))
+        }
+        _ => return Ok(None),
+    }))
+}
+
+fn relational(name: &str, args: &[Value]) -> Res<Option<Value>> {
+    let compare = |f: fn(f64, f64) -> bool| -> Res<Value> {
+        arity(name, args, 2)?;
+        let (a, b) = pair(float_arg(&args[0])?, float_arg(&args[1])?)?;
+        Ok(Value::Num(relation(&a, a.ty, |i| f(a.f(i), b.f(i)))))
+    };
+    let test = |f: fn(f64) -> bool| -> Res<Value> {
+        arity(name, args, 1)?;
+        let a = float_arg(&args[0])?;
+        Ok(Value::Num(relation(&a, a.ty, |i| f(a.f(i)))))
+    };
+    Ok(Some(match name {
+        "isequal" => compare(|x, y| x == y)?,
+        "isnotequal" => compare(|x, y| x != y)?,
+        "isgreater" => compare(|x, y| x > y)?,
+        "isgreaterequal" => compare(|x, y| x >= y)?,
+        "isless" => compare(|x, y| x < y)?,
+        "islessequal" => compare(|x, y| x <= y)?,
+        "islessgreater" => compare(|x, y| x != y && !x.is_nan() && !y.is_nan())?,
+        "isordered" => compare(|x, y| !x.is_nan() && !y.is_nan())?,
+        "isunordered" => compare(|x, y| x.is_nan() || y.is_nan())?,
+        "isfinite" => test(f64::is_finite)?,
+        "isinf" => test(f64::is_infinite)?,
+        "isnan" => test(f64::is_nan)?,
+        "isnormal" => test(|x| (x as f32).is_normal())?,
+        "signbit" => test(f64::is_sign_negative)?,
+        "any" | "all" => {
+            arity(name, args, 1)?;
+            let a = num(&args[0])?;
+            let mut msb = (0..a.len as usize).map(|i| a.lane_msb(i));
+            let result = if name == "any" { msb.any(|b| b) } else { msb.all(|b| b) };
+            Value::Num(Num::int(result as i64))
+        }
+        "select" => {
+            arity(name, args, 3)?;
+            let (a, b, c) = (num(&args[0])?, num(&args[1])?, num(&args[2])?);
+            let (a, b) = pair(a, b)?;
+            if c.len != a.len {
+                return Err("select: condition and operands have different vector lengths".into());
+            }
+            let pick = |i| if a.len == 1 { c.lane_true(i) } else { c.lane_msb(i) };
+            Value::Num(Num::from_fn_u64(a.ty, a.len, |i| if pick(i) { b.lanes[i] } else { a.lanes[i] }))
+        }
+        "bitselect" => {
+            arity(name, args, 3)?;
+            let (a, b, c) = (num(&args[0])?, num(&args[1])?, num(&args[2])?);
+            let bits = |n: &Num| -> Res<Num> { reinterpret(name, n.ty.unsigned(), n.len, *n) };
+            let (ua, ub, uc) = (bits(&a)?, bits(&b)?, bits(&c)?);
+            let mixed = Num::from_fn_u64(ua.ty, ua.len, |i| (ua.lanes[i] & !uc.lanes[i]) | (ub.lanes[i] & uc.lanes[i]));
+            Value::Num(reinterpret(name, a.ty, a.len, mixed)?)
+        }
+        _ => return Ok(None),
+    }))
+}
+
+fn call_math(item: &mut Item, name: &str, args: Vec<Value>) -> Res<Option<Value>> {
+    if let Some(f) = unary_float(name) {
+        arity(name, &args, 1)?;
+        return Ok(Some(Value::Num(map1(float_arg(&args[0])?, f))));
+    }
+    if let Some(f) = binary_float(name) {
+        arity(name, &args, 2)?;
+        return Ok(Some(Value::Num(map2(float_arg(&args[0])?, float_arg(&args[1])?, f)?)));
+    }
+    if let Some(v) = geometric(name, &args)? {
+        return Ok(Some(v));
+    }
+    if let Some(v) = relational(name, &args)? {
+        return Ok(Some(v));
+    }
+    if let Some(rest) = name.strip_prefix("convert_") {
+        arity(name, &args, 1)?;
+        return Ok(Some(Value::Num(convert(name, rest, num(&args[0])?)?)));
+    }
+    if let Some((to, len)) = name.strip_prefix("as_").and_then(parse_num_type) {
+        arity(name, &args, 1)?;
+        return Ok(Some(Value::Num(reinterpret(name, to, len, num(&args[0])?)?)));
+    }
+    if let Some(v) = memory(item, name, &args)? {
+        return Ok(Some(v));
+    }
+    let any_float = args.iter().any(|a| matches!(a, Value::Num(n) if n.ty.is_float()));
+    let v = match name {
+        "fmin" | "fmax" | "min" | "max" if any_float => {
+            arity(name, &args, 2)?;
+            let f = if name.ends_with("min") { f64::min } else { f64::max };
+            map2(float_arg(&args[0])?, float_arg(&args[1])?, f)?
+        }
+        "clamp" if any_float => {
+            arity(name, &args, 3)?;
+            map3(float_arg(&args[0])?, float_arg(&args[1])?, float_arg(&args[2])?, |x, lo, hi| x.max(lo).min(hi))?
+        }
+        "fma" | "mad" => {
+            arity(name, &args, 3)?;
+            map3(float_arg(&args[0])?, float_arg(&args[1])?, float_arg(&args[2])?, |a, b, c| a.mul_add(b, c))?
+        }
+        "mix" => {
+            arity(name, &args, 3)?;
+            map3(float_arg(&args[0])?, float_arg(&args[1])?, float_arg(&args[2])?, |x, y, a| x + (y - x) * a)?
+        }
+        "smoothstep" => {
+            arity(name, &args, 3)?;
+            let (e0, e1, x) = (float_arg(&args[0])?, float_arg(&args[1])?, float_arg(&args[2])?);
+            map3(x, e0, e1, |x, e0, e1| {
+                let t = ((x - e0) / (e1 - e0)).clamp(0.0, 1.0);
+                t * t * (3.0 - 2.0 * t)
+            })?
+        }
+        "ldexp" | "pown" | "rootn" => {
+            arity(name, &args, 2)?;
+            let f: fn(f64, f64) -> f64 = match name {
+                "ldexp" => |x, n| x * 2f64.powi(n as i32),
+                "pown" => |x, n| x.powi(n as i32),
+                _ => |x, n| x.powf(1.0 / n),
+            };
+            let x = float_arg(&args[0])?;
+            let n = num(&args[1])?.convert(x.ty);
+            map2(x, n, f)?
+        }
+        "abs" | "abs_diff" if !any_float => {
+            let result = if name == "abs" {
+                arity(name, &args, 1)?;
+                int_map(num(&args[0])?, i128::abs)
+            } else {
+                arity(name, &args, 2)?;
+                int_map2(num(&args[0])?, num(&args[1])?, |a, b| (a - b).abs())?
+            };
+            result.convert(result.ty.unsigned())
+        }
+        "min" | "max" | "add_sat" | "sub_sat" | "hadd" | "rhadd" | "mul_hi" | "mul24" | "rotate" => {
+            arity(name, &args, 2)?;
+            let (a, b) = (num(&args[0])?, num(&args[1])?);
+            let ty = if a.len >= b.len { a.ty } else { b.ty };
+            let (lo, hi) = range(ty);
+            let bits = ty.bits();
+            let mask = (1i128 << bits) - 1;
+            int_map2(a, b, |x, y| match name {
+                "min" => x.min(y),
+                "max" => x.max(y),
+                "add_sat" => (x + y).clamp(lo, hi),
+                "sub_sat" => (x - y).clamp(lo, hi),
+                "hadd" => (x + y) >> 1,
+                "rhadd" => (x + y + 1) >> 1,
+                "mul_hi" => (x * y) >> bits,
+                "mul24" => x * y,
+                _ => {
+                    let s = y.rem_euclid(bits as i128);
+                    let u = x & mask;
+                    ((u << s) | (u >> ((bits as i128 - s) % bits as i128))) & mask
+                }
+            })?
+        }
+        "clamp" | "mad24" | "mad_hi" | "mad_sat" => {
+            arity(name, &args, 3)?;
+            let (a, b, c) = (num(&args[0])?, num(&args[1])?, num(&args[2])?);
+            let (a, b) = pair(a, b)?;
+            let (a, c) = pair(a, c)?;
+            let (b, _) = pair(b, c)?;
+            let (lo, hi) = range(a.ty);
+            let bits = a.ty.bits();
+            Num::from_fn_u64(a.ty, a.len, |i| {
+                let (x, y, z) = (wide(&a, i), wide(&b, i), wide(&c, i));
+                (match name {
+                    "clamp" => x.max(y).min(z),
+                    "mad24" => x * y + z,
+                    "mad_hi" => ((x * y) >> bits) + z,
+                    _ => (x * y + z).clamp(lo, hi),
+                }) as u64
+            })
+        }
+        "clz" | "ctz" | "popcount" => {
+            arity(name, &args, 1)?;
+            let a = num(&args[0])?;
+            let bits = a.ty.bits();
+            let mask = if bits == 64 { u64::MAX } else { (1 << bits) - 1 };
+            Num::from_fn_u64(a.ty, a.len, |i| {
+                let x = a.lanes[i] & mask;
+                (match name {
+                    "clz" => x.leading_zeros() - (64 - bits),
+                    "ctz" => x.trailing_zeros().min(bits),
+                    _ => x.count_ones(),
+                }) as u64
+            })
+        }
+        "upsample" => {
+            arity(name, &args, 2)?;
+            let (hi, lo) = (num(&args[0])?, num(&args[1])?);
+            let ty = match hi.ty.size() {
+                1 => Scalar::Short,
+                2 => Scalar::Int,
+                _ => Scalar::Long,
+            };
+            let ty = if hi.ty.is_signed() { ty } else { ty.unsigned() };
+            let bits = lo.ty.bits();
+            Num::from_fn_u64(ty, hi.len, |i| (hi.lanes[i] << bits) | (lo.lanes[i] & ((1 << bits) - 1)))
+        }
+        "shuffle" | "shuffle2" => {
+            let two = name == "shuffle2";
+            arity(name, &args, if two { 3 } else { 2 })?;
+            let x = num(&args[0])?;
+            let (source, mask) = if two {
+                let y = num(&args[1])?;
+                if y.len != x.len || y.ty != x.ty {
+                    return Err("shuffle2 needs two vectors of the same type".into());
+                }
+                let mut joined = x;
+                joined.len = x.len * 2;
+                joined.lanes[x.len as usize..joined.len as usize].copy_from_slice(&y.lanes[..y.len as usize]);
+                (joined, num(&args[2])?)
+            } else {
+                (x, num(&args[1])?)
+            };
+            if mask.ty.is_float() || mask.ty.is_signed() {
+                return Err(format!("{} needs an unsigned integer mask", name).into());
+            }
+            let idx: Vec<u8> = (0..mask.len as usize).map(|i| (mask.lanes[i] % source.len as u64) as u8).collect();
+            source.select(&idx)
+        }
+        "vec_step" => {
+            arity(name, &args, 1)?;
+            let a = num(&args[0])?;
+            Num::int(if a.len == 3 { 4 } else { a.len as i64 })
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(Value::Num(v)))
+}
+
+/// Built-ins that read or write through pointers.
+fn memory(item: &mut Item, name: &str, args: &[Value]) -> Res<Option<Value>> {
+    if let Some(n) = name.strip_prefix("vload").and_then(|n| n.parse::<u8>().ok()) {
+        arity(name, args, 2)?;
+        let offset = num(&args[0])?.u(0) as i64;
+        let (ty, addr) = pointer(name, &args[1])?;
+        let Type::Num(s, 1) = ty else {
+            return Err(format!("{} needs a pointer to scalars", name).into());
+        };
+        let v = item.load(addr.add(offset * n as i64 * s.size() as i64), &Type::Num(s, n))?;
+        return Ok(Some(v));
+    }
+    if let Some(n) = name.strip_prefix("vstore").and_then(|n| n.parse::<u8>().ok()) {
+        arity(name, args, 3)?;
+        let data = num(&args[0])?;
+        let offset = num(&args[1])?.u(0) as i64;
+        let (ty, addr) = pointer(name, &args[2])?;
+        let Type::Num(s, 1) = ty else {
+            return Err(format!("{} needs a pointer to scalars", name).into());
+        };
+        if data.len != n {
+            return Err(format!("{} needs a {}-component vector", name, n).into());
+        }
+        item.store(addr.add(offset * n as i64 * s.size() as i64), &Type::Num(s, n), Value::Num(data))?;
+        return Ok(Some(Value::Void));
+    }
+    if matches!(name, "sincos" | "fract" | "modf") {
+        arity(name, args, 2)?;
+        let x = float_arg(&args[0])?;
+        let (ty, addr) = pointer(name, &args[1])?;
+        let (result, out) = match name {
+            "sincos" => (map1(x, f64::sin), map1(x, f64::cos)),
+            "fract" => (map1(x, |v| (v - v.floor()).min(f64::from(1.0f32 - f32::EPSILON / 2.0))), map1(x, f64::floor)),
+            _ => (map1(x, |v| v - v.trunc()), map1(x, f64::trunc)),
+        };
+        item.store(addr, &ty, Value::Num(out))?;
+        return Ok(Some(Value::Num(result)));
+    }
+    let Some(op) = name.strip_prefix("atomic_").or_else(|| name.strip_prefix("atom_")) else {
+        return Ok(None);
+    };
+    let operands = match op {
+        "inc" | "dec" => 1,
+        "cmpxchg" => 3,
+        "add" | "sub" | "xchg" | "min" | "max" | "and" | "or" | "xor" => 2,
+        _ => return Ok(None),
+    };
+    arity(name, args, operands)?;
+    let (ty, addr) = pointer(name, &args[0])?;
+    let old = item.load(addr, &ty)?.num()?;
+    let operand = |k: usize| -> Res<Num> { Ok(num(&args[k])?.convert(old.ty)) };
+    let new = match op {
+        "inc" => int_map(old, |x| x + 1),
+        "dec" => int_map(old, |x| x - 1),
+        "xchg" => operand(1)?,
+        "cmpxchg" => {
+            if old.lanes[0] == operand(1)?.lanes[0] {
+                operand(2)?
+            } else {
+                old
+            }
+        }
+        _ if old.ty.is_float() => return Err(format!("{} needs an integer pointer", name).into()),
+        _ => int_map2(old, operand(1)?, |x, y| match op {
+            "add" => x + y,
+            "sub" => x - y,
+            "min" => x.min(y),
+            "max" => x.max(y),
+            "and" => x & y,
+            "or" => x | y,
+            _ => x ^ y,
+        })?,
+    };
+    item.store(addr, &ty, Value::Num(new))?;
+    Ok(Some(Value::Num(old)))
+}
+
+/// `printf` with the usual conversions plus OpenCL's `%v4f` style vector
+/// specifiers. Output goes to stdout.
+pub fn printf<'a>(item: &mut Item<'a, '_>, args: &'a [Expr]) -> Res<Value> {
+    let Some(Expr::Str(format)) = args.first() else {
+        return Err("printf needs a string literal format".into());
+    };
+    let mut values = Vec::with_capacity(args.len() - 1);
+    for arg in &args[1..] {
+        values.push(match arg {
+            Expr::Str(s) => Arg::Str(s),
+            other => Arg::Value(item.eval(other)?),
+        });
+    }
+    let text = format_printf(format, &values)?;
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(text.as_bytes()).map_err(|e| Fault::Msg(format!("printf: {}", e)))?;
+    Ok(Value::Num(Num::int(0)))
+}
+
+enum Arg<'a> {
+    Str(&'a str),
+    Value(Value),
+}
+
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    zero: bool,
+    alt: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+fn pad(spec: &Spec, body: String) -> String {
+    if body.len() >= spec.width {
+        return body;
+    }
+    let fill = spec.width - body.len();
+    if spec.left {
+        format!("{}{}", body, " ".repeat(fill))
+    } else if spec.zero && body.starts_with(['-', '+', ' ']) {
+        format!("{}{}{}", &body[..1], "0".repeat(fill), &body[1..])
+    } else if spec.zero {
+        format!("{}{}", "0".repeat(fill), body)
+    } else {
+        format!("{}{}", " ".repeat(fill), body)
+    }
+}
+
+fn c_exp(x: f64, precision: usize, upper: bool) -> String {
+    let s = format!("{:.*e}", precision, x);
+    let (mantissa, exp) = s.split_once('e').expect("exponent");
+    let exp: i32 = exp.parse().expect("integer exponent");
+    let e = if upper { 'E' } else { 'e' };
+    format!("{}{}{}{:02}", mantissa, e, if exp < 0 { '-' } else { '+' }, exp.abs())
+}
+
+fn format_float(x: f64, conv: char, spec: &Spec) -> String {
+    let precision = spec.precision.unwrap_or(6);
+    let upper = conv.is_ascii_uppercase();
+    let body = if x.is_nan() {
+        "nan".to_string()
+    } else if x.is_infinite() {
+        "inf".to_string()
+    } else {
+        match conv.to_ascii_lowercase() {
+            'e' => c_exp(x, precision, upper),
+            'g' => {
+                let p = precision.max(1);
+                let exp = if x == 0.0 { 0 } else { x.abs().log10().floor() as i32 };
+                let mut s = if exp < -4 || exp >= p as i32 {
+                    c_exp(x, p - 1, upper)
+                } else {
+                    format!("{:.*}", (p as i32 - 1 - exp).max(0) as usize, x)
+                };
+                if !spec.alt {
+                    let (mantissa, exp) = match s.find(['e', 'E']) {
+                        Some(i) => (s[..i].to_string(), s[i..].to_string()),
+                        None => (s.clone(), String::new()),
+                    };
+                    if mantissa.contains('.') {
+                        s = format!("{}{}", mantissa.trim_end_matches('0').trim_end_matches('.'), exp);
+                    }
+                }
+                s
+            }
+            'a' => format!("{:e}", x),
+            _ => format!("{:.*}", precision, x),
+        }
+    };
+    let body = if upper { body.to_uppercase() } else { body };
+    let sign = if x.is_sign_negative() || x.is_nan() {
+        ""
+    } else if spec.plus {
+        "+"
+    } else if spec.space {
+        " "
+    } else {
+        ""
+    };
+    format!("{}{}", sign, body)
+}
+
+fn format_one(n: &Num, i: usize, conv: char, spec: &Spec) -> String {
+    let body = match conv {
+        'd' | 'i' => {
+            let v = n.i(i);
+            let digits = v.unsigned_abs().to_string();
+            let digits = match spec.precision {
+                Some(p) if digits.len() < p => format!("{}{}", "0".repeat(p - digits.len()), digits),
+                _ => digits,
+            };
+            let sign = if v < 0 {
+                "-"
+            } else if spec.plus {
+                "+"
+            } else if spec.space {
+                " "
+            } else {
+                ""
+            };
+            format!("{}{}", sign, digits)
+        }
+        'u' => n.u(i).to_string(),
+        'x' => format!("{}{:x}", if spec.alt { "0x" } else { "" }, n.u(i) & type_mask(n.ty)),
+        'X' => format!("{}{:X}", if spec.alt { "0X" } else { "" }, n.u(i) & type_mask(n.ty)),
+        'o' => format!("{}{:o}", if spec.alt { "0" } else { "" }, n.u(i) & type_mask(n.ty)),
+        'c' => (n.u(i) as u8 as char).to_string(),
+        _ => format_float(n.f(i), conv, spec),
+    };
+    pad(spec, body)
+}
+
+fn type_mask(ty: Scalar) -> u64 {
+    if ty.bits() == 64 { u64::MAX } else { (1 << ty.bits()) - 1 }
+}
+
+fn format_printf(format: &str, args: &[Arg]) -> Res<String> {
+    let mut out = String::new();
+    let mut chars = format.chars().peekable();
+    let mut next = args.iter();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            out.push('%');
+            continue;
+        }
+        let mut spec = Spec { left: false, plus: false, space: false, zero: false, alt: false, width: 0, precision: None };
+        while let Some(&f) = chars.peek() {
+            match f {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '0' => spec.zero = true,
+                '#' => spec.alt = true,
+                _ => break,
+            }
+            chars.next();
+        }
+        while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+            spec.width = spec.width * 10 + d as usize;
+            chars.next();
+        }
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            let mut p = 0;
+            while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+                p = p * 10 + d as usize;
+                chars.next();
+            }
+            spec.precision = Some(p);
+        }
+        let mut vector = None;
+        if chars.peek() == Some(&'v') {
+            chars.next();
+            let mut n = 0;
+            while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+                n = n * 10 + d as u8;
+                chars.next();
+            }
+            vector = Some(n);
+        }
+        while matches!(chars.peek(), Some('h' | 'l')) {
+            chars.next();
+        }
+        let conv = chars.next().ok_or("printf format ends inside a conversion")?;
+        let arg = next.next().ok_or_else(|| format!("printf: no argument for %{}", conv))?;
+        match (conv, arg) {
+            ('s', Arg::Str(s)) => out.push_str(&pad(&spec, s.to_string())),
+            ('s', _) => return Err("printf: %s needs a string literal".into()),
+            ('p', Arg::Value(Value::Ptr(_, addr))) => out.push_str(&pad(&spec, format!("0x{:x}", addr.encode()))),
+            (_, Arg::Value(Value::Num(n))) if "diuxXocfFeEgGaA".contains(conv) => match vector {
+                Some(len) if len != n.len => {
+                    return Err(format!("printf: %v{} given a {}-component value", len, n.len).into());
+                }
+                Some(_) => {
+                    let parts: Vec<String> = (0..n.len as usize).map(|i| format_one(n, i, conv, &spec)).collect();
+                    out.push_str(&parts.join(","));
+                }
+                None if n.len != 1 => return Err("printf: vector argument needs a %v specifier".into()),
+                None => out.push_str(&format_one(n, 0, conv, &spec)),
+            },
+            _ => return Err(format!("printf: argument does not match %{}", conv).into()),
+        }
+    }
+    Ok(out)
+}
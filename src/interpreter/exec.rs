@@ -0,0 +1,1028 @@
+//! Work-item execution: memory regions, scopes, statements and expressions.
+//!
+//! Barrier-free kernels run their work-items one after another. Kernels
+//! that call `barrier` run each work-item of a group on its own thread, but
+//! only the thread holding the group's baton executes; the baton is passed
+//! round-robin at every barrier, so execution order is still deterministic.
+
+use super::InterpError;
+use super::ast::*;
+use super::builtins;
+use super::types::{Scalar, Space, Type};
+use super::value::{self, Addr, Num, Region, Value};
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+const PRIVATE_LIMIT: usize = 1 << 20;
+const MAX_CALL_DEPTH: usize = 64;
+const ITEM_STACK: usize = 8 << 20;
+
+/// Why execution of a work-item stopped early.
+pub enum Fault {
+    Msg(String),
+    /// Another work-item of the group failed.
+    Aborted,
+}
+
+impl From<String> for Fault {
+    fn from(message: String) -> Fault {
+        Fault::Msg(message)
+    }
+}
+
+impl From<&str> for Fault {
+    fn from(message: &str) -> Fault {
+        Fault::Msg(message.into())
+    }
+}
+
+pub type Res<T> = Result<T, Fault>;
+
+/// How a kernel argument initializes its parameter.
+pub enum ArgSlot {
+    /// Index into the launch's buffers.
+    Buffer(u16),
+    /// Offset of a `__local` allocation.
+    Local(usize),
+    Bytes(Vec<u8>),
+}
+
+/// Everything shared, read-only, by the work-items of one launch.
+pub struct Launch<'a> {
+    pub unit: &'a Unit,
+    pub kernel: &'a Function,
+    pub dims: u32,
+    pub global_size: [usize; 3],
+    pub local_size: [usize; 3],
+    pub offset: [usize; 3],
+    pub args: Vec<ArgSlot>,
+    /// `__local` variable declaration id to arena offset.
+    pub locals: HashMap<usize, usize>,
+    pub local_bytes: usize,
+    pub constant: Vec<u8>,
+    pub constant_vars: Vec<(&'a str, Type, usize)>,
+    pub uses_barrier: bool,
+}
+
+/// Memory visible to more than one work-item.
+#[derive(Default)]
+pub struct Shared {
+    pub buffers: Vec<Vec<u8>>,
+    pub local: Vec<u8>,
+}
+
+pub struct Baton {
+    turn: usize,
+    done: Vec<bool>,
+    barriers: Vec<usize>,
+    error: Option<InterpError>,
+    shared: Shared,
+}
+
+pub struct Group {
+    state: Mutex<Baton>,
+    /// One per work-item, so passing the baton wakes only its new holder.
+    wake: Vec<Condvar>,
+}
+
+pub enum Access<'g> {
+    Direct(&'g mut Shared),
+    Baton { group: &'g Group, guard: Option<MutexGuard<'g, Baton>>, index: usize },
+}
+
+#[derive(Debug)]
+pub enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+/// Storage an expression refers to.
+pub enum Place {
+    Mem(Addr, Type),
+    /// Selected components of the vector at `addr`.
+    Lanes(Addr, Scalar, u8, Vec<u8>),
+}
+
+struct Var<'a> {
+    name: &'a str,
+    ty: Type,
+    addr: Addr,
+}
+
+pub struct Item<'a, 'g> {
+    pub launch: &'a Launch<'a>,
+    access: Access<'g>,
+    private: Vec<u8>,
+    scopes: Vec<Vec<Var<'a>>>,
+    pub global_id: [usize; 3],
+    pub local_id: [usize; 3],
+    pub group_id: [usize; 3],
+    line: u32,
+    depth: usize,
+}
+
+fn lock(group: &Group) -> MutexGuard<'_, Baton> {
+    group.state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn next_turn(baton: &Baton, me: usize) -> usize {
+    let n = baton.done.len();
+    (1..=n).map(|k| (me + k) % n).find(|&j| !baton.done[j]).unwrap_or(n)
+}
+
+/// Wakes the work-item whose turn it is, or every one once the group has failed.
+fn wake(group: &Group, baton: &Baton) {
+    if baton.error.is_some() {
+        group.wake.iter().for_each(Condvar::notify_all);
+    } else if let Some(cv) = group.wake.get(baton.turn) {
+        cv.notify_one();
+    }
+}
+
+fn wait_turn<'g>(group: &'g Group, mut baton: MutexGuard<'g, Baton>, me: usize) -> MutexGuard<'g, Baton> {
+    while baton.turn != me && baton.error.is_none() {
+        baton = group.wake[me].wait(baton).unwrap_or_else(PoisonError::into_inner);
+    }
+    baton
+}
+
+fn region_name(region: Region) -> &'static str {
+    match region {
+        Region::Null => "null",
+        Region::Global(_) => "__global",
+        Region::Constant => "__constant",
+        Region::Local => "__local",
+        Region::Private => "private",
+    }
+}
+
+/// Collects the `__local` declarations of a kernel body.
+fn collect_locals<'a>(stmts: &'a [Stmt], out: &mut Vec<&'a Decl>) {
+    for stmt in stmts {
+        match &stmt.kind {
+            StmtKind::Decls(decls) => out.extend(decls.iter().filter(|d| d.space == Space::Local)),
+            StmtKind::Block(body) | StmtKind::Switch(_, body) => collect_locals(body, out),
+            StmtKind::If(_, a, b) => {
+                collect_locals(std::slice::from_ref(a), out);
+                if let Some(b) = b {
+                    collect_locals(std::slice::from_ref(b), out);
+                }
+            }
+            StmtKind::While(_, body) | StmtKind::DoWhile(body, _) => collect_locals(std::slice::from_ref(body), out),
+            StmtKind::For(init, _, _, body) => {
+                if let Some(init) = init {
+                    collect_locals(std::slice::from_ref(init), out);
+                }
+                collect_locals(std::slice::from_ref(body), out);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a> Launch<'a> {
+    /// Lays out `__local` variables after `local_args` bytes of `__local`
+    /// kernel arguments and evaluates program-scope constants.
+    pub fn prepare(&mut self, local_args: usize) -> Result<(), InterpError> {
+        let mut decls = Vec::new();
+        collect_locals(&self.kernel.body, &mut decls);
+        let mut end = local_args;
+        for decl in decls {
+            let offset = end.next_multiple_of(decl.ty.align());
+            self.locals.insert(decl.id, offset);
+            end = offset + decl.ty.size();
+        }
+        self.local_bytes = end;
+
+        let unit = self.unit;
+        let (constant, vars) = {
+            let mut shared = Shared::default();
+            let mut item = Item::new(self, Access::Direct(&mut shared), [0; 3], [0; 3]);
+            item.scopes.push(Vec::new());
+            let mut vars = Vec::new();
+            for decl in &unit.constants {
+                item.declare(decl).map_err(|f| item.fault_error(f))?;
+                let var = item.scopes[0].last().expect("just declared");
+                vars.push((decl.name.as_str(), var.ty.clone(), var.addr.offset));
+            }
+            (std::mem::take(&mut item.private), vars)
+        };
+        self.constant = constant;
+        self.constant_vars = vars;
+        Ok(())
+    }
+
+    fn group_items(&self, group_id: [usize; 3]) -> Vec<([usize; 3], [usize; 3])> {
+        let [lx, ly, lz] = self.local_size;
+        let mut ids = Vec::with_capacity(lx * ly * lz);
+        for z in 0..lz {
+            for y in 0..ly {
+                for x in 0..lx {
+                    ids.push(([x, y, z], group_id));
+                }
+            }
+        }
+        ids
+    }
+
+    /// Runs every work-group in order, x fastest.
+    pub fn run(&self, shared: &mut Shared) -> Result<(), InterpError> {
+        let groups: Vec<usize> = (0..3).map(|d| self.global_size[d] / self.local_size[d]).collect();
+        for gz in 0..groups[2] {
+            for gy in 0..groups[1] {
+                for gx in 0..groups[0] {
+                    shared.local.clear();
+                    shared.local.resize(self.local_bytes, 0);
+                    let items = self.group_items([gx, gy, gz]);
+                    if self.uses_barrier && items.len() > 1 {
+                        self.run_threaded(shared, items)?;
+                    } else {
+                        for (local_id, group_id) in items {
+                            let mut item = Item::new(self, Access::Direct(shared), local_id, group_id);
+                            item.run().map_err(|f| item.fault_error(f))?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn run_threaded(&self, shared: &mut Shared, items: Vec<([usize; 3], [usize; 3])>) -> Result<(), InterpError> {
+        let n = items.len();
+        let group = Group {
+            state: Mutex::new(Baton {
+                turn: 0,
+                done: vec![false; n],
+                barriers: vec![0; n],
+                error: None,
+                shared: std::mem::take(shared),
+            }),
+            wake: (0..n).map(|_| Condvar::new()).collect(),
+        };
+        std::thread::scope(|scope| {
+            for (index, (local_id, group_id)) in items.into_iter().enumerate() {
+                let group = &group;
+                let spawned = std::thread::Builder::new()
+                    .stack_size(ITEM_STACK)
+                    .spawn_scoped(scope, move || self.run_baton_item(group, index, local_id, group_id));
+                if let Err(e) = spawned {
+                    // Work-items already started are waiting for the baton; abort them.
+                    let mut baton = lock(group);
+                    baton.error.get_or_insert(InterpError::Launch(format!("cannot start work-item thread: {}", e)));
+                    wake(group, &baton);
+                    break;
+                }
+            }
+        });
+        let baton = group.state.into_inner().unwrap_or_else(PoisonError::into_inner);
+        *shared = baton.shared;
+        baton.error.map_or(Ok(()), Err)
+    }
+
+    fn run_baton_item(&self, group: &Group, index: usize, local_id: [usize; 3], group_id: [usize; 3]) {
+        let guard = wait_turn(group, lock(group), index);
+        let mut guard = if guard.error.is_some() {
+            guard
+        } else {
+            let mut item = Item::new(self, Access::Baton { group, guard: Some(guard), index }, local_id, group_id);
+            let result = item.run();
+            let error = match result {
+                Ok(()) | Err(Fault::Aborted) => None,
+                Err(fault) => Some(item.fault_error(fault)),
+            };
+            let global_id = item.global_id;
+            let line = item.line;
+            let mut guard = match item.access {
+                Access::Baton { guard, .. } => guard.expect("work-item holds the baton"),
+                Access::Direct(_) => unreachable!(),
+            };
+            let mine = guard.barriers[index];
+            let diverged = (0..guard.done.len())
+                .find(|&j| j != index && guard.barriers[j] != mine && (guard.done[j] || guard.barriers[j] > mine));
+            match (error, diverged) {
+                (Some(e), _) => {
+                    guard.error.get_or_insert(e);
+                }
+                (None, Some(j)) if guard.error.is_none() => {
+                    guard.error = Some(InterpError::Runtime {
+                        line,
+                        message: format!(
+                            "work-item {:?} finished after {} barriers but work-item {} of its group reached {}",
+                            global_id, mine, j, guard.barriers[j]
+                        ),
+                    });
+                }
+                _ => {}
+            }
+            guard
+        };
+        guard.done[index] = true;
+        guard.turn = next_turn(&guard, index);
+        wake(group, &guard);
+    }
+}
+
+impl<'a, 'g> Item<'a, 'g> {
+    fn new(launch: &'a Launch<'a>, access: Access<'g>, local_id: [usize; 3], group_id: [usize; 3]) -> Self {
+        let global_id = [0, 1, 2].map(|d| group_id[d] * launch.local_size[d] + local_id[d] + launch.offset[d]);
+        Item {
+            launch,
+            access,
+            private: Vec::new(),
+            scopes: Vec::new(),
+            global_id,
+            local_id,
+            group_id,
+            line: launch.kernel.line,
+            depth: 0,
+        }
+    }
+
+    fn fault_error(&self, fault: Fault) -> InterpError {
+        let message = match fault {
+            Fault::Msg(m) => m,
+            Fault::Aborted => "aborted".into(),
+        };
+        let [x, y, z] = self.global_id;
+        let id = match self.launch.dims {
+            1 => format!("{}", x),
+            2 => format!("({}, {})", x, y),
+            _ => format!("({}, {}, {})", x, y, z),
+        };
+        InterpError::Runtime { line: self.line, message: format!("work-item {}: {}", id, message) }
+    }
+
+    fn run(&mut self) -> Res<()> {
+        let kernel = self.launch.kernel;
+        self.scopes.push(Vec::new());
+        for (param, arg) in kernel.params.iter().zip(&self.launch.args) {
+            let addr = self.alloc(&param.ty)?;
+            match arg {
+                ArgSlot::Buffer(i) => self.write(addr, &Addr::new(Region::Global(*i), 0).encode().to_le_bytes())?,
+                ArgSlot::Local(offset) => self.write(addr, &Addr::new(Region::Local, *offset).encode().to_le_bytes())?,
+                ArgSlot::Bytes(bytes) => self.write(addr, bytes)?,
+            }
+            self.bind(&param.name, param.ty.clone(), addr);
+        }
+        self.exec_block(&kernel.body)?;
+        Ok(())
+    }
+
+    fn shared(&mut self) -> &mut Shared {
+        match &mut self.access {
+            Access::Direct(shared) => shared,
+            Access::Baton { guard, .. } => &mut guard.as_mut().expect("work-item holds the baton").shared,
+        }
+    }
+
+    /// Waits at a work-group barrier.
+    pub fn barrier(&mut self) -> Res<()> {
+        let Access::Baton { group, guard, index } = &mut self.access else {
+            return Ok(());
+        };
+        let (group, me) = (*group, *index);
+        let mut baton = guard.take().expect("work-item holds the baton");
+        baton.barriers[me] += 1;
+        let count = baton.barriers[me];
+        if let Some(j) = (0..baton.done.len()).find(|&j| baton.done[j] && baton.barriers[j] < count) {
+            *guard = Some(baton);
+            return Err(format!("barrier {} is never reached by work-item {} of the group, which has finished", count, j)
+                .into());
+        }
+        baton.turn = next_turn(&baton, me);
+        wake(group, &baton);
+        let baton = wait_turn(group, baton, me);
+        let aborted = baton.error.is_some();
+        *guard = Some(baton);
+        if aborted { Err(Fault::Aborted) } else { Ok(()) }
+    }
+
+    // ---- memory ----
+
+    fn bounds(addr: Addr, len: usize, size: usize, what: &str) -> Res<()> {
+        if addr.offset.checked_add(len).is_none_or(|end| end > size) {
+            return Err(format!(
+                "out-of-bounds {} {} of {} bytes at offset {} (region holds {} bytes)",
+                region_name(addr.region),
+                what,
+                len,
+                addr.offset as isize,
+                size
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    pub fn read(&mut self, addr: Addr, out: &mut [u8]) -> Res<()> {
+        let src: &[u8] = match addr.region {
+            Region::Null => return Err("null pointer dereference".into()),
+            Region::Private => &self.private,
+            Region::Constant => &self.launch.constant,
+            Region::Local => &self.shared().local,
+            Region::Global(i) => &self.shared().buffers[i as usize],
+        };
+        Self::bounds(addr, out.len(), src.len(), "read")?;
+        out.copy_from_slice(&src[addr.offset..addr.offset + out.len()]);
+        Ok(())
+    }
+
+    pub fn write(&mut self, addr: Addr, data: &[u8]) -> Res<()> {
+        let dst: &mut [u8] = match addr.region {
+            Region::Null => return Err("null pointer dereference".into()),
+            Region::Constant => return Err("write to __constant memory".into()),
+            Region::Private => &mut self.private,
+            Region::Local => &mut self.shared().local,
+            Region::Global(i) => &mut self.shared().buffers[i as usize],
+        };
+        Self::bounds(addr, data.len(), dst.len(), "write")?;
+        dst[addr.offset..addr.offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn alloc(&mut self, ty: &Type) -> Res<Addr> {
+        let start = self.private.len().next_multiple_of(ty.align());
+        let end = start + ty.size();
+        if end > PRIVATE_LIMIT {
+            return Err("private memory exhausted".into());
+        }
+        self.private.resize(end, 0);
+        Ok(Addr::new(Region::Private, start))
+    }
+
+    pub fn load(&mut self, addr: Addr, ty: &Type) -> Res<Value> {
+        let mut buf = [0u8; 128];
+        Ok(match ty {
+            Type::Num(s, n) => {
+                let bytes = &mut buf[..s.size() * *n as usize];
+                self.read(addr, bytes)?;
+                Value::Num(Num::read_bytes(*s, *n, bytes))
+            }
+            Type::Ptr(target, _) => {
+                self.read(addr, &mut buf[..8])?;
+                let bits = u64::from_le_bytes(buf[..8].try_into().expect("eight bytes"));
+                Value::Ptr((**target).clone(), Addr::decode(bits))
+            }
+            Type::Array(elem, _) => Value::Ptr((**elem).clone(), addr),
+            Type::Void => return Err("cannot load a void value".into()),
+        })
+    }
+
+    /// Converts `value` to `ty` and stores it, returning the stored value.
+    pub fn store(&mut self, addr: Addr, ty: &Type, value: Value) -> Res<Value> {
+        let value = coerce(ty, value)?;
+        match &value {
+            Value::Num(n) => {
+                let mut bytes = Vec::with_capacity(128);
+                n.write_bytes(&mut bytes);
+                self.write(addr, &bytes)?;
+            }
+            Value::Ptr(_, target) => self.write(addr, &target.encode().to_le_bytes())?,
+            Value::Void => {}
+        }
+        Ok(value)
+    }
+
+    // ---- scopes ----
+
+    fn bind(&mut self, name: &'a str, ty: Type, addr: Addr) {
+        self.scopes.last_mut().expect("inside a scope").push(Var { name, ty, addr });
+    }
+
+    fn lookup(&self, name: &str) -> Option<(Addr, Type)> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(var) = scope.iter().rev().find(|v| v.name == name) {
+                return Some((var.addr, var.ty.clone()));
+            }
+        }
+        self.launch
+            .constant_vars
+            .iter()
+            .find(|(n, ..)| *n == name)
+            .map(|(_, ty, offset)| (Addr::new(Region::Constant, *offset), ty.clone()))
+    }
+
+    fn push_scope(&mut self) -> usize {
+        self.scopes.push(Vec::new());
+        self.private.len()
+    }
+
+    fn pop_scope(&mut self, mark: usize) {
+        self.scopes.pop();
+        self.private.truncate(mark);
+    }
+
+    fn declare(&mut self, decl: &'a Decl) -> Res<()> {
+        let addr = match decl.space {
+            Space::Local => match self.launch.locals.get(&decl.id) {
+                Some(offset) => Addr::new(Region::Local, *offset),
+                None => return Err(format!("__local variable {} must be declared in a kernel", decl.name).into()),
+            },
+            _ => self.alloc(&decl.ty)?,
+        };
+        if let (Some(init), false) = (&decl.init, decl.space == Space::Local) {
+            self.init(addr, &decl.ty, init)?;
+        }
+        self.bind(&decl.name, decl.ty.clone(), addr);
+        Ok(())
+    }
+
+    fn init(&mut self, addr: Addr, ty: &Type, e: &'a Expr) -> Res<()> {
+        match (ty, e) {
+            (Type::Array(elem, len), Expr::Init(items)) => {
+                if items.len() > *len {
+                    return Err(format!("{} initializers for an array of {}", items.len(), len).into());
+                }
+                for (i, item) in items.iter().enumerate() {
+                    self.init(addr.add((i * elem.size()) as i64), elem, item)?;
+                }
+            }
+            (Type::Num(s, n), Expr::Init(items)) => {
+                let v = self.vector(*s, *n, items, true)?;
+                self.store(addr, ty, Value::Num(v))?;
+            }
+            (_, Expr::Init(items)) if items.len() == 1 => self.init(addr, ty, &items[0])?,
+            (_, Expr::Init(_)) => return Err(format!("too many initializers for {}", ty).into()),
+            _ => {
+                let v = self.eval(e)?;
+                self.store(addr, ty, v)?;
+            }
+        }
+        Ok(())
+    }
+
+    // ---- statements ----
+
+    fn truth(&mut self, e: &'a Expr) -> Res<bool> {
+        Ok(self.eval(e)?.is_true()?)
+    }
+
+    fn exec_block(&mut self, stmts: &'a [Stmt]) -> Res<Flow> {
+        let mark = self.push_scope();
+        for stmt in stmts {
+            let flow = self.exec(stmt)?;
+            if !matches!(flow, Flow::Normal) {
+                self.pop_scope(mark);
+                return Ok(flow);
+            }
+        }
+        self.pop_scope(mark);
+        Ok(Flow::Normal)
+    }
+
+    fn exec(&mut self, stmt: &'a Stmt) -> Res<Flow> {
+        self.line = stmt.line;
+        Ok(match &stmt.kind {
+            StmtKind::Empty | StmtKind::Case(_) | StmtKind::Default => Flow::Normal,
+            StmtKind::Expr(e) => {
+                self.eval(e)?;
+                Flow::Normal
+            }
+            StmtKind::Decls(decls) => {
+                for decl in decls {
+                    self.declare(decl)?;
+                }
+                Flow::Normal
+            }
+            StmtKind::Block(stmts) => self.exec_block(stmts)?,
+            StmtKind::If(cond, then, otherwise) => {
+                if self.truth(cond)? {
+                    self.exec(then)?
+                } else if let Some(otherwise) = otherwise {
+                    self.exec(otherwise)?
+                } else {
+                    Flow::Normal
+                }
+            }
+            StmtKind::While(cond, body) => {
+                while self.truth(cond)? {
+                    match self.exec(body)? {
+                        Flow::Break => break,
+                        Flow::Return(v) => return Ok(Flow::Return(v)),
+                        _ => {}
+                    }
+                }
+                Flow::Normal
+            }
+            StmtKind::DoWhile(body, cond) => {
+                loop {
+                    match self.exec(body)? {
+                        Flow::Break => break,
+                        Flow::Return(v) => return Ok(Flow::Return(v)),
+                        _ => {}
+                    }
+                    if !self.truth(cond)? {
+                        break;
+                    }
+                }
+                Flow::Normal
+            }
+            StmtKind::For(init, cond, step, body) => {
+                let mark = self.push_scope();
+                if let Some(init) = init {
+                    self.exec(init)?;
+                }
+                let mut flow = Flow::Normal;
+                loop {
+                    if let Some(cond) = cond
+                        && !self.truth(cond)?
+                    {
+                        break;
+                    }
+                    match self.exec(body)? {
+                        Flow::Break => break,
+                        Flow::Return(v) => {
+                            flow = Flow::Return(v);
+                            break;
+                        }
+                        _ => {}
+                    }
+                    if let Some(step) = step {
+                        self.eval(step)?;
+                    }
+                }
+                self.pop_scope(mark);
+                flow
+            }
+            StmtKind::Switch(value, body) => {
+                let value = self.eval(value)?.num()?;
+                if value.len != 1 || value.ty.is_float() {
+                    return Err("switch needs an integer scalar".into());
+                }
+                let mut start = None;
+                let mut default = None;
+                for (i, stmt) in body.iter().enumerate() {
+                    match &stmt.kind {
+                        StmtKind::Case(label) if self.eval(label)?.num()?.i(0) == value.i(0) => {
+                            start = Some(i);
+                            break;
+                        }
+                        StmtKind::Default => default = default.or(Some(i)),
+                        _ => {}
+                    }
+                }
+                let mut flow = Flow::Normal;
+                if let Some(start) = start.or(default) {
+                    let mark = self.push_scope();
+                    for stmt in &body[start..] {
+                        match self.exec(stmt)? {
+                            Flow::Normal => {}
+                            Flow::Break => break,
+                            other => {
+                                flow = other;
+                                break;
+                            }
+                        }
+                    }
+                    self.pop_scope(mark);
+                }
+                flow
+            }
+            StmtKind::Break => Flow::Break,
+            StmtKind::Continue => Flow::Continue,
+            StmtKind::Return(value) => Flow::Return(match value {
+                Some(e) => self.eval(e)?,
+                None => Value::Void,
+            }),
+        })
+    }
+
+    // ---- expressions ----
+
+    fn place(&mut self, e: &'a Expr) -> Res<Place> {
+        match e {
+            Expr::Var(name) => match self.lookup(name) {
+                Some((addr, ty)) => Ok(Place::Mem(addr, ty)),
+                None => Err(format!("undeclared identifier {}", name).into()),
+            },
+            Expr::Deref(inner) => match self.eval(inner)? {
+                Value::Ptr(ty, addr) => Ok(Place::Mem(addr, ty)),
+                _ => Err("dereference of a non-pointer".into()),
+            },
+            Expr::Index(base, index) => {
+                enum Base {
+                    Place(Place),
+                    Value(Value),
+                }
+                let base = if base.is_place() { Base::Place(self.place(base)?) } else { Base::Value(self.eval(base)?) };
+                let i = self.eval(index)?.num()?;
+                if i.len != 1 || i.ty.is_float() {
+                    return Err("array subscript is not an integer".into());
+                }
+                let i = i.i(0);
+                let pointer = match base {
+                    Base::Place(Place::Mem(addr, Type::Array(elem, len))) => {
+                        if i < 0 || i as usize >= len {
+                            return Err(format!("index {} out of bounds for array of {}", i, len).into());
+                        }
+                        return Ok(Place::Mem(addr.add(i * elem.size() as i64), *elem));
+                    }
+                    Base::Place(Place::Mem(addr, Type::Num(s, n))) if n > 1 => {
+                        if i < 0 || i >= n as i64 {
+                            return Err(format!("component {} out of range for a {}-component vector", i, n).into());
+                        }
+                        return Ok(Place::Lanes(addr, s, n, vec![i as u8]));
+                    }
+                    Base::Place(place) => self.read_place(&place)?,
+                    Base::Value(value) => value,
+                };
+                match pointer {
+                    Value::Ptr(ty, addr) => {
+                        let size = ty.size() as i64;
+                        Ok(Place::Mem(addr.add(i * size), ty))
+                    }
+                    _ => Err("subscripted value is not an array or pointer".into()),
+                }
+            }
+            Expr::Swizzle(inner, sel) => match self.place(inner)? {
+                Place::Mem(addr, Type::Num(s, n)) => Ok(Place::Lanes(addr, s, n, sel.lanes(n)?)),
+                Place::Lanes(addr, s, n, idx) => {
+                    let sub = sel.lanes(idx.len() as u8)?;
+                    Ok(Place::Lanes(addr, s, n, sub.iter().map(|&j| idx[j as usize]).collect()))
+                }
+                Place::Mem(_, ty) => Err(format!("component selection on {}", ty).into()),
+            },
+            _ => Err("expression is not assignable".into()),
+        }
+    }
+
+    fn read_place(&mut self, place: &Place) -> Res<Value> {
+        match place {
+            Place::Mem(addr, ty) => self.load(*addr, ty),
+            Place::Lanes(addr, s, n, idx) => {
+                let v = self.load(*addr, &Type::Num(*s, *n))?.num()?;
+                Ok(Value::Num(v.select(idx)))
+            }
+        }
+    }
+
+    fn write_place(&mut self, place: &Place, value: Value) -> Res<Value> {
+        match place {
+            Place::Mem(addr, ty) => self.store(*addr, ty, value),
+            Place::Lanes(addr, s, n, idx) => {
+                let ty = Type::Num(*s, *n);
+                let src = value.num()?.convert(*s);
+                if src.len != 1 && src.len as usize != idx.len() {
+                    return Err(format!("cannot assign {} components to {}", src.len, idx.len()).into());
+                }
+                let src = src.splat(idx.len() as u8);
+                let mut vector = self.load(*addr, &ty)?.num()?;
+                for (k, &lane) in idx.iter().enumerate() {
+                    vector.lanes[lane as usize] = src.lanes[k];
+                }
+                self.store(*addr, &ty, Value::Num(vector))?;
+                Ok(Value::Num(src))
+            }
+        }
+    }
+
+    /// Builds a vector from components, as in `(float4)(a.xy, 0, 1)`.
+    /// Brace initializers (`pad`) may give fewer components than lanes.
+    pub fn vector(&mut self, s: Scalar, n: u8, items: &'a [Expr], pad: bool) -> Res<Num> {
+        let mut lanes = Vec::with_capacity(n as usize);
+        for item in items {
+            let v = self.eval(item)?.num()?.convert(s);
+            lanes.extend_from_slice(&v.lanes[..v.len as usize]);
+        }
+        let count = lanes.len();
+        if count == 1 && !pad {
+            lanes.resize(n as usize, lanes[0]);
+        } else if count < n as usize && pad {
+            lanes.resize(n as usize, 0);
+        } else if count != n as usize {
+            return Err(format!("vector literal has {} components, {}{} needs {}", count, s.name(), n, n).into());
+        }
+        Ok(Num::from_fn_u64(s, n, |i| lanes[i]))
+    }
+
+    pub fn eval(&mut self, e: &'a Expr) -> Res<Value> {
+        Ok(match e {
+            Expr::Int(v, s) => Value::Num(Num::from_u64(*s, *v)),
+            Expr::Float(v, s) => Value::Num(Num::from_f64(*s, *v)),
+            Expr::Str(_) => return Err("string literals are only supported as the printf format".into()),
+            Expr::Var(name) => match self.lookup(name) {
+                Some((addr, ty)) => self.load(addr, &ty)?,
+                None => builtins::constant(name).ok_or_else(|| format!("undeclared identifier {}", name))?,
+            },
+            Expr::Unary(op, a) => match self.eval(a)? {
+                Value::Ptr(_, addr) if *op == UnOp::Not => Value::Num(Num::int((addr.region == Region::Null) as i64)),
+                v => Value::Num(value::unary(*op, v.num()?)?),
+            },
+            Expr::Binary(op, a, b) => self.binary(*op, a, b)?,
+            Expr::Assign(op, lhs, rhs) => {
+                let place = self.place(lhs)?;
+                let rhs = self.eval(rhs)?;
+                let v = match op {
+                    Some(op) => {
+                        let current = self.read_place(&place)?;
+                        binary_values(*op, current, rhs)?
+                    }
+                    None => rhs,
+                };
+                self.write_place(&place, v)?
+            }
+            Expr::Step { expr, pre, inc } => {
+                let place = self.place(expr)?;
+                let old = self.read_place(&place)?;
+                let op = if *inc { BinOp::Add } else { BinOp::Sub };
+                let new = binary_values(op, old.clone(), Value::Num(Num::int(1)))?;
+                let new = self.write_place(&place, new)?;
+                if *pre { new } else { old }
+            }
+            Expr::Cond(cond, a, b) => match self.eval(cond)? {
+                Value::Num(c) if c.len > 1 => {
+                    let (a, b) = (self.eval(a)?.num()?, self.eval(b)?.num()?);
+                    let ty = if a.len > 1 { a.ty } else { b.ty };
+                    let (a, b) = (a.convert(ty).splat(c.len), b.convert(ty).splat(c.len));
+                    Value::Num(Num::from_fn_u64(ty, c.len, |i| if c.lane_msb(i) { a.lanes[i] } else { b.lanes[i] }))
+                }
+                c => {
+                    if c.is_true()? {
+                        self.eval(a)?
+                    } else {
+                        self.eval(b)?
+                    }
+                }
+            },
+            Expr::Call(name, args) => self.call(name, args)?,
+            Expr::Index(..) | Expr::Deref(_) => {
+                let place = self.place(e)?;
+                self.read_place(&place)?
+            }
+            Expr::Swizzle(inner, sel) => {
+                if inner.is_place() {
+                    let place = self.place(e)?;
+                    self.read_place(&place)?
+                } else {
+                    let v = self.eval(inner)?.num()?;
+                    Value::Num(v.select(&sel.lanes(v.len)?))
+                }
+            }
+            Expr::Cast(ty, inner) => {
+                let v = self.eval(inner)?;
+                cast(ty, v)?
+            }
+            Expr::Vector(ty, items) => match ty {
+                Type::Num(s, n) => Value::Num(self.vector(*s, *n, items, false)?),
+                _ => return Err(format!("{} is not a vector type", ty).into()),
+            },
+            Expr::Init(_) => return Err("initializer list outside a declaration".into()),
+            Expr::AddrOf(inner) => match self.place(inner)? {
+                Place::Mem(addr, ty) => Value::Ptr(ty, addr),
+                Place::Lanes(addr, s, _, idx) if idx.len() == 1 => {
+                    Value::Ptr(Type::scalar(s), addr.add(idx[0] as i64 * s.size() as i64))
+                }
+                Place::Lanes(..) => return Err("cannot take the address of several vector components".into()),
+            },
+            Expr::SizeOf(ty) => Value::Num(Num::from_u64(Scalar::ULong, ty.size() as u64)),
+            Expr::SizeOfValue(inner) => {
+                let size = if inner.is_place() {
+                    match self.place(inner)? {
+                        Place::Mem(_, ty) => ty.size(),
+                        Place::Lanes(_, s, _, idx) => Type::Num(s, idx.len() as u8).size(),
+                    }
+                } else {
+                    match self.eval(inner)? {
+                        Value::Num(n) => Type::Num(n.ty, n.len).size(),
+                        Value::Ptr(..) => 8,
+                        Value::Void => 1,
+                    }
+                };
+                Value::Num(Num::from_u64(Scalar::ULong, size as u64))
+            }
+            Expr::Comma(a, b) => {
+                self.eval(a)?;
+                self.eval(b)?
+            }
+        })
+    }
+
+    fn binary(&mut self, op: BinOp, a: &'a Expr, b: &'a Expr) -> Res<Value> {
+        let a = self.eval(a)?;
+        if matches!(op, BinOp::LogAnd | BinOp::LogOr) && !matches!(&a, Value::Num(n) if n.len > 1) {
+            let lhs = a.is_true()?;
+            let result = match op {
+                BinOp::LogAnd => lhs && self.eval(b)?.is_true()?,
+                _ => lhs || self.eval(b)?.is_true()?,
+            };
+            return Ok(Value::Num(Num::int(result as i64)));
+        }
+        let b = self.eval(b)?;
+        Ok(binary_values(op, a, b)?)
+    }
+
+    fn call(&mut self, name: &str, args: &'a [Expr]) -> Res<Value> {
+        if name == "printf" {
+            return builtins::printf(self, args);
+        }
+        let mut values = Vec::with_capacity(args.len());
+        for arg in args {
+            values.push(self.eval(arg)?);
+        }
+        if let Some(func) = self.launch.unit.function(name) {
+            return self.call_user(func, values);
+        }
+        match builtins::call(self, name, values)? {
+            Some(v) => Ok(v),
+            None => Err(format!("call to undeclared function {}", name).into()),
+        }
+    }
+
+    fn call_user(&mut self, func: &'a Function, args: Vec<Value>) -> Res<Value> {
+        if args.len() != func.params.len() {
+            return Err(format!("{} takes {} arguments, {} given", func.name, func.params.len(), args.len()).into());
+        }
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(format!("call depth exceeds {} in {}", MAX_CALL_DEPTH, func.name).into());
+        }
+        let saved = std::mem::take(&mut self.scopes);
+        let (line, mark) = (self.line, self.push_scope());
+        for (param, arg) in func.params.iter().zip(args) {
+            let addr = self.alloc(&param.ty)?;
+            self.store(addr, &param.ty, arg)?;
+            self.bind(&param.name, param.ty.clone(), addr);
+        }
+        self.depth += 1;
+        let flow = self.exec_block(&func.body)?;
+        self.depth -= 1;
+        self.pop_scope(mark);
+        self.scopes = saved;
+        self.line = line;
+        match (flow, &func.ret) {
+            (Flow::Return(v), ret) => Ok(coerce(ret, v)?),
+            (_, Type::Void) => Ok(Value::Void),
+            _ => Err(format!("{} ended without returning a value", func.name).into()),
+        }
+    }
+}
+
+/// Converts a value for storage in (or passing as) `ty`.
+pub fn coerce(ty: &Type, value: Value) -> Result<Value, String> {
+    match (ty, value) {
+        (Type::Void, _) => Ok(Value::Void),
+        (Type::Num(s, n), Value::Num(x)) => {
+            if x.len == *n {
+                Ok(Value::Num(x.convert(*s)))
+            } else if x.len == 1 {
+                Ok(Value::Num(x.convert(*s).splat(*n)))
+            } else {
+                Err(format!("cannot convert a {}-component vector to {}", x.len, ty))
+            }
+        }
+        (Type::Ptr(target, _), Value::Ptr(_, addr)) => Ok(Value::Ptr((**target).clone(), addr)),
+        (Type::Ptr(target, _), Value::Num(x)) if x.len == 1 && x.u(0) == 0 => {
+            Ok(Value::Ptr((**target).clone(), Addr::NULL))
+        }
+        (ty, Value::Void) => Err(format!("void value used as {}", ty)),
+        (ty, Value::Ptr(..)) => Err(format!("pointer used as {}", ty)),
+        (ty, Value::Num(_)) => Err(format!("number used as {}", ty)),
+    }
+}
+
+fn cast(ty: &Type, value: Value) -> Result<Value, String> {
+    match (ty, value) {
+        (Type::Ptr(target, _), Value::Num(x)) if x.len == 1 => Ok(Value::Ptr((**target).clone(), Addr::decode(x.u(0)))),
+        (Type::Num(s, 1), Value::Ptr(_, addr)) => Ok(Value::Num(Num::from_u64(*s, addr.encode()))),
+        (ty, value) => coerce(ty, value),
+    }
+}
+
+/// Binary operators including pointer arithmetic and comparison.
+pub fn binary_values(op: BinOp, a: Value, b: Value) -> Result<Value, String> {
+    let pointer_cmp = |x: u64, y: u64| {
+        let ord = x.cmp(&y);
+        let result = match op {
+            BinOp::Lt => ord.is_lt(),
+            BinOp::Gt => ord.is_gt(),
+            BinOp::Le => ord.is_le(),
+            BinOp::Ge => ord.is_ge(),
+            BinOp::Eq => ord.is_eq(),
+            _ => ord.is_ne(),
+        };
+        Value::Num(Num::int(result as i64))
+    };
+    match (a, b) {
+        (Value::Num(a), Value::Num(b)) => Ok(Value::Num(value::binary(op, a, b)?)),
+        (Value::Ptr(ty, addr), Value::Num(n)) | (Value::Num(n), Value::Ptr(ty, addr))
+            if op == BinOp::Add && n.len == 1 =>
+        {
+            let step = ty.size() as i64;
+            Ok(Value::Ptr(ty, addr.add(n.i(0) * step)))
+        }
+        (Value::Ptr(ty, addr), Value::Num(n)) if op == BinOp::Sub && n.len == 1 => {
+            let step = ty.size() as i64;
+            Ok(Value::Ptr(ty, addr.add(-n.i(0) * step)))
+        }
+        (Value::Ptr(ty, a), Value::Ptr(_, b)) if op == BinOp::Sub => {
+            let diff = a.offset as i64 - b.offset as i64;
+            Ok(Value::Num(Num::from_i64(Scalar::Long, diff / ty.size() as i64)))
+        }
+        (Value::Ptr(_, a), Value::Ptr(_, b)) if op.is_comparison() => Ok(pointer_cmp(a.encode(), b.encode())),
+        (Value::Ptr(_, a), Value::Num(n)) if op.is_comparison() && n.len == 1 => Ok(pointer_cmp(a.encode(), n.u(0))),
+        (Value::Num(n), Value::Ptr(_, b)) if op.is_comparison() && n.len == 1 => Ok(pointer_cmp(n.u(0), b.encode())),
+        _ => Err(format!("invalid operands to {:?}", op)),
+    }
+}
@@ -0,0 +1,526 @@
+//! Tokenizer and a small preprocessor (`#define`, conditionals, `#pragma`).
+
+use super::InterpError;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tok {
+    Ident(String),
+    /// Value, `u` suffix, `l` suffix.
+    Int(u64, bool, bool),
+    /// Value, `f` suffix.
+    Float(f64, bool),
+    Str(String),
+    Punct(&'static str),
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub tok: Tok,
+    pub line: u32,
+}
+
+const PUNCTS: [&str; 49] = [
+    "<<=", ">>=", "...", "->", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=", "*=", "/=",
+    "%=", "&=", "^=", "|=", "##", "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "|", "^", "?", ":", ";",
+    ",", ".", "(", ")", "[", "]", "{", "}", "#", "\\",
+];
+
+fn parse_error(line: u32, message: impl Into<String>) -> InterpError {
+    InterpError::Parse { line, message: message.into() }
+}
+
+struct Macro {
+    params: Option<Vec<String>>,
+    body: Vec<Token>,
+}
+
+struct Cond {
+    active: bool,
+    parent_active: bool,
+    taken: bool,
+}
+
+/// Preprocesses and tokenizes `src`. `defines` are `-D` style definitions.
+pub fn tokenize(src: &str, defines: &[(String, String)]) -> Result<Vec<Token>, InterpError> {
+    let text = strip_comments(src)?;
+    let mut macros: HashMap<String, Macro> = HashMap::new();
+    for (name, value) in defines {
+        macros.insert(name.clone(), Macro { params: None, body: lex_line(value, 0)? });
+    }
+    let mut conds: Vec<Cond> = Vec::new();
+    let mut out = Vec::new();
+
+    let raw: Vec<&str> = text.split('\n').collect();
+    let mut i = 0;
+    while i < raw.len() {
+        let line_no = i as u32 + 1;
+        let mut logical = raw[i].to_string();
+        while logical.ends_with('\\') && i + 1 < raw.len() {
+            logical.pop();
+            i += 1;
+            logical.push(' ');
+            logical.push_str(raw[i]);
+        }
+        i += 1;
+        let active = conds.last().is_none_or(|c| c.active);
+        let trimmed = logical.trim_start();
+        if let Some(directive) = trimmed.strip_prefix('#') {
+            directive_line(directive.trim(), line_no, active, &mut macros, &mut conds)?;
+            continue;
+        }
+        if active {
+            let tokens = lex_line(&logical, line_no)?;
+            out.extend(expand(&tokens, &macros, &mut Vec::new())?);
+        }
+    }
+    if !conds.is_empty() {
+        return Err(parse_error(raw.len() as u32, "unterminated #if"));
+    }
+    out.push(Token { tok: Tok::Eof, line: raw.len() as u32 });
+    Ok(out)
+}
+
+fn directive_line(
+    directive: &str,
+    line: u32,
+    active: bool,
+    macros: &mut HashMap<String, Macro>,
+    conds: &mut Vec<Cond>,
+) -> Result<(), InterpError> {
+    let (name, rest) = match directive.find(|c: char| !c.is_ascii_alphanumeric() && c != '_') {
+        Some(split) => (&directive[..split], directive[split..].trim()),
+        None => (directive, ""),
+    };
+    match name {
+        "ifdef" | "ifndef" | "if" => {
+            let value = active
+                && match name {
+                    "ifdef" => macros.contains_key(rest),
+                    "ifndef" => !macros.contains_key(rest),
+                    _ => eval_condition(rest, line, macros)?,
+                };
+            conds.push(Cond { active: value, parent_active: active, taken: value });
+        }
+        "elif" => {
+            let cond = conds.last_mut().ok_or_else(|| parse_error(line, "#elif without #if"))?;
+            let value = cond.parent_active && !cond.taken && eval_condition(rest, line, macros)?;
+            cond.active = value;
+            cond.taken |= value;
+        }
+        "else" => {
+            let cond = conds.last_mut().ok_or_else(|| parse_error(line, "#else without #if"))?;
+            cond.active = cond.parent_active && !cond.taken;
+            cond.taken = true;
+        }
+        "endif" => {
+            conds.pop().ok_or_else(|| parse_error(line, "#endif without #if"))?;
+        }
+        _ if !active => {}
+        "define" => {
+            let split = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            let (macro_name, body) = rest.split_at(split);
+            if macro_name.is_empty() {
+                return Err(parse_error(line, "#define without a name"));
+            }
+            let (params, body) = match body.strip_prefix('(') {
+                Some(after) => {
+                    let close = after.find(')').ok_or_else(|| parse_error(line, "unterminated macro parameter list"))?;
+                    let params =
+                        after[..close].split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect();
+                    (Some(params), &after[close + 1..])
+                }
+                None => (None, body),
+            };
+            macros.insert(macro_name.to_string(), Macro { params, body: lex_line(body, line)? });
+        }
+        "undef" => {
+            macros.remove(rest);
+        }
+        "pragma" | "line" | "" => {}
+        "error" => return Err(parse_error(line, format!("#error {}", rest))),
+        "include" => return Err(parse_error(line, "#include is not supported by the interpreter")),
+        other => return Err(parse_error(line, format!("unknown directive #{}", other))),
+    }
+    Ok(())
+}
+
+/// Evaluates an `#if` expression: integers, `defined`, and C operators.
+fn eval_condition(expr: &str, line: u32, macros: &HashMap<String, Macro>) -> Result<bool, InterpError> {
+    let raw = lex_line(expr, line)?;
+    // Resolve `defined X` / `defined(X)` before expanding macros.
+    let mut resolved = Vec::new();
+    let mut i = 0;
+    while i < raw.len() {
+        if raw[i].tok == Tok::Ident("defined".into()) {
+            let (name, skip) = match (raw.get(i + 1).map(|t| &t.tok), raw.get(i + 2).map(|t| &t.tok)) {
+                (Some(Tok::Punct("(")), Some(Tok::Ident(n))) => (n.clone(), 4),
+                (Some(Tok::Ident(n)), _) => (n.clone(), 2),
+                _ => return Err(parse_error(line, "malformed `defined`")),
+            };
+            resolved.push(Token { tok: Tok::Int(macros.contains_key(&name) as u64, false, false), line });
+            i += skip;
+        } else {
+            resolved.push(raw[i].clone());
+            i += 1;
+        }
+    }
+    let tokens = expand(&resolved, macros, &mut Vec::new())?;
+    let mut pos = 0;
+    let value = cond_expr(&tokens, &mut pos, 0, line)?;
+    Ok(value != 0)
+}
+
+fn cond_expr(tokens: &[Token], pos: &mut usize, min_prec: u8, line: u32) -> Result<i64, InterpError> {
+    let mut lhs = match tokens.get(*pos).map(|t| &t.tok) {
+        Some(Tok::Int(v, ..)) => {
+            *pos += 1;
+            *v as i64
+        }
+        // Identifiers left after expansion are 0, as in C.
+        Some(Tok::Ident(_)) => {
+            *pos += 1;
+            0
+        }
+        Some(Tok::Punct("(")) => {
+            *pos += 1;
+            let v = cond_expr(tokens, pos, 0, line)?;
+            *pos += 1;
+            v
+        }
+        Some(Tok::Punct("!")) => {
+            *pos += 1;
+            (cond_expr(tokens, pos, 11, line)? == 0) as i64
+        }
+        Some(Tok::Punct("-")) => {
+            *pos += 1;
+            -cond_expr(tokens, pos, 11, line)?
+        }
+        _ => return Err(parse_error(line, "malformed #if expression")),
+    };
+    while let Some(Tok::Punct(op)) = tokens.get(*pos).map(|t| &t.tok) {
+        let op = *op;
+        let prec = match op {
+            "||" => 1,
+            "&&" => 2,
+            "==" | "!=" => 6,
+            "<" | ">" | "<=" | ">=" => 7,
+            "+" | "-" => 9,
+            "*" | "/" | "%" => 10,
+            _ => break,
+        };
+        if prec < min_prec {
+            break;
+        }
+        *pos += 1;
+        let rhs = cond_expr(tokens, pos, prec + 1, line)?;
+        lhs = match op {
+            "||" => (lhs != 0 || rhs != 0) as i64,
+            "&&" => (lhs != 0 && rhs != 0) as i64,
+            "==" => (lhs == rhs) as i64,
+            "!=" => (lhs != rhs) as i64,
+            "<" => (lhs < rhs) as i64,
+            ">" => (lhs > rhs) as i64,
+            "<=" => (lhs <= rhs) as i64,
+            ">=" => (lhs >= rhs) as i64,
+            "+" => lhs.wrapping_add(rhs),
+            "-" => lhs.wrapping_sub(rhs),
+            "*" => lhs.wrapping_mul(rhs),
+            "/" | "%" if rhs == 0 => return Err(parse_error(line, "division by zero in #if")),
+            "/" => lhs / rhs,
+            _ => lhs % rhs,
+        };
+    }
+    Ok(lhs)
+}
+
+fn expand(tokens: &[Token], macros: &HashMap<String, Macro>, hide: &mut Vec<String>) -> Result<Vec<Token>, InterpError> {
+    let mut out = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = &tokens[i];
+        let name = match &token.tok {
+            Tok::Ident(name) if macros.contains_key(name) && !hide.contains(name) => name,
+            _ => {
+                out.push(token.clone());
+                i += 1;
+                continue;
+            }
+        };
+        let mac = &macros[name];
+        let body = match &mac.params {
+            None => {
+                i += 1;
+                mac.body.clone()
+            }
+            Some(params) => {
+                if tokens.get(i + 1).map(|t| &t.tok) != Some(&Tok::Punct("(")) {
+                    out.push(token.clone());
+                    i += 1;
+                    continue;
+                }
+                let (args, next) = macro_args(tokens, i + 2, token.line)?;
+                if args.len() != params.len() && !(params.is_empty() && args.len() == 1 && args[0].is_empty()) {
+                    return Err(parse_error(
+                        token.line,
+                        format!("macro {} expects {} arguments, got {}", name, params.len(), args.len()),
+                    ));
+                }
+                i = next;
+                let mut body = Vec::new();
+                for t in &mac.body {
+                    match &t.tok {
+                        Tok::Ident(p) if params.contains(p) => {
+                            let arg = &args[params.iter().position(|q| q == p).unwrap()];
+                            body.extend(expand(arg, macros, hide)?);
+                        }
+                        _ => body.push(t.clone()),
+                    }
+                }
+                body
+            }
+        };
+        let body: Vec<Token> = body.into_iter().map(|t| Token { tok: t.tok, line: token.line }).collect();
+        hide.push(name.clone());
+        out.extend(expand(&body, macros, hide)?);
+        hide.pop();
+    }
+    Ok(out)
+}
+
+/// Splits macro arguments at top-level commas, starting after the `(`.
+fn macro_args(tokens: &[Token], mut i: usize, line: u32) -> Result<(Vec<Vec<Token>>, usize), InterpError> {
+    let mut args = vec![Vec::new()];
+    let mut depth = 0;
+    loop {
+        let token = tokens.get(i).ok_or_else(|| parse_error(line, "unterminated macro invocation"))?;
+        i += 1;
+        match token.tok {
+            Tok::Punct("(") => depth += 1,
+            Tok::Punct(")") if depth == 0 => return Ok((args, i)),
+            Tok::Punct(")") => depth -= 1,
+            Tok::Punct(",") if depth == 0 => {
+                args.push(Vec::new());
+                continue;
+            }
+            _ => {}
+        }
+        args.last_mut().unwrap().push(token.clone());
+    }
+}
+
+/// Replaces comments with whitespace, keeping line breaks.
+fn strip_comments(src: &str) -> Result<String, InterpError> {
+    let bytes = src.as_bytes();
+    let mut out = String::with_capacity(src.len());
+    let mut i = 0;
+    let mut line = 1;
+    let mut quote: Option<u8> = None;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if let Some(q) = quote {
+            out.push(c as char);
+            if c == b'\\' && i + 1 < bytes.len() {
+                out.push(bytes[i + 1] as char);
+                i += 2;
+                continue;
+            }
+            if c == q || c == b'\n' {
+                quote = None;
+            }
+        } else if c == b'/' && bytes.get(i + 1) == Some(&b'/') {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+            continue;
+        } else if c == b'/' && bytes.get(i + 1) == Some(&b'*') {
+            let start = line;
+            i += 2;
+            loop {
+                match bytes.get(i) {
+                    None => return Err(parse_error(start, "unterminated comment")),
+                    Some(b'*') if bytes.get(i + 1) == Some(&b'/') => break,
+                    Some(b'\n') => {
+                        out.push('\n');
+                        line += 1;
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+            out.push(' ');
+            i += 2;
+            continue;
+        } else {
+            if c == b'"' || c == b'\'' {
+                quote = Some(c);
+            }
+            out.push(c as char);
+        }
+        if c == b'\n' {
+            line += 1;
+        }
+        i += 1;
+    }
+    Ok(out)
+}
+
+fn lex_line(s: &str, line: u32) -> Result<Vec<Token>, InterpError> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            out.push(Token { tok: Tok::Ident(s[start..i].to_string()), line });
+        } else if c.is_ascii_digit() || (c == b'.' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit)) {
+            let (tok, next) = lex_number(s, i, line)?;
+            out.push(Token { tok, line });
+            i = next;
+        } else if c == b'\'' {
+            let (value, next) = lex_char(bytes, i + 1, line)?;
+            if bytes.get(next) != Some(&b'\'') {
+                return Err(parse_error(line, "unterminated character literal"));
+            }
+            out.push(Token { tok: Tok::Int(value as u64, false, false), line });
+            i = next + 1;
+        } else if c == b'"' {
+            let mut text = String::new();
+            i += 1;
+            while bytes.get(i) != Some(&b'"') {
+                if i >= bytes.len() {
+                    return Err(parse_error(line, "unterminated string literal"));
+                }
+                let (value, next) = lex_char(bytes, i, line)?;
+                text.push(value as char);
+                i = next;
+            }
+            out.push(Token { tok: Tok::Str(text), line });
+            i += 1;
+        } else {
+            let rest = &s[i..];
+            let punct = PUNCTS
+                .iter()
+                .find(|p| rest.starts_with(**p))
+                .ok_or_else(|| parse_error(line, format!("unexpected character '{}'", c as char)))?;
+            out.push(Token { tok: Tok::Punct(punct), line });
+            i += punct.len();
+        }
+    }
+    Ok(out)
+}
+
+fn lex_char(bytes: &[u8], i: usize, line: u32) -> Result<(u8, usize), InterpError> {
+    match bytes.get(i) {
+        Some(b'\\') => {
+            let esc = *bytes.get(i + 1).ok_or_else(|| parse_error(line, "unterminated escape"))?;
+            Ok(match esc {
+                b'n' => (b'\n', i + 2),
+                b't' => (b'\t', i + 2),
+                b'r' => (b'\r', i + 2),
+                b'a' => (7, i + 2),
+                b'b' => (8, i + 2),
+                b'f' => (12, i + 2),
+                b'v' => (11, i + 2),
+                b'x' => {
+                    let mut j = i + 2;
+                    let mut v: u32 = 0;
+                    while let Some(d) = bytes.get(j).and_then(|b| (*b as char).to_digit(16)) {
+                        v = v * 16 + d;
+                        j += 1;
+                    }
+                    (v as u8, j)
+                }
+                b'0'..=b'7' => {
+                    let mut j = i + 1;
+                    let mut v: u32 = 0;
+                    while j < i + 4 && bytes.get(j).is_some_and(|b| (b'0'..=b'7').contains(b)) {
+                        v = v * 8 + (bytes[j] - b'0') as u32;
+                        j += 1;
+                    }
+                    (v as u8, j)
+                }
+                other => (other, i + 2),
+            })
+        }
+        Some(&c) => Ok((c, i + 1)),
+        None => Err(parse_error(line, "unterminated literal")),
+    }
+}
+
+fn lex_number(s: &str, start: usize, line: u32) -> Result<(Tok, usize), InterpError> {
+    let bytes = s.as_bytes();
+    let mut i = start;
+    if bytes[i] == b'0' && matches!(bytes.get(i + 1), Some(b'x' | b'X')) {
+        i += 2;
+        let digits_start = i;
+        while i < bytes.len() && bytes[i].is_ascii_hexdigit() {
+            i += 1;
+        }
+        let value = u64::from_str_radix(&s[digits_start..i], 16)
+            .map_err(|_| parse_error(line, format!("invalid hex literal {}", &s[start..i])))?;
+        let (unsigned, long, next) = int_suffix(bytes, i);
+        return Ok((Tok::Int(value, unsigned, long), next));
+    }
+    let mut is_float = false;
+    while i < bytes.len() && bytes[i].is_ascii_digit() {
+        i += 1;
+    }
+    if bytes.get(i) == Some(&b'.') {
+        is_float = true;
+        i += 1;
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+    }
+    if matches!(bytes.get(i), Some(b'e' | b'E')) {
+        let mut j = i + 1;
+        if matches!(bytes.get(j), Some(b'+' | b'-')) {
+            j += 1;
+        }
+        if bytes.get(j).is_some_and(u8::is_ascii_digit) {
+            is_float = true;
+            i = j;
+            while i < bytes.len() && bytes[i].is_ascii_digit() {
+                i += 1;
+            }
+        }
+    }
+    let text = &s[start..i];
+    if is_float || matches!(bytes.get(i), Some(b'f' | b'F')) {
+        let value: f64 = text.parse().map_err(|_| parse_error(line, format!("invalid float literal {}", text)))?;
+        return Ok(match bytes.get(i) {
+            Some(b'f' | b'F') => (Tok::Float(value, true), i + 1),
+            Some(b'l' | b'L' | b'h' | b'H') => (Tok::Float(value, false), i + 1),
+            _ => (Tok::Float(value, false), i),
+        });
+    }
+    let value = if text.len() > 1 && text.starts_with('0') {
+        u64::from_str_radix(&text[1..], 8)
+    } else {
+        text.parse::<u64>()
+    }
+    .map_err(|_| parse_error(line, format!("invalid integer literal {}", text)))?;
+    let (unsigned, long, next) = int_suffix(bytes, i);
+    Ok((Tok::Int(value, unsigned, long), next))
+}
+
+fn int_suffix(bytes: &[u8], mut i: usize) -> (bool, bool, usize) {
+    let (mut unsigned, mut long) = (false, false);
+    while let Some(c) = bytes.get(i) {
+        match c {
+            b'u' | b'U' => unsigned = true,
+            b'l' | b'L' => long = true,
+            _ => break,
+        }
+        i += 1;
+    }
+    (unsigned, long, i)
+}
@@ -0,0 +1,763 @@
+//! Recursive-descent parser for the supported OpenCL C subset.
+
+use super::InterpError;
+use super::ast::*;
+use super::lexer::{Tok, Token};
+use super::types::{Scalar, Space, Type, parse_num_type};
+use std::collections::HashMap;
+
+pub struct Parser {
+    toks: Vec<Token>,
+    pos: usize,
+    next_id: usize,
+    typedefs: HashMap<String, Type>,
+}
+
+/// Declaration specifiers: base type, address space and flags.
+struct Specs {
+    ty: Type,
+    space: Option<Space>,
+    kernel: bool,
+    typedef: bool,
+}
+
+const IGNORED: [&str; 16] = [
+    "const", "volatile", "restrict", "__restrict", "inline", "__inline", "static", "extern", "__read_only",
+    "read_only", "__write_only", "write_only", "__read_write", "read_write", "__const", "unroll",
+];
+
+const ASSIGN_OPS: [(&str, Option<BinOp>); 11] = [
+    ("=", None),
+    ("+=", Some(BinOp::Add)),
+    ("-=", Some(BinOp::Sub)),
+    ("*=", Some(BinOp::Mul)),
+    ("/=", Some(BinOp::Div)),
+    ("%=", Some(BinOp::Rem)),
+    ("<<=", Some(BinOp::Shl)),
+    (">>=", Some(BinOp::Shr)),
+    ("&=", Some(BinOp::And)),
+    ("|=", Some(BinOp::Or)),
+    ("^=", Some(BinOp::Xor)),
+];
+
+fn binop(p: &str) -> Option<(BinOp, u8)> {
+    Some(match p {
+        "||" => (BinOp::LogOr, 1),
+        "&&" => (BinOp::LogAnd, 2),
+        "|" => (BinOp::Or, 3),
+        "^" => (BinOp::Xor, 4),
+        "&" => (BinOp::And, 5),
+        "==" => (BinOp::Eq, 6),
+        "!=" => (BinOp::Ne, 6),
+        "<" => (BinOp::Lt, 7),
+        ">" => (BinOp::Gt, 7),
+        "<=" => (BinOp::Le, 7),
+        ">=" => (BinOp::Ge, 7),
+        "<<" => (BinOp::Shl, 8),
+        ">>" => (BinOp::Shr, 8),
+        "+" => (BinOp::Add, 9),
+        "-" => (BinOp::Sub, 9),
+        "*" => (BinOp::Mul, 10),
+        "/" => (BinOp::Div, 10),
+        "%" => (BinOp::Rem, 10),
+        _ => return None,
+    })
+}
+
+fn space_of(name: &str) -> Option<Space> {
+    Some(match name {
+        "__global" | "global" => Space::Global,
+        "__local" | "local" => Space::Local,
+        "__constant" | "constant" => Space::Constant,
+        "__private" | "private" => Space::Private,
+        _ => return None,
+    })
+}
+
+/// Parses a token stream into a program.
+pub fn parse(toks: Vec<Token>) -> Result<Unit, InterpError> {
+    let mut parser = Parser { toks, pos: 0, next_id: 0, typedefs: HashMap::new() };
+    parser.unit()
+}
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.toks[self.pos].tok
+    }
+
+    fn peek_at(&self, ahead: usize) -> &Tok {
+        &self.toks[(self.pos + ahead).min(self.toks.len() - 1)].tok
+    }
+
+    fn line(&self) -> u32 {
+        self.toks[self.pos].line
+    }
+
+    fn bump(&mut self) -> Tok {
+        let tok = self.toks[self.pos].tok.clone();
+        if self.pos + 1 < self.toks.len() {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, InterpError> {
+        Err(InterpError::Parse { line: self.line(), message: message.into() })
+    }
+
+    fn is_punct(&self, p: &str) -> bool {
+        matches!(self.peek(), Tok::Punct(q) if *q == p)
+    }
+
+    fn eat(&mut self, p: &str) -> bool {
+        if self.is_punct(p) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, p: &str) -> Result<(), InterpError> {
+        if self.eat(p) {
+            Ok(())
+        } else {
+            let found = self.describe();
+            self.error(format!("expected `{}`, found {}", p, found))
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.peek() {
+            Tok::Ident(name) => format!("`{}`", name),
+            Tok::Int(v, ..) => format!("`{}`", v),
+            Tok::Float(v, _) => format!("`{}`", v),
+            Tok::Str(s) => format!("\"{}\"", s),
+            Tok::Punct(p) => format!("`{}`", p),
+            Tok::Eof => "end of input".into(),
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, InterpError> {
+        match self.peek().clone() {
+            Tok::Ident(name) => {
+                self.bump();
+                Ok(name)
+            }
+            _ => {
+                let found = self.describe();
+                self.error(format!("expected an identifier, found {}", found))
+            }
+        }
+    }
+
+    fn is_type_word(&self, tok: &Tok) -> bool {
+        match tok {
+            Tok::Ident(name) => {
+                matches!(
+                    name.as_str(),
+                    "void" | "unsigned" | "signed" | "long" | "short" | "__kernel" | "kernel" | "typedef" | "struct"
+                        | "union" | "enum" | "__attribute__" | "half"
+                ) || IGNORED.contains(&name.as_str())
+                    || space_of(name).is_some()
+                    || parse_num_type(name).is_some()
+                    || self.typedefs.contains_key(name)
+            }
+            _ => false,
+        }
+    }
+
+    fn skip_attribute(&mut self) -> Result<(), InterpError> {
+        self.expect("(")?;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.bump() {
+                Tok::Punct("(") => depth += 1,
+                Tok::Punct(")") => depth -= 1,
+                Tok::Eof => return self.error("unterminated __attribute__"),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn specifiers(&mut self) -> Result<Specs, InterpError> {
+        let (mut space, mut kernel, mut typedef) = (None, false, false);
+        let (mut unsigned, mut signed, mut longs, mut short) = (false, false, 0, false);
+        let mut base: Option<Type> = None;
+        loop {
+            let name = match self.peek() {
+                Tok::Ident(name) if self.is_type_word(self.peek()) => name.clone(),
+                _ => break,
+            };
+            match name.as_str() {
+                "__kernel" | "kernel" => kernel = true,
+                "typedef" => typedef = true,
+                "unsigned" => unsigned = true,
+                "signed" => signed = true,
+                "long" => longs += 1,
+                "short" => short = true,
+                "__attribute__" => {
+                    self.bump();
+                    self.skip_attribute()?;
+                    continue;
+                }
+                "struct" | "union" | "enum" => return self.error(format!("{} types are not supported", name)),
+                "half" => return self.error("half is not supported"),
+                _ if IGNORED.contains(&name.as_str()) => {}
+                _ if space_of(&name).is_some() => space = space_of(&name),
+                _ if base.is_some() => break,
+                "void" => base = Some(Type::Void),
+                _ => {
+                    base = Some(match self.typedefs.get(&name) {
+                        Some(ty) => ty.clone(),
+                        None => {
+                            let (s, n) = parse_num_type(&name).expect("checked by is_type_word");
+                            Type::Num(s, n)
+                        }
+                    })
+                }
+            }
+            self.bump();
+        }
+        let sized = match (longs > 0, short) {
+            (true, _) => Some(Scalar::Long),
+            (_, true) => Some(Scalar::Short),
+            _ => None,
+        };
+        let ty = match (base, sized) {
+            (Some(Type::Num(Scalar::Int, 1)) | None, Some(s)) => Type::scalar(s),
+            (Some(ty), _) => ty,
+            (None, None) if unsigned || signed => Type::scalar(Scalar::Int),
+            (None, None) => return self.error(format!("expected a type, found {}", self.describe())),
+        };
+        let ty = match ty {
+            Type::Num(s, n) if unsigned => Type::Num(s.unsigned(), n),
+            other => other,
+        };
+        Ok(Specs { ty, space, kernel, typedef })
+    }
+
+    /// Parses `*`s, the name and array suffixes. `space` is the address
+    /// space written in the specifiers, which applies to the pointee.
+    fn declarator(&mut self, base: Type, space: Space, named: bool) -> Result<(String, Type), InterpError> {
+        let mut ty = base;
+        let mut first = true;
+        while self.eat("*") {
+            ty = Type::Ptr(Box::new(ty), if first { space } else { Space::Private });
+            first = false;
+            while let Tok::Ident(q) = self.peek() {
+                if IGNORED.contains(&q.as_str()) {
+                    self.bump();
+                } else {
+                    break;
+                }
+            }
+        }
+        let name = if named || matches!(self.peek(), Tok::Ident(_)) { self.ident()? } else { String::new() };
+        let mut dims = Vec::new();
+        while self.eat("[") {
+            if self.eat("]") {
+                dims.push(0);
+                continue;
+            }
+            let e = self.expr()?;
+            let len = self.const_eval(&e)?;
+            if len <= 0 {
+                return self.error("array size must be positive");
+            }
+            dims.push(len as usize);
+            self.expect("]")?;
+        }
+        for len in dims.into_iter().rev() {
+            ty = Type::Array(Box::new(ty), len);
+        }
+        while matches!(self.peek(), Tok::Ident(n) if n == "__attribute__") {
+            self.bump();
+            self.skip_attribute()?;
+        }
+        Ok((name, ty))
+    }
+
+    /// Evaluates an integer constant expression (array sizes, `case` labels).
+    fn const_eval(&self, e: &Expr) -> Result<i64, InterpError> {
+        Ok(match e {
+            Expr::Int(v, _) => *v as i64,
+            Expr::Unary(UnOp::Neg, a) => -self.const_eval(a)?,
+            Expr::Unary(UnOp::Plus, a) => self.const_eval(a)?,
+            Expr::Unary(UnOp::BitNot, a) => !self.const_eval(a)?,
+            Expr::Cast(_, a) => self.const_eval(a)?,
+            Expr::SizeOf(ty) => ty.size() as i64,
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (self.const_eval(a)?, self.const_eval(b)?);
+                match op {
+                    BinOp::Add => a.wrapping_add(b),
+                    BinOp::Sub => a.wrapping_sub(b),
+                    BinOp::Mul => a.wrapping_mul(b),
+                    BinOp::Div | BinOp::Rem if b == 0 => return self.error("division by zero in constant expression"),
+                    BinOp::Div => a / b,
+                    BinOp::Rem => a % b,
+                    BinOp::Shl => a << (b & 63),
+                    BinOp::Shr => a >> (b & 63),
+                    BinOp::And => a & b,
+                    BinOp::Or => a | b,
+                    BinOp::Xor => a ^ b,
+                    _ => return self.error("unsupported operator in constant expression"),
+                }
+            }
+            _ => return self.error("expected an integer constant expression"),
+        })
+    }
+
+    fn unit(&mut self) -> Result<Unit, InterpError> {
+        let mut unit = Unit::default();
+        while *self.peek() != Tok::Eof {
+            if self.eat(";") {
+                continue;
+            }
+            let line = self.line();
+            let specs = self.specifiers()?;
+            let space = specs.space.unwrap_or(Space::Private);
+            let (name, ty) = self.declarator(specs.ty.clone(), space, true)?;
+            if specs.typedef {
+                self.typedefs.insert(name, ty);
+                self.expect(";")?;
+                continue;
+            }
+            if self.eat("(") {
+                let params = self.params()?;
+                if self.eat(";") {
+                    continue;
+                }
+                self.expect("{")?;
+                let body = self.block_body()?;
+                if specs.kernel && ty != Type::Void {
+                    return Err(InterpError::Parse { line, message: format!("kernel {} must return void", name) });
+                }
+                unit.functions.push(Function { name, ret: ty, params, body, is_kernel: specs.kernel, line });
+                continue;
+            }
+            if space != Space::Constant {
+                return self.error("program-scope variables must be in the __constant address space");
+            }
+            let first = self.finish_decl(name, ty, space)?;
+            unit.constants.push(first);
+            while self.eat(",") {
+                let (name, ty) = self.declarator(specs.ty.clone(), space, true)?;
+                let decl = self.finish_decl(name, ty, space)?;
+                unit.constants.push(decl);
+            }
+            self.expect(";")?;
+        }
+        Ok(unit)
+    }
+
+    fn params(&mut self) -> Result<Vec<Param>, InterpError> {
+        let mut params = Vec::new();
+        if self.eat(")") {
+            return Ok(params);
+        }
+        if matches!(self.peek(), Tok::Ident(v) if v == "void") && *self.peek_at(1) == Tok::Punct(")") {
+            self.bump();
+            self.bump();
+            return Ok(params);
+        }
+        loop {
+            let specs = self.specifiers()?;
+            let space = specs.space.unwrap_or(Space::Private);
+            let (name, ty) = self.declarator(specs.ty, space, false)?;
+            // Array parameters are pointers.
+            let ty = match ty {
+                Type::Array(elem, _) => Type::Ptr(elem, space),
+                other => other,
+            };
+            params.push(Param { name, ty });
+            if self.eat(")") {
+                return Ok(params);
+            }
+            self.expect(",")?;
+        }
+    }
+
+    /// Parses the initializer (if any) of a declarator.
+    fn finish_decl(&mut self, name: String, mut ty: Type, space: Space) -> Result<Decl, InterpError> {
+        let init = if self.eat("=") { Some(self.initializer()?) } else { None };
+        if let Type::Array(elem, 0) = &ty {
+            match &init {
+                Some(Expr::Init(items)) => ty = Type::Array(elem.clone(), items.len()),
+                _ => return self.error(format!("array {} needs a size or an initializer list", name)),
+            }
+        }
+        self.next_id += 1;
+        Ok(Decl { id: self.next_id, name, ty, space, init })
+    }
+
+    fn initializer(&mut self) -> Result<Expr, InterpError> {
+        if !self.eat("{") {
+            return self.assign();
+        }
+        let mut items = Vec::new();
+        while !self.eat("}") {
+            items.push(self.initializer()?);
+            if !self.eat(",") {
+                self.expect("}")?;
+                break;
+            }
+        }
+        Ok(Expr::Init(items))
+    }
+
+    fn block_body(&mut self) -> Result<Vec<Stmt>, InterpError> {
+        let mut stmts = Vec::new();
+        while !self.eat("}") {
+            if *self.peek() == Tok::Eof {
+                return self.error("unterminated block");
+            }
+            stmts.push(self.stmt()?);
+        }
+        Ok(stmts)
+    }
+
+    fn stmt(&mut self) -> Result<Stmt, InterpError> {
+        let line = self.line();
+        let stmt = |kind| Ok(Stmt { kind, line });
+        if self.eat("{") {
+            return stmt(StmtKind::Block(self.block_body()?));
+        }
+        if self.eat(";") {
+            return stmt(StmtKind::Empty);
+        }
+        if self.is_type_word(self.peek()) {
+            let kind = self.decls()?;
+            self.expect(";")?;
+            return stmt(kind);
+        }
+        let keyword = match self.peek() {
+            Tok::Ident(k) => k.clone(),
+            _ => String::new(),
+        };
+        match keyword.as_str() {
+            "if" => {
+                self.bump();
+                let cond = self.paren_expr()?;
+                let then = Box::new(self.stmt()?);
+                let otherwise = if matches!(self.peek(), Tok::Ident(k) if k == "else") {
+                    self.bump();
+                    Some(Box::new(self.stmt()?))
+                } else {
+                    None
+                };
+                stmt(StmtKind::If(cond, then, otherwise))
+            }
+            "while" => {
+                self.bump();
+                let cond = self.paren_expr()?;
+                stmt(StmtKind::While(cond, Box::new(self.stmt()?)))
+            }
+            "do" => {
+                self.bump();
+                let body = Box::new(self.stmt()?);
+                if self.ident()? != "while" {
+                    return self.error("expected `while` after do body");
+                }
+                let cond = self.paren_expr()?;
+                self.expect(";")?;
+                stmt(StmtKind::DoWhile(body, cond))
+            }
+            "for" => {
+                self.bump();
+                self.expect("(")?;
+                let init = if self.eat(";") {
+                    None
+                } else if self.is_type_word(self.peek()) {
+                    let kind = self.decls()?;
+                    self.expect(";")?;
+                    Some(Box::new(Stmt { kind, line }))
+                } else {
+                    let e = self.expr()?;
+                    self.expect(";")?;
+                    Some(Box::new(Stmt { kind: StmtKind::Expr(e), line }))
+                };
+                let cond = if self.is_punct(";") { None } else { Some(self.expr()?) };
+                self.expect(";")?;
+                let step = if self.is_punct(")") { None } else { Some(self.expr()?) };
+                self.expect(")")?;
+                stmt(StmtKind::For(init, cond, step, Box::new(self.stmt()?)))
+            }
+            "switch" => {
+                self.bump();
+                let value = self.paren_expr()?;
+                self.expect("{")?;
+                stmt(StmtKind::Switch(value, self.block_body()?))
+            }
+            "case" => {
+                self.bump();
+                let value = self.cond()?;
+                self.expect(":")?;
+                stmt(StmtKind::Case(value))
+            }
+            "default" => {
+                self.bump();
+                self.expect(":")?;
+                stmt(StmtKind::Default)
+            }
+            "break" | "continue" => {
+                self.bump();
+                self.expect(";")?;
+                stmt(if keyword == "break" { StmtKind::Break } else { StmtKind::Continue })
+            }
+            "return" => {
+                self.bump();
+                let value = if self.is_punct(";") { None } else { Some(self.expr()?) };
+                self.expect(";")?;
+                stmt(StmtKind::Return(value))
+            }
+            "goto" => self.error("goto is not supported"),
+            _ => {
+                let e = self.expr()?;
+                self.expect(";")?;
+                stmt(StmtKind::Expr(e))
+            }
+        }
+    }
+
+    fn decls(&mut self) -> Result<StmtKind, InterpError> {
+        let specs = self.specifiers()?;
+        if specs.typedef {
+            let (name, ty) = self.declarator(specs.ty, specs.space.unwrap_or(Space::Private), true)?;
+            self.typedefs.insert(name, ty);
+            return Ok(StmtKind::Empty);
+        }
+        let space = specs.space.unwrap_or(Space::Private);
+        let mut decls = Vec::new();
+        loop {
+            let (name, ty) = self.declarator(specs.ty.clone(), space, true)?;
+            // A pointer variable lives in private memory whatever it points to.
+            let var_space = if matches!(ty, Type::Ptr(..)) { Space::Private } else { space };
+            decls.push(self.finish_decl(name, ty, var_space)?);
+            if !self.eat(",") {
+                return Ok(StmtKind::Decls(decls));
+            }
+        }
+    }
+
+    fn paren_expr(&mut self) -> Result<Expr, InterpError> {
+        self.expect("(")?;
+        let e = self.expr()?;
+        self.expect(")")?;
+        Ok(e)
+    }
+
+    pub fn expr(&mut self) -> Result<Expr, InterpError> {
+        let mut e = self.assign()?;
+        while self.eat(",") {
+            e = Expr::Comma(Box::new(e), Box::new(self.assign()?));
+        }
+        Ok(e)
+    }
+
+    fn assign(&mut self) -> Result<Expr, InterpError> {
+        let lhs = self.cond()?;
+        for (p, op) in ASSIGN_OPS {
+            if self.eat(p) {
+                if !lhs.is_place() {
+                    return self.error("left side of assignment is not assignable");
+                }
+                return Ok(Expr::Assign(op, Box::new(lhs), Box::new(self.assign()?)));
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn cond(&mut self) -> Result<Expr, InterpError> {
+        let c = self.binary(1)?;
+        if !self.eat("?") {
+            return Ok(c);
+        }
+        let a = self.expr()?;
+        self.expect(":")?;
+        let b = self.cond()?;
+        Ok(Expr::Cond(Box::new(c), Box::new(a), Box::new(b)))
+    }
+
+    fn binary(&mut self, min_prec: u8) -> Result<Expr, InterpError> {
+        let mut lhs = self.unary()?;
+        while let Tok::Punct(p) = self.peek() {
+            let (op, prec) = match binop(p) {
+                Some(found) if found.1 >= min_prec => found,
+                _ => break,
+            };
+            self.bump();
+            let rhs = self.binary(prec + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, InterpError> {
+        let op = match self.peek() {
+            Tok::Punct(p) => *p,
+            Tok::Ident(k) if k == "sizeof" => {
+                self.bump();
+                if self.is_punct("(") && self.is_type_word(self.peek_at(1)) {
+                    self.bump();
+                    let ty = self.type_name()?;
+                    self.expect(")")?;
+                    return Ok(Expr::SizeOf(ty));
+                }
+                return Ok(Expr::SizeOfValue(Box::new(self.unary()?)));
+            }
+            _ => return self.postfix(),
+        };
+        let simple = match op {
+            "-" => Some(UnOp::Neg),
+            "+" => Some(UnOp::Plus),
+            "!" => Some(UnOp::Not),
+            "~" => Some(UnOp::BitNot),
+            _ => None,
+        };
+        if let Some(u) = simple {
+            self.bump();
+            return Ok(Expr::Unary(u, Box::new(self.unary()?)));
+        }
+        match op {
+            "++" | "--" => {
+                self.bump();
+                let e = self.unary()?;
+                if !e.is_place() {
+                    return self.error(format!("operand of {} is not assignable", op));
+                }
+                Ok(Expr::Step { expr: Box::new(e), pre: true, inc: op == "++" })
+            }
+            "*" => {
+                self.bump();
+                Ok(Expr::Deref(Box::new(self.unary()?)))
+            }
+            "&" => {
+                self.bump();
+                let e = self.unary()?;
+                if !e.is_place() {
+                    return self.error("cannot take the address of this expression");
+                }
+                Ok(Expr::AddrOf(Box::new(e)))
+            }
+            "(" if self.is_type_word(self.peek_at(1)) => {
+                self.bump();
+                let ty = self.type_name()?;
+                self.expect(")")?;
+                if matches!(ty, Type::Num(_, n) if n > 1) && self.is_punct("(") {
+                    self.bump();
+                    let mut items = vec![self.assign()?];
+                    while self.eat(",") {
+                        items.push(self.assign()?);
+                    }
+                    self.expect(")")?;
+                    return self.postfix_ops(Expr::Vector(ty, items));
+                }
+                Ok(Expr::Cast(ty, Box::new(self.unary()?)))
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn type_name(&mut self) -> Result<Type, InterpError> {
+        let specs = self.specifiers()?;
+        let (_, ty) = self.declarator(specs.ty, specs.space.unwrap_or(Space::Private), false)?;
+        Ok(ty)
+    }
+
+    fn postfix(&mut self) -> Result<Expr, InterpError> {
+        let primary = self.primary()?;
+        self.postfix_ops(primary)
+    }
+
+    fn postfix_ops(&mut self, mut e: Expr) -> Result<Expr, InterpError> {
+        loop {
+            if self.eat("[") {
+                let index = self.expr()?;
+                self.expect("]")?;
+                e = Expr::Index(Box::new(e), Box::new(index));
+            } else if self.is_punct("(") {
+                let name = match e {
+                    Expr::Var(name) => name,
+                    _ => return self.error("only named functions can be called"),
+                };
+                self.bump();
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(self.assign()?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                e = Expr::Call(name, args);
+            } else if self.eat(".") {
+                let name = self.ident()?;
+                let sel = self.swizzle(&name)?;
+                e = Expr::Swizzle(Box::new(e), sel);
+            } else if self.is_punct("->") {
+                return self.error("structs are not supported");
+            } else if self.is_punct("++") || self.is_punct("--") {
+                let inc = self.bump() == Tok::Punct("++");
+                if !e.is_place() {
+                    return self.error("operand of ++/-- is not assignable");
+                }
+                e = Expr::Step { expr: Box::new(e), pre: false, inc };
+            } else {
+                return Ok(e);
+            }
+        }
+    }
+
+    fn swizzle(&self, name: &str) -> Result<Swizzle, InterpError> {
+        match name {
+            "lo" => return Ok(Swizzle::Lo),
+            "hi" => return Ok(Swizzle::Hi),
+            "even" => return Ok(Swizzle::Even),
+            "odd" => return Ok(Swizzle::Odd),
+            _ => {}
+        }
+        let lanes: Option<Vec<u8>> = if let Some(digits) = name.strip_prefix(['s', 'S']) {
+            digits.chars().map(|c| c.to_digit(16).map(|d| d as u8)).collect()
+        } else if name.chars().all(|c| "xyzw".contains(c)) {
+            name.chars().map(|c| "xyzw".find(c).map(|i| i as u8)).collect()
+        } else {
+            name.chars().map(|c| "rgba".find(c).map(|i| i as u8)).collect()
+        };
+        match lanes {
+            Some(lanes) if !lanes.is_empty() && lanes.len() <= 16 && lanes.len() != 5 => Ok(Swizzle::Lanes(lanes)),
+            _ => self.error(format!("invalid vector component selector .{}", name)),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, InterpError> {
+        let found = self.describe();
+        match self.bump() {
+            Tok::Int(v, unsigned, long) => {
+                let ty = match (unsigned, long) {
+                    (false, false) if v <= i32::MAX as u64 => Scalar::Int,
+                    (true, false) if v <= u32::MAX as u64 => Scalar::UInt,
+                    (false, _) if v <= i64::MAX as u64 => Scalar::Long,
+                    _ => Scalar::ULong,
+                };
+                Ok(Expr::Int(v, ty))
+            }
+            Tok::Float(v, single) => Ok(Expr::Float(v, if single { Scalar::Float } else { Scalar::Double })),
+            Tok::Str(s) => Ok(Expr::Str(s)),
+            Tok::Ident(name) => match name.as_str() {
+                "true" => Ok(Expr::Int(1, Scalar::Int)),
+                "false" => Ok(Expr::Int(0, Scalar::Int)),
+                _ => Ok(Expr::Var(name)),
+            },
+            Tok::Punct("(") => {
+                let e = self.expr()?;
+                self.expect(")")?;
+                Ok(e)
+            }
+            _ => self.error(format!("expected an expression, found {}", found)),
+        }
+    }
+}
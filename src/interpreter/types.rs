@@ -0,0 +1,188 @@
+//! OpenCL C types understood by the interpreter.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scalar {
+    Bool,
+    Char,
+    UChar,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Long,
+    ULong,
+    Float,
+    Double,
+}
+
+impl Scalar {
+    pub fn size(self) -> usize {
+        match self {
+            Scalar::Bool | Scalar::Char | Scalar::UChar => 1,
+            Scalar::Short | Scalar::UShort => 2,
+            Scalar::Int | Scalar::UInt | Scalar::Float => 4,
+            Scalar::Long | Scalar::ULong | Scalar::Double => 8,
+        }
+    }
+
+    pub fn bits(self) -> u32 {
+        self.size() as u32 * 8
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, Scalar::Float | Scalar::Double)
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(self, Scalar::Char | Scalar::Short | Scalar::Int | Scalar::Long | Scalar::Float | Scalar::Double)
+    }
+
+    fn rank(self) -> u8 {
+        match self {
+            Scalar::Bool => 0,
+            Scalar::Char | Scalar::UChar => 1,
+            Scalar::Short | Scalar::UShort => 2,
+            Scalar::Int => 3,
+            Scalar::UInt => 4,
+            Scalar::Long => 5,
+            Scalar::ULong => 6,
+            Scalar::Float => 7,
+            Scalar::Double => 8,
+        }
+    }
+
+    /// Integer promotion: anything narrower than `int` becomes `int`.
+    pub fn promote(self) -> Scalar {
+        if self.rank() < Scalar::Int.rank() { Scalar::Int } else { self }
+    }
+
+    /// Usual arithmetic conversions for two scalar operands.
+    pub fn common(a: Scalar, b: Scalar) -> Scalar {
+        let (a, b) = (a.promote(), b.promote());
+        if a.rank() >= b.rank() { a } else { b }
+    }
+
+    /// The signed integer type of the same size, used for the result of
+    /// vector comparisons.
+    pub fn mask_type(self) -> Scalar {
+        match self.size() {
+            1 => Scalar::Char,
+            2 => Scalar::Short,
+            4 => Scalar::Int,
+            _ => Scalar::Long,
+        }
+    }
+
+    pub fn unsigned(self) -> Scalar {
+        match self {
+            Scalar::Char => Scalar::UChar,
+            Scalar::Short => Scalar::UShort,
+            Scalar::Int | Scalar::Float => Scalar::UInt,
+            Scalar::Long | Scalar::Double => Scalar::ULong,
+            other => other,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scalar::Bool => "bool",
+            Scalar::Char => "char",
+            Scalar::UChar => "uchar",
+            Scalar::Short => "short",
+            Scalar::UShort => "ushort",
+            Scalar::Int => "int",
+            Scalar::UInt => "uint",
+            Scalar::Long => "long",
+            Scalar::ULong => "ulong",
+            Scalar::Float => "float",
+            Scalar::Double => "double",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Scalar> {
+        Some(match name {
+            "bool" => Scalar::Bool,
+            "char" => Scalar::Char,
+            "uchar" => Scalar::UChar,
+            "short" => Scalar::Short,
+            "ushort" => Scalar::UShort,
+            "int" => Scalar::Int,
+            "uint" => Scalar::UInt,
+            "long" => Scalar::Long,
+            "ulong" | "size_t" | "uintptr_t" => Scalar::ULong,
+            "ptrdiff_t" | "intptr_t" => Scalar::Long,
+            "float" => Scalar::Float,
+            "double" => Scalar::Double,
+            _ => return None,
+        })
+    }
+}
+
+/// Parses `float4`, `uchar16`, `int` and so on.
+pub fn parse_num_type(name: &str) -> Option<(Scalar, u8)> {
+    if let Some(s) = Scalar::from_name(name) {
+        return Some((s, 1));
+    }
+    let split = name.find(|c: char| c.is_ascii_digit())?;
+    let (base, width) = name.split_at(split);
+    let scalar = Scalar::from_name(base).filter(|s| *s != Scalar::Bool && !base.ends_with("_t"))?;
+    match width.parse::<u8>().ok()? {
+        n @ (2 | 3 | 4 | 8 | 16) => Some((scalar, n)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Space {
+    Global,
+    Constant,
+    Local,
+    Private,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Void,
+    /// Scalar (`1`) or vector of the given width.
+    Num(Scalar, u8),
+    Ptr(Box<Type>, Space),
+    Array(Box<Type>, usize),
+}
+
+impl Type {
+    pub fn scalar(s: Scalar) -> Type {
+        Type::Num(s, 1)
+    }
+
+    /// Storage size; three-component vectors occupy four.
+    pub fn size(&self) -> usize {
+        match self {
+            Type::Void => 1,
+            Type::Num(s, 3) => s.size() * 4,
+            Type::Num(s, n) => s.size() * *n as usize,
+            Type::Ptr(..) => 8,
+            Type::Array(elem, len) => elem.size() * len,
+        }
+    }
+
+    pub fn align(&self) -> usize {
+        match self {
+            Type::Array(elem, _) => elem.align(),
+            other => other.size().clamp(1, 128),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Void => write!(f, "void"),
+            Type::Num(s, 1) => write!(f, "{}", s.name()),
+            Type::Num(s, n) => write!(f, "{}{}", s.name(), n),
+            Type::Ptr(t, _) => write!(f, "{}*", t),
+            Type::Array(t, n) => write!(f, "{}[{}]", t, n),
+        }
+    }
+}
@@ -0,0 +1,362 @@
+//! Runtime values: scalars and vectors of up to sixteen lanes, and pointers.
+
+use super::ast::{BinOp, UnOp};
+use super::types::{Scalar, Type};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Null,
+    /// Index of the buffer argument.
+    Global(u16),
+    Constant,
+    Local,
+    Private,
+}
+
+/// A byte address in one of the memory regions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Addr {
+    pub region: Region,
+    pub offset: usize,
+}
+
+const OFFSET_BITS: u32 = 40;
+
+impl Addr {
+    pub const NULL: Addr = Addr { region: Region::Null, offset: 0 };
+
+    pub fn new(region: Region, offset: usize) -> Addr {
+        Addr { region, offset }
+    }
+
+    pub fn add(self, bytes: i64) -> Addr {
+        Addr { region: self.region, offset: self.offset.wrapping_add_signed(bytes as isize) }
+    }
+
+    /// Packs the address into the eight bytes a pointer occupies in memory.
+    pub fn encode(self) -> u64 {
+        let (tag, index) = match self.region {
+            Region::Null => (0, 0),
+            Region::Global(i) => (1, i as u64),
+            Region::Constant => (2, 0),
+            Region::Local => (3, 0),
+            Region::Private => (4, 0),
+        };
+        (tag << 56) | (index << OFFSET_BITS) | (self.offset as u64 & ((1 << OFFSET_BITS) - 1))
+    }
+
+    pub fn decode(bits: u64) -> Addr {
+        let region = match bits >> 56 {
+            1 => Region::Global((bits >> OFFSET_BITS) as u16),
+            2 => Region::Constant,
+            3 => Region::Local,
+            4 => Region::Private,
+            _ => Region::Null,
+        };
+        Addr { region, offset: (bits & ((1 << OFFSET_BITS) - 1)) as usize }
+    }
+}
+
+/// A scalar (`len == 1`) or vector. Integer lanes hold the value sign- or
+/// zero-extended to 64 bits; floating-point lanes hold `f64` bits, rounded
+/// to `f32` precision for `float`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Num {
+    pub ty: Scalar,
+    pub len: u8,
+    pub lanes: [u64; 16],
+}
+
+fn norm(ty: Scalar, v: u64) -> u64 {
+    let bits = ty.bits();
+    match ty {
+        Scalar::Bool => (v != 0) as u64,
+        _ if bits == 64 => v,
+        _ if ty.is_signed() => (((v << (64 - bits)) as i64) >> (64 - bits)) as u64,
+        _ => v & ((1 << bits) - 1),
+    }
+}
+
+fn from_f64_bits(ty: Scalar, x: f64) -> u64 {
+    match ty {
+        Scalar::Float => (x as f32 as f64).to_bits(),
+        Scalar::Double => x.to_bits(),
+        Scalar::Bool => (x != 0.0) as u64,
+        _ if x < 0.0 || ty.is_signed() => norm(ty, x as i64 as u64),
+        _ => norm(ty, x as u64),
+    }
+}
+
+impl Num {
+    pub fn from_u64(ty: Scalar, v: u64) -> Num {
+        let mut lanes = [0; 16];
+        lanes[0] = if ty.is_float() { from_f64_bits(ty, v as f64) } else { norm(ty, v) };
+        Num { ty, len: 1, lanes }
+    }
+
+    pub fn from_i64(ty: Scalar, v: i64) -> Num {
+        let mut lanes = [0; 16];
+        lanes[0] = if ty.is_float() { from_f64_bits(ty, v as f64) } else { norm(ty, v as u64) };
+        Num { ty, len: 1, lanes }
+    }
+
+    pub fn from_f64(ty: Scalar, x: f64) -> Num {
+        let mut lanes = [0; 16];
+        lanes[0] = from_f64_bits(ty, x);
+        Num { ty, len: 1, lanes }
+    }
+
+    pub fn int(v: i64) -> Num {
+        Num::from_i64(Scalar::Int, v)
+    }
+
+    /// Builds a vector from per-lane floats.
+    pub fn from_fn_f64(ty: Scalar, len: u8, f: impl Fn(usize) -> f64) -> Num {
+        let mut lanes = [0; 16];
+        for (i, lane) in lanes.iter_mut().enumerate().take(len as usize) {
+            *lane = from_f64_bits(ty, f(i));
+        }
+        Num { ty, len, lanes }
+    }
+
+    /// Builds a vector from raw lanes: two's complement integers, or `f64`
+    /// bits for floating-point types.
+    pub fn from_fn_u64(ty: Scalar, len: u8, f: impl Fn(usize) -> u64) -> Num {
+        let mut lanes = [0; 16];
+        for (i, lane) in lanes.iter_mut().enumerate().take(len as usize) {
+            *lane = if ty.is_float() { f(i) } else { norm(ty, f(i)) };
+        }
+        Num { ty, len, lanes }
+    }
+
+    pub fn f(&self, i: usize) -> f64 {
+        let v = self.lanes[i];
+        if self.ty.is_float() {
+            f64::from_bits(v)
+        } else if self.ty.is_signed() {
+            v as i64 as f64
+        } else {
+            v as f64
+        }
+    }
+
+    pub fn i(&self, i: usize) -> i64 {
+        if self.ty.is_float() { self.f(i) as i64 } else { self.lanes[i] as i64 }
+    }
+
+    pub fn u(&self, i: usize) -> u64 {
+        if self.ty.is_float() {
+            let x = self.f(i);
+            if x < 0.0 { x as i64 as u64 } else { x as u64 }
+        } else {
+            self.lanes[i]
+        }
+    }
+
+    /// Lane `i` is nonzero (or, for vector masks, has its top bit set).
+    pub fn lane_true(&self, i: usize) -> bool {
+        if self.ty.is_float() { self.f(i) != 0.0 } else { self.lanes[i] != 0 }
+    }
+
+    pub fn lane_msb(&self, i: usize) -> bool {
+        if self.ty.is_float() { self.f(i).is_sign_negative() } else { (self.lanes[i] >> (self.ty.bits() - 1)) & 1 == 1 }
+    }
+
+    pub fn is_true(&self) -> Result<bool, String> {
+        if self.len != 1 {
+            return Err("vector used as a condition".into());
+        }
+        Ok(self.lane_true(0))
+    }
+
+    pub fn convert(&self, to: Scalar) -> Num {
+        let mut lanes = [0; 16];
+        for (i, lane) in lanes.iter_mut().enumerate().take(self.len as usize) {
+            *lane = if to.is_float() || self.ty.is_float() {
+                from_f64_bits(to, self.f(i))
+            } else {
+                norm(to, self.lanes[i])
+            };
+        }
+        Num { ty: to, len: self.len, lanes }
+    }
+
+    /// Repeats a scalar across `len` lanes; vectors are returned unchanged.
+    pub fn splat(&self, len: u8) -> Num {
+        if self.len != 1 {
+            return *self;
+        }
+        let mut lanes = [self.lanes[0]; 16];
+        lanes[len as usize..].fill(0);
+        Num { ty: self.ty, len, lanes }
+    }
+
+    /// Selects lanes, as for a swizzle or `shuffle`.
+    pub fn select(&self, idx: &[u8]) -> Num {
+        let mut lanes = [0; 16];
+        for (lane, &i) in lanes.iter_mut().zip(idx) {
+            *lane = self.lanes[i as usize];
+        }
+        Num { ty: self.ty, len: idx.len() as u8, lanes }
+    }
+
+    pub fn write_bytes(&self, out: &mut Vec<u8>) {
+        for i in 0..self.len as usize {
+            match self.ty {
+                Scalar::Float => out.extend_from_slice(&(self.f(i) as f32).to_le_bytes()),
+                Scalar::Double => out.extend_from_slice(&self.f(i).to_le_bytes()),
+                ty => out.extend_from_slice(&self.lanes[i].to_le_bytes()[..ty.size()]),
+            }
+        }
+    }
+
+    pub fn read_bytes(ty: Scalar, len: u8, bytes: &[u8]) -> Num {
+        let size = ty.size();
+        let mut lanes = [0; 16];
+        for (lane, chunk) in lanes.iter_mut().zip(bytes.chunks_exact(size)).take(len as usize) {
+            let mut raw = [0u8; 8];
+            raw[..size].copy_from_slice(chunk);
+            let v = u64::from_le_bytes(raw);
+            *lane = match ty {
+                Scalar::Float => (f32::from_bits(v as u32) as f64).to_bits(),
+                Scalar::Double => v,
+                _ => norm(ty, v),
+            };
+        }
+        Num { ty, len, lanes }
+    }
+}
+
+/// Applies a binary operator with OpenCL's conversion rules: scalars are
+/// widened to the other operand's vector type, comparisons of vectors give
+/// `-1` / `0` masks.
+pub fn binary(op: BinOp, a: Num, b: Num) -> Result<Num, String> {
+    let len = match (a.len, b.len) {
+        (x, y) if x == y => x,
+        (1, y) => y,
+        (x, 1) => x,
+        (x, y) => return Err(format!("operands have different vector lengths ({} and {})", x, y)),
+    };
+    let shift = matches!(op, BinOp::Shl | BinOp::Shr);
+    let ty = if shift {
+        if a.len == 1 { a.ty.promote() } else { a.ty }
+    } else if a.len > 1 && (b.len == 1 || a.ty == b.ty) {
+        a.ty
+    } else if b.len > 1 && a.len == 1 {
+        b.ty
+    } else {
+        Scalar::common(a.ty, b.ty)
+    };
+    let (a, b) = (a.convert(ty).splat(len), if shift { b.splat(len) } else { b.convert(ty).splat(len) });
+    let (mask, out_ty) = match len {
+        1 => (1, Scalar::Int),
+        _ => (u64::MAX, ty.mask_type()),
+    };
+    let logical = |x: bool| if x { mask } else { 0 };
+
+    let mut lanes = [0; 16];
+    for (i, lane) in lanes.iter_mut().enumerate().take(len as usize) {
+        *lane = match op {
+            BinOp::LogAnd => logical(a.lane_true(i) && b.lane_true(i)),
+            BinOp::LogOr => logical(a.lane_true(i) || b.lane_true(i)),
+            _ if op.is_comparison() => {
+                let ord = if ty.is_float() {
+                    a.f(i).partial_cmp(&b.f(i))
+                } else if ty.is_signed() {
+                    Some(a.i(i).cmp(&b.i(i)))
+                } else {
+                    Some(a.u(i).cmp(&b.u(i)))
+                };
+                logical(match op {
+                    BinOp::Lt => ord.is_some_and(|o| o.is_lt()),
+                    BinOp::Gt => ord.is_some_and(|o| o.is_gt()),
+                    BinOp::Le => ord.is_some_and(|o| o.is_le()),
+                    BinOp::Ge => ord.is_some_and(|o| o.is_ge()),
+                    BinOp::Eq => ord.is_some_and(|o| o.is_eq()),
+                    _ => ord.is_none_or(|o| o.is_ne()),
+                })
+            }
+            _ if ty.is_float() => {
+                let (x, y) = (a.f(i), b.f(i));
+                from_f64_bits(
+                    ty,
+                    match op {
+                        BinOp::Add => x + y,
+                        BinOp::Sub => x - y,
+                        BinOp::Mul => x * y,
+                        BinOp::Div => x / y,
+                        BinOp::Rem => x % y,
+                        _ => return Err(format!("operator {:?} needs integer operands", op)),
+                    },
+                )
+            }
+            _ => {
+                let (x, y) = (a.lanes[i], b.lanes[i]);
+                let signed = ty.is_signed();
+                norm(
+                    ty,
+                    match op {
+                        BinOp::Add => x.wrapping_add(y),
+                        BinOp::Sub => x.wrapping_sub(y),
+                        BinOp::Mul => x.wrapping_mul(y),
+                        BinOp::Div | BinOp::Rem if y == 0 => return Err("integer division by zero".into()),
+                        BinOp::Div if signed => (x as i64).wrapping_div(y as i64) as u64,
+                        BinOp::Div => x / y,
+                        BinOp::Rem if signed => (x as i64).wrapping_rem(y as i64) as u64,
+                        BinOp::Rem => x % y,
+                        BinOp::Shl => x << (b.u(i) & (ty.bits() as u64 - 1)),
+                        BinOp::Shr if signed => ((x as i64) >> (b.u(i) & (ty.bits() as u64 - 1))) as u64,
+                        BinOp::Shr => x >> (b.u(i) & (ty.bits() as u64 - 1)),
+                        BinOp::And => x & y,
+                        BinOp::Or => x | y,
+                        _ => x ^ y,
+                    },
+                )
+            }
+        };
+    }
+    let result_ty = if op.is_comparison() || matches!(op, BinOp::LogAnd | BinOp::LogOr) { out_ty } else { ty };
+    Ok(Num { ty: result_ty, len, lanes: lanes.map(|l| if result_ty.is_float() { l } else { norm(result_ty, l) }) })
+}
+
+pub fn unary(op: UnOp, a: Num) -> Result<Num, String> {
+    let ty = if a.len == 1 { a.ty.promote() } else { a.ty };
+    let a = a.convert(ty);
+    if op == UnOp::Not {
+        let (mask, out_ty) = if a.len == 1 { (1, Scalar::Int) } else { (u64::MAX, ty.mask_type()) };
+        return Ok(Num::from_fn_u64(out_ty, a.len, |i| if a.lane_true(i) { 0 } else { mask }));
+    }
+    Ok(match (op, ty.is_float()) {
+        (UnOp::Plus, _) => a,
+        (UnOp::Neg, true) => Num::from_fn_f64(ty, a.len, |i| -a.f(i)),
+        (UnOp::Neg, false) => Num::from_fn_u64(ty, a.len, |i| a.lanes[i].wrapping_neg()),
+        (_, false) => Num::from_fn_u64(ty, a.len, |i| !a.lanes[i]),
+        (_, true) => return Err("operator ~ needs an integer operand".into()),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Void,
+    Num(Num),
+    /// Pointee type and address.
+    Ptr(Type, Addr),
+}
+
+impl Value {
+    pub fn num(self) -> Result<Num, String> {
+        match self {
+            Value::Num(n) => Ok(n),
+            Value::Ptr(..) => Err("expected a number, found a pointer".into()),
+            Value::Void => Err("expected a number, found void".into()),
+        }
+    }
+
+    pub fn is_true(&self) -> Result<bool, String> {
+        match self {
+            Value::Num(n) => n.is_true(),
+            Value::Ptr(_, addr) => Ok(addr.region != Region::Null),
+            Value::Void => Err("void value used as a condition".into()),
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! Per-program kernel cache for multi-pass algorithms.
+//!
+//! Each kernel is created once, on first use, and keeps its arguments between
+//! enqueues. Arguments are rebound by name using the program's argument info,
+//! so the program must be built with `program_info::KERNEL_ARG_INFO`
+//! (`KernelCache::build` does this).
+
+use crate::{Error, Result};
+use crate::program_info::{KernelInfo, ProgramInfo};
+use ocl::core::{self, ArgVal, KernelArgAddressQualifier, Mem, Status};
+use ocl::{Buffer, Context, Device, Event, EventList, OclPrm, Program, Queue, SpatialDims};
+use ocl::traits::WorkDims;
+use std::collections::HashMap;
+
+/// A kernel created once and re-enqueued with rebound arguments.
+///
+/// Unlike `ocl::Kernel`, arguments are not type-checked on every set; the
+/// driver still rejects values of the wrong size.
+pub struct CachedKernel {
+    kernel: core::Kernel,
+    info: KernelInfo,
+    // Keeps buffers bound as arguments alive while the kernel can use them.
+    mem_args: HashMap<u32, Mem>,
+    gws: SpatialDims,
+    lws: SpatialDims,
+}
+
+impl CachedKernel {
+    fn new(program: &Program, info: KernelInfo) -> Result<CachedKernel> {
+        let kernel = core::create_kernel(program, &info.name).map_err(|err| Error::from(err).kernel(&info.name))?;
+        Ok(CachedKernel { kernel, info, mem_args: HashMap::new(), gws: SpatialDims::Unspecified, lws: SpatialDims::Unspecified })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.info.name
+    }
+
+    pub fn info(&self) -> &KernelInfo {
+        &self.info
+    }
+
+    pub fn as_core(&self) -> &core::Kernel {
+        &self.kernel
+    }
+
+    /// Resolves an argument name to its index.
+    pub fn arg_index(&self, name: &str) -> Result<u32> {
+        match self.info.arg(name) {
+            Some(arg) => Ok(arg.index),
+            None if self.info.args.iter().all(|a| a.name.is_none()) => {
+                Err(Error::cl(Status::CL_KERNEL_ARG_INFO_NOT_AVAILABLE).kernel(&self.info.name))
+            }
+            None => Err(Error::ocl(format!("no argument named '{}'", name)).kernel(&self.info.name)),
+        }
+    }
+
+    /// Sets a scalar or vector argument.
+    pub fn set_arg_by_name<T: OclPrm>(&mut self, name: &str, value: T) -> Result<()> {
+        let index = self.arg_index(name)?;
+        self.set_arg(index, value)
+    }
+
+    pub fn set_buffer_by_name<T: OclPrm>(&mut self, name: &str, buffer: &Buffer<T>) -> Result<()> {
+        let index = self.arg_index(name)?;
+        self.set_buffer(index, buffer)
+    }
+
+    /// Sets a `__local` argument to `len` elements of `T`.
+    pub fn set_local_by_name<T: OclPrm>(&mut self, name: &str, len: usize) -> Result<()> {
+        let index = self.arg_index(name)?;
+        self.set_local::<T>(index, len)
+    }
+
+    pub fn set_arg<T: OclPrm>(&mut self, index: u32, value: T) -> Result<()> {
+        self.check_address(index, false)?;
+        core::set_kernel_arg(&self.kernel, index, ArgVal::scalar(&value)).map_err(|err| self.context(err, index))?;
+        self.mem_args.remove(&index);
+        Ok(())
+    }
+
+    pub fn set_buffer<T: OclPrm>(&mut self, index: u32, buffer: &Buffer<T>) -> Result<()> {
+        self.check_address(index, true)?;
+        core::set_kernel_arg(&self.kernel, index, ArgVal::mem(buffer.as_core())).map_err(|err| self.context(err, index))?;
+        self.mem_args.insert(index, buffer.as_core().clone());
+        Ok(())
+    }
+
+    pub fn set_local<T: OclPrm>(&mut self, index: u32, len: usize) -> Result<()> {
+        if self.address(index)?.is_some_and(|address| address != KernelArgAddressQualifier::Local) {
+            return Err(self.arg_error(index, "is not a __local argument"));
+        }
+        core::set_kernel_arg(&self.kernel, index, ArgVal::local::<T>(&len)).map_err(|err| self.context(err, index))?;
+        self.mem_args.remove(&index);
+        Ok(())
+    }
+
+    fn address(&self, index: u32) -> Result<Option<KernelArgAddressQualifier>> {
+        match self.info.args.get(index as usize) {
+            Some(arg) => Ok(arg.address),
+            None => Err(Error::cl(Status::CL_INVALID_ARG_INDEX).kernel(&self.info.name).arg(index)),
+        }
+    }
+
+    fn check_address(&self, index: u32, buffer: bool) -> Result<()> {
+        match self.address(index)? {
+            Some(KernelArgAddressQualifier::Local) => Err(self.arg_error(index, "is a __local argument; use set_local")),
+            Some(KernelArgAddressQualifier::Private) if buffer => Err(self.arg_error(index, "is not a buffer argument")),
+            Some(KernelArgAddressQualifier::Global | KernelArgAddressQualifier::Constant) if !buffer => {
+                Err(self.arg_error(index, "is a buffer argument; use set_buffer"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn arg_error(&self, index: u32, problem: &str) -> Error {
+        let arg = &self.info.args[index as usize];
+        let name = arg.name.clone().unwrap_or_else(|| format!("arg{}", index));
+        Error::ocl(format!("`{}` {}", name, problem)).kernel(&self.info.name).arg(index)
+    }
+
+    /// Names the kernel and argument in a failed `clSetKernelArg`.
+    fn context(&self, err: core::Error, index: u32) -> Error {
+        Error::from(err).kernel(&self.info.name).arg(index)
+    }
+
+    /// Default global work size for `enq`.
+    pub fn global_work_size<D: Into<SpatialDims>>(&mut self, gws: D) -> &mut Self {
+        self.gws = gws.into();
+        self
+    }
+
+    /// Default local work size for `enq`.
+    pub fn local_work_size<D: Into<SpatialDims>>(&mut self, lws: D) -> &mut Self {
+        self.lws = lws.into();
+        self
+    }
+
+    /// Enqueues the kernel with its current arguments.
+    ///
+    /// # Safety
+    ///
+    /// Same contract as `ocl::Kernel::enq`: kernel code is untrusted.
+    pub unsafe fn enq(&self, queue: &Queue) -> Result<()> {
+        unsafe { self.enq_with(queue, None, None) }
+    }
+
+    /// Enqueues the kernel after `ewait`, storing its event in `enew`.
+    ///
+    /// # Safety
+    ///
+    /// Same contract as `ocl::Kernel::enq`: kernel code is untrusted.
+    pub unsafe fn enq_with(&self, queue: &Queue, ewait: Option<&EventList>, enew: Option<&mut Event>) -> Result<()> {
+        let gws = match self.gws.to_work_size() {
+            Some(gws) => gws,
+            None => return Err(Error::ocl("no global work size set").kernel(&self.info.name)),
+        };
+        log::trace!("enqueue {} with global size {:?}, local size {:?}", self.info.name, self.gws, self.lws);
+        unsafe {
+            core::enqueue_kernel(queue, &self.kernel, self.gws.dim_count(), None, &gws, self.lws.to_work_size(), ewait, enew)
+                .map_err(|err| Error::from(err).kernel(&self.info.name))?
+        };
+        Ok(())
+    }
+}
+
+/// Lazily created kernels of one program, keyed by name.
+pub struct KernelCache {
+    program: Program,
+    info: ProgramInfo,
+    kernels: HashMap<String, CachedKernel>,
+}
+
+impl KernelCache {
+    /// Builds `src` for `device` with argument info enabled.
+    pub fn build(context: &Context, device: Device, src: &str) -> Result<KernelCache> {
+        let (program, info) = ProgramInfo::build(context, device, src)?;
+        Ok(KernelCache { program, info, kernels: HashMap::new() })
+    }
+
+    /// Wraps an already built program. Name-based setters only work if it was
+    /// built with `program_info::KERNEL_ARG_INFO`.
+    pub fn new(program: Program, device: Device) -> Result<KernelCache> {
+        let info = ProgramInfo::from_program(&program, device)?;
+        Ok(KernelCache { program, info, kernels: HashMap::new() })
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn info(&self) -> &ProgramInfo {
+        &self.info
+    }
+
+    /// Returns the kernel, creating it on first use.
+    pub fn kernel(&mut self, name: &str) -> Result<&mut CachedKernel> {
+        if !self.kernels.contains_key(name) {
+            let info = match self.info.kernel(name) {
+                Some(info) => info.clone(),
+                None => {
+                    let names: Vec<&str> = self.info.kernel_names().collect();
+                    let message = format!("no kernel named '{}' (available: {})", name, names.join(", "));
+                    return Err(Error::ocl(message).call("clCreateKernel"));
+                }
+            };
+            let kernel = CachedKernel::new(&self.program, info)?;
+            self.kernels.insert(name.to_string(), kernel);
+        }
+        Ok(self.kernels.get_mut(name).expect("kernel was just inserted"))
+    }
+
+    /// Shorthand for `kernel(kernel)?.set_arg_by_name(arg, value)`.
+    pub fn set_arg_by_name<T: OclPrm>(&mut self, kernel: &str, arg: &str, value: T) -> Result<()> {
+        self.kernel(kernel)?.set_arg_by_name(arg, value)
+    }
+
+    /// Enqueues a cached kernel with its current arguments.
+    ///
+    /// # Safety
+    ///
+    /// Same contract as `ocl::Kernel::enq`: kernel code is untrusted.
+    pub unsafe fn enq(&mut self, kernel: &str, queue: &Queue) -> Result<()> {
+        let kernel = self.kernel(kernel)?;
+        unsafe { kernel.enq(queue) }
+    }
+
+    /// Number of kernels created so far.
+    pub fn len(&self) -> usize {
+        self.kernels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kernels.is_empty()
+    }
+}
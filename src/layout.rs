@@ -0,0 +1,409 @@
+//! What a device's memory looks like from the host: byte order, and the
+//! size and alignment of the scalar and vector types.
+//!
+//! `Layout::probe` asks the device for `CL_DEVICE_ENDIAN_LITTLE` and then
+//! checks it with a kernel that stores a known `uint` and reads it back as
+//! bytes, the way `vector_byte.rs` does. The same kernel reports `sizeof` of
+//! every built-in type, and its alignment as the offset of a member placed
+//! after a `char` in a struct.
+//!
+//! `Transfer` reads and writes buffers in the host's byte order, swapping
+//! each component when the device disagrees, or refuses to when asked to.
+
+use crate::Result;
+use crate::logging::span;
+use crate::vector::Vector;
+use log::Level;
+use ocl::enums::{DeviceInfo, DeviceInfoResult};
+use ocl::{Buffer, Kernel, OclPrm, Program, Queue};
+use std::fmt;
+
+const SCALARS: [(&str, usize); 10] = [
+    ("char", 1),
+    ("uchar", 1),
+    ("short", 2),
+    ("ushort", 2),
+    ("int", 4),
+    ("uint", 4),
+    ("long", 8),
+    ("ulong", 8),
+    ("float", 4),
+    ("double", 8),
+];
+
+const WIDTHS: [usize; 6] = [1, 2, 3, 4, 8, 16];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    pub fn host() -> ByteOrder {
+        if cfg!(target_endian = "little") { ByteOrder::Little } else { ByteOrder::Big }
+    }
+}
+
+impl fmt::Display for ByteOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ByteOrder::Little => write!(f, "little-endian"),
+            ByteOrder::Big => write!(f, "big-endian"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LayoutError {
+    /// The probe kernel stored something that is neither byte order.
+    Probe([u8; 4]),
+    /// `CL_DEVICE_ENDIAN_LITTLE` contradicts what the device actually does.
+    Reported { reported: ByteOrder, measured: ByteOrder },
+    /// A type whose size differs from the one `ocl::prm` assumes.
+    Size { name: String, expected: usize, found: usize },
+    /// Host and device byte orders differ and the transfer may not swap.
+    ByteOrder { host: ByteOrder, device: ByteOrder },
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutError::Probe(bytes) => {
+                write!(f, "layout probe read 0x01020304 back as bytes {:02X?}, which is neither byte order", bytes)
+            }
+            LayoutError::Reported { reported, measured } => {
+                write!(f, "device reports itself {} but stores words {}", reported, measured)
+            }
+            LayoutError::Size { name, expected, found } => {
+                write!(f, "{} is {} bytes on the device, the host expects {}", name, found, expected)
+            }
+            LayoutError::ByteOrder { host, device } => {
+                write!(f, "device is {} but the host is {}, and byte swapping is off", device, host)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
+/// Size and alignment of one built-in type on the device, in bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeLayout {
+    /// The OpenCL C name: `char`, `float4`, ...
+    pub name: String,
+    pub size: usize,
+    pub align: usize,
+    /// Size of one component.
+    pub component: usize,
+    /// 1 for scalars.
+    pub lanes: usize,
+}
+
+impl TypeLayout {
+    /// The size OpenCL specifies, which is also what the `ocl::prm` types
+    /// occupy: three-component vectors take the room of four.
+    pub fn expected_size(&self) -> usize {
+        self.component * if self.lanes == 3 { 4 } else { self.lanes }
+    }
+
+    /// The alignment OpenCL specifies: the type's own size. The `ocl::prm`
+    /// vectors only have the alignment of their components, so a
+    /// `#[repr(C)]` struct mirroring a device struct with vector members
+    /// needs explicit padding.
+    pub fn expected_align(&self) -> usize {
+        self.expected_size()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Layout {
+    /// What `CL_DEVICE_ENDIAN_LITTLE` says.
+    pub reported: ByteOrder,
+    /// What the probe kernel found.
+    pub measured: ByteOrder,
+    pub address_bits: u32,
+    /// `CL_DEVICE_MEM_BASE_ADDR_ALIGN`, in bits.
+    pub base_addr_align: u32,
+    /// Every scalar and vector type; `double` types are missing on devices
+    /// without `cl_khr_fp64`.
+    pub types: Vec<TypeLayout>,
+}
+
+impl Layout {
+    /// Queries and measures the device of `queue`.
+    pub fn probe(queue: &Queue) -> Result<Layout> {
+        let device = queue.device();
+        let reported = match device.info(DeviceInfo::EndianLittle)? {
+            DeviceInfoResult::EndianLittle(false) => ByteOrder::Big,
+            _ => ByteOrder::Little,
+        };
+        let address_bits = match device.info(DeviceInfo::AddressBits)? {
+            DeviceInfoResult::AddressBits(bits) => bits,
+            _ => 0,
+        };
+        let base_addr_align = match device.info(DeviceInfo::MemBaseAddrAlign)? {
+            DeviceInfoResult::MemBaseAddrAlign(bits) => bits,
+            _ => 0,
+        };
+
+        let count = SCALARS.len() * WIDTHS.len();
+        let span = span!(Level::Debug, "probe the layout of {} types on {:?}", count, device);
+        let (bytes, size_values, align_values) = span.finish(run_probe(queue, count))?;
+
+        let measured = match bytes {
+            [4, 3, 2, 1] => ByteOrder::Little,
+            [1, 2, 3, 4] => ByteOrder::Big,
+            other => return Err(LayoutError::Probe(other).into()),
+        };
+        let types = type_names()
+            .zip(size_values.iter().zip(&align_values))
+            // Zero marks a type the device does not have.
+            .filter(|(_, (size, _))| **size != 0)
+            .map(|((name, component, lanes), (&size, &align))| TypeLayout {
+                name,
+                size: size as usize,
+                align: align as usize,
+                component,
+                lanes,
+            })
+            .collect();
+        Ok(Layout { reported, measured, address_bits, base_addr_align, types })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TypeLayout> {
+        self.types.iter().find(|t| t.name == name)
+    }
+
+    /// Whether the device stores words the way the host does.
+    pub fn matches_host(&self) -> bool {
+        self.measured == ByteOrder::host()
+    }
+
+    /// Fails if the device misreports its byte order, or if any type has a
+    /// size other than the one the host-side types assume.
+    pub fn check(&self) -> Result<()> {
+        if self.reported != self.measured {
+            return Err(LayoutError::Reported { reported: self.reported, measured: self.measured }.into());
+        }
+        match self.types.iter().find(|t| t.size != t.expected_size()) {
+            Some(t) => Err(LayoutError::Size { name: t.name.clone(), expected: t.expected_size(), found: t.size }.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// A `Transfer` for this device, after `check`. With `swap` false it
+    /// fails unless the device has the host's byte order.
+    pub fn transfer(&self, swap: bool) -> Result<Transfer> {
+        self.check()?;
+        if !swap && !self.matches_host() {
+            return Err(LayoutError::ByteOrder { host: ByteOrder::host(), device: self.measured }.into());
+        }
+        Ok(Transfer { swap: !self.matches_host() })
+    }
+}
+
+/// (name, component size, lanes) in the order the probe kernel writes them.
+/// Runs the probe kernel for `count` types; returns the byte order bytes
+/// and the measured sizes and alignments.
+fn run_probe(queue: &Queue, count: usize) -> Result<([u8; 4], Vec<u32>, Vec<u32>)> {
+    let program = Program::builder().src(probe_source()).devices(queue.device()).build(&queue.context())?;
+    let order = Buffer::<u8>::builder().queue(queue.clone()).len(4).build()?;
+    let sizes = Buffer::<u32>::builder().queue(queue.clone()).len(count).build()?;
+    let aligns = Buffer::<u32>::builder().queue(queue.clone()).len(count).build()?;
+    let kernel = Kernel::builder()
+        .program(&program)
+        .name("probe_layout")
+        .queue(queue.clone())
+        .global_work_size(1)
+        .arg(&order)
+        .arg(&sizes)
+        .arg(&aligns)
+        .build()?;
+    unsafe {
+        kernel.enq()?;
+    }
+
+    let mut bytes = [0u8; 4];
+    order.read(&mut bytes[..]).enq()?;
+    let mut size_values = vec![0u32; count];
+    sizes.read(&mut size_values).enq()?;
+    let mut align_values = vec![0u32; count];
+    aligns.read(&mut align_values).enq()?;
+    Ok((bytes, size_values, align_values))
+}
+
+fn type_names() -> impl Iterator<Item = (String, usize, usize)> {
+    SCALARS.iter().flat_map(|&(scalar, size)| {
+        WIDTHS.iter().map(move |&lanes| {
+            let name = if lanes == 1 { scalar.to_string() } else { format!("{}{}", scalar, lanes) };
+            (name, size, lanes)
+        })
+    })
+}
+
+fn probe_source() -> String {
+    let mut src = String::from(
+        "#ifdef cl_khr_fp64\n#pragma OPENCL EXTENSION cl_khr_fp64 : enable\n#endif\n\n\
+         __kernel void probe_layout(__global uchar *order, __global uint *sizes, __global uint *aligns) {\n\
+         \x20   uint word = 0x01020304;\n\
+         \x20   uchar *bytes = (uchar *)&word;\n\
+         \x20   for (int i = 0; i < 4; i++) order[i] = bytes[i];\n",
+    );
+    for (i, (name, _, _)) in type_names().enumerate() {
+        let guarded = name.starts_with("double");
+        if guarded {
+            src.push_str("#ifdef cl_khr_fp64\n");
+        }
+        src.push_str(&format!(
+            "    {{ struct {{ char pad; {name} value; }} s; sizes[{i}] = sizeof({name}); \
+             aligns[{i}] = (uint)((char *)&s.value - (char *)&s); }}\n"
+        ));
+        if guarded {
+            src.push_str(&format!("#else\n    sizes[{i}] = 0; aligns[{i}] = 0;\n#endif\n"));
+        }
+    }
+    src.push_str("}\n");
+    src
+}
+
+/// A scalar or vector whose components can be byte-swapped one by one.
+pub trait Swappable: OclPrm {
+    /// Size of one component in bytes.
+    const COMPONENT: usize;
+}
+
+impl Swappable for i8 {
+    const COMPONENT: usize = 1;
+}
+impl Swappable for u8 {
+    const COMPONENT: usize = 1;
+}
+impl Swappable for i16 {
+    const COMPONENT: usize = 2;
+}
+impl Swappable for u16 {
+    const COMPONENT: usize = 2;
+}
+impl Swappable for i32 {
+    const COMPONENT: usize = 4;
+}
+impl Swappable for u32 {
+    const COMPONENT: usize = 4;
+}
+impl Swappable for i64 {
+    const COMPONENT: usize = 8;
+}
+impl Swappable for u64 {
+    const COMPONENT: usize = 8;
+}
+impl Swappable for f32 {
+    const COMPONENT: usize = 4;
+}
+impl Swappable for f64 {
+    const COMPONENT: usize = 8;
+}
+
+impl<V: Vector> Swappable for V {
+    const COMPONENT: usize = std::mem::size_of::<V::Scalar>();
+}
+
+/// Reverses the bytes of every component of `values`.
+pub fn swap_components<T: Swappable>(values: &mut [T]) {
+    if T::COMPONENT == 1 {
+        return;
+    }
+    // SAFETY: `OclPrm` types are plain numbers or arrays of them, so every
+    // byte pattern is valid and the slice covers exactly their memory.
+    let bytes = unsafe { std::slice::from_raw_parts_mut(values.as_mut_ptr().cast::<u8>(), std::mem::size_of_val(values)) };
+    bytes.chunks_exact_mut(T::COMPONENT).for_each(<[u8]>::reverse);
+}
+
+/// Buffer reads and writes in host byte order, from `Layout::transfer`.
+/// All of them use the buffer's default queue.
+#[derive(Debug, Clone, Copy)]
+pub struct Transfer {
+    swap: bool,
+}
+
+impl Transfer {
+    /// Whether transfers byte-swap.
+    pub fn swaps(&self) -> bool {
+        self.swap
+    }
+
+    pub fn write<T: Swappable>(&self, buffer: &Buffer<T>, data: &[T]) -> Result<()> {
+        if self.swap {
+            let mut swapped = data.to_vec();
+            swap_components(&mut swapped);
+            buffer.write(&swapped).enq()?;
+        } else {
+            buffer.write(data).enq()?;
+        }
+        log::trace!("write {} bytes to {:?}, swapped: {}", std::mem::size_of_val(data), buffer.as_core().as_ptr(), self.swap);
+        Ok(())
+    }
+
+    pub fn read_into<T: Swappable>(&self, buffer: &Buffer<T>, data: &mut [T]) -> Result<()> {
+        buffer.read(&mut *data).enq()?;
+        log::trace!("read {} bytes from {:?}, swapped: {}", std::mem::size_of_val(data), buffer.as_core().as_ptr(), self.swap);
+        if self.swap {
+            swap_components(data);
+        }
+        Ok(())
+    }
+
+    /// The whole buffer.
+    pub fn read<T: Swappable>(&self, buffer: &Buffer<T>) -> Result<Vec<T>> {
+        let mut data = vec![T::default(); buffer.len()];
+        self.read_into(buffer, &mut data)?;
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ocl::prm::{Double2, Short3, Uchar4};
+
+    fn swapped_twice<T: Swappable + PartialEq + fmt::Debug>(values: &[T]) {
+        let mut copy = values.to_vec();
+        swap_components(&mut copy);
+        if T::COMPONENT > 1 {
+            assert_ne!(copy, values);
+        }
+        swap_components(&mut copy);
+        assert_eq!(copy, values);
+    }
+
+    #[test]
+    fn swapping_twice_restores_the_values() {
+        swapped_twice(&[0x0102u16, 0xfffe, 7]);
+        swapped_twice(&[1i32, -2, i32::MAX]);
+        swapped_twice(&[1.5f32, -0.25]);
+        swapped_twice(&[1.5f64, f64::MIN_POSITIVE]);
+        swapped_twice(&[Short3::from([1, -2, 0x0304]), Short3::from([5, 6, 7])]);
+        swapped_twice(&[Double2::from([1.0, -3.5])]);
+        swapped_twice(&[Uchar4::from([1, 2, 3, 4])]);
+    }
+
+    #[test]
+    fn each_component_is_reversed_in_place() {
+        let mut words = [0x0102_0304u32, 0xa0b0_c0d0];
+        swap_components(&mut words);
+        assert_eq!(words, [0x0403_0201, 0xd0c0_b0a0]);
+
+        let mut vector = [Short3::from([0x0102, 0x0304, 0x0506])];
+        swap_components(&mut vector);
+        assert_eq!(vector[0].lanes(), [0x0201, 0x0403, 0x0605]);
+
+        let mut float = [1.0f32];
+        swap_components(&mut float);
+        assert_eq!(float[0].to_bits(), 0x0000_803f);
+
+        // Bytes have nothing to swap.
+        let mut bytes = [Uchar4::from([1, 2, 3, 4])];
+        swap_components(&mut bytes);
+        assert_eq!(*bytes[0], [1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,26 @@
+pub mod backend;
+pub mod build_options;
+pub mod datagen;
+pub mod error;
+pub mod fft;
+pub mod future;
+pub mod graph;
+pub mod histogram;
+pub mod icd;
+pub mod interpreter;
+pub mod kernel_cache;
+pub mod layout;
+pub mod link;
+pub mod logging;
+pub mod mapped;
+pub mod multi_device;
+pub mod npy;
+pub mod partition;
+pub mod program_info;
+pub mod reference;
+pub mod sparse;
+pub mod template;
+pub mod tracker;
+pub mod vector;
+
+pub use error::{Error, Result};
@@ -0,0 +1,362 @@
+//! Separate compilation and linking (OpenCL 1.2).
+//!
+//! `Linker::compile` compiles one source file into an `Object`. Its
+//! `#include`s are served from `Includes`: in-memory files first, then the
+//! include directories in order. Each header found is handed to
+//! `clCompileProgram` as an embedded header under the name it was included
+//! by, so the device compiler never looks at the file system. Objects are
+//! then linked into a library, which can be linked again, or an executable
+//! program. Kernel libraries can share one header across programs this way
+//! instead of pasting it into each source.
+//!
+//! The interpreter has neither `#include` nor a linker; `link_interpreted`
+//! pastes the headers in and concatenates the units instead. Headers are
+//! pasted at most once per include chain, as guards would, but unguarded
+//! definitions in a header shared by two units end up twice.
+//!
+//! Includes are found textually, so an `#include` inside a disabled `#if`
+//! block must still resolve.
+
+use crate::build_options::{BuildOptions, BuildOptionsError, ClVersion};
+use crate::error::ResultExt;
+use crate::interpreter;
+use crate::logging::span;
+use crate::{Error, Result};
+use log::Level;
+use ocl::core::{self, Status};
+use ocl::{Context, Device, Program};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum LinkError {
+    /// An `#include` found neither in memory nor in an include directory.
+    Include { file: String, line: usize, name: String },
+    /// A source, header or option string with a NUL byte.
+    Nul(String),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::Include { file, line, name } => write!(f, "{}:{}: cannot find include `{}`", file, line, name),
+            LinkError::Nul(what) => write!(f, "{} contains a NUL byte", what),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+/// The `#include` directives of `src`: (line, name) in order.
+fn include_directives(src: &str) -> impl Iterator<Item = (usize, &str)> {
+    src.lines().enumerate().filter_map(|(i, line)| {
+        let rest = line.trim_start().strip_prefix('#')?.trim_start().strip_prefix("include")?.trim();
+        let name = match rest.chars().next()? {
+            '"' => rest[1..].split_once('"')?.0,
+            '<' => rest[1..].split_once('>')?.0,
+            _ => return None,
+        };
+        Some((i + 1, name))
+    })
+}
+
+/// Where `#include`s are looked up.
+#[derive(Debug, Clone, Default)]
+pub struct Includes {
+    files: BTreeMap<String, String>,
+    dirs: Vec<PathBuf>,
+}
+
+impl Includes {
+    pub fn new() -> Includes {
+        Includes::default()
+    }
+
+    /// An in-memory header, found as `name`.
+    pub fn file(mut self, name: &str, src: &str) -> Self {
+        self.files.insert(name.to_string(), src.to_string());
+        self
+    }
+
+    /// A directory searched after the in-memory headers and the directories
+    /// added before it.
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dirs.push(dir.into());
+        self
+    }
+
+    fn find(&self, name: &str) -> Result<Option<String>> {
+        if let Some(src) = self.files.get(name) {
+            return Ok(Some(src.clone()));
+        }
+        for dir in &self.dirs {
+            match std::fs::read_to_string(dir.join(name)) {
+                Ok(src) => return Ok(Some(src)),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(None)
+    }
+
+    fn require(&self, file: &str, line: usize, name: &str) -> Result<String> {
+        let missing = || LinkError::Include { file: file.to_string(), line, name: name.to_string() };
+        Ok(self.find(name)?.ok_or_else(missing)?)
+    }
+
+    /// The headers `src` includes, directly or through other headers, as
+    /// (include name, source), each once.
+    pub fn headers(&self, file: &str, src: &str) -> Result<Vec<(String, String)>> {
+        let mut headers: Vec<(String, String)> = Vec::new();
+        let mut pending = vec![(file.to_string(), src.to_string())];
+        while let Some((file, src)) = pending.pop() {
+            for (line, name) in include_directives(&src).collect::<Vec<_>>().into_iter().rev() {
+                if headers.iter().any(|(known, _)| known == name) {
+                    continue;
+                }
+                let header = self.require(&file, line, name)?;
+                headers.push((name.to_string(), header.clone()));
+                pending.push((name.to_string(), header));
+            }
+        }
+        Ok(headers)
+    }
+
+    /// `src` with its includes pasted in.
+    pub fn expand(&self, file: &str, src: &str) -> Result<String> {
+        let mut out = String::new();
+        self.expand_into(&mut out, &mut vec![file.to_string()], src)?;
+        Ok(out)
+    }
+
+    fn expand_into(&self, out: &mut String, stack: &mut Vec<String>, src: &str) -> Result<()> {
+        let mut directives = include_directives(src).peekable();
+        for (i, line) in src.lines().enumerate() {
+            let Some(&(_, name)) = directives.peek().filter(|&&(at, _)| at == i + 1) else {
+                out.push_str(line);
+                out.push('\n');
+                continue;
+            };
+            directives.next();
+            // An include cycle: guards would make the inner one empty.
+            if stack.iter().any(|open| open == name) {
+                continue;
+            }
+            let header = self.require(stack.last().map_or("", String::as_str), i + 1, name)?;
+            stack.push(name.to_string());
+            self.expand_into(out, stack, &header)?;
+            stack.pop();
+        }
+        Ok(())
+    }
+}
+
+/// A compiled unit or a library.
+#[derive(Debug, Clone)]
+pub struct Object {
+    name: String,
+    library: bool,
+    program: core::Program,
+}
+
+impl Object {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_library(&self) -> bool {
+        self.library
+    }
+
+    pub fn as_core(&self) -> &core::Program {
+        &self.program
+    }
+}
+
+fn c_string(what: &str, text: &str) -> Result<CString> {
+    Ok(CString::new(text).map_err(|_| LinkError::Nul(what.to_string()))?)
+}
+
+/// `ocl` reports a failed compile like a failed build, with the log.
+fn compile_error(err: core::Error, name: &str) -> Error {
+    match Error::from(err) {
+        Error::Cl { status: Status::CL_BUILD_PROGRAM_FAILURE, mut context } => {
+            context.call = Some("clCompileProgram".into());
+            Error::Cl { status: Status::CL_COMPILE_PROGRAM_FAILURE, context }.program(name)
+        }
+        err => err.program(name),
+    }
+}
+
+/// Compiles and links programs for one device.
+#[derive(Debug, Clone)]
+pub struct Linker {
+    context: Context,
+    device: Device,
+    version: ClVersion,
+    includes: Includes,
+}
+
+impl Linker {
+    /// Fails on devices older than OpenCL C 1.2, which cannot link.
+    pub fn new(context: &Context, device: Device, includes: Includes) -> Result<Linker> {
+        let version = ClVersion::of_device(device)?;
+        if version < ClVersion::V1_2 {
+            let option = "separate compilation".to_string();
+            return Err(BuildOptionsError::Unsupported { option, required: ClVersion::V1_2, device: version }.into());
+        }
+        Ok(Linker { context: context.clone(), device, version, includes })
+    }
+
+    pub fn includes(&self) -> &Includes {
+        &self.includes
+    }
+
+    /// Compiles `src`, named `name` in errors and include diagnostics.
+    pub fn compile(&self, name: &str, src: &str, options: &BuildOptions) -> Result<Object> {
+        options.check(self.version)?;
+        let mut headers = Vec::new();
+        let mut header_names = Vec::new();
+        for (header, header_src) in self.includes.headers(name, src)? {
+            headers.push(core::create_program_with_source(&self.context, &[c_string(&header, &header_src)?])?);
+            header_names.push(c_string(&header, &header)?);
+        }
+        let program = core::create_program_with_source(&self.context, &[c_string(name, src)?])?;
+        let c_options = c_string("the options", &options.to_string())?;
+        let headers: Vec<&core::Program> = headers.iter().collect();
+        log::debug!("{}: {} embedded header(s)", name, headers.len());
+        let span = span!(Level::Debug, "compile {} ({} bytes) with `{}`", name, src.len(), options);
+        let compiled = core::compile_program(&program, Some(&[self.device]), &c_options, &headers, &header_names, None, None, None);
+        span.finish(compiled.map_err(|err| compile_error(err, name)))?;
+        Ok(Object { name: name.to_string(), library: false, program })
+    }
+
+    /// Compiles the file at `path`, named by its path.
+    pub fn compile_file(&self, path: impl AsRef<Path>, options: &BuildOptions) -> Result<Object> {
+        let path = path.as_ref();
+        self.compile(&path.display().to_string(), &std::fs::read_to_string(path)?, options)
+    }
+
+    fn link(&self, name: &str, objects: &[&Object], options: &str) -> Result<core::Program> {
+        let programs: Vec<&core::Program> = objects.iter().map(|o| &o.program).collect();
+        let c_options = c_string("the options", options)?;
+        let span = span!(Level::Debug, "link {} with `{}`", name, options);
+        span.finish(core::link_program(self.context.as_core(), Some(&[self.device]), &c_options, &programs, None, None, None).program(name))
+    }
+
+    /// Links `objects` into a library for later links.
+    pub fn library(&self, name: &str, objects: &[&Object], options: &BuildOptions) -> Result<Object> {
+        let options = format!("{} -create-library", options.link_options()?);
+        let program = self.link(name, objects, options.trim())?;
+        Ok(Object { name: name.to_string(), library: true, program })
+    }
+
+    /// Links `objects` into a program whose kernels can run.
+    pub fn executable(&self, objects: &[&Object], options: &BuildOptions) -> Result<Program> {
+        let names: Vec<&str> = objects.iter().map(|o| o.name.as_str()).collect();
+        Ok(Program::from(self.link(&names.join(" + "), objects, &options.link_options()?)?))
+    }
+}
+
+/// The interpreter's stand-in for compiling and linking `units`, given as
+/// (name, source): their expanded sources, built as one program.
+pub fn link_interpreted(
+    units: &[(&str, &str)],
+    includes: &Includes,
+    options: &BuildOptions,
+) -> Result<interpreter::Program> {
+    let mut src = String::new();
+    for (name, unit) in units {
+        src.push_str(&includes.expand(name, unit)?);
+    }
+    interpreter::Program::build_with_options(&src, &options.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link_error(result: Result<impl fmt::Debug>) -> LinkError {
+        match result.unwrap_err() {
+            Error::Link(err) => err,
+            err => panic!("expected a link error, got {:?}", err),
+        }
+    }
+
+    /// A fresh directory under the system temp directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("simple_gpu_link_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn includes_come_from_memory_then_directories_in_order() {
+        let (first, second) = (temp_dir("first"), temp_dir("second"));
+        std::fs::write(first.join("real.h"), "#define REAL float\n").unwrap();
+        std::fs::write(second.join("real.h"), "#define REAL double\n").unwrap();
+        std::fs::create_dir(second.join("util")).unwrap();
+        std::fs::write(second.join("util/scale.h"), "#include \"real.h\"\nREAL scale(REAL x) { return 2 * x; }\n").unwrap();
+        let includes = Includes::new()
+            .file("consts.h", "#include <real.h>\n#define HALF ((REAL)0.5)\n")
+            .file("shadow.h", "// in memory\n")
+            .dir(&first)
+            .dir(&second);
+        std::fs::write(first.join("shadow.h"), "// on disk\n").unwrap();
+
+        let src = "#include \"consts.h\"\n  #  include <util/scale.h>\n#include \"shadow.h\"\nkernel void k() {}\n";
+        let headers = includes.headers("main.cl", src).unwrap();
+        let mut names: Vec<&str> = headers.iter().map(|(name, _)| name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["consts.h", "real.h", "shadow.h", "util/scale.h"]);
+        let find = |name: &str| headers.iter().find(|(n, _)| n == name).unwrap().1.as_str();
+        assert_eq!(find("real.h"), "#define REAL float\n");
+        assert_eq!(find("shadow.h"), "// in memory\n");
+
+        let expanded = includes.expand("main.cl", src).unwrap();
+        assert_eq!(expanded.matches("#define REAL float").count(), 2);
+        assert!(expanded.contains("// in memory") && !expanded.contains("#include"));
+
+        let _ = std::fs::remove_dir_all(first);
+        let _ = std::fs::remove_dir_all(second);
+    }
+
+    #[test]
+    fn missing_includes_name_the_including_file_and_line() {
+        let includes = Includes::new().file("a.h", "\n\n#include \"missing.h\"\n");
+        for err in [
+            link_error(includes.headers("main.cl", "#include \"a.h\"\n")),
+            link_error(includes.expand("main.cl", "#include \"a.h\"\n")),
+        ] {
+            match err {
+                LinkError::Include { file, line, name } => assert_eq!((file.as_str(), line, name.as_str()), ("a.h", 3, "missing.h")),
+                err => panic!("{:?}", err),
+            }
+        }
+        // Not a directive: no include to resolve.
+        assert!(includes.headers("main.cl", "// #include \"missing.h\"\n#include missing.h\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn include_cycles_expand_once() {
+        let includes = Includes::new().file("a.h", "#include \"b.h\"\nint a;\n").file("b.h", "#include \"a.h\"\nint b;\n");
+        assert_eq!(includes.expand("main.cl", "#include \"a.h\"\n").unwrap(), "int b;\nint a;\n");
+        assert_eq!(includes.headers("main.cl", "#include \"a.h\"\n").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn interpreted_units_share_headers() {
+        let includes = Includes::new().file("scale.h", "float scale(float x) { return x * FACTOR; }\n");
+        let units = [
+            ("scale.cl", "#include \"scale.h\"\n"),
+            ("main.cl", "kernel void run(global float *data) { data[get_global_id(0)] = scale(data[get_global_id(0)]); }\n"),
+        ];
+        let program = link_interpreted(&units, &includes, &BuildOptions::new().define_value("FACTOR", 3)).unwrap();
+        let mut data = interpreter::Buffer::from_slice(&[1.0f32, 2.0]);
+        program.kernel("run").unwrap().arg_buf(&mut data).global_work_size(2).run().unwrap();
+        assert_eq!(data.to_vec::<f32>(), [3.0, 6.0]);
+    }
+}
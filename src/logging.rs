@@ -0,0 +1,191 @@
+//! Log output for the library's OpenCL work.
+//!
+//! The library emits `log` records: device selection and program builds at
+//! `info` and `debug`, buffer creation at `debug`, and every enqueue, read
+//! and write at `trace`, with sizes and handles. Spans (`span!`) time an
+//! operation and log its duration when it finishes, or its error if it fails.
+//!
+//! `init` installs a logger configured from `SIMPLE_GPU_LOG`: a level
+//! (`off`, `error`, `warn`, `info`, `debug`, `trace`) and/or comma-separated
+//! `target=level` overrides, e.g. `info,simple_gpu::backend=trace`. Targets
+//! match by module path prefix. Unset, only warnings and errors are shown.
+//! Records go to stderr, or are appended to the file named by
+//! `SIMPLE_GPU_LOG_FILE`. Programs that install their own logger simply
+//! don't call `init`.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+pub const LOG_VAR: &str = "SIMPLE_GPU_LOG";
+pub const LOG_FILE_VAR: &str = "SIMPLE_GPU_LOG_FILE";
+
+/// Which records are logged: a default level and per-target overrides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    default: LevelFilter,
+    /// (target prefix, level); the longest matching prefix wins.
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    /// Parses `level`, `target=level` and combinations of them separated by
+    /// commas; `None` if a level is not one of the names above.
+    pub fn parse(spec: &str) -> Option<Filter> {
+        let mut filter = Filter::default();
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let parse = |level: &str| level.trim().parse::<LevelFilter>().ok();
+            match directive.split_once('=') {
+                Some((target, level)) => filter.targets.push((target.trim().to_string(), parse(level)?)),
+                None => filter.default = parse(directive)?,
+            }
+        }
+        filter.targets.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        Some(filter)
+    }
+
+    pub fn level(&self, target: &str) -> LevelFilter {
+        self.targets.iter().find(|(prefix, _)| target.starts_with(prefix.as_str())).map_or(self.default, |&(_, level)| level)
+    }
+
+    /// The most verbose level any target uses.
+    pub fn max_level(&self) -> LevelFilter {
+        self.targets.iter().map(|&(_, level)| level).fold(self.default, Ord::max)
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter { default: LevelFilter::Warn, targets: Vec::new() }
+    }
+}
+
+struct Logger {
+    filter: Filter,
+    start: Instant,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "[{:>10.6} {:<5} {}] {}\n",
+            self.start.elapsed().as_secs_f64(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        let mut out = self.out.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Logging must never fail the operation being logged.
+        let _ = out.write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {
+        let _ = self.out.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).flush();
+    }
+}
+
+/// Installs the logger configured from the environment. Later calls, and
+/// calls after another logger was installed, do nothing.
+pub fn init() {
+    let spec = std::env::var(LOG_VAR).unwrap_or_default();
+    let filter = Filter::parse(&spec).unwrap_or_else(|| {
+        eprintln!("{}: cannot parse `{}`; logging warnings and errors", LOG_VAR, spec);
+        Filter::default()
+    });
+    init_with(filter);
+}
+
+/// Installs a logger with `filter`, writing where `SIMPLE_GPU_LOG_FILE`
+/// says.
+pub fn init_with(filter: Filter) {
+    static INSTALLED: OnceLock<()> = OnceLock::new();
+    INSTALLED.get_or_init(|| {
+        let out: Box<dyn Write + Send> = match std::env::var(LOG_FILE_VAR) {
+            Ok(path) => match OpenOptions::new().create(true).append(true).open(&path) {
+                Ok(file) => Box::new(file),
+                Err(err) => {
+                    eprintln!("{}: cannot open {}: {}; logging to stderr", LOG_FILE_VAR, path, err);
+                    Box::new(io::stderr())
+                }
+            },
+            Err(_) => Box::new(io::stderr()),
+        };
+        let max_level = filter.max_level();
+        let logger = Box::leak(Box::new(Logger { filter, start: Instant::now(), out: Mutex::new(out) }));
+        if log::set_logger(logger).is_ok() {
+            log::set_max_level(max_level);
+        }
+    });
+}
+
+/// A timed operation; `finish` logs its duration or its error. A span
+/// dropped without `finish`, e.g. by an early return, only logs that it
+/// stopped.
+#[must_use = "a span ends with `finish`"]
+pub struct Span {
+    target: &'static str,
+    level: Level,
+    /// `None` when nothing would be logged, so nothing is formatted.
+    name: Option<String>,
+    start: Instant,
+}
+
+impl Span {
+    #[doc(hidden)]
+    pub fn enter(target: &'static str, level: Level, name: Option<String>) -> Span {
+        if let Some(name) = &name {
+            log::log!(target: target, level, "{} ...", name);
+        }
+        Span { target, level, name, start: Instant::now() }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Ends the span with the outcome of its operation; failures are logged
+    /// as warnings whatever the span's level.
+    pub fn finish<T, E: fmt::Display>(mut self, result: Result<T, E>) -> Result<T, E> {
+        if let Some(name) = self.name.take() {
+            match &result {
+                Ok(_) => log::log!(target: self.target, self.level, "{} done in {:.3?}", name, self.elapsed()),
+                Err(err) => log::warn!(target: self.target, "{} failed after {:.3?}: {}", name, self.elapsed(), err),
+            }
+        }
+        result
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(name) = &self.name {
+            log::log!(target: self.target, self.level, "{} stopped after {:.3?}", name, self.elapsed());
+        }
+    }
+}
+
+/// Starts a `Span` at `level` in the calling module, named by a format
+/// string: `span!(Level::Debug, "build {} bytes", src.len())`.
+macro_rules! span {
+    ($level:expr, $($arg:tt)+) => {{
+        let level = $level;
+        // Named whenever it may log: at its level, or on failure.
+        let enabled = log::log_enabled!(target: module_path!(), level)
+            || log::log_enabled!(target: module_path!(), log::Level::Warn);
+        let name = enabled.then(|| format!($($arg)+));
+        $crate::logging::Span::enter(module_path!(), level, name)
+    }};
+}
+
+pub(crate) use span;
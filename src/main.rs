@@ -1 +1,10 @@
-fn main(){}
\ No newline at end of file
+mod cli;
+
+fn main() {
+    simple_gpu::logging::init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(err) = cli::run(args) {
+        eprintln!("error: {}", err);
+        std::process::exit(if matches!(err, cli::CliError::Usage(_)) { 2 } else { 1 });
+    }
+}
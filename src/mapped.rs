@@ -0,0 +1,264 @@
+//! RAII guards over `clEnqueueMapBuffer`.
+//!
+//! A guard mutably borrows its `Buffer<T>`, so the buffer cannot be passed to
+//! a kernel builder, `set_arg`, read, write or copy while it is mapped. Kernels
+//! that already hold the buffer as an argument must not be enqueued until the
+//! guard has been dropped or `unmap`ped.
+
+use crate::{Error, Result};
+use crate::future::CommandFuture;
+use ocl::{Buffer, Event, EventList, MemMap, OclPrm, Queue};
+use ocl::flags::MapFlags;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Access mode of a mapping, fixed at the type level.
+pub trait MapAccess: sealed::Sealed {
+    fn flags() -> MapFlags;
+}
+
+/// Access modes whose guard hands out `&mut [T]`.
+pub trait Writable: MapAccess {}
+
+/// `CL_MAP_READ`
+pub struct Read;
+/// `CL_MAP_WRITE`
+pub struct Write;
+/// `CL_MAP_WRITE_INVALIDATE_REGION`: the mapped region starts undefined, so
+/// the guard only offers write access.
+pub struct WriteInvalidate;
+
+impl sealed::Sealed for Read {}
+impl sealed::Sealed for Write {}
+impl sealed::Sealed for WriteInvalidate {}
+
+impl MapAccess for Read {
+    fn flags() -> MapFlags {
+        MapFlags::new().read()
+    }
+}
+
+impl MapAccess for Write {
+    fn flags() -> MapFlags {
+        MapFlags::new().read().write()
+    }
+}
+
+impl MapAccess for WriteInvalidate {
+    fn flags() -> MapFlags {
+        MapFlags::new().write_invalidate_region()
+    }
+}
+
+impl Writable for Write {}
+impl Writable for WriteInvalidate {}
+
+pub type MappedRead<'b, T> = Mapped<'b, T, Read>;
+pub type MappedWrite<'b, T> = Mapped<'b, T, Write>;
+pub type MappedWriteInvalidate<'b, T> = Mapped<'b, T, WriteInvalidate>;
+
+/// Host view of a mapped buffer region. The region is unmapped when the guard
+/// is dropped; use `unmap` to observe errors or obtain the unmap event.
+pub struct Mapped<'b, T: OclPrm, A: MapAccess> {
+    map: MemMap<T>,
+    _buffer: PhantomData<&'b mut Buffer<T>>,
+    _access: PhantomData<A>,
+}
+
+impl<T: OclPrm, A: MapAccess> Mapped<'_, T, A> {
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Enqueues the unmap and returns its event, so commands on other queues
+    /// (or an out-of-order queue) can wait for the buffer to become usable.
+    pub fn unmap(mut self) -> Result<Event> {
+        let mut event = Event::empty();
+        log::trace!("unmap {} bytes at {:?}", self.len() * std::mem::size_of::<T>(), self.map.as_ptr());
+        self.map.unmap().enew(&mut event).enq()?;
+        Ok(event)
+    }
+}
+
+impl<T: OclPrm> Deref for Mapped<'_, T, Read> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.map
+    }
+}
+
+impl<T: OclPrm> Deref for Mapped<'_, T, Write> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.map
+    }
+}
+
+impl<T: OclPrm> DerefMut for Mapped<'_, T, Write> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.map
+    }
+}
+
+impl<T: OclPrm, A: Writable> Mapped<'_, T, A> {
+    /// Copies `src` into the start of the mapped region.
+    pub fn copy_from_slice(&mut self, src: &[T]) {
+        assert!(src.len() <= self.map.len(), "source slice is longer than the mapped region");
+        unsafe { std::ptr::copy_nonoverlapping(src.as_ptr(), self.map.as_mut_ptr(), src.len()) };
+    }
+
+    pub fn fill(&mut self, value: T) {
+        let ptr = self.map.as_mut_ptr();
+        for i in 0..self.map.len() {
+            unsafe { ptr.add(i).write(value) };
+        }
+    }
+
+    pub fn set(&mut self, index: usize, value: T) {
+        assert!(index < self.map.len(), "index {} out of mapped range {}", index, self.map.len());
+        unsafe { self.map.as_mut_ptr().add(index).write(value) };
+    }
+}
+
+/// Map command for one access mode; see `MapExt`.
+pub struct MapCmd<'b, T: OclPrm, A: MapAccess> {
+    buffer: &'b mut Buffer<T>,
+    queue: Option<&'b Queue>,
+    offset: usize,
+    len: Option<usize>,
+    ewait: Option<&'b EventList>,
+    _access: PhantomData<A>,
+}
+
+impl<'b, T: OclPrm, A: MapAccess> MapCmd<'b, T, A> {
+    /// Queue used for the map and the unmap (defaults to the buffer's queue).
+    pub fn queue(mut self, queue: &'b Queue) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    /// Offset of the mapped region, in elements.
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Length of the mapped region, in elements (defaults to the rest of the
+    /// buffer).
+    pub fn len(mut self, len: usize) -> Self {
+        self.len = Some(len);
+        self
+    }
+
+    /// Commands that must complete before the map, e.g. the kernel that
+    /// produced the data.
+    pub fn ewait(mut self, ewait: &'b EventList) -> Self {
+        self.ewait = Some(ewait);
+        self
+    }
+
+    /// Performs a blocking map.
+    pub fn enq(self) -> Result<Mapped<'b, T, A>> {
+        let len = self.len.unwrap_or(self.buffer.len().saturating_sub(self.offset));
+        let mut cmd = self.buffer.map().flags(A::flags()).offset(self.offset).len(len);
+        if let Some(queue) = self.queue {
+            cmd = cmd.queue(queue);
+        }
+        if let Some(ewait) = self.ewait {
+            cmd = cmd.ewait(ewait);
+        }
+        // The exclusive borrow of the buffer held by the guard rules out a
+        // second overlapping mapping.
+        let map = unsafe { cmd.enq()? };
+        log::trace!("map {} bytes of {:?} at {:?}", len * std::mem::size_of::<T>(), self.buffer.as_core().as_ptr(), map.as_ptr());
+        Ok(Mapped { map, _buffer: PhantomData, _access: PhantomData })
+    }
+
+    /// Performs a non-blocking map; the guard is handed out once the map
+    /// command has completed.
+    pub fn enq_future(self) -> Result<MapFuture<'b, T, A>> {
+        let len = self.len.unwrap_or(self.buffer.len().saturating_sub(self.offset));
+        let queue = match self.queue.or(self.buffer.default_queue()) {
+            Some(queue) => queue.clone(),
+            None => return Err(Error::ocl("MapCmd::enq_future: no queue set and the buffer has no default queue")),
+        };
+        let mut event = Event::empty();
+        let map = unsafe {
+            let core = ocl::core::enqueue_map_buffer::<T, _, _, _>(
+                &queue,
+                self.buffer.as_core(),
+                false,
+                A::flags(),
+                self.offset,
+                len,
+                self.ewait,
+                Some(&mut event),
+            )?;
+            MemMap::new(core, len, None, None, self.buffer.as_core().clone(), queue)
+        };
+        log::trace!("map {} bytes of {:?} at {:?}, non-blocking", len * std::mem::size_of::<T>(), self.buffer.as_core().as_ptr(), map.as_ptr());
+        Ok(MapFuture { map: Some(map), command: CommandFuture::new(event)?, _buffer: PhantomData, _access: PhantomData })
+    }
+}
+
+/// A pending non-blocking map; resolves to the guard.
+pub struct MapFuture<'b, T: OclPrm, A: MapAccess> {
+    map: Option<MemMap<T>>,
+    command: CommandFuture,
+    _buffer: PhantomData<&'b mut Buffer<T>>,
+    _access: PhantomData<A>,
+}
+
+impl<T: OclPrm, A: MapAccess> Unpin for MapFuture<'_, T, A> {}
+
+impl<'b, T: OclPrm, A: MapAccess> Future for MapFuture<'b, T, A> {
+    type Output = Result<Mapped<'b, T, A>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.command).poll(cx) {
+            Poll::Ready(result) => {
+                let map = self.map.take().expect("MapFuture polled after completion");
+                Poll::Ready(result.map(|()| Mapped { map, _buffer: PhantomData, _access: PhantomData }))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Typed mapping entry points for `Buffer<T>`.
+pub trait MapExt<T: OclPrm> {
+    fn map_read(&mut self) -> MapCmd<'_, T, Read>;
+    fn map_write(&mut self) -> MapCmd<'_, T, Write>;
+    fn map_write_invalidate(&mut self) -> MapCmd<'_, T, WriteInvalidate>;
+}
+
+impl<T: OclPrm> MapExt<T> for Buffer<T> {
+    fn map_read(&mut self) -> MapCmd<'_, T, Read> {
+        map_cmd(self)
+    }
+
+    fn map_write(&mut self) -> MapCmd<'_, T, Write> {
+        map_cmd(self)
+    }
+
+    fn map_write_invalidate(&mut self) -> MapCmd<'_, T, WriteInvalidate> {
+        map_cmd(self)
+    }
+}
+
+fn map_cmd<T: OclPrm, A: MapAccess>(buffer: &mut Buffer<T>) -> MapCmd<'_, T, A> {
+    MapCmd { buffer, queue: None, offset: 0, len: None, ewait: None, _access: PhantomData }
+}
@@ -0,0 +1,441 @@
+//! Splitting one NDRange across every usable device on every platform.
+//!
+//! The split runs along the last dimension (items for 1D, rows for 2D) in
+//! whole work-groups, in proportion to each device's weight. Weights start
+//! as an estimate (compute units × clock) and can be replaced by measured
+//! throughput with `MultiDevice::rebalance`. Each device gets its own copy of
+//! its slice of every split input; outputs are gathered back in order.
+
+use crate::Result;
+use crate::kernel_cache::{CachedKernel, KernelCache};
+use ocl::enums::{DeviceInfo, DeviceInfoResult, ProfilingInfo};
+use ocl::{flags, Buffer, Context, Device, Event, OclPrm, Platform, Queue, SpatialDims};
+use std::fmt;
+use std::ops::Range;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum MultiDeviceError {
+    /// No device could build the program; holds each device's failure.
+    NoDevices(Vec<String>),
+    UnsupportedDims(u32),
+    /// The split dimension is not a whole number of work-groups.
+    Indivisible { len: usize, group: usize },
+    LengthMismatch { arg: usize, expected: usize, actual: usize },
+}
+
+impl fmt::Display for MultiDeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultiDeviceError::NoDevices(reasons) if reasons.is_empty() => write!(f, "no OpenCL devices found"),
+            MultiDeviceError::NoDevices(reasons) => write!(f, "no usable OpenCL device: {}", reasons.join("; ")),
+            MultiDeviceError::UnsupportedDims(dims) => {
+                write!(f, "only 1D and 2D ranges can be split across devices, got {}D", dims)
+            }
+            MultiDeviceError::Indivisible { len, group } => write!(
+                f,
+                "split dimension of length {} is not a multiple of the work-group size {}",
+                len, group
+            ),
+            MultiDeviceError::LengthMismatch { arg, expected, actual } => write!(
+                f,
+                "argument {} has {} elements; the range needs {}",
+                arg, actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MultiDeviceError {}
+
+/// One device taking part in the split.
+pub struct DeviceSlot {
+    pub platform: Platform,
+    pub device: Device,
+    pub name: String,
+    pub queue: Queue,
+    /// Relative throughput; only ratios between devices matter.
+    pub weight: f64,
+    /// `estimate_weight` of the device.
+    estimate: f64,
+    /// Whether `weight` is a measurement or set by hand rather than scaled
+    /// from `estimate`.
+    measured: bool,
+    kernels: KernelCache,
+}
+
+/// Every usable device, each with its own queue and copy of the program.
+pub struct MultiDevice {
+    slots: Vec<DeviceSlot>,
+    skipped: Vec<String>,
+}
+
+impl MultiDevice {
+    /// Builds `src` on every device of every platform. Devices whose context,
+    /// queue or build fails are skipped and listed by `skipped`.
+    pub fn new(src: &str) -> Result<MultiDevice> {
+        let mut slots = Vec::new();
+        let mut skipped = Vec::new();
+        // `Platform::list` panics when no ICD is installed.
+        let platforms = ocl::core::get_platform_ids().map_err(|err| MultiDeviceError::NoDevices(vec![err.to_string()]))?;
+        for platform in Platform::list_from_core(platforms) {
+            let devices = match Device::list_all(platform) {
+                Ok(devices) if !devices.is_empty() => devices,
+                Ok(_) => continue,
+                Err(err) => {
+                    skipped.push(format!("{}: {}", platform.name().unwrap_or_default(), err));
+                    continue;
+                }
+            };
+            // Devices from different platforms cannot share a context.
+            let context = match Context::builder().platform(platform).devices(&devices[..]).build() {
+                Ok(context) => context,
+                Err(err) => {
+                    skipped.push(format!("{}: {}", platform.name().unwrap_or_default(), err));
+                    continue;
+                }
+            };
+            for device in devices {
+                let name = device.name().unwrap_or_else(|_| "unknown device".into());
+                match DeviceSlot::new(platform, device, name.clone(), &context, src) {
+                    Ok(slot) => slots.push(slot),
+                    Err(err) => skipped.push(format!("{}: {}", name, err)),
+                }
+            }
+        }
+        if slots.is_empty() {
+            return Err(MultiDeviceError::NoDevices(skipped).into());
+        }
+        Ok(MultiDevice { slots, skipped })
+    }
+
+    pub fn devices(&self) -> &[DeviceSlot] {
+        &self.slots
+    }
+
+    /// Devices that were found but could not be used, with the reason.
+    pub fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+
+    /// Sets a weight by hand; `rebalance` keeps it until the device is
+    /// measured.
+    pub fn set_weight(&mut self, device: usize, weight: f64) {
+        self.slots[device].weight = weight;
+        self.slots[device].measured = true;
+    }
+
+    /// Replaces the weights of measured devices with their throughput in
+    /// work-groups per second. Devices never measured, such as those that
+    /// got no work, keep their estimate scaled into the same unit by the
+    /// mean throughput per unit of estimate of the measured ones.
+    pub fn rebalance(&mut self, reports: &[PartReport]) {
+        let mut ratios = Vec::new();
+        for report in reports {
+            let secs = report.kernel_time.as_secs_f64();
+            if secs > 0.0 && report.groups > 0 {
+                let slot = &mut self.slots[report.device];
+                slot.weight = report.groups as f64 / secs;
+                slot.measured = true;
+                ratios.push(slot.weight / slot.estimate);
+            }
+        }
+        if ratios.is_empty() {
+            return;
+        }
+        let scale = ratios.iter().sum::<f64>() / ratios.len() as f64;
+        for slot in self.slots.iter_mut().filter(|slot| !slot.measured) {
+            slot.weight = slot.estimate * scale;
+        }
+    }
+
+    /// Starts describing a split launch of kernel `name`.
+    pub fn launch<'a, T: OclPrm>(&'a mut self, name: &'a str) -> Launch<'a, T> {
+        Launch { md: self, name, gws: SpatialDims::Unspecified, lws: SpatialDims::Unspecified, args: Vec::new() }
+    }
+
+    /// Splits `groups` work-groups by weight, largest remainders first.
+    fn split(&self, groups: usize) -> Vec<usize> {
+        let total: f64 = self.slots.iter().map(|s| s.weight.max(0.0)).sum();
+        if total <= 0.0 {
+            let mut counts = vec![groups / self.slots.len(); self.slots.len()];
+            counts[0] += groups % self.slots.len();
+            return counts;
+        }
+        let shares: Vec<f64> = self.slots.iter().map(|s| groups as f64 * s.weight.max(0.0) / total).collect();
+        let mut counts: Vec<usize> = shares.iter().map(|s| s.floor() as usize).collect();
+        let mut order: Vec<usize> = (0..shares.len()).collect();
+        order.sort_by(|&a, &b| (shares[b] - shares[b].floor()).total_cmp(&(shares[a] - shares[a].floor())));
+        let assigned: usize = counts.iter().sum();
+        for &i in order.iter().cycle().take(groups - assigned) {
+            counts[i] += 1;
+        }
+        counts
+    }
+}
+
+impl DeviceSlot {
+    fn new(platform: Platform, device: Device, name: String, context: &Context, src: &str) -> Result<DeviceSlot> {
+        let queue = Queue::new(context, device, Some(flags::QUEUE_PROFILING_ENABLE))?;
+        let kernels = KernelCache::build(context, device, src)?;
+        let estimate = estimate_weight(device);
+        Ok(DeviceSlot { platform, device, name, queue, weight: estimate, estimate, measured: false, kernels })
+    }
+}
+
+/// Compute units × clock (MHz), a rough stand-in until a run is measured.
+pub fn estimate_weight(device: Device) -> f64 {
+    let units = match device.info(DeviceInfo::MaxComputeUnits) {
+        Ok(DeviceInfoResult::MaxComputeUnits(n)) => n.max(1),
+        _ => 1,
+    };
+    let clock = match device.info(DeviceInfo::MaxClockFrequency) {
+        Ok(DeviceInfoResult::MaxClockFrequency(mhz)) => mhz.max(1),
+        _ => 1,
+    };
+    units as f64 * clock as f64
+}
+
+type BindFn<'a> = Box<dyn Fn(&mut CachedKernel, u32) -> Result<()> + 'a>;
+
+enum LaunchArg<'a, T: OclPrm> {
+    Split { data: &'a [T], per_group: usize },
+    Broadcast(&'a [T]),
+    Output { data: &'a mut [T], per_group: usize },
+    Bind(BindFn<'a>),
+}
+
+/// Kernel arguments in declaration order, plus the range to split.
+///
+/// `per_group` is the number of elements that belong to one work-group
+/// along the split dimension. For example, an element-wise kernel with a
+/// local size of 64 uses 64, and a reduction writing one partial sum per
+/// group uses 1 for its output.
+pub struct Launch<'a, T: OclPrm> {
+    md: &'a mut MultiDevice,
+    name: &'a str,
+    gws: SpatialDims,
+    lws: SpatialDims,
+    args: Vec<LaunchArg<'a, T>>,
+}
+
+impl<'a, T: OclPrm> Launch<'a, T> {
+    pub fn global_work_size<D: Into<SpatialDims>>(mut self, gws: D) -> Self {
+        self.gws = gws.into();
+        self
+    }
+
+    pub fn local_work_size<D: Into<SpatialDims>>(mut self, lws: D) -> Self {
+        self.lws = lws.into();
+        self
+    }
+
+    /// An input divided between devices.
+    pub fn split(mut self, data: &'a [T], per_group: usize) -> Self {
+        self.args.push(LaunchArg::Split { data, per_group });
+        self
+    }
+
+    /// An input copied whole to every device.
+    pub fn broadcast(mut self, data: &'a [T]) -> Self {
+        self.args.push(LaunchArg::Broadcast(data));
+        self
+    }
+
+    /// An output each device writes its part of.
+    pub fn output(mut self, data: &'a mut [T], per_group: usize) -> Self {
+        self.args.push(LaunchArg::Output { data, per_group });
+        self
+    }
+
+    pub fn scalar<S: OclPrm>(mut self, value: S) -> Self {
+        self.args.push(LaunchArg::Bind(Box::new(move |kernel, index| kernel.set_arg(index, value))));
+        self
+    }
+
+    /// A `__local` argument of `len` elements of `L` on every device.
+    pub fn local<L: OclPrm>(mut self, len: usize) -> Self {
+        self.args.push(LaunchArg::Bind(Box::new(move |kernel, index| kernel.set_local::<L>(index, len))));
+        self
+    }
+
+    /// Runs every part and blocks until the outputs have been gathered.
+    pub fn run(mut self) -> Result<Vec<PartReport>> {
+        let dims = self.gws.dim_count();
+        if dims != 1 && dims != 2 {
+            return Err(MultiDeviceError::UnsupportedDims(dims).into());
+        }
+        let gws = self.gws.to_lens().map_err(|_| MultiDeviceError::UnsupportedDims(0))?;
+        let split_dim = dims as usize - 1;
+        let group = match self.lws.to_lens() {
+            Ok(lws) if !self.lws.is_unspecified() => lws[split_dim].max(1),
+            _ => 1,
+        };
+        let len = gws[split_dim];
+        if len % group != 0 {
+            return Err(MultiDeviceError::Indivisible { len, group }.into());
+        }
+        let groups = len / group;
+        let counts = self.md.split(groups);
+        let mut ranges = Vec::with_capacity(counts.len());
+        let mut start = 0;
+        for &count in &counts {
+            ranges.push(start..start + count);
+            start += count;
+        }
+
+        // Hand each part its own slice of every output.
+        let mut outputs: Vec<Vec<&'a mut [T]>> = ranges.iter().map(|_| Vec::new()).collect();
+        for (arg, launch_arg) in self.args.iter_mut().enumerate() {
+            match launch_arg {
+                LaunchArg::Split { data, per_group } => check_len(arg, data.len(), groups * *per_group)?,
+                LaunchArg::Output { data, per_group } => {
+                    check_len(arg, data.len(), groups * *per_group)?;
+                    let mut rest = std::mem::take(data);
+                    for (part, range) in ranges.iter().enumerate() {
+                        let (head, tail) = rest.split_at_mut(range.len() * *per_group);
+                        outputs[part].push(head);
+                        rest = tail;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut pending = Vec::new();
+        let mut result = Ok(());
+        for ((device, range), chunks) in ranges.iter().enumerate().zip(outputs) {
+            if range.is_empty() {
+                continue;
+            }
+            let mut part_gws = gws;
+            part_gws[split_dim] = range.len() * group;
+            match self.enqueue_part(device, range.clone(), part_gws, dims, chunks) {
+                Ok(part) => pending.push(part),
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+
+        // The non-blocking reads write into the caller's slices, so every
+        // queue must drain before returning, even after an error.
+        for part in &pending {
+            if let Err(err) = self.md.slots[part.device].queue.finish() {
+                result = result.and(Err(err.into()));
+            }
+        }
+        result?;
+        pending.into_iter().map(|part| part.report(&self.md.slots)).collect()
+    }
+
+    fn enqueue_part(
+        &mut self,
+        device: usize,
+        range: Range<usize>,
+        gws: [usize; 3],
+        dims: u32,
+        mut chunks: Vec<&'a mut [T]>,
+    ) -> Result<Pending<T>> {
+        let slot = &mut self.md.slots[device];
+        log::debug!("{}: groups {:?} on device {} ({:?})", self.name, range, device, slot.device);
+        let queue = slot.queue.clone();
+        let kernel = slot.kernels.kernel(self.name)?;
+        let mut buffers = Vec::new();
+        let mut outputs = Vec::new();
+        for (index, arg) in self.args.iter().enumerate() {
+            let index = index as u32;
+            match arg {
+                LaunchArg::Split { data, per_group } => {
+                    let part = &data[range.start * per_group..range.end * per_group];
+                    let buffer = input_buffer(&queue, part)?;
+                    kernel.set_buffer(index, &buffer)?;
+                    buffers.push(buffer);
+                }
+                LaunchArg::Broadcast(data) => {
+                    let buffer = input_buffer(&queue, data)?;
+                    kernel.set_buffer(index, &buffer)?;
+                    buffers.push(buffer);
+                }
+                LaunchArg::Output { per_group, .. } => {
+                    let buffer = Buffer::<T>::builder()
+                        .queue(queue.clone())
+                        .flags(flags::MEM_WRITE_ONLY)
+                        .len((range.len() * per_group).max(1))
+                        .build()?;
+                    kernel.set_buffer(index, &buffer)?;
+                    outputs.push(buffer);
+                }
+                LaunchArg::Bind(bind) => bind(kernel, index)?,
+            }
+        }
+        let gws = match dims {
+            1 => SpatialDims::One(gws[0]),
+            _ => SpatialDims::Two(gws[0], gws[1]),
+        };
+        kernel.global_work_size(gws).local_work_size(self.lws);
+        let mut event = Event::empty();
+        unsafe { kernel.enq_with(&queue, None, Some(&mut event))? };
+        for (buffer, chunk) in outputs.iter().zip(chunks.iter_mut()) {
+            if !chunk.is_empty() {
+                // Safe as long as the queue is finished before `run` returns:
+                // `run` finishes the queues of returned parts, and a failure
+                // here finishes this one, as it is not returned.
+                if let Err(err) = unsafe { buffer.read(&mut **chunk).block(false).enq() } {
+                    queue.finish().ok();
+                    return Err(err.into());
+                }
+                log::trace!("read {} bytes from {:?}, non-blocking", std::mem::size_of_val(&**chunk), buffer.as_core().as_ptr());
+            }
+        }
+        buffers.extend(outputs);
+        Ok(Pending { device, groups: range.len(), event, _buffers: buffers })
+    }
+}
+
+fn check_len(arg: usize, actual: usize, expected: usize) -> Result<()> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(MultiDeviceError::LengthMismatch { arg, expected, actual }.into())
+    }
+}
+
+fn input_buffer<T: OclPrm>(queue: &Queue, data: &[T]) -> Result<Buffer<T>> {
+    Ok(Buffer::<T>::builder()
+        .queue(queue.clone())
+        .flags(flags::MEM_READ_ONLY | flags::MEM_COPY_HOST_PTR)
+        .len(data.len())
+        .copy_host_slice(data)
+        .build()?)
+}
+
+struct Pending<T: OclPrm> {
+    device: usize,
+    groups: usize,
+    event: Event,
+    _buffers: Vec<Buffer<T>>,
+}
+
+impl<T: OclPrm> Pending<T> {
+    fn report(self, slots: &[DeviceSlot]) -> Result<PartReport> {
+        let start = self.event.profiling_info(ProfilingInfo::Start)?.time()?;
+        let end = self.event.profiling_info(ProfilingInfo::End)?.time()?;
+        Ok(PartReport {
+            device: self.device,
+            name: slots[self.device].name.clone(),
+            groups: self.groups,
+            kernel_time: Duration::from_nanos(end.saturating_sub(start)),
+        })
+    }
+}
+
+/// How much of the range one device ran and how long its kernel took.
+#[derive(Debug, Clone)]
+pub struct PartReport {
+    pub device: usize,
+    pub name: String,
+    pub groups: usize,
+    pub kernel_time: Duration,
+}
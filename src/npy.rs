@@ -0,0 +1,639 @@
+//! NumPy `.npy` and `.npz` files for device buffers and images.
+//!
+//! `Array<T>` is a shaped host array that moves between files, `Buffer<T>`
+//! and `Image<T>`, checking the dtype against `T` and the shape against the
+//! device object. Files are read in C order; Fortran-ordered and big-endian
+//! arrays are converted on load, and arrays are always written little-endian
+//! in C order, as `numpy.save` does on common hosts.
+
+mod zip;
+
+use crate::Result;
+use ocl::enums::{ImageChannelDataType, ImageChannelOrder, MemInfo, MemInfoResult, MemObjectType};
+use ocl::{Buffer, Image, OclPrm, Queue, SpatialDims};
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum NpyError {
+    /// Not a valid `.npy` or `.npz` file.
+    Format(String),
+    /// The file's dtype differs from the requested element type.
+    Dtype { expected: String, found: String },
+    Shape { expected: Vec<usize>, found: Vec<usize> },
+    /// No array of this name in an `.npz` file.
+    Missing(String),
+}
+
+impl fmt::Display for NpyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NpyError::Format(msg) => write!(f, "{}", msg),
+            NpyError::Dtype { expected, found } => write!(f, "dtype mismatch: expected '{}', found '{}'", expected, found),
+            NpyError::Shape { expected, found } => write!(f, "shape mismatch: expected {:?}, found {:?}", expected, found),
+            NpyError::Missing(name) => write!(f, "no array named '{}'", name),
+        }
+    }
+}
+
+impl std::error::Error for NpyError {}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Element types with a NumPy dtype.
+pub trait Element: OclPrm + sealed::Sealed {
+    /// NumPy's kind code: `f`, `i` or `u`.
+    const KIND: char;
+
+    /// The little-endian dtype string, e.g. `<f4` or `|u1`.
+    fn descr() -> String {
+        let size = std::mem::size_of::<Self>();
+        format!("{}{}{}", if size == 1 { '|' } else { '<' }, Self::KIND, size)
+    }
+}
+
+impl sealed::Sealed for f32 {}
+impl sealed::Sealed for f64 {}
+impl sealed::Sealed for i8 {}
+impl sealed::Sealed for i16 {}
+impl sealed::Sealed for i32 {}
+impl sealed::Sealed for i64 {}
+impl sealed::Sealed for u8 {}
+impl sealed::Sealed for u16 {}
+impl sealed::Sealed for u32 {}
+impl sealed::Sealed for u64 {}
+
+impl Element for f32 {
+    const KIND: char = 'f';
+}
+impl Element for f64 {
+    const KIND: char = 'f';
+}
+impl Element for i8 {
+    const KIND: char = 'i';
+}
+impl Element for i16 {
+    const KIND: char = 'i';
+}
+impl Element for i32 {
+    const KIND: char = 'i';
+}
+impl Element for i64 {
+    const KIND: char = 'i';
+}
+impl Element for u8 {
+    const KIND: char = 'u';
+}
+impl Element for u16 {
+    const KIND: char = 'u';
+}
+impl Element for u32 {
+    const KIND: char = 'u';
+}
+impl Element for u64 {
+    const KIND: char = 'u';
+}
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// An array of any dtype, as raw C-ordered bytes. Tools that pick the
+/// element type at run time use this; `Array<T>` is the typed view.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawArray {
+    pub descr: String,
+    pub shape: Vec<usize>,
+    pub data: Vec<u8>,
+}
+
+impl RawArray {
+    /// Bytes per element, from the dtype.
+    pub fn item_size(&self) -> Option<usize> {
+        self.descr.get(2..)?.parse().ok()
+    }
+
+    /// The same array with a little-endian dtype (`|` for single bytes),
+    /// byte-swapping big-endian data.
+    pub fn into_little_endian(mut self) -> RawArray {
+        let (Some(item), Some((order, kind))) = (self.item_size(), self.descr.split_at_checked(1)) else {
+            return self;
+        };
+        let big_endian = match order {
+            ">" => true,
+            "=" => cfg!(target_endian = "big"),
+            _ => false,
+        };
+        if big_endian {
+            self.data.chunks_exact_mut(item).for_each(<[u8]>::reverse);
+        }
+        self.descr = format!("{}{}", if item == 1 { '|' } else { '<' }, kind);
+        self
+    }
+
+    pub fn read(mut reader: impl Read) -> Result<RawArray> {
+        let mut prefix = [0u8; 8];
+        reader.read_exact(&mut prefix)?;
+        if &prefix[..6] != MAGIC {
+            return Err(NpyError::Format("not a .npy file".into()).into());
+        }
+        let header_len = match prefix[6] {
+            1 => {
+                let mut len = [0u8; 2];
+                reader.read_exact(&mut len)?;
+                u16::from_le_bytes(len) as usize
+            }
+            2 | 3 => {
+                let mut len = [0u8; 4];
+                reader.read_exact(&mut len)?;
+                u32::from_le_bytes(len) as usize
+            }
+            v => return Err(NpyError::Format(format!(".npy version {} is not supported", v)).into()),
+        };
+        let mut header = vec![0u8; header_len];
+        reader.read_exact(&mut header)?;
+        let header = String::from_utf8(header).map_err(|_| NpyError::Format(".npy header is not text".into()))?;
+        let (descr, fortran_order, shape) = parse_header(&header)?;
+
+        let mut raw = RawArray { descr, shape, data: Vec::new() };
+        let item = raw
+            .item_size()
+            .filter(|_| raw.descr.len() >= 3 && "<>|=".contains(&raw.descr[..1]))
+            .ok_or_else(|| NpyError::Format(format!("unsupported dtype '{}'", raw.descr)))?;
+        let len = raw
+            .shape
+            .iter()
+            .try_fold(item, |len, &dim| len.checked_mul(dim))
+            .ok_or_else(|| NpyError::Format(format!(".npy shape {:?} is too large", raw.shape)))?;
+        reader.take(len as u64).read_to_end(&mut raw.data)?;
+        if raw.data.len() != len {
+            return Err(NpyError::Format(format!(".npy data is truncated: {} of {} bytes", raw.data.len(), len)).into());
+        }
+        if fortran_order {
+            raw.data = fortran_to_c(&raw.data, &raw.shape, item);
+        }
+        Ok(raw)
+    }
+
+    pub fn write(&self, mut writer: impl Write) -> Result<()> {
+        let shape = match &self.shape[..] {
+            [len] => format!("({},)", len),
+            dims => format!("({})", dims.iter().map(usize::to_string).collect::<Vec<_>>().join(", ")),
+        };
+        let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", self.descr, shape);
+        // The data starts 64-byte aligned after the 10-byte prefix.
+        let padded = (10 + header.len() + 1).next_multiple_of(64) - 10;
+        header.extend(std::iter::repeat_n(' ', padded - header.len() - 1));
+        header.push('\n');
+        let header_len = u16::try_from(header.len()).map_err(|_| NpyError::Format("shape too long".into()))?;
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[1, 0])?;
+        writer.write_all(&header_len.to_le_bytes())?;
+        writer.write_all(header.as_bytes())?;
+        writer.write_all(&self.data)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<RawArray> {
+        RawArray::read(std::io::BufReader::new(std::fs::File::open(path)?))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// `descr`, `fortran_order` and `shape` from a header such as
+/// `{'descr': '<f4', 'fortran_order': False, 'shape': (3, 4), }`.
+fn parse_header(header: &str) -> Result<(String, bool, Vec<usize>)> {
+    let bad = || NpyError::Format(format!("malformed .npy header: {}", header.trim()));
+    let value = |key: &str| {
+        let at = header.find(&format!("'{}':", key)).or_else(|| header.find(&format!("\"{}\":", key)))?;
+        Some(header[at + key.len() + 3..].trim_start())
+    };
+
+    let descr = value("descr").ok_or_else(bad)?;
+    let quote = descr.chars().next().filter(|&c| c == '\'' || c == '"').ok_or_else(bad)?;
+    let descr = descr[1..].split(quote).next().ok_or_else(bad)?.to_string();
+    let fortran_order = match value("fortran_order").ok_or_else(bad)? {
+        v if v.starts_with("True") => true,
+        v if v.starts_with("False") => false,
+        _ => return Err(bad().into()),
+    };
+    let shape = value("shape").and_then(|v| v.strip_prefix('(')).and_then(|v| v.split(')').next()).ok_or_else(bad)?;
+    let shape = shape
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(|d| d.trim_end_matches('L').parse().map_err(|_| bad()))
+        .collect::<std::result::Result<_, _>>()?;
+    Ok((descr, fortran_order, shape))
+}
+
+fn fortran_to_c(data: &[u8], shape: &[usize], item: usize) -> Vec<u8> {
+    let mut strides = Vec::with_capacity(shape.len());
+    let mut stride = 1;
+    for &dim in shape {
+        strides.push(stride);
+        stride *= dim;
+    }
+    let mut out = Vec::with_capacity(data.len());
+    let mut index = vec![0; shape.len()];
+    for _ in 0..data.len() / item.max(1) {
+        let from = index.iter().zip(&strides).map(|(i, s)| i * s).sum::<usize>() * item;
+        out.extend_from_slice(&data[from..from + item]);
+        for d in (0..shape.len()).rev() {
+            index[d] += 1;
+            if index[d] < shape[d] {
+                break;
+            }
+            index[d] = 0;
+        }
+    }
+    out
+}
+
+/// A shaped host array of `T`, in C order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Array<T: Element> {
+    shape: Vec<usize>,
+    data: Vec<T>,
+}
+
+impl<T: Element> Array<T> {
+    /// Fails unless `shape` holds exactly `data.len()` elements.
+    pub fn new(shape: impl Into<Vec<usize>>, data: Vec<T>) -> Result<Array<T>> {
+        let shape = shape.into();
+        if shape.iter().product::<usize>() != data.len() {
+            return Err(NpyError::Shape { expected: shape, found: vec![data.len()] }.into());
+        }
+        Ok(Array { shape, data })
+    }
+
+    /// A one-dimensional array.
+    pub fn from_vec(data: Vec<T>) -> Array<T> {
+        Array { shape: vec![data.len()], data }
+    }
+
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    pub fn data(&self) -> &[T] {
+        &self.data
+    }
+
+    pub fn into_data(self) -> Vec<T> {
+        self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The same elements under another shape of the same size.
+    pub fn reshape(self, shape: impl Into<Vec<usize>>) -> Result<Array<T>> {
+        Array::new(shape, self.data)
+    }
+
+    /// Fails unless the shape is `expected`.
+    pub fn check_shape(&self, expected: &[usize]) -> Result<()> {
+        if self.shape != expected {
+            return Err(NpyError::Shape { expected: expected.to_vec(), found: self.shape.clone() }.into());
+        }
+        Ok(())
+    }
+
+    /// Checks the dtype against `T`, byte-swapping big-endian data.
+    pub fn from_raw(raw: RawArray) -> Result<Array<T>> {
+        let raw = raw.into_little_endian();
+        if raw.descr != T::descr() {
+            return Err(NpyError::Dtype { expected: T::descr(), found: raw.descr }.into());
+        }
+        let size = std::mem::size_of::<T>();
+        let mut bytes = raw.data;
+        if cfg!(target_endian = "big") {
+            bytes.chunks_exact_mut(size).for_each(<[u8]>::reverse);
+        }
+        let mut data = vec![T::default(); bytes.len() / size];
+        // SAFETY: `T` is a plain numeric type valid for any bit pattern and
+        // `data` holds exactly `bytes.len()` bytes.
+        unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), data.as_mut_ptr().cast::<u8>(), bytes.len()) };
+        Array::new(raw.shape, data)
+    }
+
+    pub fn to_raw(&self) -> RawArray {
+        let size = std::mem::size_of_val(self.data.as_slice());
+        let mut data = vec![0u8; size];
+        // SAFETY: `data` holds exactly the bytes of `self.data`.
+        unsafe { std::ptr::copy_nonoverlapping(self.data.as_ptr().cast::<u8>(), data.as_mut_ptr(), size) };
+        if cfg!(target_endian = "big") {
+            data.chunks_exact_mut(std::mem::size_of::<T>()).for_each(<[u8]>::reverse);
+        }
+        RawArray { descr: T::descr(), shape: self.shape.clone(), data }
+    }
+
+    pub fn read(reader: impl Read) -> Result<Array<T>> {
+        Array::from_raw(RawArray::read(reader)?)
+    }
+
+    pub fn write(&self, writer: impl Write) -> Result<()> {
+        self.to_raw().write(writer)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Array<T>> {
+        Array::from_raw(RawArray::load(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.to_raw().save(path)
+    }
+
+    /// Reads a whole buffer into a one-dimensional array, using its default
+    /// queue.
+    pub fn from_buffer(buffer: &Buffer<T>) -> Result<Array<T>> {
+        let mut data = vec![T::default(); buffer.len()];
+        buffer.read(&mut data).enq()?;
+        log::trace!("read {} bytes from {:?}", std::mem::size_of_val(&data[..]), buffer.as_core().as_ptr());
+        Ok(Array::from_vec(data))
+    }
+
+    /// A new buffer holding the array's elements.
+    pub fn to_buffer(&self, queue: &Queue) -> Result<Buffer<T>> {
+        if self.data.is_empty() {
+            return Err(NpyError::Shape { expected: vec![1], found: self.shape.clone() }.into());
+        }
+        let buffer = Buffer::builder().queue(queue.clone()).len(self.data.len()).copy_host_slice(&self.data).build()?;
+        log::debug!("buffer {:?}: {} bytes from a {:?} array", buffer.as_core().as_ptr(), std::mem::size_of_val(&self.data[..]), self.shape);
+        Ok(buffer)
+    }
+
+    /// Overwrites `buffer`, which must have as many elements as the array.
+    pub fn write_buffer(&self, buffer: &Buffer<T>) -> Result<()> {
+        if buffer.len() != self.data.len() {
+            return Err(NpyError::Shape { expected: vec![buffer.len()], found: self.shape.clone() }.into());
+        }
+        buffer.write(&self.data).enq()?;
+        log::trace!("write {} bytes to {:?}", std::mem::size_of_val(&self.data[..]), buffer.as_core().as_ptr());
+        Ok(())
+    }
+
+    /// Reads a 1D, 2D or 3D image as `(width,)`, `(height, width)` or
+    /// `(depth, height, width)`, with a trailing channel axis when pixels
+    /// have more than one channel.
+    pub fn from_image(image: &Image<T>) -> Result<Array<T>> {
+        let mut data = vec![T::default(); image.element_count()];
+        image.read(&mut data).enq()?;
+        log::trace!("read {} bytes from image {:?}", std::mem::size_of_val(&data[..]), image.as_core().as_ptr());
+        Array::new(image_shape(image_dims(image)?, image.pixel_element_len()), data)
+    }
+
+    /// A new image of the given format; the array's shape must be
+    /// `from_image`'s layout for it and the channel type must match `T`.
+    pub fn to_image(
+        &self,
+        queue: &Queue,
+        order: ImageChannelOrder,
+        data_type: ImageChannelDataType,
+    ) -> Result<Image<T>> {
+        let (channels, channel_size) = channel_layout(order, data_type)
+            .ok_or_else(|| NpyError::Format(format!("unsupported image format {:?}/{:?}", order, data_type)))?;
+        if channel_size != std::mem::size_of::<T>() {
+            return Err(NpyError::Dtype { expected: format!("{} bytes per channel", channel_size), found: T::descr() }.into());
+        }
+        let spatial = if channels > 1 { self.shape.len().saturating_sub(1) } else { self.shape.len() };
+        if !(1..=3).contains(&spatial) || self.data.is_empty() {
+            return Err(NpyError::Format(format!("an image needs 1 to 3 non-empty dimensions, found {:?}", self.shape)).into());
+        }
+        let dims: Vec<usize> = self.shape[..spatial].iter().rev().copied().collect();
+        self.check_shape(&image_shape(dims.clone(), channels))?;
+        let (image_type, dims) = match dims[..] {
+            [w] => (MemObjectType::Image1d, SpatialDims::One(w)),
+            [w, h] => (MemObjectType::Image2d, SpatialDims::Two(w, h)),
+            [w, h, d] => (MemObjectType::Image3d, SpatialDims::Three(w, h, d)),
+            _ => unreachable!("1 to 3 dimensions checked above"),
+        };
+        Ok(Image::<T>::builder()
+            .channel_order(order)
+            .channel_data_type(data_type)
+            .image_type(image_type)
+            .dims(dims)
+            .copy_host_slice(&self.data)
+            .queue(queue.clone())
+            .build()?)
+    }
+}
+
+/// Width, height and depth, as many as the image type has.
+fn image_dims<T: OclPrm>(image: &Image<T>) -> Result<Vec<usize>> {
+    let count = match image.mem_info(MemInfo::Type)? {
+        MemInfoResult::Type(MemObjectType::Image1d) => 1,
+        MemInfoResult::Type(MemObjectType::Image2d) => 2,
+        MemInfoResult::Type(MemObjectType::Image3d) => 3,
+        other => return Err(NpyError::Format(format!("unsupported image type {:?}", other)).into()),
+    };
+    Ok((0..count).map(|d| image.dims()[d]).collect())
+}
+
+fn image_shape(mut dims: Vec<usize>, channels: usize) -> Vec<usize> {
+    dims.reverse();
+    if channels > 1 {
+        dims.push(channels);
+    }
+    dims
+}
+
+/// Channels per pixel and bytes per channel.
+fn channel_layout(order: ImageChannelOrder, data_type: ImageChannelDataType) -> Option<(usize, usize)> {
+    use ImageChannelDataType as D;
+    use ImageChannelOrder as O;
+    match data_type {
+        // Packed formats hold a whole pixel in one element.
+        D::UnormShort565 | D::UnormShort555 => return Some((1, 2)),
+        D::UnormInt101010 => return Some((1, 4)),
+        _ => {}
+    }
+    let channels = match order {
+        O::R | O::A | O::Intensity | O::Luminance => 1,
+        O::Rg | O::Ra | O::Rx => 2,
+        O::Rgb | O::Rgx => 3,
+        O::Rgba | O::Bgra | O::Argb | O::Rgbx => 4,
+        _ => return None,
+    };
+    let size = match data_type {
+        D::SnormInt8 | D::UnormInt8 | D::SignedInt8 | D::UnsignedInt8 => 1,
+        D::SnormInt16 | D::UnormInt16 | D::SignedInt16 | D::UnsignedInt16 | D::HalfFloat => 2,
+        D::SignedInt32 | D::UnsignedInt32 | D::Float => 4,
+        _ => return None,
+    };
+    Some((channels, size))
+}
+
+/// The arrays of an `.npz` file, in file order.
+#[derive(Debug, Clone, Default)]
+pub struct Npz {
+    arrays: Vec<(String, RawArray)>,
+}
+
+impl Npz {
+    pub fn new() -> Npz {
+        Npz::default()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Npz> {
+        Npz::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Npz> {
+        let mut npz = Npz::new();
+        for (name, contents) in zip::read_members(data)? {
+            let name = name.strip_suffix(".npy").unwrap_or(&name).to_string();
+            let raw = RawArray::read(&contents[..]).map_err(|err| NpyError::Format(format!("{}: {}", name, err)))?;
+            npz.arrays.push((name, raw));
+        }
+        Ok(npz)
+    }
+
+    /// Writes an archive as `numpy.savez` does, or deflated as
+    /// `numpy.savez_compressed` does.
+    pub fn to_bytes(&self, compress: bool) -> Result<Vec<u8>> {
+        let mut members = Vec::with_capacity(self.arrays.len());
+        for (name, raw) in &self.arrays {
+            let mut contents = Vec::new();
+            raw.write(&mut contents)?;
+            members.push((format!("{}.npy", name), contents));
+        }
+        zip::write_members(&members, compress)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        Ok(std::fs::write(path, self.to_bytes(false)?)?)
+    }
+
+    pub fn save_compressed(&self, path: impl AsRef<Path>) -> Result<()> {
+        Ok(std::fs::write(path, self.to_bytes(true)?)?)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.arrays.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn raw(&self, name: &str) -> Option<&RawArray> {
+        self.arrays.iter().find(|(n, _)| n == name).map(|(_, raw)| raw)
+    }
+
+    pub fn get<T: Element>(&self, name: &str) -> Result<Array<T>> {
+        let raw = self.raw(name).ok_or_else(|| NpyError::Missing(name.to_string()))?;
+        Array::from_raw(raw.clone())
+    }
+
+    /// Adds an array, replacing any of the same name.
+    pub fn insert_raw(&mut self, name: &str, raw: RawArray) {
+        match self.arrays.iter_mut().find(|(n, _)| n == name) {
+            Some(entry) => entry.1 = raw,
+            None => self.arrays.push((name.to_string(), raw)),
+        }
+    }
+
+    pub fn insert<T: Element>(&mut self, name: &str, array: &Array<T>) {
+        self.insert_raw(name, array.to_raw());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format_error(result: Result<impl fmt::Debug>) -> String {
+        match result.unwrap_err() {
+            crate::Error::Npy(NpyError::Format(msg)) => msg,
+            err => panic!("expected a format error, got {:?}", err),
+        }
+    }
+
+    /// A `.npy` file with a hand-written header.
+    fn npy(header: &str, data: &[u8]) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&[1, 0]);
+        out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        out.extend_from_slice(header.as_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    fn round_trip<T: Element + From<u8>>() {
+        let array = Array::new([2, 3], (0..6u8).map(T::from).collect()).unwrap();
+        let mut bytes = Vec::new();
+        array.write(&mut bytes).unwrap();
+        assert_eq!(Array::<T>::read(&bytes[..]).unwrap(), array, "{}", T::descr());
+
+        let mut npz = Npz::new();
+        npz.insert("a", &array);
+        npz.insert("b", &array.clone().reshape([6]).unwrap());
+        for compress in [false, true] {
+            let loaded = Npz::from_bytes(&npz.to_bytes(compress).unwrap()).unwrap();
+            assert_eq!(loaded.names().collect::<Vec<_>>(), ["a", "b"]);
+            assert_eq!(loaded.get::<T>("a").unwrap(), array, "{} compress {}", T::descr(), compress);
+            assert_eq!(loaded.get::<T>("b").unwrap().shape(), [6]);
+        }
+    }
+
+    #[test]
+    fn every_dtype_round_trips() {
+        round_trip::<f32>();
+        round_trip::<f64>();
+        round_trip::<i16>();
+        round_trip::<i32>();
+        round_trip::<i64>();
+        round_trip::<u8>();
+        round_trip::<u16>();
+        round_trip::<u32>();
+        round_trip::<u64>();
+        let array = Array::from_vec(vec![-1i8, 0, 1]);
+        let mut bytes = Vec::new();
+        array.write(&mut bytes).unwrap();
+        assert_eq!(Array::<i8>::read(&bytes[..]).unwrap(), array);
+    }
+
+    #[test]
+    fn fortran_order_and_big_endian_are_converted() {
+        // [[1, 2, 3], [4, 5, 6]] stored column by column.
+        let data: Vec<u8> = [1u16, 4, 2, 5, 3, 6].iter().flat_map(|v| v.to_be_bytes()).collect();
+        let file = npy("{'descr': '>u2', 'fortran_order': True, 'shape': (2, 3), }\n", &data);
+        let array = Array::<u16>::read(&file[..]).unwrap();
+        assert_eq!(array.shape(), [2, 3]);
+        assert_eq!(array.data(), [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn malformed_files_are_format_errors() {
+        let mut bad_magic = npy("{'descr': '<f4', 'fortran_order': False, 'shape': (1,), }\n", &[0; 4]);
+        bad_magic[1] = b'M';
+        assert_eq!(format_error(RawArray::read(&bad_magic[..])), "not a .npy file");
+
+        let truncated = npy("{'descr': '<f4', 'fortran_order': False, 'shape': (2,), }\n", &[0; 6]);
+        assert!(format_error(RawArray::read(&truncated[..])).contains("truncated: 6 of 8 bytes"));
+
+        let huge = npy("{'descr': '<f4', 'fortran_order': False, 'shape': (4294967296, 4294967296), }\n", &[]);
+        assert!(format_error(RawArray::read(&huge[..])).contains("too large"));
+
+        let no_shape = npy("{'descr': '<f4', 'fortran_order': False, }\n", &[]);
+        assert!(format_error(RawArray::read(&no_shape[..])).starts_with("malformed .npy header"));
+    }
+
+    #[test]
+    fn dtype_and_shape_are_checked() {
+        let array = Array::from_vec(vec![1.0f32, 2.0]);
+        assert!(matches!(Array::<i32>::from_raw(array.to_raw()), Err(crate::Error::Npy(NpyError::Dtype { .. }))));
+        assert!(matches!(array.clone().reshape([3]), Err(crate::Error::Npy(NpyError::Shape { .. }))));
+        assert!(matches!(Npz::new().get::<f32>("a"), Err(crate::Error::Npy(NpyError::Missing(_)))));
+    }
+}
@@ -0,0 +1,255 @@
+//! Just enough of the zip format for `.npz` files: stored and deflated
+//! members, zip64 sizes and offsets when reading, plain 32-bit headers when
+//! writing.
+
+use crate::{Error, Result};
+use super::NpyError;
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use std::io::{Read, Write};
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+const ZIP64_END_OF_DIRECTORY: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR: u32 = 0x0706_4b50;
+const ZIP64_EXTRA: u16 = 0x0001;
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+/// Deflate never expands data by more than about 1032 to 1.
+const MAX_DEFLATE_RATIO: u64 = 1032;
+
+fn corrupt(what: &str) -> Error {
+    NpyError::Format(format!("npz archive: {}", what)).into()
+}
+
+/// `at + len`, or a corrupt-archive error if an offset from the file
+/// overflows.
+fn offset_at(at: usize, len: usize) -> Result<usize> {
+    at.checked_add(len).ok_or_else(|| corrupt("truncated"))
+}
+
+fn bytes_at(data: &[u8], at: usize, len: usize) -> Option<&[u8]> {
+    data.get(at..at.checked_add(len)?)
+}
+
+fn u16_at(data: &[u8], at: usize) -> Result<u16> {
+    bytes_at(data, at, 2).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or_else(|| corrupt("truncated"))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Result<u32> {
+    bytes_at(data, at, 4).map(|b| u32::from_le_bytes(b.try_into().expect("4 bytes"))).ok_or_else(|| corrupt("truncated"))
+}
+
+fn u64_at(data: &[u8], at: usize) -> Result<u64> {
+    bytes_at(data, at, 8).map(|b| u64::from_le_bytes(b.try_into().expect("8 bytes"))).ok_or_else(|| corrupt("truncated"))
+}
+
+/// Every member of the archive in `data`, as (name, contents).
+pub fn read_members(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    // The end record sits at the end, followed by a comment of up to 64 KiB.
+    let end = (0..=data.len().saturating_sub(22))
+        .rev()
+        .take(0x1_0000 + 22)
+        .find(|&at| u32_at(data, at).is_ok_and(|sig| sig == END_OF_DIRECTORY))
+        .ok_or_else(|| corrupt("no end of central directory"))?;
+    let mut count = u16_at(data, end + 10)? as u64;
+    let mut offset = u32_at(data, end + 16)? as u64;
+    if offset == u32::MAX as u64 || count == u16::MAX as u64 {
+        let locator = end.checked_sub(20).ok_or_else(|| corrupt("truncated"))?;
+        if u32_at(data, locator)? != ZIP64_LOCATOR {
+            return Err(corrupt("missing zip64 locator"));
+        }
+        let record = usize::try_from(u64_at(data, locator + 8)?).map_err(|_| corrupt("truncated"))?;
+        if u32_at(data, record)? != ZIP64_END_OF_DIRECTORY {
+            return Err(corrupt("bad zip64 end of central directory"));
+        }
+        count = u64_at(data, offset_at(record, 32)?)?;
+        offset = u64_at(data, offset_at(record, 48)?)?;
+    }
+
+    let mut members = Vec::new();
+    let mut at = usize::try_from(offset).map_err(|_| corrupt("truncated"))?;
+    for _ in 0..count {
+        if u32_at(data, at)? != CENTRAL_HEADER {
+            return Err(corrupt("bad central directory entry"));
+        }
+        let method = u16_at(data, at + 10)?;
+        let crc = u32_at(data, at + 16)?;
+        let mut compressed = u32_at(data, at + 20)? as u64;
+        let mut size = u32_at(data, at + 24)? as u64;
+        let name_len = u16_at(data, at + 28)? as usize;
+        let extra_len = u16_at(data, at + 30)? as usize;
+        let comment_len = u16_at(data, at + 32)? as usize;
+        let mut local = u32_at(data, at + 42)? as u64;
+        let name_start = offset_at(at, 46)?;
+        let name = data.get(name_start..offset_at(name_start, name_len)?).ok_or_else(|| corrupt("truncated"))?;
+        let name = String::from_utf8_lossy(name).into_owned();
+
+        // Fields that overflowed 32 bits are in the zip64 extra, in this order.
+        let mut extra = offset_at(name_start, name_len)?;
+        let extra_end = offset_at(extra, extra_len)?;
+        while offset_at(extra, 4)? <= extra_end {
+            let (id, len) = (u16_at(data, extra)?, u16_at(data, extra + 2)? as usize);
+            if id == ZIP64_EXTRA {
+                let mut field = extra + 4;
+                for value in [&mut size, &mut compressed, &mut local] {
+                    if *value == u32::MAX as u64 {
+                        *value = u64_at(data, field)?;
+                        field += 8;
+                    }
+                }
+            }
+            extra = offset_at(extra, 4 + len)?;
+        }
+        at = offset_at(extra_end, comment_len)?;
+
+        let local = usize::try_from(local).map_err(|_| corrupt("truncated"))?;
+        if u32_at(data, local)? != LOCAL_HEADER {
+            return Err(corrupt("bad local header"));
+        }
+        let start = offset_at(local, 30)?;
+        let start = offset_at(start, u16_at(data, local + 26)? as usize + u16_at(data, local + 28)? as usize)?;
+        let end = usize::try_from(compressed).ok().and_then(|len| start.checked_add(len));
+        let raw = end.and_then(|end| data.get(start..end)).ok_or_else(|| corrupt("truncated member"))?;
+        let contents = match method {
+            STORED => raw.to_vec(),
+            DEFLATED => {
+                // The size comes from the file, so only trust it as far as
+                // deflate's best ratio allows.
+                let capacity = size.min(raw.len() as u64 * MAX_DEFLATE_RATIO);
+                let mut out = Vec::with_capacity(capacity as usize);
+                DeflateDecoder::new(raw).take(size.saturating_add(1)).read_to_end(&mut out)?;
+                out
+            }
+            other => return Err(corrupt(&format!("{} uses unsupported compression method {}", name, other))),
+        };
+        if contents.len() as u64 != size || crc32fast::hash(&contents) != crc {
+            return Err(corrupt(&format!("{} fails its size or CRC check", name)));
+        }
+        members.push((name, contents));
+    }
+    Ok(members)
+}
+
+/// An archive of `members`, deflated if `compress`.
+pub fn write_members(members: &[(String, Vec<u8>)], compress: bool) -> Result<Vec<u8>> {
+    let too_large = || NpyError::Format("npz archive: members of 4 GiB or more are not supported".into());
+    let mut out = Vec::new();
+    let mut directory = Vec::new();
+    for (name, contents) in members {
+        let (method, stored) = if compress {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(contents)?;
+            (DEFLATED, encoder.finish()?)
+        } else {
+            (STORED, contents.clone())
+        };
+        let size = u32::try_from(contents.len()).map_err(|_| too_large())?;
+        let compressed = u32::try_from(stored.len()).map_err(|_| too_large())?;
+        let offset = u32::try_from(out.len()).map_err(|_| too_large())?;
+        let crc = crc32fast::hash(contents);
+        // Version needed, flags, method, DOS time and date (1980-01-01), CRC
+        // and sizes: shared by both headers.
+        let mut common = Vec::with_capacity(26);
+        for field in [20u16, 0, method, 0, 0x21] {
+            common.extend_from_slice(&field.to_le_bytes());
+        }
+        for field in [crc, compressed, size] {
+            common.extend_from_slice(&field.to_le_bytes());
+        }
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+
+        out.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        out.extend_from_slice(&common);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(&stored);
+
+        directory.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+        directory.extend_from_slice(&20u16.to_le_bytes());
+        directory.extend_from_slice(&common);
+        // Comment length, disk, internal and external attributes.
+        directory.extend_from_slice(&[0; 10]);
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+    let offset = u32::try_from(out.len()).map_err(|_| too_large())?;
+    let count = u16::try_from(members.len()).map_err(|_| NpyError::Format("npz archive: too many arrays".into()))?;
+    out.extend_from_slice(&directory);
+    out.extend_from_slice(&END_OF_DIRECTORY.to_le_bytes());
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&count.to_le_bytes());
+    out.extend_from_slice(&count.to_le_bytes());
+    out.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    out.extend_from_slice(&offset.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members() -> Vec<(String, Vec<u8>)> {
+        vec![("a.npy".into(), b"first member".to_vec()), ("b.npy".into(), vec![7; 5000])]
+    }
+
+    fn message(result: Result<Vec<(String, Vec<u8>)>>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn stored_and_deflated_archives_round_trip() {
+        for compress in [false, true] {
+            let archive = write_members(&members(), compress).unwrap();
+            assert_eq!(read_members(&archive).unwrap(), members());
+        }
+        let deflated = write_members(&members(), true).unwrap();
+        assert!(deflated.len() < write_members(&members(), false).unwrap().len());
+    }
+
+    #[test]
+    fn bad_crcs_are_rejected() {
+        let mut archive = write_members(&members(), false).unwrap();
+        // The first member's contents follow its 30-byte header and name.
+        archive[30 + 5] ^= 1;
+        assert!(message(read_members(&archive)).contains("a.npy fails its size or CRC check"));
+    }
+
+    #[test]
+    fn truncated_archives_are_rejected() {
+        let archive = write_members(&members(), true).unwrap();
+        assert!(message(read_members(&archive[..archive.len() - 1])).contains("no end of central directory"));
+        assert!(message(read_members(&archive[40..])).starts_with("npz archive:"));
+        assert!(message(read_members(b"PK")).contains("no end of central directory"));
+    }
+
+    #[test]
+    fn offsets_that_overflow_are_rejected() {
+        let mut archive = write_members(&members(), false).unwrap();
+        let end = archive.len() - 22;
+        // Point the end record at a zip64 record near the top of memory.
+        let mut zip64 = ZIP64_LOCATOR.to_le_bytes().to_vec();
+        zip64.extend_from_slice(&[0; 4]);
+        zip64.extend_from_slice(&u64::MAX.to_le_bytes());
+        zip64.extend_from_slice(&[0; 4]);
+        archive.splice(end..end, zip64);
+        let end = archive.len() - 22;
+        archive[end + 16..end + 20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(message(read_members(&archive)).starts_with("npz archive:"));
+
+        // A central directory entry whose local header offset wraps around.
+        let mut archive = write_members(&members(), false).unwrap();
+        let directory = archive.len() - 22 - 2 * 46 - "a.npy".len() - "b.npy".len();
+        archive[directory + 42..directory + 46].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut extra = ZIP64_EXTRA.to_le_bytes().to_vec();
+        extra.extend_from_slice(&8u16.to_le_bytes());
+        extra.extend_from_slice(&(usize::MAX as u64 - 10).to_le_bytes());
+        let name_end = directory + 46 + "a.npy".len();
+        archive[directory + 30..directory + 32].copy_from_slice(&(extra.len() as u16).to_le_bytes());
+        archive.splice(name_end..name_end, extra);
+        assert!(message(read_members(&archive)).starts_with("npz archive:"));
+    }
+}
@@ -0,0 +1,324 @@
+//! Splitting a `Buffer<T>` into sub-buffers that respect the device's
+//! `CL_DEVICE_MEM_BASE_ADDR_ALIGN`.
+
+use crate::Result;
+use ocl::{Buffer, Device, MemFlags, OclPrm};
+use std::fmt;
+use std::mem;
+
+/// How an offset that is not on an alignment boundary is handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Reject misaligned offsets with `PartitionError::Misaligned`.
+    Exact,
+    /// Move the offset back to the previous boundary (the region grows).
+    Down,
+    /// Move the offset forward to the next boundary (the region shrinks).
+    Up,
+}
+
+#[derive(Debug)]
+pub enum PartitionError {
+    Misaligned { offset: usize, offset_bytes: usize, align_bytes: usize },
+    OutOfBounds { offset: usize, len: usize, buffer_len: usize },
+    EmptyRegion { offset: usize },
+    ZeroChunkLen,
+    /// An alignment in bytes.
+    InvalidAlignment(usize),
+    /// A device's `CL_DEVICE_MEM_BASE_ADDR_ALIGN`, in bits.
+    InvalidDeviceAlignment(usize),
+}
+
+impl fmt::Display for PartitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionError::Misaligned { offset, offset_bytes, align_bytes } => write!(
+                f,
+                "sub-buffer offset {} ({} bytes) is not a multiple of the device base address alignment ({} bytes); nearest aligned offsets are {} and {} bytes",
+                offset,
+                offset_bytes,
+                align_bytes,
+                offset_bytes / align_bytes * align_bytes,
+                offset_bytes.div_ceil(*align_bytes) * align_bytes
+            ),
+            PartitionError::OutOfBounds { offset, len, buffer_len } => match offset.checked_add(*len) {
+                Some(end) => write!(f, "sub-buffer region [{}, {}) exceeds the parent buffer length {}", offset, end, buffer_len),
+                None => write!(f, "sub-buffer region of {} elements at {} exceeds the parent buffer length {}", len, offset, buffer_len),
+            },
+            PartitionError::EmptyRegion { offset } => {
+                write!(f, "sub-buffer at offset {} would be empty after alignment", offset)
+            }
+            PartitionError::ZeroChunkLen => write!(f, "chunk length must be greater than zero"),
+            PartitionError::InvalidAlignment(align) => {
+                write!(f, "invalid base address alignment: {} bytes", align)
+            }
+            PartitionError::InvalidDeviceAlignment(bits) => {
+                write!(f, "invalid device base address alignment: {} bits, less than a byte", bits)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PartitionError {}
+
+/// Element offset and length of one sub-buffer within its parent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub index: usize,
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// Returns the device base address alignment in bytes.
+///
+/// OpenCL reports `CL_DEVICE_MEM_BASE_ADDR_ALIGN` in bits.
+pub fn base_align_bytes(device: &Device) -> Result<usize> {
+    align_bits_to_bytes(device.mem_base_addr_align()? as usize)
+}
+
+fn align_bits_to_bytes(bits: usize) -> Result<usize> {
+    if bits < 8 {
+        return Err(PartitionError::InvalidDeviceAlignment(bits).into());
+    }
+    Ok(bits / 8)
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// The offset arithmetic of a `Partitioner`, which needs only the parent's
+/// length.
+struct Plan {
+    buffer_len: usize,
+    elem_bytes: usize,
+    align_bytes: usize,
+    align_elems: usize,
+}
+
+impl Plan {
+    fn new(buffer_len: usize, elem_bytes: usize, align_bytes: usize) -> Result<Plan> {
+        if align_bytes == 0 {
+            return Err(PartitionError::InvalidAlignment(align_bytes).into());
+        }
+        // Smallest element count whose byte size is a multiple of the alignment.
+        let align_elems = align_bytes / gcd(align_bytes, elem_bytes);
+        Ok(Plan { buffer_len, elem_bytes, align_bytes, align_elems })
+    }
+
+    fn align_offset(&self, offset: usize, rounding: Rounding) -> Result<usize> {
+        let rem = offset % self.align_elems;
+        if rem == 0 {
+            return Ok(offset);
+        }
+        match rounding {
+            Rounding::Exact => Err(PartitionError::Misaligned {
+                offset,
+                offset_bytes: offset.saturating_mul(self.elem_bytes),
+                align_bytes: self.align_bytes,
+            }.into()),
+            Rounding::Down => Ok(offset - rem),
+            Rounding::Up => offset
+                .checked_add(self.align_elems - rem)
+                .ok_or_else(|| PartitionError::OutOfBounds { offset, len: 0, buffer_len: self.buffer_len }.into()),
+        }
+    }
+
+    fn region(&self, offset: usize, len: usize, rounding: Rounding) -> Result<Region> {
+        let buffer_len = self.buffer_len;
+        let end = offset
+            .checked_add(len)
+            .filter(|&end| end <= buffer_len)
+            .ok_or(PartitionError::OutOfBounds { offset, len, buffer_len })?;
+        let aligned = self.align_offset(offset, rounding)?;
+        if aligned >= end {
+            return Err(PartitionError::EmptyRegion { offset }.into());
+        }
+        Ok(Region { index: 0, offset: aligned, len: end - aligned })
+    }
+
+    fn regions(&self, chunk_len: usize) -> Result<Vec<Region>> {
+        if chunk_len == 0 {
+            return Err(PartitionError::ZeroChunkLen.into());
+        }
+        let stride = chunk_len.div_ceil(self.align_elems) * self.align_elems;
+        let buffer_len = self.buffer_len;
+        Ok((0..buffer_len)
+            .step_by(stride)
+            .enumerate()
+            .map(|(index, offset)| Region { index, offset, len: stride.min(buffer_len - offset) })
+            .collect())
+    }
+}
+
+/// Creates aligned sub-buffers of a parent buffer.
+pub struct Partitioner<'b, T: OclPrm> {
+    buffer: &'b Buffer<T>,
+    plan: Plan,
+    flags: Option<MemFlags>,
+}
+
+impl<'b, T: OclPrm> Partitioner<'b, T> {
+    /// Uses the base address alignment reported by `device`.
+    pub fn new(buffer: &'b Buffer<T>, device: &Device) -> Result<Self> {
+        Self::with_alignment(buffer, base_align_bytes(device)?)
+    }
+
+    /// Uses an explicit alignment in bytes, e.g. one queried earlier.
+    pub fn with_alignment(buffer: &'b Buffer<T>, align_bytes: usize) -> Result<Self> {
+        let plan = Plan::new(buffer.len(), mem::size_of::<T>(), align_bytes)?;
+        Ok(Partitioner { buffer, plan, flags: None })
+    }
+
+    /// Flags passed to every sub-buffer (host pointer flags are inherited
+    /// from the parent and must not be given here).
+    pub fn flags(mut self, flags: MemFlags) -> Self {
+        self.flags = Some(flags);
+        self
+    }
+
+    pub fn align_bytes(&self) -> usize {
+        self.plan.align_bytes
+    }
+
+    /// Alignment expressed in elements of `T`.
+    pub fn align_elems(&self) -> usize {
+        self.plan.align_elems
+    }
+
+    /// Checks or rounds an element offset to the alignment.
+    pub fn align_offset(&self, offset: usize, rounding: Rounding) -> Result<usize> {
+        self.plan.align_offset(offset, rounding)
+    }
+
+    /// Resolves the region a call to `sub_buffer` would create, without
+    /// creating it. The end of the requested region is kept fixed.
+    pub fn region(&self, offset: usize, len: usize, rounding: Rounding) -> Result<Region> {
+        self.plan.region(offset, len, rounding)
+    }
+
+    /// Creates one sub-buffer covering `[offset, offset + len)` elements.
+    pub fn sub_buffer(&self, offset: usize, len: usize, rounding: Rounding) -> Result<Buffer<T>> {
+        let region = self.region(offset, len, rounding)?;
+        self.create(&region)
+    }
+
+    /// Plans consecutive regions of roughly `chunk_len` elements. Every
+    /// chunk but the last is rounded up to a multiple of the alignment.
+    pub fn regions(&self, chunk_len: usize) -> Result<Vec<Region>> {
+        self.plan.regions(chunk_len)
+    }
+
+    /// Splits the whole buffer into aligned sub-buffers at once.
+    pub fn split(&self, chunk_len: usize) -> Result<Vec<Buffer<T>>> {
+        self.regions(chunk_len)?.iter().map(|r| self.create(r)).collect()
+    }
+
+    /// Iterates chunk by chunk, creating each sub-buffer only when reached.
+    pub fn chunks(&self, chunk_len: usize) -> Result<Chunks<'_, 'b, T>> {
+        Ok(Chunks { partitioner: self, regions: self.regions(chunk_len)?.into_iter() })
+    }
+
+    fn create(&self, region: &Region) -> Result<Buffer<T>> {
+        Ok(self.buffer.create_sub_buffer(self.flags, region.offset, region.len)?)
+    }
+}
+
+/// A sub-buffer yielded by `Chunks` along with its place in the parent.
+pub struct Chunk<T: OclPrm> {
+    pub region: Region,
+    pub buffer: Buffer<T>,
+}
+
+pub struct Chunks<'p, 'b, T: OclPrm> {
+    partitioner: &'p Partitioner<'b, T>,
+    regions: std::vec::IntoIter<Region>,
+}
+
+impl<T: OclPrm> Iterator for Chunks<'_, '_, T> {
+    type Item = Result<Chunk<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let region = self.regions.next()?;
+        Some(self.partitioner.create(&region).map(|buffer| Chunk { region, buffer }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.regions.size_hint()
+    }
+}
+
+impl<T: OclPrm> ExactSizeIterator for Chunks<'_, '_, T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    /// A 100-element `f32` buffer on a device aligning to 64 bytes, i.e.
+    /// 16 elements.
+    fn plan() -> Plan {
+        Plan::new(100, 4, 64).unwrap()
+    }
+
+    fn partition_error(result: Result<impl fmt::Debug>) -> PartitionError {
+        match result.unwrap_err() {
+            Error::Partition(err) => err,
+            err => panic!("expected a partition error, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn misaligned_offsets_follow_the_rounding() {
+        let plan = plan();
+        assert_eq!(plan.align_elems, 16);
+        for rounding in [Rounding::Exact, Rounding::Down, Rounding::Up] {
+            assert_eq!(plan.region(32, 20, rounding).unwrap(), Region { index: 0, offset: 32, len: 20 });
+        }
+        match partition_error(plan.region(20, 30, Rounding::Exact)) {
+            PartitionError::Misaligned { offset: 20, offset_bytes: 80, align_bytes: 64 } => {}
+            err => panic!("{:?}", err),
+        }
+        // The end stays at 50 either way.
+        assert_eq!(plan.region(20, 30, Rounding::Down).unwrap(), Region { index: 0, offset: 16, len: 34 });
+        assert_eq!(plan.region(20, 30, Rounding::Up).unwrap(), Region { index: 0, offset: 32, len: 18 });
+        assert!(matches!(partition_error(plan.region(20, 10, Rounding::Up)), PartitionError::EmptyRegion { offset: 20 }));
+    }
+
+    #[test]
+    fn regions_past_the_end_are_out_of_bounds() {
+        let plan = plan();
+        assert_eq!(plan.region(96, 4, Rounding::Down).unwrap(), Region { index: 0, offset: 96, len: 4 });
+        assert!(matches!(partition_error(plan.region(96, 5, Rounding::Exact)), PartitionError::OutOfBounds { .. }));
+        let err = partition_error(plan.region(usize::MAX - 1, 2, Rounding::Exact));
+        assert!(matches!(err, PartitionError::OutOfBounds { .. }));
+        assert!(err.to_string().contains("exceeds the parent buffer length 100"), "{}", err);
+        assert!(matches!(partition_error(plan.align_offset(usize::MAX, Rounding::Up)), PartitionError::OutOfBounds { .. }));
+    }
+
+    #[test]
+    fn the_last_chunk_holds_the_remainder() {
+        let plan = plan();
+        let regions = plan.regions(20).unwrap();
+        let expected = [(0, 32), (32, 32), (64, 32), (96, 4)];
+        assert_eq!(regions.len(), expected.len());
+        for (index, (region, (offset, len))) in regions.iter().zip(expected).enumerate() {
+            assert_eq!(*region, Region { index, offset, len });
+        }
+        assert_eq!(plan.regions(100).unwrap(), [Region { index: 0, offset: 0, len: 100 }]);
+        assert!(matches!(partition_error(plan.regions(0)), PartitionError::ZeroChunkLen));
+    }
+
+    #[test]
+    fn alignments_need_whole_elements_and_bytes() {
+        // 12-byte elements reach a 64-byte boundary every 16 elements.
+        assert_eq!(Plan::new(100, 12, 64).unwrap().align_elems, 16);
+        assert_eq!(Plan::new(100, 8, 4).unwrap().align_elems, 1);
+        assert!(matches!(partition_error(Plan::new(100, 4, 0).map(|_| ())), PartitionError::InvalidAlignment(0)));
+
+        assert_eq!(align_bits_to_bytes(1024).unwrap(), 128);
+        let err = partition_error(align_bits_to_bytes(4));
+        assert!(matches!(err, PartitionError::InvalidDeviceAlignment(4)));
+        assert_eq!(err.to_string(), "invalid device base address alignment: 4 bits, less than a byte");
+    }
+}
@@ -0,0 +1,312 @@
+//! Kernel and argument introspection for built programs.
+
+use crate::logging::span;
+use crate::{Error, Result};
+use log::Level;
+use ocl::core::{
+    self, KernelArgAccessQualifier, KernelArgAddressQualifier, KernelArgInfo, KernelArgInfoResult,
+    KernelArgTypeQualifier, KernelInfo as KernelInfoKind, KernelInfoResult, KernelWorkGroupInfo,
+    KernelWorkGroupInfoResult,
+};
+use ocl::enums::{ProgramInfo as ProgramInfoKind, ProgramInfoResult};
+use ocl::{Context, Device, Program};
+use std::fmt::{self, Write};
+
+/// Build option required for argument names and qualifiers to be available.
+pub const KERNEL_ARG_INFO: &str = "-cl-kernel-arg-info";
+
+#[derive(Debug, Clone)]
+pub struct ArgInfo {
+    pub index: u32,
+    pub name: Option<String>,
+    pub type_name: Option<String>,
+    pub address: Option<KernelArgAddressQualifier>,
+    pub access: Option<KernelArgAccessQualifier>,
+    pub type_qualifier: Option<KernelArgTypeQualifier>,
+}
+
+#[derive(Debug, Clone)]
+pub struct KernelInfo {
+    pub name: String,
+    pub attributes: String,
+    pub args: Vec<ArgInfo>,
+    /// `reqd_work_group_size`, if the kernel declares one.
+    pub reqd_work_group_size: Option<[usize; 3]>,
+    pub max_work_group_size: Option<usize>,
+    pub preferred_work_group_multiple: Option<usize>,
+    pub local_mem_size: Option<u64>,
+    pub private_mem_size: Option<u64>,
+}
+
+impl KernelInfo {
+    pub fn arg(&self, name: &str) -> Option<&ArgInfo> {
+        self.args.iter().find(|a| a.name.as_deref() == Some(name))
+    }
+
+    /// The kernel's signature, as far as argument info is available.
+    pub fn signature(&self) -> String {
+        let args: Vec<String> = self
+            .args
+            .iter()
+            .map(|a| {
+                let mut parts = Vec::new();
+                if let Some(q) = a.address.filter(|&q| q != KernelArgAddressQualifier::Private) {
+                    parts.push(address_str(q).to_string());
+                }
+                if let Some(q) = a.access.filter(|&q| q != KernelArgAccessQualifier::None) {
+                    parts.push(access_str(q).to_string());
+                }
+                if let Some(q) = a.type_qualifier.filter(|q| !q.is_empty()) {
+                    parts.push(type_qualifier_str(q));
+                }
+                parts.push(a.type_name.clone().unwrap_or_else(|| "?".into()));
+                parts.push(a.name.clone().unwrap_or_else(|| format!("arg{}", a.index)));
+                parts.join(" ")
+            })
+            .collect();
+        format!("__kernel void {}({})", self.name, args.join(", "))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProgramInfo {
+    pub kernels: Vec<KernelInfo>,
+}
+
+impl ProgramInfo {
+    /// Builds `src` for `device` with `-cl-kernel-arg-info` and inspects it.
+    pub fn build(context: &Context, device: Device, src: &str) -> Result<(Program, ProgramInfo)> {
+        let span = span!(Level::Debug, "build {} bytes for {:?} with argument info", src.len(), device);
+        let program = span.finish(Program::builder().src(src).devices(device).cmplr_opt(KERNEL_ARG_INFO).build(context))?;
+        let info = ProgramInfo::from_program(&program, device)?;
+        Ok((program, info))
+    }
+
+    /// Inspects an already built program. Argument names and qualifiers are
+    /// `None` unless it was built with `KERNEL_ARG_INFO`.
+    pub fn from_program(program: &Program, device: Device) -> Result<ProgramInfo> {
+        let names = match program.info(ProgramInfoKind::KernelNames)? {
+            ProgramInfoResult::KernelNames(names) => names,
+            other => return Err(Error::ocl(format!("ProgramInfo: unexpected kernel names result: {:?}", other))),
+        };
+        let version = device.version()?;
+        let kernels = names
+            .split(';')
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .map(|name| kernel_info(program, device, name, version))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ProgramInfo { kernels })
+    }
+
+    pub fn kernel(&self, name: &str) -> Option<&KernelInfo> {
+        self.kernels.iter().find(|k| k.name == name)
+    }
+
+    pub fn kernel_names(&self) -> impl Iterator<Item = &str> {
+        self.kernels.iter().map(|k| k.name.as_str())
+    }
+
+    /// One row per argument, grouped by kernel.
+    pub fn to_table(&self) -> String {
+        let mut out = String::new();
+        for k in &self.kernels {
+            let _ = writeln!(out, "{}", k.signature());
+            if let Some(size) = k.reqd_work_group_size {
+                let _ = writeln!(out, "  reqd_work_group_size: ({}, {}, {})", size[0], size[1], size[2]);
+            }
+            if !k.attributes.is_empty() {
+                let _ = writeln!(out, "  attributes: {}", k.attributes);
+            }
+            if let Some(size) = k.max_work_group_size {
+                let _ = writeln!(out, "  max work-group size: {}", size);
+            }
+            if let Some(size) = k.local_mem_size {
+                let _ = writeln!(out, "  local memory: {} bytes", size);
+            }
+            let _ = writeln!(out, "  {:>3}  {:<16} {:<16} {:<9} {:<11} qualifiers", "#", "name", "type", "address", "access");
+            for a in &k.args {
+                let _ = writeln!(
+                    out,
+                    "  {:>3}  {:<16} {:<16} {:<9} {:<11} {}",
+                    a.index,
+                    a.name.as_deref().unwrap_or("-"),
+                    a.type_name.as_deref().unwrap_or("-"),
+                    a.address.map_or("-", address_str),
+                    a.access.map_or("-", access_str),
+                    a.type_qualifier.map_or_else(|| "-".to_string(), type_qualifier_str)
+                );
+            }
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{\"kernels\":[");
+        for (i, k) in self.kernels.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(
+                out,
+                "{{\"name\":{},\"attributes\":{},\"reqd_work_group_size\":{},\"max_work_group_size\":{},\"preferred_work_group_multiple\":{},\"local_mem_size\":{},\"private_mem_size\":{},\"args\":[",
+                json_str(&k.name),
+                json_str(&k.attributes),
+                k.reqd_work_group_size.map_or("null".into(), |s| format!("[{},{},{}]", s[0], s[1], s[2])),
+                json_opt(k.max_work_group_size),
+                json_opt(k.preferred_work_group_multiple),
+                json_opt(k.local_mem_size),
+                json_opt(k.private_mem_size)
+            );
+            for (j, a) in k.args.iter().enumerate() {
+                if j > 0 {
+                    out.push(',');
+                }
+                let _ = write!(
+                    out,
+                    "{{\"index\":{},\"name\":{},\"type_name\":{},\"address\":{},\"access\":{},\"type_qualifier\":{}}}",
+                    a.index,
+                    a.name.as_deref().map_or("null".into(), json_str),
+                    a.type_name.as_deref().map_or("null".into(), json_str),
+                    a.address.map_or("null".into(), |q| json_str(address_str(q))),
+                    a.access.map_or("null".into(), |q| json_str(access_str(q))),
+                    a.type_qualifier.map_or("null".into(), |q| json_str(&type_qualifier_str(q)))
+                );
+            }
+            out.push_str("]}");
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+impl fmt::Display for ProgramInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_table())
+    }
+}
+
+fn kernel_info(program: &Program, device: Device, name: &str, version: core::OpenclVersion) -> Result<KernelInfo> {
+    let kernel = core::create_kernel(program, name)?;
+    let num_args = match core::get_kernel_info(&kernel, KernelInfoKind::NumArgs)? {
+        KernelInfoResult::NumArgs(n) => n,
+        _ => 0,
+    };
+    let attributes = match core::get_kernel_info(&kernel, KernelInfoKind::Attributes) {
+        Ok(KernelInfoResult::Attributes(s)) => s,
+        _ => String::new(),
+    };
+    let versions = [version];
+    let args = (0..num_args)
+        .map(|index| {
+            let info = |kind| core::get_kernel_arg_info(&kernel, index, kind, Some(&versions)).ok();
+            ArgInfo {
+                index,
+                name: match info(KernelArgInfo::Name) {
+                    Some(KernelArgInfoResult::Name(s)) => Some(s),
+                    _ => None,
+                },
+                type_name: match info(KernelArgInfo::TypeName) {
+                    Some(KernelArgInfoResult::TypeName(s)) => Some(s),
+                    _ => None,
+                },
+                address: match info(KernelArgInfo::AddressQualifier) {
+                    Some(KernelArgInfoResult::AddressQualifier(q)) => Some(q),
+                    _ => None,
+                },
+                access: match info(KernelArgInfo::AccessQualifier) {
+                    Some(KernelArgInfoResult::AccessQualifier(q)) => Some(q),
+                    _ => None,
+                },
+                type_qualifier: match info(KernelArgInfo::TypeQualifier) {
+                    Some(KernelArgInfoResult::TypeQualifier(q)) => Some(q),
+                    _ => None,
+                },
+            }
+        })
+        .collect();
+    let wg = |kind| core::get_kernel_work_group_info(&kernel, device, kind).ok();
+    let reqd_work_group_size = match wg(KernelWorkGroupInfo::CompileWorkGroupSize) {
+        Some(KernelWorkGroupInfoResult::CompileWorkGroupSize(s)) if s != [0, 0, 0] => Some(s),
+        _ => None,
+    };
+    Ok(KernelInfo {
+        name: name.to_string(),
+        attributes,
+        args,
+        reqd_work_group_size,
+        max_work_group_size: match wg(KernelWorkGroupInfo::WorkGroupSize) {
+            Some(KernelWorkGroupInfoResult::WorkGroupSize(s)) => Some(s),
+            _ => None,
+        },
+        preferred_work_group_multiple: match wg(KernelWorkGroupInfo::PreferredWorkGroupSizeMultiple) {
+            Some(KernelWorkGroupInfoResult::PreferredWorkGroupSizeMultiple(s)) => Some(s),
+            _ => None,
+        },
+        local_mem_size: match wg(KernelWorkGroupInfo::LocalMemSize) {
+            Some(KernelWorkGroupInfoResult::LocalMemSize(s)) => Some(s),
+            _ => None,
+        },
+        private_mem_size: match wg(KernelWorkGroupInfo::PrivateMemSize) {
+            Some(KernelWorkGroupInfoResult::PrivateMemSize(s)) => Some(s),
+            _ => None,
+        },
+    })
+}
+
+fn address_str(q: KernelArgAddressQualifier) -> &'static str {
+    match q {
+        KernelArgAddressQualifier::Global => "global",
+        KernelArgAddressQualifier::Local => "local",
+        KernelArgAddressQualifier::Constant => "constant",
+        KernelArgAddressQualifier::Private => "private",
+    }
+}
+
+fn access_str(q: KernelArgAccessQualifier) -> &'static str {
+    match q {
+        KernelArgAccessQualifier::ReadOnly => "read_only",
+        KernelArgAccessQualifier::WriteOnly => "write_only",
+        KernelArgAccessQualifier::ReadWrite => "read_write",
+        KernelArgAccessQualifier::None => "none",
+    }
+}
+
+fn type_qualifier_str(q: KernelArgTypeQualifier) -> String {
+    let mut parts = Vec::new();
+    if q.contains(KernelArgTypeQualifier::CONST) {
+        parts.push("const");
+    }
+    if q.contains(KernelArgTypeQualifier::RESTRICT) {
+        parts.push("restrict");
+    }
+    if q.contains(KernelArgTypeQualifier::VOLATILE) {
+        parts.push("volatile");
+    }
+    if parts.is_empty() { "none".to_string() } else { parts.join(" ") }
+}
+
+fn json_opt<T: fmt::Display>(v: Option<T>) -> String {
+    v.map_or("null".into(), |v| v.to_string())
+}
+
+/// Quotes and escapes `s` as a JSON string.
+pub fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,243 @@
+//! Plain-Rust reference implementations of the example kernels.
+//!
+//! Each function computes what the device should produce for the
+//! corresponding kernel, using rayon instead of OpenCL, so results can be
+//! checked on machines without a driver. Use `diff` / `diff_exact` to
+//! compare device output against them.
+
+use crate::histogram::{Counts, Histogram, Sample};
+use crate::sparse::Csr;
+use ocl::prm::Float2;
+use rayon::prelude::*;
+use std::fmt;
+
+/// How device output differs from the reference.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch<T> {
+    Len { actual: usize, expected: usize },
+    /// `count` of `len` elements differ; `index` is the first of them.
+    Values { index: usize, actual: T, expected: T, count: usize, len: usize },
+}
+
+impl<T: fmt::Debug> fmt::Display for Mismatch<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Len { actual, expected } => {
+                write!(f, "device produced {} elements, reference {}", actual, expected)
+            }
+            Mismatch::Values { index, actual, expected, count, len } => write!(
+                f,
+                "{} of {} elements differ; first at index {}: device {:?}, reference {:?}",
+                count, len, index, actual, expected
+            ),
+        }
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for Mismatch<T> {}
+
+/// Compares floats with a tolerance relative to the larger magnitude (and
+/// absolute near zero).
+pub fn diff(actual: &[f32], expected: &[f32], tolerance: f32) -> Result<(), Mismatch<f32>> {
+    let close = |a: f32, e: f32| a == e || (a - e).abs() <= tolerance * a.abs().max(e.abs()).max(1.0);
+    compare(actual, expected, close)
+}
+
+/// Compares element for element.
+pub fn diff_exact<T: PartialEq + Copy + Send + Sync>(actual: &[T], expected: &[T]) -> Result<(), Mismatch<T>> {
+    compare(actual, expected, |a, e| a == e)
+}
+
+fn compare<T: Copy + Send + Sync>(
+    actual: &[T],
+    expected: &[T],
+    eq: impl Fn(T, T) -> bool + Sync,
+) -> Result<(), Mismatch<T>> {
+    if actual.len() != expected.len() {
+        return Err(Mismatch::Len { actual: actual.len(), expected: expected.len() });
+    }
+    let count = actual.par_iter().zip(expected).filter(|&(&a, &e)| !eq(a, e)).count();
+    if count == 0 {
+        return Ok(());
+    }
+    let index = actual.iter().zip(expected).position(|(&a, &e)| !eq(a, e)).expect("count > 0");
+    Err(Mismatch::Values { index, actual: actual[index], expected: expected[index], count, len: actual.len() })
+}
+
+/// `saxpy_kernel`: `alpha * a + b`.
+pub fn saxpy(alpha: f32, a: &[f32], b: &[f32]) -> Vec<f32> {
+    a.par_iter().zip(b).map(|(&a, &b)| alpha * a + b).collect()
+}
+
+/// `matvec_mult`: row-major `mat` (`vec.len()` columns) times `vec`.
+pub fn matvec(mat: &[f32], vec: &[f32]) -> Vec<f32> {
+    mat.par_chunks(vec.len()).map(|row| row.iter().zip(vec).map(|(&m, &v)| m * v).sum()).collect()
+}
+
+/// One partial sum per work-group, as written by `reduction_scalar`
+/// (`group_elems` = local size) and `reduction_vector` (local size × 4).
+pub fn partial_sums(data: &[f32], group_elems: usize) -> Vec<f32> {
+    data.par_chunks(group_elems).map(|chunk| chunk.iter().map(|&x| x as f64).sum::<f64>() as f32).collect()
+}
+
+/// Full sum, as produced by `reduction_complete`. Accumulates in `f64`.
+pub fn sum(data: &[f32]) -> f32 {
+    data.par_iter().map(|&x| x as f64).sum::<f64>() as f32
+}
+
+/// Result of the complete bitonic sort (`bsort_init` through
+/// `bsort_merge_last`). `descending` corresponds to a direction of -1.
+pub fn bitonic_sort(data: &mut [f32], descending: bool) {
+    if descending {
+        data.par_sort_by(|a, b| b.total_cmp(a));
+    } else {
+        data.par_sort_by(|a, b| a.total_cmp(b));
+    }
+}
+
+/// Result of `bsort_init` alone: each block of `block_len` elements (eight
+/// per work-item) is sorted, ascending in even work-groups and descending in
+/// odd ones, ready for the merge stages.
+pub fn bsort_init(data: &mut [f32], block_len: usize) {
+    data.par_chunks_mut(block_len).enumerate().for_each(|(group, block)| {
+        if group % 2 == 0 {
+            block.sort_by(|a, b| a.total_cmp(b));
+        } else {
+            block.sort_by(|a, b| b.total_cmp(a));
+        }
+    });
+}
+
+/// `bsort8`: sorts eight floats; `dir` is 0 (ascending) or -1 (descending).
+pub fn bsort8(data: &mut [f32; 8], dir: i32) {
+    bitonic_sort(data, dir != 0);
+}
+
+/// `radix_sort8`.
+pub fn radix_sort8(data: &mut [u16; 8]) {
+    data.sort_unstable();
+}
+
+/// `string_search`: occurrences of each four-character word of `pattern`.
+pub fn string_search(text: &[u8], pattern: &[u8; 16]) -> [i32; 4] {
+    let mut counts = [0i32; 4];
+    for (word, count) in pattern.chunks(4).zip(counts.iter_mut()) {
+        *count = text.par_windows(4).filter(|w| *w == word).count() as i32;
+    }
+    counts
+}
+
+/// `interp`: nearest-neighbour upscaling of an RGBA image by `scale`.
+pub fn interp<T: Copy + Send + Sync>(src: &[T], width: usize, height: usize, scale: usize) -> Vec<T> {
+    assert_eq!(src.len(), width * height * 4, "interp: source is not {}x{} RGBA", width, height);
+    let dst_width = width * scale;
+    (0..height * scale)
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            let src_row = &src[(y / scale) * width * 4..(y / scale + 1) * width * 4];
+            (0..dst_width).flat_map(move |x| src_row[(x / scale) * 4..(x / scale) * 4 + 4].iter().copied())
+        })
+        .collect()
+}
+
+/// `histogram`: the counts per channel and bin, using the kernel's own
+/// binning rule.
+pub fn histogram<T: Sample + Sync>(hist: &Histogram<T>, data: &[T]) -> Counts {
+    let (bins, channels) = (hist.bin_count(), hist.channel_count());
+    let counts = data
+        .par_chunks(channels)
+        .fold(
+            || vec![0u32; bins * channels],
+            |mut counts, pixel| {
+                for (channel, &x) in pixel.iter().enumerate() {
+                    if let Some(bin) = hist.bin_of(x) {
+                        counts[channel * bins + bin] += 1;
+                    }
+                }
+                counts
+            },
+        )
+        .reduce(
+            || vec![0u32; bins * channels],
+            |mut a, b| {
+                a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+                a
+            },
+        );
+    Counts { bins, channels, counts }
+}
+
+/// Discrete Fourier transform of each `len`-point sequence in `data`,
+/// evaluated directly in `f64`. The inverse divides by `len`, as `fft` does.
+pub fn dft(data: &[Float2], len: usize, inverse: bool) -> Vec<Float2> {
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let scale = if inverse { 1.0 / len as f64 } else { 1.0 };
+    data.par_chunks(len)
+        .flat_map_iter(|x| {
+            (0..len).map(move |k| {
+                let (mut re, mut im) = (0.0, 0.0);
+                for (j, v) in x.iter().enumerate() {
+                    // Reduce the angle exactly before scaling it.
+                    let angle = sign * std::f64::consts::TAU * ((j * k) % len) as f64 / len as f64;
+                    let (sin, cos) = angle.sin_cos();
+                    re += v[0] as f64 * cos - v[1] as f64 * sin;
+                    im += v[0] as f64 * sin + v[1] as f64 * cos;
+                }
+                Float2::new((re * scale) as f32, (im * scale) as f32)
+            })
+        })
+        .collect()
+}
+
+/// `dft` of each row-major `height` × `width` array in `data`: the rows,
+/// then the columns.
+pub fn dft_2d(data: &[Float2], height: usize, width: usize, inverse: bool) -> Vec<Float2> {
+    let rows = dft(data, width, inverse);
+    let transpose = |data: &[Float2], height: usize, width: usize| -> Vec<Float2> {
+        data.chunks(height * width)
+            .flat_map(|a| (0..width).flat_map(move |c| (0..height).map(move |r| a[r * width + c])))
+            .collect()
+    };
+    let columns = dft(&transpose(&rows, height, width), height, inverse);
+    transpose(&columns, width, height)
+}
+
+/// `sqrt(Σ (actual - expected)²) / sqrt(Σ expected²)`, or the absolute error
+/// if `expected` is all zero. Complex values count as two floats each.
+pub fn relative_error(actual: &[f32], expected: &[f32]) -> f64 {
+    let error: f64 = actual.iter().zip(expected).map(|(&a, &e)| (a as f64 - e as f64).powi(2)).sum();
+    let norm: f64 = expected.iter().map(|&e| (e as f64).powi(2)).sum();
+    if norm == 0.0 { error.sqrt() } else { (error / norm).sqrt() }
+}
+
+/// `csr_scalar`, `csr_vector` and `ell`: `A x`, accumulated in `f64`.
+pub fn spmv(a: &Csr, x: &[f32]) -> Vec<f32> {
+    (0..a.rows)
+        .into_par_iter()
+        .map(|row| {
+            let entries = a.row_ptr[row] as usize..a.row_ptr[row + 1] as usize;
+            entries.map(|j| a.values[j] as f64 * x[a.col_idx[j] as usize] as f64).sum::<f64>() as f32
+        })
+        .collect()
+}
+
+/// `polar_rect`: `(r cos θ, r sin θ)` for each pair.
+pub fn polar_rect(r: &[f32], angles: &[f32]) -> (Vec<f32>, Vec<f32>) {
+    r.par_iter().zip(angles).map(|(&r, &a)| (r * a.cos(), r * a.sin())).unzip()
+}
+
+/// `mod_round`: `[fmod, remainder]` of the two inputs and
+/// `[rint, round, ceil, floor, trunc]` of each rounding input.
+pub fn mod_round(mod_input: [f32; 2], round_input: [f32; 4]) -> ([f32; 2], [[f32; 4]; 5]) {
+    let [x, y] = mod_input;
+    let modulo = [x % y, remainder(x, y)];
+    let ops: [fn(f32) -> f32; 5] = [f32::round_ties_even, f32::round, f32::ceil, f32::floor, f32::trunc];
+    (modulo, ops.map(|op| round_input.map(op)))
+}
+
+/// IEEE 754 remainder: `x - n * y` with `n` rounded to the nearest integer,
+/// ties to even.
+pub fn remainder(x: f32, y: f32) -> f32 {
+    let n = (x as f64 / y as f64).round_ties_even();
+    (x as f64 - n * y as f64) as f32
+}
@@ -0,0 +1,476 @@
+//! Sparse matrices in CSR and ELLPACK form, and `y = A x` on the device.
+//!
+//! `Csr` is the general format: row `r` holds `values[row_ptr[r]..row_ptr[r
+//! + 1]]` at columns `col_idx[..]`. It has two kernels: `Variant::Scalar`
+//! gives each row one work-item, which suits short rows, and
+//! `Variant::Vector` gives each row a team of work-items that split the row
+//! and add their partial sums in local memory, which suits long ones.
+//! `Variant::choose` picks by the mean row length.
+//!
+//! `Ell` pads every row to the longest one and stores the entries column
+//! by column, so neighbouring work-items read neighbouring memory. It is
+//! fastest for matrices with rows of similar length and wasteful otherwise;
+//! `Ell::fill` tells how much of it is padding.
+//!
+//! Both are built from (row, column, value) triplets, where duplicates are
+//! added up, or from Matrix Market files, and uploaded once with `upload`
+//! for repeated products.
+
+mod mtx;
+
+use crate::Result;
+use crate::interpreter;
+use crate::logging::span;
+use log::Level;
+use ocl::{Buffer, Kernel, Program, Queue};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+const KERNEL_SRC: &str = r#"
+__kernel void csr_scalar(__global const uint *row_ptr, __global const uint *col_idx,
+                         __global const float *values, __global const float *x,
+                         __global float *y, uint rows) {
+   uint row = get_global_id(0);
+   if (row >= rows) return;
+   float sum = 0.0f;
+   for (uint j = row_ptr[row]; j < row_ptr[row + 1]; j++)
+      sum += values[j] * x[col_idx[j]];
+   y[row] = sum;
+}
+
+/* `lanes` consecutive work-items (a power of two) share a row. */
+__kernel void csr_vector(__global const uint *row_ptr, __global const uint *col_idx,
+                         __global const float *values, __global const float *x,
+                         __global float *y, uint rows, uint lanes, __local float *partial) {
+   uint lid = get_local_id(0);
+   uint lane = lid % lanes;
+   uint row = get_global_id(0) / lanes;
+   float sum = 0.0f;
+   if (row < rows)
+      for (uint j = row_ptr[row] + lane; j < row_ptr[row + 1]; j += lanes)
+         sum += values[j] * x[col_idx[j]];
+   partial[lid] = sum;
+   barrier(CLK_LOCAL_MEM_FENCE);
+   for (uint s = lanes / 2; s > 0; s /= 2) {
+      if (lane < s) partial[lid] += partial[lid + s];
+      barrier(CLK_LOCAL_MEM_FENCE);
+   }
+   if (lane == 0 && row < rows) y[row] = partial[lid];
+}
+
+/* Entry k of row r is at k * rows + r; padding has value zero. */
+__kernel void ell(__global const uint *col_idx, __global const float *values,
+                  __global const float *x, __global float *y, uint rows, uint width) {
+   uint row = get_global_id(0);
+   if (row >= rows) return;
+   float sum = 0.0f;
+   for (uint k = 0; k < width; k++) {
+      float v = values[k * rows + row];
+      if (v != 0.0f) sum += v * x[col_idx[k * rows + row]];
+   }
+   y[row] = sum;
+}
+"#;
+
+/// A (row, column, value) entry, 0-based.
+pub type Triplet = (usize, usize, f32);
+
+/// Mean entries per row from which `Variant::choose` prefers `Vector`.
+const VECTOR_THRESHOLD: f64 = 16.0;
+/// Work-group size of the vector kernel.
+const VECTOR_GROUP: usize = 128;
+/// Most work-items per row in the vector kernel.
+const MAX_LANES: usize = 32;
+
+#[derive(Debug)]
+pub enum SparseError {
+    Parse { line: usize, message: String },
+    /// A Matrix Market feature outside the supported subset.
+    Unsupported(String),
+    Index { row: usize, col: usize, rows: usize, cols: usize },
+    /// A vector of the wrong length; `what` names it.
+    Len { what: &'static str, expected: usize, found: usize },
+}
+
+impl fmt::Display for SparseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SparseError::Parse { line, message } => write!(f, "Matrix Market line {}: {}", line, message),
+            SparseError::Unsupported(what) => write!(f, "Matrix Market files with {} are not supported", what),
+            SparseError::Index { row, col, rows, cols } => {
+                write!(f, "entry ({}, {}) is outside the {}x{} matrix", row, col, rows, cols)
+            }
+            SparseError::Len { what, expected, found } => {
+                write!(f, "{} has {} elements, the matrix needs {}", what, found, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SparseError {}
+
+/// The CSR kernel to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// One work-item per row.
+    Scalar,
+    /// `lanes` work-items per row, a power of two up to 32.
+    Vector { lanes: usize },
+}
+
+impl Variant {
+    /// `Scalar` for rows averaging fewer than 16 entries, otherwise
+    /// `Vector` with about one entry per lane.
+    pub fn choose(mean_row_len: f64) -> Variant {
+        if mean_row_len < VECTOR_THRESHOLD {
+            Variant::Scalar
+        } else {
+            Variant::Vector { lanes: (mean_row_len.ceil() as usize).next_power_of_two().min(MAX_LANES) }
+        }
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Variant::Scalar => write!(f, "scalar"),
+            Variant::Vector { lanes } => write!(f, "vector ({} lanes)", lanes),
+        }
+    }
+}
+
+/// A compressed sparse row matrix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Csr {
+    pub rows: usize,
+    pub cols: usize,
+    /// `rows + 1` offsets into `col_idx` and `values`.
+    pub row_ptr: Vec<u32>,
+    pub col_idx: Vec<u32>,
+    pub values: Vec<f32>,
+}
+
+impl Csr {
+    /// Duplicate entries are added up; columns are sorted within each row.
+    pub fn from_triplets(rows: usize, cols: usize, triplets: &[Triplet]) -> Result<Csr> {
+        if let Some(&(row, col, _)) = triplets.iter().find(|&&(r, c, _)| r >= rows || c >= cols) {
+            return Err(SparseError::Index { row, col, rows, cols }.into());
+        }
+        let mut sorted = triplets.to_vec();
+        sorted.sort_by_key(|&(r, c, _)| (r, c));
+        sorted.dedup_by(|next, kept| {
+            let same = (next.0, next.1) == (kept.0, kept.1);
+            if same {
+                kept.2 += next.2;
+            }
+            same
+        });
+        let mut row_ptr = vec![0u32; rows + 1];
+        for &(row, _, _) in &sorted {
+            row_ptr[row + 1] += 1;
+        }
+        for r in 0..rows {
+            row_ptr[r + 1] += row_ptr[r];
+        }
+        Ok(Csr {
+            rows,
+            cols,
+            row_ptr,
+            col_idx: sorted.iter().map(|&(_, c, _)| c as u32).collect(),
+            values: sorted.iter().map(|&(_, _, v)| v).collect(),
+        })
+    }
+
+    /// Reads a Matrix Market coordinate file.
+    pub fn load_mtx(path: impl AsRef<Path>) -> Result<Csr> {
+        let (rows, cols, triplets) = mtx::read(BufReader::new(File::open(path)?))?;
+        Csr::from_triplets(rows, cols, &triplets)
+    }
+
+    pub fn save_mtx(&self, path: impl AsRef<Path>) -> Result<()> {
+        Ok(mtx::write(BufWriter::new(File::create(path)?), self.rows, self.cols, self.triplets())?)
+    }
+
+    /// The stored entries, row by row.
+    pub fn triplets(&self) -> impl ExactSizeIterator<Item = Triplet> + '_ {
+        let mut row = 0;
+        (0..self.nnz()).map(move |j| {
+            while self.row_ptr[row + 1] as usize <= j {
+                row += 1;
+            }
+            (row, self.col_idx[j] as usize, self.values[j])
+        })
+    }
+
+    pub fn nnz(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn row_len(&self, row: usize) -> usize {
+        (self.row_ptr[row + 1] - self.row_ptr[row]) as usize
+    }
+
+    pub fn mean_row_len(&self) -> f64 {
+        self.nnz() as f64 / self.rows.max(1) as f64
+    }
+
+    pub fn max_row_len(&self) -> usize {
+        (0..self.rows).map(|r| self.row_len(r)).max().unwrap_or(0)
+    }
+
+    /// `Variant::choose` for this matrix.
+    pub fn variant(&self) -> Variant {
+        Variant::choose(self.mean_row_len())
+    }
+
+    fn check_x(&self, len: usize) -> Result<()> {
+        if len != self.cols {
+            return Err(SparseError::Len { what: "x", expected: self.cols, found: len }.into());
+        }
+        Ok(())
+    }
+
+    /// Copies the matrix to the device of `queue` and builds the kernels.
+    pub fn upload(&self, queue: &Queue) -> Result<DeviceCsr> {
+        Ok(DeviceCsr {
+            rows: self.rows,
+            cols: self.cols,
+            variant: self.variant(),
+            program: build(queue)?,
+            row_ptr: upload(queue, &self.row_ptr)?,
+            col_idx: upload(queue, &self.col_idx)?,
+            values: upload(queue, &self.values)?,
+            queue: queue.clone(),
+        })
+    }
+
+    /// `A x` on the interpreter with the given kernel.
+    pub fn spmv_interpreted(&self, x: &[f32], variant: Variant) -> Result<Vec<f32>> {
+        self.check_x(x.len())?;
+        let program = interpreter::Program::build(KERNEL_SRC)?;
+        let mut row_ptr = interpreter::Buffer::from_slice(&self.row_ptr);
+        let mut col_idx = interpreter::Buffer::from_slice(&self.col_idx);
+        let mut values = interpreter::Buffer::from_slice(&self.values);
+        let mut x = interpreter::Buffer::from_slice(x);
+        let mut y = interpreter::Buffer::new::<f32>(self.rows);
+        let launch = match variant {
+            Variant::Scalar => program.kernel("csr_scalar")?,
+            Variant::Vector { .. } => program.kernel("csr_vector")?,
+        }
+        .arg_buf(&mut row_ptr)
+        .arg_buf(&mut col_idx)
+        .arg_buf(&mut values)
+        .arg_buf(&mut x)
+        .arg_buf(&mut y)
+        .arg(self.rows as u32);
+        match variant {
+            Variant::Scalar => launch.global_work_size(self.rows.max(1)).run()?,
+            Variant::Vector { lanes } => launch
+                .arg(lanes as u32)
+                .arg_local::<f32>(VECTOR_GROUP)
+                .global_work_size(vector_global(self.rows, lanes))
+                .local_work_size(VECTOR_GROUP)
+                .run()?,
+        }
+        Ok(y.to_vec())
+    }
+}
+
+/// Whole groups of `VECTOR_GROUP` covering `lanes` work-items per row.
+fn vector_global(rows: usize, lanes: usize) -> usize {
+    (rows * lanes).div_ceil(VECTOR_GROUP).max(1) * VECTOR_GROUP
+}
+
+fn build(queue: &Queue) -> Result<Program> {
+    let span = span!(Level::Debug, "build SpMV kernels for {:?}", queue.device());
+    Ok(span.finish(Program::builder().src(KERNEL_SRC).devices(queue.device()).build(&queue.context()))?)
+}
+
+/// A read-only copy of `data`; empty slices get one element, as OpenCL
+/// buffers cannot be empty.
+fn upload<T: ocl::OclPrm>(queue: &Queue, data: &[T]) -> Result<Buffer<T>> {
+    let builder = Buffer::builder().queue(queue.clone()).flags(ocl::flags::MEM_READ_ONLY);
+    let buffer = if data.is_empty() { builder.len(1).fill_val(T::default()).build()? } else { builder.len(data.len()).copy_host_slice(data).build()? };
+    log::debug!("upload {:?}: {} bytes", buffer.as_core().as_ptr(), std::mem::size_of_val(data));
+    Ok(buffer)
+}
+
+fn check_vectors(rows: usize, cols: usize, x: &Buffer<f32>, y: &Buffer<f32>) -> Result<()> {
+    if x.len() != cols {
+        return Err(SparseError::Len { what: "x", expected: cols, found: x.len() }.into());
+    }
+    if y.len() != rows {
+        return Err(SparseError::Len { what: "y", expected: rows, found: y.len() }.into());
+    }
+    Ok(())
+}
+
+/// A `Csr` matrix on the device.
+#[derive(Debug, Clone)]
+pub struct DeviceCsr {
+    rows: usize,
+    cols: usize,
+    variant: Variant,
+    program: Program,
+    row_ptr: Buffer<u32>,
+    col_idx: Buffer<u32>,
+    values: Buffer<f32>,
+    queue: Queue,
+}
+
+impl DeviceCsr {
+    /// The kernel `spmv` runs, chosen from the mean row length.
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// `y = A x` with the chosen kernel.
+    pub fn spmv(&self, x: &Buffer<f32>, y: &Buffer<f32>) -> Result<()> {
+        self.spmv_with(self.variant, x, y)
+    }
+
+    pub fn spmv_with(&self, variant: Variant, x: &Buffer<f32>, y: &Buffer<f32>) -> Result<()> {
+        check_vectors(self.rows, self.cols, x, y)?;
+        let mut builder = Kernel::builder();
+        builder
+            .program(&self.program)
+            .queue(self.queue.clone())
+            .arg(&self.row_ptr)
+            .arg(&self.col_idx)
+            .arg(&self.values)
+            .arg(x)
+            .arg(y)
+            .arg(self.rows as u32);
+        match variant {
+            Variant::Scalar => builder.name("csr_scalar").global_work_size(self.rows.max(1)),
+            Variant::Vector { lanes } => builder
+                .name("csr_vector")
+                .arg(lanes as u32)
+                .arg_local::<f32>(VECTOR_GROUP)
+                .global_work_size(vector_global(self.rows, lanes))
+                .local_work_size(VECTOR_GROUP),
+        };
+        let kernel = builder.build()?;
+        log::trace!("enqueue {:?} on {} rows, {} nonzeros", variant, self.rows, self.values.len());
+        unsafe {
+            kernel.enq()?;
+        }
+        Ok(())
+    }
+}
+
+/// An ELLPACK matrix: `width` entries per row, stored column by column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ell {
+    pub rows: usize,
+    pub cols: usize,
+    pub width: usize,
+    /// `width * rows` column indices; entry `k` of row `r` is at
+    /// `k * rows + r`.
+    pub col_idx: Vec<u32>,
+    /// As `col_idx`; padding entries are zero.
+    pub values: Vec<f32>,
+}
+
+impl Ell {
+    pub fn from_csr(csr: &Csr) -> Ell {
+        let (rows, width) = (csr.rows, csr.max_row_len());
+        let mut col_idx = vec![0; rows * width];
+        let mut values = vec![0.0; rows * width];
+        for row in 0..rows {
+            let start = csr.row_ptr[row] as usize;
+            for k in 0..csr.row_len(row) {
+                col_idx[k * rows + row] = csr.col_idx[start + k];
+                values[k * rows + row] = csr.values[start + k];
+            }
+        }
+        Ell { rows, cols: csr.cols, width, col_idx, values }
+    }
+
+    pub fn from_triplets(rows: usize, cols: usize, triplets: &[Triplet]) -> Result<Ell> {
+        Ok(Ell::from_csr(&Csr::from_triplets(rows, cols, triplets)?))
+    }
+
+    pub fn load_mtx(path: impl AsRef<Path>) -> Result<Ell> {
+        Ok(Ell::from_csr(&Csr::load_mtx(path)?))
+    }
+
+    /// The share of stored entries that are nonzero; the rest is padding.
+    pub fn fill(&self) -> f64 {
+        let stored = self.values.len().max(1);
+        self.values.iter().filter(|&&v| v != 0.0).count() as f64 / stored as f64
+    }
+
+    pub fn upload(&self, queue: &Queue) -> Result<DeviceEll> {
+        Ok(DeviceEll {
+            rows: self.rows,
+            cols: self.cols,
+            width: self.width,
+            program: build(queue)?,
+            col_idx: upload(queue, &self.col_idx)?,
+            values: upload(queue, &self.values)?,
+            queue: queue.clone(),
+        })
+    }
+
+    /// `A x` on the interpreter.
+    pub fn spmv_interpreted(&self, x: &[f32]) -> Result<Vec<f32>> {
+        if x.len() != self.cols {
+            return Err(SparseError::Len { what: "x", expected: self.cols, found: x.len() }.into());
+        }
+        let program = interpreter::Program::build(KERNEL_SRC)?;
+        let mut col_idx = interpreter::Buffer::from_slice(&self.col_idx);
+        let mut values = interpreter::Buffer::from_slice(&self.values);
+        let mut x = interpreter::Buffer::from_slice(x);
+        let mut y = interpreter::Buffer::new::<f32>(self.rows);
+        program
+            .kernel("ell")?
+            .arg_buf(&mut col_idx)
+            .arg_buf(&mut values)
+            .arg_buf(&mut x)
+            .arg_buf(&mut y)
+            .arg(self.rows as u32)
+            .arg(self.width as u32)
+            .global_work_size(self.rows.max(1))
+            .run()?;
+        Ok(y.to_vec())
+    }
+}
+
+/// An `Ell` matrix on the device.
+#[derive(Debug, Clone)]
+pub struct DeviceEll {
+    rows: usize,
+    cols: usize,
+    width: usize,
+    program: Program,
+    col_idx: Buffer<u32>,
+    values: Buffer<f32>,
+    queue: Queue,
+}
+
+impl DeviceEll {
+    /// `y = A x`.
+    pub fn spmv(&self, x: &Buffer<f32>, y: &Buffer<f32>) -> Result<()> {
+        check_vectors(self.rows, self.cols, x, y)?;
+        let kernel = Kernel::builder()
+            .program(&self.program)
+            .name("ell")
+            .queue(self.queue.clone())
+            .global_work_size(self.rows.max(1))
+            .arg(&self.col_idx)
+            .arg(&self.values)
+            .arg(x)
+            .arg(y)
+            .arg(self.rows as u32)
+            .arg(self.width as u32)
+            .build()?;
+        log::trace!("enqueue ell on {} rows, width {}", self.rows, self.width);
+        unsafe {
+            kernel.enq()?;
+        }
+        Ok(())
+    }
+}
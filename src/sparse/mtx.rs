@@ -0,0 +1,133 @@
+//! Matrix Market exchange files: the `coordinate` format with `real`,
+//! `integer` or `pattern` entries and `general`, `symmetric` or
+//! `skew-symmetric` storage.
+
+use crate::Result;
+use super::{SparseError, Triplet};
+use std::io::{BufRead, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Symmetry {
+    General,
+    Symmetric,
+    Skew,
+}
+
+/// (rows, cols, triplets) of the matrix in `reader`, with 0-based indices
+/// and the mirrored half of symmetric matrices filled in.
+pub fn read(reader: impl BufRead) -> Result<(usize, usize, Vec<Triplet>)> {
+    let mut lines = reader.lines().enumerate().map(|(i, line)| (i + 1, line));
+    let parse = |line: usize, message: String| SparseError::Parse { line, message };
+
+    let (_, banner) = lines.next().ok_or_else(|| parse(1, "empty file".into()))?;
+    let banner = banner?.to_lowercase();
+    let words: Vec<&str> = banner.split_whitespace().collect();
+    let [header, object, format, field, symmetry] = words[..] else {
+        return Err(parse(1, "expected `%%MatrixMarket matrix coordinate <field> <symmetry>`".into()).into());
+    };
+    if header != "%%matrixmarket" || object != "matrix" {
+        return Err(parse(1, "not a Matrix Market matrix".into()).into());
+    }
+    if format != "coordinate" {
+        return Err(SparseError::Unsupported(format!("the {} format", format)).into());
+    }
+    let pattern = match field {
+        "real" | "integer" | "double" => false,
+        "pattern" => true,
+        other => return Err(SparseError::Unsupported(format!("{} entries", other)).into()),
+    };
+    let symmetry = match symmetry {
+        "general" => Symmetry::General,
+        "symmetric" => Symmetry::Symmetric,
+        "skew-symmetric" => Symmetry::Skew,
+        other => return Err(SparseError::Unsupported(format!("{} symmetry", other)).into()),
+    };
+
+    let mut size = None;
+    let mut triplets = Vec::new();
+    // Entry lines read, and the number of the last line.
+    let (mut entries, mut last) = (0, 1);
+    for (number, line) in lines {
+        last = number;
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('%') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let Some((rows, cols, nnz)) = size else {
+            let [rows, cols, nnz] = fields[..] else {
+                return Err(parse(number, "expected `rows cols entries`".into()).into());
+            };
+            let number_at = |s: &str| s.parse::<usize>().map_err(|_| parse(number, format!("bad count `{}`", s)));
+            let nnz = number_at(nnz)?;
+            size = Some((number_at(rows)?, number_at(cols)?, nnz));
+            triplets.reserve(if symmetry == Symmetry::General { nnz } else { 2 * nnz });
+            continue;
+        };
+        let expected = if pattern { 2 } else { 3 };
+        if fields.len() != expected {
+            return Err(parse(number, format!("expected {} fields, found {}", expected, fields.len())).into());
+        }
+        let index = |s: &str, len: usize| match s.parse::<usize>() {
+            Ok(i) if (1..=len).contains(&i) => Ok(i - 1),
+            _ => Err(parse(number, format!("index `{}` outside 1..={}", s, len))),
+        };
+        let (row, col) = (index(fields[0], rows)?, index(fields[1], cols)?);
+        let value = if pattern {
+            1.0
+        } else {
+            fields[2].parse::<f32>().map_err(|_| parse(number, format!("bad value `{}`", fields[2])))?
+        };
+        triplets.push((row, col, value));
+        if row != col {
+            match symmetry {
+                Symmetry::General => {}
+                Symmetry::Symmetric => triplets.push((col, row, value)),
+                Symmetry::Skew => triplets.push((col, row, -value)),
+            }
+        }
+        entries += 1;
+        if entries > nnz {
+            return Err(parse(number, format!("more than the {} entries announced", nnz)).into());
+        }
+    }
+    let (rows, cols, nnz) = size.ok_or_else(|| parse(1, "missing size line".into()))?;
+    if entries != nnz {
+        return Err(parse(last, format!("found {} of the {} entries announced", entries, nnz)).into());
+    }
+    Ok((rows, cols, triplets))
+}
+
+/// Writes `triplets` as a general real coordinate matrix.
+pub fn write(
+    mut writer: impl Write,
+    rows: usize,
+    cols: usize,
+    triplets: impl ExactSizeIterator<Item = Triplet>,
+) -> std::io::Result<()> {
+    writeln!(writer, "%%MatrixMarket matrix coordinate real general")?;
+    writeln!(writer, "{} {} {}", rows, cols, triplets.len())?;
+    for (row, col, value) in triplets {
+        writeln!(writer, "{} {} {:?}", row + 1, col + 1, value)?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    const HEADER: &str = "%%MatrixMarket matrix coordinate real symmetric\n3 3 3\n";
+
+    #[test]
+    fn entry_count_must_match_the_size_line() {
+        let (_, _, triplets) = read(format!("{}1 1 1.0\n2 1 2.0\n3 2 3.0\n", HEADER).as_bytes()).unwrap();
+        assert_eq!(triplets.len(), 5);
+        for body in ["1 1 1.0\n2 1 2.0\n", "1 1 1.0\n2 1 2.0\n3 2 3.0\n3 3 4.0\n"] {
+            let err = read(format!("{}{}", HEADER, body).as_bytes()).unwrap_err();
+            assert!(matches!(err, Error::Sparse(SparseError::Parse { .. })), "{}", err);
+        }
+    }
+}
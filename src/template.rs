@@ -0,0 +1,418 @@
+//! Kernel sources with typed parameters.
+//!
+//! A `KernelTemplate` is OpenCL C with optional `${NAME}` placeholders.
+//! `Params` gives each name a typed value: an element type, a vector type,
+//! an integer, a float or a flag. Parameters with a placeholder are
+//! substituted into the text; the rest become `-D` compiler options, so
+//! plain `#ifdef NAME` and macro uses work unchanged. Flags define `NAME`
+//! when true and nothing when false (`1`/`0` when substituted).
+//!
+//! A `double` type parameter also enables `cl_khr_fp64`, so one source can
+//! serve `float`, `double` and `int`. Built programs are cached per device
+//! and parameter set.
+
+use crate::Result;
+use crate::interpreter;
+use crate::logging::span;
+use log::Level;
+use ocl::{Context, Device, Program};
+use std::any::TypeId;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+const FP64_PRAGMA: &str = "#pragma OPENCL EXTENSION cl_khr_fp64 : enable\n";
+
+#[derive(Debug)]
+pub enum TemplateError {
+    /// A `${` without a closing `}` or with something other than a name
+    /// inside, at the given line.
+    Placeholder { line: usize, text: String },
+    /// A placeholder with no parameter.
+    Missing(String),
+    /// A parameter name that is not a C identifier.
+    Name(String),
+    /// A vector width other than 2, 3, 4, 8 or 16.
+    Width { name: String, width: usize },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Placeholder { line, text } => write!(f, "line {}: malformed placeholder `{}`", line, text),
+            TemplateError::Missing(name) => write!(f, "no value for placeholder ${{{}}}", name),
+            TemplateError::Name(name) => write!(f, "`{}` is not a valid parameter name", name),
+            TemplateError::Width { name, width } => {
+                write!(f, "parameter {}: vectors have 2, 3, 4, 8 or 16 components, not {}", name, width)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// An OpenCL C scalar type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScalarType {
+    Char,
+    Uchar,
+    Short,
+    Ushort,
+    Int,
+    Uint,
+    Long,
+    Ulong,
+    Float,
+    Double,
+}
+
+impl ScalarType {
+    pub fn name(self) -> &'static str {
+        match self {
+            ScalarType::Char => "char",
+            ScalarType::Uchar => "uchar",
+            ScalarType::Short => "short",
+            ScalarType::Ushort => "ushort",
+            ScalarType::Int => "int",
+            ScalarType::Uint => "uint",
+            ScalarType::Long => "long",
+            ScalarType::Ulong => "ulong",
+            ScalarType::Float => "float",
+            ScalarType::Double => "double",
+        }
+    }
+
+    /// The OpenCL type of the Rust scalar `T`, if it has one.
+    pub fn of<T: 'static>() -> Option<ScalarType> {
+        let id = TypeId::of::<T>();
+        [
+            (TypeId::of::<i8>(), ScalarType::Char),
+            (TypeId::of::<u8>(), ScalarType::Uchar),
+            (TypeId::of::<i16>(), ScalarType::Short),
+            (TypeId::of::<u16>(), ScalarType::Ushort),
+            (TypeId::of::<i32>(), ScalarType::Int),
+            (TypeId::of::<u32>(), ScalarType::Uint),
+            (TypeId::of::<i64>(), ScalarType::Long),
+            (TypeId::of::<u64>(), ScalarType::Ulong),
+            (TypeId::of::<f32>(), ScalarType::Float),
+            (TypeId::of::<f64>(), ScalarType::Double),
+        ]
+        .into_iter()
+        .find(|&(t, _)| t == id)
+        .map(|(_, ty)| ty)
+    }
+}
+
+impl fmt::Display for ScalarType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A parameter value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Type(ScalarType),
+    /// A vector type such as `float4`.
+    Vector(ScalarType, usize),
+    Int(i64),
+    /// Written with an `f` suffix.
+    Float(f32),
+    Double(f64),
+    Flag(bool),
+}
+
+impl Value {
+    /// The OpenCL C text of the value; `None` for a false flag, which is
+    /// left undefined.
+    fn render(&self) -> Option<String> {
+        Some(match self {
+            Value::Type(ty) => ty.name().to_string(),
+            Value::Vector(ty, width) => format!("{}{}", ty.name(), width),
+            Value::Int(x) if *x < 0 => format!("({})", x),
+            Value::Int(x) => x.to_string(),
+            // `{:e}` writes the shortest digits that read back exactly.
+            Value::Float(x) => format!("({:e}f)", x),
+            Value::Double(x) => format!("({:e})", x),
+            Value::Flag(true) => "1".into(),
+            Value::Flag(false) => return None,
+        })
+    }
+
+    fn uses_double(&self) -> bool {
+        matches!(self, Value::Type(ScalarType::Double) | Value::Vector(ScalarType::Double, _))
+    }
+}
+
+/// Named parameter values, in name order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Params {
+    values: BTreeMap<String, Value>,
+}
+
+impl Params {
+    pub fn new() -> Params {
+        Params::default()
+    }
+
+    pub fn set(mut self, name: &str, value: Value) -> Self {
+        self.values.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn ty(self, name: &str, ty: ScalarType) -> Self {
+        self.set(name, Value::Type(ty))
+    }
+
+    pub fn vector(self, name: &str, ty: ScalarType, width: usize) -> Self {
+        self.set(name, Value::Vector(ty, width))
+    }
+
+    pub fn int(self, name: &str, value: i64) -> Self {
+        self.set(name, Value::Int(value))
+    }
+
+    pub fn float(self, name: &str, value: f32) -> Self {
+        self.set(name, Value::Float(value))
+    }
+
+    pub fn double(self, name: &str, value: f64) -> Self {
+        self.set(name, Value::Double(value))
+    }
+
+    pub fn flag(self, name: &str, on: bool) -> Self {
+        self.set(name, Value::Flag(on))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.values.get(name)
+    }
+
+    /// Identifies the parameter set in the program caches.
+    fn key(&self) -> String {
+        self.values.iter().map(|(name, value)| format!("{}={:?};", name, value)).collect()
+    }
+
+    fn check(&self) -> Result<()> {
+        for (name, value) in &self.values {
+            if !is_identifier(name) {
+                return Err(TemplateError::Name(name.clone()).into());
+            }
+            if let Value::Vector(_, width) = value
+                && ![2, 3, 4, 8, 16].contains(width)
+            {
+                return Err(TemplateError::Width { name: name.clone(), width: *width }.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The source and compiler options of one parameter set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instance {
+    pub source: String,
+    pub options: String,
+}
+
+/// Text or a placeholder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Placeholder(String),
+}
+
+/// A kernel source with placeholders, and the programs built from it.
+#[derive(Debug, Clone)]
+pub struct KernelTemplate {
+    pieces: Vec<Piece>,
+    programs: HashMap<(usize, Device, String), Program>,
+    interpreted: HashMap<String, interpreter::Program>,
+}
+
+impl KernelTemplate {
+    pub fn new(source: &str) -> Result<KernelTemplate> {
+        let mut pieces = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("${") {
+            pieces.push(Piece::Text(rest[..start].to_string()));
+            let line = source[..source.len() - rest.len() + start].matches('\n').count() + 1;
+            let after = &rest[start + 2..];
+            let malformed = |text: &str| TemplateError::Placeholder { line, text: text.to_string() };
+            let end = after.find('}').ok_or_else(|| malformed(&rest[start..rest.len().min(start + 20)]))?;
+            let name = &after[..end];
+            if !is_identifier(name) {
+                return Err(malformed(&rest[start..start + end + 3]).into());
+            }
+            pieces.push(Piece::Placeholder(name.to_string()));
+            rest = &after[end + 1..];
+        }
+        pieces.push(Piece::Text(rest.to_string()));
+        Ok(KernelTemplate { pieces, programs: HashMap::new(), interpreted: HashMap::new() })
+    }
+
+    /// Placeholder names, in order of first use.
+    pub fn placeholders(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for piece in &self.pieces {
+            if let Piece::Placeholder(name) = piece
+                && !names.contains(&name.as_str())
+            {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// Fills in the placeholders and turns the other parameters into
+    /// options.
+    pub fn instantiate(&self, params: &Params) -> Result<Instance> {
+        params.check()?;
+        let mut source = String::new();
+        if params.values.values().any(Value::uses_double) {
+            source.push_str(FP64_PRAGMA);
+        }
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(text) => source.push_str(text),
+                Piece::Placeholder(name) => {
+                    let value = params.get(name).ok_or_else(|| TemplateError::Missing(name.clone()))?;
+                    source.push_str(&value.render().unwrap_or_else(|| "0".into()));
+                }
+            }
+        }
+        let placeholders = self.placeholders();
+        let options: Vec<String> = params
+            .values
+            .iter()
+            .filter(|(name, _)| !placeholders.contains(&name.as_str()))
+            .filter_map(|(name, value)| match value {
+                Value::Flag(true) => Some(format!("-D {}", name)),
+                other => other.render().map(|text| format!("-D {}={}", name, text)),
+            })
+            .collect();
+        Ok(Instance { source, options: options.join(" ") })
+    }
+
+    /// The program for `params` on `device`, built on first use.
+    pub fn program(&mut self, context: &Context, device: Device, params: &Params) -> Result<&Program> {
+        let key = (context.as_core().as_ptr() as usize, device, params.key());
+        if !self.programs.contains_key(&key) {
+            let instance = self.instantiate(params)?;
+            let span = span!(Level::Debug, "build template instance for {:?} with `{}`", device, instance.options);
+            let program = span.finish(
+                Program::builder().src(instance.source).devices(device).cmplr_opt(instance.options).build(context),
+            )?;
+            self.programs.insert(key.clone(), program);
+        }
+        Ok(&self.programs[&key])
+    }
+
+    /// The interpreter program for `params`, parsed on first use.
+    pub fn interpreted(&mut self, params: &Params) -> Result<&interpreter::Program> {
+        let key = params.key();
+        if !self.interpreted.contains_key(&key) {
+            let instance = self.instantiate(params)?;
+            let program = interpreter::Program::build_with_options(&instance.source, &instance.options)?;
+            self.interpreted.insert(key.clone(), program);
+        }
+        Ok(&self.interpreted[&key])
+    }
+
+    /// Programs built or parsed so far.
+    pub fn cached(&self) -> usize {
+        self.programs.len() + self.interpreted.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+    use crate::interpreter::Buffer;
+
+    const SCALE_SRC: &str = r#"
+__kernel void scale(__global ${T}* data) {
+    uint i = get_global_id(0);
+#ifdef NEGATE
+    data[i] = -data[i] * FACTOR;
+#else
+    data[i] = data[i] * FACTOR;
+#endif
+}
+"#;
+
+    fn template_error(result: Result<impl fmt::Debug>) -> TemplateError {
+        match result.unwrap_err() {
+            Error::Template(err) => err,
+            err => panic!("expected a template error, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn placeholders_are_substituted_and_the_rest_defined() {
+        let template = KernelTemplate::new("${T} x = ${ONE}; ${T}${W} v; // ${ONE}").unwrap();
+        assert_eq!(template.placeholders(), ["T", "ONE", "W"]);
+        let params = Params::new()
+            .ty("T", ScalarType::Double)
+            .int("ONE", -1)
+            .int("W", 4)
+            .vector("V", ScalarType::Uchar, 16)
+            .float("F", 0.1)
+            .double("D", 2.5)
+            .flag("ON", true)
+            .flag("OFF", false);
+        let instance = template.instantiate(&params).unwrap();
+        assert_eq!(instance.source, format!("{}double x = (-1); double4 v; // (-1)", FP64_PRAGMA));
+        assert_eq!(instance.options, "-D D=(2.5e0) -D F=(1e-1f) -D ON -D V=uchar16");
+
+        let flags = KernelTemplate::new("${ON} ${OFF}").unwrap();
+        let instance = flags.instantiate(&Params::new().flag("ON", true).flag("OFF", false)).unwrap();
+        assert_eq!(instance.source, "1 0");
+        assert_eq!(instance.options, "");
+    }
+
+    #[test]
+    fn bad_templates_and_parameters_are_rejected() {
+        assert!(matches!(
+            template_error(KernelTemplate::new("int x;\n${T y;")),
+            TemplateError::Placeholder { line: 2, .. }
+        ));
+        assert!(matches!(template_error(KernelTemplate::new("${1T}")), TemplateError::Placeholder { line: 1, .. }));
+        let template = KernelTemplate::new("${T}").unwrap();
+        assert!(matches!(template_error(template.instantiate(&Params::new())), TemplateError::Missing(name) if name == "T"));
+        let params = Params::new().ty("T", ScalarType::Int).int("not-a-name", 1);
+        assert!(matches!(template_error(template.instantiate(&params)), TemplateError::Name(_)));
+        let params = Params::new().vector("T", ScalarType::Int, 5);
+        assert!(matches!(template_error(template.instantiate(&params)), TemplateError::Width { width: 5, .. }));
+    }
+
+    #[test]
+    fn each_parameter_set_is_built_once() {
+        let mut template = KernelTemplate::new(SCALE_SRC).unwrap();
+        let run = |template: &mut KernelTemplate, params: &Params| {
+            let mut data = Buffer::from_slice(&[1.0f32, 2.0, 3.0]);
+            template.interpreted(params).unwrap().kernel("scale").unwrap().arg_buf(&mut data).global_work_size(3).run().unwrap();
+            data.to_vec::<f32>()
+        };
+        let twice = Params::new().ty("T", ScalarType::Float).float("FACTOR", 2.0);
+        assert_eq!(run(&mut template, &twice), [2.0, 4.0, 6.0]);
+        assert_eq!(template.cached(), 1);
+        assert_eq!(run(&mut template, &twice.clone()), [2.0, 4.0, 6.0]);
+        assert_eq!(template.cached(), 1);
+
+        // A new value, or a new parameter, is a new program.
+        let triple = Params::new().ty("T", ScalarType::Float).float("FACTOR", 3.0);
+        assert_eq!(run(&mut template, &triple), [3.0, 6.0, 9.0]);
+        assert_eq!(template.cached(), 2);
+        let negated = triple.flag("NEGATE", true);
+        assert_eq!(run(&mut template, &negated), [-3.0, -6.0, -9.0]);
+        assert_eq!(template.cached(), 3);
+        assert_eq!(run(&mut template, &twice), [2.0, 4.0, 6.0]);
+        assert_eq!(template.cached(), 3);
+    }
+}
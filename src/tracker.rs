@@ -0,0 +1,350 @@
+//! Debug-mode lifetime tracker for OpenCL handles.
+//!
+//! Enabled by default in debug builds. Set `SIMPLE_GPU_TRACK=1` to enable it
+//! in release builds or `SIMPLE_GPU_TRACK=0` to turn it off.
+//!
+//! Every context, queue, program, kernel, memory object and event is
+//! recorded as it passes through the OpenCL entry points in `icd`, whether
+//! this crate, `ocl` or the caller made the call. Handles from anywhere else
+//! can be recorded with the `record_*` functions.
+
+use crate::{Error, Result};
+use ocl::ffi::{self, cl_int};
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ObjectKind {
+    Context,
+    Queue,
+    Program,
+    Kernel,
+    Buffer,
+    Image,
+    Event,
+}
+
+impl fmt::Display for ObjectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+struct Entry {
+    refs: usize,
+    created: Backtrace,
+}
+
+#[derive(Default)]
+struct Registry {
+    live: HashMap<(ObjectKind, usize), Entry>,
+    over_released: Vec<(ObjectKind, usize, Backtrace)>,
+    created: usize,
+    adopted: usize,
+    released: usize,
+}
+
+impl Registry {
+    /// Buffers and images share `clRetainMemObject` and
+    /// `clReleaseMemObject`, so either kind finds a live entry of the other.
+    fn key(&self, kind: ObjectKind, handle: usize) -> (ObjectKind, usize) {
+        let other = match kind {
+            ObjectKind::Buffer => ObjectKind::Image,
+            ObjectKind::Image => ObjectKind::Buffer,
+            _ => return (kind, handle),
+        };
+        if !self.live.contains_key(&(kind, handle)) && self.live.contains_key(&(other, handle)) {
+            (other, handle)
+        } else {
+            (kind, handle)
+        }
+    }
+}
+
+fn registry() -> &'static Mutex<Registry> {
+    static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+fn with_registry<R>(f: impl FnOnce(&mut Registry) -> R) -> R {
+    let mut reg = registry().lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    f(&mut reg)
+}
+
+/// Returns whether events are being recorded.
+pub fn enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| match std::env::var("SIMPLE_GPU_TRACK") {
+        Ok(v) => !matches!(v.as_str(), "" | "0" | "false" | "off"),
+        Err(_) => cfg!(debug_assertions),
+    })
+}
+
+/// Records a newly created handle (reference count 1). A handle that is
+/// already live, e.g. one recorded twice, gains a reference instead.
+pub fn record_create(kind: ObjectKind, handle: usize) {
+    if !enabled() {
+        return;
+    }
+    with_registry(|reg| match reg.live.get_mut(&(kind, handle)) {
+        Some(entry) => entry.refs += 1,
+        None => {
+            reg.created += 1;
+            reg.live.insert((kind, handle), Entry { refs: 1, created: Backtrace::force_capture() });
+        }
+    });
+}
+
+/// Records an additional reference to a handle. Handles that were never
+/// recorded as created are adopted, and counted as such, with this call as
+/// their origin.
+pub fn record_retain(kind: ObjectKind, handle: usize) {
+    if !enabled() {
+        return;
+    }
+    with_registry(|reg| {
+        let key = reg.key(kind, handle);
+        match reg.live.get_mut(&key) {
+            Some(entry) => entry.refs += 1,
+            None => {
+                reg.adopted += 1;
+                reg.live.insert(key, Entry { refs: 1, created: Backtrace::force_capture() });
+            }
+        }
+    });
+}
+
+/// Records a dropped reference. Releasing a handle with no outstanding
+/// references is logged as an over-release.
+pub fn record_release(kind: ObjectKind, handle: usize) {
+    if !enabled() {
+        return;
+    }
+    with_registry(|reg| {
+        let key = reg.key(kind, handle);
+        match reg.live.get_mut(&key) {
+            Some(entry) => {
+                entry.refs -= 1;
+                if entry.refs == 0 {
+                    reg.live.remove(&key);
+                    reg.released += 1;
+                }
+            }
+            None => reg.over_released.push((kind, handle, Backtrace::force_capture())),
+        }
+    });
+}
+
+/// Calls the matching `clRetain*`, which records it.
+///
+/// # Safety
+///
+/// `handle` must be a valid OpenCL object of the given kind.
+pub unsafe fn retain(kind: ObjectKind, handle: *mut c_void) -> Result<()> {
+    let status = unsafe {
+        match kind {
+            ObjectKind::Context => ffi::clRetainContext(handle),
+            ObjectKind::Queue => ffi::clRetainCommandQueue(handle),
+            ObjectKind::Program => ffi::clRetainProgram(handle),
+            ObjectKind::Kernel => ffi::clRetainKernel(handle),
+            ObjectKind::Buffer | ObjectKind::Image => ffi::clRetainMemObject(handle),
+            ObjectKind::Event => ffi::clRetainEvent(handle),
+        }
+    };
+    log::trace!("retain {:?} {:?}", kind, handle);
+    check(status, "Retain", kind)
+}
+
+/// Calls the matching `clRelease*`, which records it.
+///
+/// # Safety
+///
+/// `handle` must be a valid OpenCL object of the given kind. The caller gives
+/// up the reference being released.
+pub unsafe fn release(kind: ObjectKind, handle: *mut c_void) -> Result<()> {
+    log::trace!("release {:?} {:?}", kind, handle);
+    let status = unsafe {
+        match kind {
+            ObjectKind::Context => ffi::clReleaseContext(handle),
+            ObjectKind::Queue => ffi::clReleaseCommandQueue(handle),
+            ObjectKind::Program => ffi::clReleaseProgram(handle),
+            ObjectKind::Kernel => ffi::clReleaseKernel(handle),
+            ObjectKind::Buffer | ObjectKind::Image => ffi::clReleaseMemObject(handle),
+            ObjectKind::Event => ffi::clReleaseEvent(handle),
+        }
+    };
+    check(status, "Release", kind)
+}
+
+fn check(status: cl_int, op: &str, kind: ObjectKind) -> Result<()> {
+    let object = match kind {
+        ObjectKind::Context => "Context",
+        ObjectKind::Queue => "CommandQueue",
+        ObjectKind::Program => "Program",
+        ObjectKind::Kernel => "Kernel",
+        ObjectKind::Buffer | ObjectKind::Image => "MemObject",
+        ObjectKind::Event => "Event",
+    };
+    Error::check(status).map_err(|err| err.call(&format!("cl{}{}", op, object)))
+}
+
+/// A handle still holding references when the report was taken.
+pub struct Leak {
+    pub kind: ObjectKind,
+    pub handle: usize,
+    pub refs: usize,
+    pub created: String,
+}
+
+/// A release of a handle the tracker held no references for.
+pub struct OverRelease {
+    pub kind: ObjectKind,
+    pub handle: usize,
+    pub released: String,
+}
+
+pub struct Report {
+    pub created: usize,
+    /// Handles first seen being retained rather than created.
+    pub adopted: usize,
+    pub released: usize,
+    pub leaked: Vec<Leak>,
+    pub over_released: Vec<OverRelease>,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.leaked.is_empty() && self.over_released.is_empty()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "OpenCL object tracker: {} created, {} adopted, {} released, {} leaked, {} over-released",
+            self.created,
+            self.adopted,
+            self.released,
+            self.leaked.len(),
+            self.over_released.len()
+        )?;
+        for leak in &self.leaked {
+            writeln!(f, "leaked {} {:#x} ({} outstanding references), created at:", leak.kind, leak.handle, leak.refs)?;
+            writeln!(f, "{}", leak.created)?;
+        }
+        for over in &self.over_released {
+            writeln!(f, "over-released {} {:#x} at:", over.kind, over.handle)?;
+            writeln!(f, "{}", over.released)?;
+        }
+        Ok(())
+    }
+}
+
+/// Takes a snapshot of outstanding and over-released handles.
+pub fn report() -> Report {
+    with_registry(|reg| {
+        let mut leaked: Vec<Leak> = reg
+            .live
+            .iter()
+            .map(|(&(kind, handle), entry)| Leak { kind, handle, refs: entry.refs, created: entry.created.to_string() })
+            .collect();
+        leaked.sort_by_key(|leak| (leak.kind, leak.handle));
+        Report {
+            created: reg.created,
+            adopted: reg.adopted,
+            released: reg.released,
+            leaked,
+            over_released: reg
+                .over_released
+                .iter()
+                .map(|(kind, handle, released)| OverRelease { kind: *kind, handle: *handle, released: released.to_string() })
+                .collect(),
+        }
+    })
+}
+
+/// Forgets everything recorded so far.
+pub fn reset() {
+    with_registry(|reg| *reg = Registry::default());
+}
+
+/// Prints the report to stderr when dropped, if anything leaked or was
+/// over-released. Bind it at the top of `main`.
+pub struct ReportOnExit;
+
+pub fn report_on_exit() -> ReportOnExit {
+    ReportOnExit
+}
+
+impl Drop for ReportOnExit {
+    fn drop(&mut self) {
+        if !enabled() {
+            return;
+        }
+        let report = report();
+        if !report.is_clean() {
+            eprint!("{}", report);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creating_a_live_handle_adds_a_reference() {
+        if !enabled() {
+            return;
+        }
+        let handle = 0x7e57_0001;
+        record_create(ObjectKind::Buffer, handle);
+        record_create(ObjectKind::Buffer, handle);
+        let refs = |report: &Report| report.leaked.iter().find(|l| l.handle == handle).map(|l| l.refs);
+        assert_eq!(refs(&report()), Some(2));
+        record_release(ObjectKind::Buffer, handle);
+        assert_eq!(refs(&report()), Some(1));
+        record_release(ObjectKind::Buffer, handle);
+        let report = report();
+        assert_eq!(refs(&report), None);
+        assert!(report.over_released.iter().all(|r| r.handle != handle));
+    }
+
+    #[test]
+    fn retaining_an_unknown_handle_adopts_it() {
+        if !enabled() {
+            return;
+        }
+        let handle = 0x7e57_0002;
+        let adopted = report().adopted;
+        record_retain(ObjectKind::Queue, handle);
+        let report = report();
+        assert!(report.adopted > adopted);
+        assert!(report.leaked.iter().any(|l| l.kind == ObjectKind::Queue && l.handle == handle && l.refs == 1));
+        assert!(report.to_string().contains(" adopted, "));
+        record_release(ObjectKind::Queue, handle);
+        assert!(super::report().leaked.iter().all(|l| l.handle != handle));
+    }
+
+    #[test]
+    fn memory_objects_are_found_under_either_kind() {
+        if !enabled() {
+            return;
+        }
+        let handle = 0x7e57_0003;
+        record_create(ObjectKind::Image, handle);
+        // `clRetainMemObject` cannot tell an image from a buffer.
+        record_retain(ObjectKind::Buffer, handle);
+        let refs = |report: &Report| report.leaked.iter().find(|l| l.handle == handle).map(|l| (l.kind, l.refs));
+        assert_eq!(refs(&report()), Some((ObjectKind::Image, 2)));
+        record_release(ObjectKind::Buffer, handle);
+        record_release(ObjectKind::Buffer, handle);
+        let report = report();
+        assert_eq!(refs(&report), None);
+        assert!(report.over_released.iter().all(|r| r.handle != handle));
+    }
+}
@@ -0,0 +1,277 @@
+//! Conversions between Rust arrays, slices and strings and the `ocl::prm`
+//! vector types.
+//!
+//! The `ocl::prm` types already convert from and into arrays of exactly
+//! their width (`Char16::from([0i8; 16])`). `Vector` adds slices, with these
+//! padding rules:
+//!
+//! * `try_from_slice` needs exactly as many components as the vector has.
+//! * `from_slice_padded` fills missing trailing components with a pad value
+//!   and rejects slices that are too long.
+//! * Three-component types occupy four components in memory, as in OpenCL.
+//!   The fourth is always zero when built here, and is skipped by the
+//!   `lanes` and `to_flat` conversions but included in `flatten`, which
+//!   shows the memory as-is.
+//!
+//! `ByteVector` builds char and uchar vectors from `&str` and `&[u8]` and
+//! back.
+
+use crate::Result;
+use ocl::prm::*;
+use ocl::{Buffer, OclPrm};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorError {
+    /// More components than the vector holds.
+    TooLong { lanes: usize, found: usize },
+    /// An exact conversion given the wrong number of components.
+    Length { lanes: usize, found: usize },
+    /// A flat slice that does not divide into whole vectors.
+    Ragged { lanes: usize, found: usize },
+}
+
+impl fmt::Display for VectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VectorError::TooLong { lanes, found } => {
+                write!(f, "{} components do not fit a {}-component vector", found, lanes)
+            }
+            VectorError::Length { lanes, found } => {
+                write!(f, "a {}-component vector needs exactly {} components, found {}", lanes, lanes, found)
+            }
+            VectorError::Ragged { lanes, found } => {
+                write!(f, "{} components are not a whole number of {}-component vectors", found, lanes)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VectorError {}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// An `ocl::prm` vector type: `Char2` to `Char16`, `Uchar2`, ..., `Double16`.
+pub trait Vector: OclPrm + Deref<Target = [Self::Scalar]> + DerefMut + sealed::Sealed {
+    type Scalar: OclPrm;
+
+    /// Number of components: 2, 3, 4, 8 or 16.
+    const LANES: usize;
+
+    /// Components in memory, counting the padding of three-component types.
+    fn storage() -> usize {
+        std::mem::size_of::<Self>() / std::mem::size_of::<Self::Scalar>()
+    }
+
+    fn try_from_slice(values: &[Self::Scalar]) -> Result<Self> {
+        if values.len() != Self::LANES {
+            return Err(VectorError::Length { lanes: Self::LANES, found: values.len() }.into());
+        }
+        Self::from_slice_padded(values, Self::Scalar::default())
+    }
+
+    /// Missing trailing components are set to `pad`.
+    fn from_slice_padded(values: &[Self::Scalar], pad: Self::Scalar) -> Result<Self> {
+        if values.len() > Self::LANES {
+            return Err(VectorError::TooLong { lanes: Self::LANES, found: values.len() }.into());
+        }
+        let mut vector = Self::default();
+        vector[..values.len()].copy_from_slice(values);
+        vector[values.len()..Self::LANES].fill(pad);
+        Ok(vector)
+    }
+
+    /// The components, without the padding of three-component types.
+    fn lanes(&self) -> &[Self::Scalar] {
+        &self[..Self::LANES]
+    }
+}
+
+macro_rules! vectors {
+    ($scalar:ty: $($name:ident $lanes:expr),+) => {
+        $(
+            impl sealed::Sealed for $name {}
+            impl Vector for $name {
+                type Scalar = $scalar;
+                const LANES: usize = $lanes;
+            }
+        )+
+    };
+}
+
+vectors!(i8: Char2 2, Char3 3, Char4 4, Char8 8, Char16 16);
+vectors!(u8: Uchar2 2, Uchar3 3, Uchar4 4, Uchar8 8, Uchar16 16);
+vectors!(i16: Short2 2, Short3 3, Short4 4, Short8 8, Short16 16);
+vectors!(u16: Ushort2 2, Ushort3 3, Ushort4 4, Ushort8 8, Ushort16 16);
+vectors!(i32: Int2 2, Int3 3, Int4 4, Int8 8, Int16 16);
+// `Uint8` is left out: ocl-core-vector 0.1 declares it with `i8` components,
+// so it is 8 bytes rather than OpenCL's 32.
+vectors!(u32: Uint2 2, Uint3 3, Uint4 4, Uint16 16);
+vectors!(i64: Long2 2, Long3 3, Long4 4, Long8 8, Long16 16);
+vectors!(u64: Ulong2 2, Ulong3 3, Ulong4 4, Ulong8 8, Ulong16 16);
+vectors!(f32: Float2 2, Float3 3, Float4 4, Float8 8, Float16 16);
+vectors!(f64: Double2 2, Double3 3, Double4 4, Double8 8, Double16 16);
+
+/// `i8` or `u8`: the components of char and uchar vectors.
+pub trait ByteScalar: OclPrm + sealed::Sealed {
+    fn from_byte(byte: u8) -> Self;
+    fn to_byte(self) -> u8;
+}
+
+impl sealed::Sealed for i8 {}
+impl sealed::Sealed for u8 {}
+
+impl ByteScalar for i8 {
+    fn from_byte(byte: u8) -> Self {
+        byte as i8
+    }
+
+    fn to_byte(self) -> u8 {
+        self as u8
+    }
+}
+
+impl ByteScalar for u8 {
+    fn from_byte(byte: u8) -> Self {
+        byte
+    }
+
+    fn to_byte(self) -> u8 {
+        self
+    }
+}
+
+/// Char and uchar vectors as bytes and text.
+pub trait ByteVector: Vector<Scalar: ByteScalar> {
+    /// Missing trailing bytes are set to `pad`.
+    fn from_bytes_padded(bytes: &[u8], pad: u8) -> Result<Self> {
+        let values: Vec<Self::Scalar> = bytes.iter().map(|&b| Self::Scalar::from_byte(b)).collect();
+        Self::from_slice_padded(&values, Self::Scalar::from_byte(pad))
+    }
+
+    /// The UTF-8 bytes of `s`, zero-padded like a C string.
+    fn from_str_padded(s: &str) -> Result<Self> {
+        Self::from_bytes_padded(s.as_bytes(), 0)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.lanes().iter().map(|&c| c.to_byte()).collect()
+    }
+
+    /// The bytes up to the first zero, as text.
+    fn to_string_lossy(&self) -> String {
+        let bytes = self.to_bytes();
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    }
+}
+
+impl<V: Vector<Scalar: ByteScalar>> ByteVector for V {}
+
+/// The components of `vectors` as they lie in memory, including the
+/// padding of three-component types.
+pub fn flatten<V: Vector>(vectors: &[V]) -> &[V::Scalar] {
+    assert_storage::<V>();
+    // SAFETY: each vector is an array of `storage()` scalars with nothing
+    // else in it (checked above), so the slice covers exactly their memory.
+    unsafe { std::slice::from_raw_parts(vectors.as_ptr().cast(), vectors.len() * V::storage()) }
+}
+
+pub fn flatten_mut<V: Vector>(vectors: &mut [V]) -> &mut [V::Scalar] {
+    assert_storage::<V>();
+    // SAFETY: as in `flatten`; the borrow of `vectors` is carried over.
+    unsafe { std::slice::from_raw_parts_mut(vectors.as_mut_ptr().cast(), vectors.len() * V::storage()) }
+}
+
+fn assert_storage<V: Vector>() {
+    assert_eq!(V::default().len(), V::storage(), "vector type holds something besides its components");
+}
+
+/// The components of `vectors`, without padding.
+pub fn to_flat<V: Vector>(vectors: &[V]) -> Vec<V::Scalar> {
+    vectors.iter().flat_map(|v| v.lanes().iter().copied()).collect()
+}
+
+/// Groups `values` into vectors; the length must be a multiple of the
+/// vector width.
+pub fn from_flat<V: Vector>(values: &[V::Scalar]) -> Result<Vec<V>> {
+    if !values.len().is_multiple_of(V::LANES) {
+        return Err(VectorError::Ragged { lanes: V::LANES, found: values.len() }.into());
+    }
+    values.chunks_exact(V::LANES).map(V::try_from_slice).collect()
+}
+
+/// Reads a whole buffer of vectors as their components, without padding,
+/// using the buffer's default queue.
+pub fn read_flat<V: Vector>(buffer: &Buffer<V>) -> Result<Vec<V::Scalar>> {
+    let mut vectors = vec![V::default(); buffer.len()];
+    buffer.read(&mut vectors).enq()?;
+    log::trace!("read {} bytes from {:?}", std::mem::size_of_val(&vectors[..]), buffer.as_core().as_ptr());
+    Ok(to_flat(&vectors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    fn vector_error(result: Result<impl fmt::Debug>) -> VectorError {
+        match result.unwrap_err() {
+            Error::Vector(err) => err,
+            err => panic!("expected a vector error, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn padded_slices_fill_the_missing_lanes() {
+        let v = Float3::from_slice_padded(&[1.0, 2.0], 9.0).unwrap();
+        assert_eq!(v.lanes(), [1.0, 2.0, 9.0]);
+        // The padding lane is zero whatever the pad value.
+        assert_eq!(Float3::storage(), 4);
+        assert_eq!(v[3], 0.0);
+        assert_eq!(Float3::try_from_slice(&[1.0, 2.0, 3.0]).unwrap().lanes(), [1.0, 2.0, 3.0]);
+        assert_eq!(
+            vector_error(Float3::from_slice_padded(&[0.0; 4], 0.0)),
+            VectorError::TooLong { lanes: 3, found: 4 }
+        );
+        assert_eq!(vector_error(Float3::try_from_slice(&[0.0; 2])), VectorError::Length { lanes: 3, found: 2 });
+
+        let c = Char16::from_slice_padded(&[1, -2, 3], -1).unwrap();
+        assert_eq!(&c[..4], [1, -2, 3, -1]);
+        assert!(c[3..].iter().all(|&x| x == -1));
+        assert_eq!(Char16::from_str_padded("hello").unwrap().to_string_lossy(), "hello");
+        assert_eq!(
+            vector_error(Char16::from_str_padded("seventeen bytes!!")),
+            VectorError::TooLong { lanes: 16, found: 17 }
+        );
+    }
+
+    #[test]
+    fn flat_conversions_reject_ragged_input() {
+        let values = [1, 2, 3, 4, 5, 6];
+        let vectors = from_flat::<Int3>(&values).unwrap();
+        assert_eq!(vectors.len(), 2);
+        assert_eq!(to_flat(&vectors), values);
+        assert_eq!(vector_error(from_flat::<Int3>(&values[..5])), VectorError::Ragged { lanes: 3, found: 5 });
+        assert_eq!(vector_error(from_flat::<Int4>(&values)), VectorError::Ragged { lanes: 4, found: 6 });
+        assert!(from_flat::<Int4>(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn flatten_shows_the_padding_lane() {
+        let mut vectors = from_flat::<Float3>(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        assert_eq!(flatten(&vectors), [1.0, 2.0, 3.0, 0.0, 4.0, 5.0, 6.0, 0.0]);
+        let flat = flatten_mut(&mut vectors);
+        flat[3] = 7.0;
+        flat[4] = 8.0;
+        assert_eq!(vectors[0][3], 7.0);
+        // `to_flat` still skips the padding.
+        assert_eq!(to_flat(&vectors), [1.0, 2.0, 3.0, 8.0, 5.0, 6.0]);
+
+        let bytes = [Uchar2::from([1, 2]), Uchar2::from([3, 4])];
+        assert_eq!(flatten(&bytes), [1, 2, 3, 4]);
+    }
+}
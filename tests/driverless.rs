@@ -0,0 +1,24 @@
+//! The CLI on a machine without OpenCL: `SIMPLE_GPU_OPENCL` names a library
+//! that does not exist, so nothing is loaded and commands fall back to the
+//! interpreter.
+
+use simple_gpu::icd::LIBRARY_VAR;
+use std::process::Command;
+
+fn simple_gpu(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_simple_gpu"))
+        .args(args)
+        .env(LIBRARY_VAR, "/nonexistent/libOpenCL.so")
+        .env_remove("LD_LIBRARY_PATH")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(output.status.success(), "simple_gpu {:?} failed: {}{}", args, stdout, String::from_utf8_lossy(&output.stderr));
+    stdout
+}
+
+#[test]
+fn cli_starts_and_falls_back_to_the_interpreter() {
+    assert!(simple_gpu(&["info"]).contains("interpreter"));
+    assert!(simple_gpu(&["sort", "--size", "256"]).contains("passed"));
+}
@@ -0,0 +1,520 @@
+//! Library, command-line and example kernels run on the interpreter and
+//! diffed against `simple_gpu::reference`.
+
+use ocl::prm::{Float2, Float4};
+use simple_gpu::datagen::{DataGen, Distribution};
+use simple_gpu::fft::{Direction, Fft};
+use simple_gpu::histogram::{Bins, Histogram};
+use simple_gpu::interpreter::{Buffer, Program};
+use simple_gpu::reference;
+use simple_gpu::sparse::{Csr, Ell, Variant};
+use std::f32::consts::PI;
+
+/// `saxpy_kernel` from examples/saxpy.rs.
+const SAXPY_SRC: &str = r#"
+    __kernel void saxpy_kernel(float alpha,
+                               __global const float *A,
+                               __global const float *B,
+                               __global float *C) {
+        int index = get_global_id(0);
+        C[index] = alpha * A[index] + B[index];
+    }
+"#;
+
+/// `matvec_mult` from examples/matvec.rs.
+const MATVEC_SRC: &str = r#"
+    __kernel void matvec_mult(__global float* mat, __global float* vec, __global float* result) {
+        int gid = get_global_id(0);
+        result[gid] = 0.0f;
+        for (int i = 0; i < 4; i++) {
+            result[gid] += mat[gid * 4 + i] * vec[i];
+        }
+    }
+"#;
+
+/// `polar_rect` from examples/polar_rect.rs.
+const POLAR_RECT_SRC: &str = r#"
+    __kernel void polar_rect(__global float4 *r_vals,
+                             __global float4 *angles,
+                             __global float4 *x_coords,
+                             __global float4 *y_coords) {
+       *y_coords = sincos(*angles, x_coords);
+       *x_coords *= *r_vals;
+       *y_coords *= *r_vals;
+    }
+"#;
+
+/// `mod_round` from examples/mod_round.rs.
+const MOD_ROUND_SRC: &str = r#"
+    __kernel void mod_round(__global float *mod_input,
+                            __global float *mod_output,
+                            __global float4 *round_input,
+                            __global float4 *round_output) {
+       mod_output[0] = fmod(mod_input[0], mod_input[1]);
+       mod_output[1] = remainder(mod_input[0], mod_input[1]);
+
+       round_output[0] = rint(*round_input);
+       round_output[1] = round(*round_input);
+       round_output[2] = ceil(*round_input);
+       round_output[3] = floor(*round_input);
+       round_output[4] = trunc(*round_input);
+    }
+"#;
+
+/// `bsort8` as examples/bsort.rs calls it: eight floats as two `float4`s,
+/// `dir` 0 for ascending and -1 for descending. The examples load it from
+/// hello_kernel.cl, which is not in the tree.
+const BSORT8_SRC: &str = r#"
+    /* Sorts a bitonic float4. */
+    float4 merge4(float4 v, int dir) {
+       float4 t = v.zwxy;
+       float4 lo = min(v, t), hi = max(v, t);
+       v = dir ? (float4)(hi.xy, lo.zw) : (float4)(lo.xy, hi.zw);
+       t = v.yxwz;
+       lo = min(v, t);
+       hi = max(v, t);
+       return dir ? (float4)(hi.x, lo.y, hi.z, lo.w) : (float4)(lo.x, hi.y, lo.z, hi.w);
+    }
+
+    float4 sort4(float4 v, int dir) {
+       float4 t = v.yxwz;
+       float4 lo = min(v, t), hi = max(v, t);
+       return merge4((float4)(lo.x, hi.y, hi.z, lo.w), dir);
+    }
+
+    __kernel void bsort8(__global float4 *data, int dir) {
+       float4 a = sort4(data[0], 0);
+       float4 b = sort4(data[1], -1);
+       float4 lo = min(a, b), hi = max(a, b);
+       data[0] = merge4(dir ? hi : lo, dir);
+       data[1] = merge4(dir ? lo : hi, dir);
+    }
+"#;
+
+/// `radix_sort8` as examples/radix.rs calls it: one bit per pass over eight
+/// `ushort`s, gathering each pass with `shuffle2`. Like `bsort8`, the example
+/// loads it from hello_kernel.cl.
+const RADIX_SORT8_SRC: &str = r#"
+    __kernel void radix_sort8(__global ushort8 *global_data) {
+       ushort8 data = global_data[0];
+       ushort ones[8];
+       ushort values[8];
+       ushort mask[8];
+       vstore8(data, 0, values);
+       for (uint bit = 1; bit < 8; bit <<= 1) {
+          uint zero_count = 0, one_count = 0;
+          for (int j = 0; j < 8; j++) {
+             if (values[j] & bit)
+                ones[one_count++] = values[j];
+             else
+                mask[zero_count++] = j;
+          }
+          for (uint j = zero_count; j < 8; j++)
+             mask[j] = 8 + j - zero_count;
+          for (uint j = one_count; j < 8; j++)
+             ones[j] = 0;
+          data = shuffle2(data, vload8(0, ones), vload8(0, mask));
+          vstore8(data, 0, values);
+       }
+       global_data[0] = data;
+    }
+"#;
+
+/// `bitonic_step` from src/cli/sort.rs.
+const BITONIC_STEP_SRC: &str = r#"
+    __kernel void bitonic_step(__global float *data, uint j, uint k, int descending) {
+       uint i = get_global_id(0);
+       uint partner = i ^ j;
+       if (partner > i) {
+          int ascending = ((i & k) == 0) != descending;
+          float a = data[i];
+          float b = data[partner];
+          if ((a > b) == ascending) {
+             data[i] = b;
+             data[partner] = a;
+          }
+       }
+    }
+"#;
+
+/// `reduction_scalar` and `reduction_vector` from src/cli/reduce.rs.
+const REDUCTION_SRC: &str = r#"
+    __kernel void reduction_scalar(__global float *data,
+                                   __local float *partial_sums,
+                                   __global float *output) {
+       int lid = get_local_id(0);
+       int group_size = get_local_size(0);
+
+       partial_sums[lid] = data[get_global_id(0)];
+       barrier(CLK_LOCAL_MEM_FENCE);
+
+       for(int i = group_size/2; i > 0; i >>= 1) {
+          if(lid < i)
+             partial_sums[lid] += partial_sums[lid + i];
+          barrier(CLK_LOCAL_MEM_FENCE);
+       }
+
+       if(lid == 0)
+          output[get_group_id(0)] = partial_sums[0];
+    }
+
+    __kernel void reduction_vector(__global float4 *data,
+                                   __local float4 *partial_sums,
+                                   __global float *output) {
+       int lid = get_local_id(0);
+       int group_size = get_local_size(0);
+
+       partial_sums[lid] = data[get_global_id(0)];
+       barrier(CLK_LOCAL_MEM_FENCE);
+
+       for(int i = group_size/2; i > 0; i >>= 1) {
+          if(lid < i)
+             partial_sums[lid] += partial_sums[lid + i];
+          barrier(CLK_LOCAL_MEM_FENCE);
+       }
+
+       if(lid == 0)
+          output[get_group_id(0)] = dot(partial_sums[0], (float4)(1.0f));
+    }
+"#;
+
+/// `count_word` from src/cli/search.rs.
+const COUNT_WORD_SRC: &str = r#"
+    __kernel void count_word(__global const uchar *text, uint text_len,
+                             __global const uchar *word, uint word_len,
+                             __global int *count) {
+       uint start = get_global_id(0);
+       if (start + word_len > text_len)
+          return;
+       for (uint i = 0; i < word_len; i++) {
+          if (text[start + i] != word[i])
+             return;
+       }
+       atomic_inc(count);
+    }
+"#;
+
+/// `resize_nearest` from src/cli/resize.rs.
+const RESIZE_NEAREST_SRC: &str = r#"
+    __kernel void resize_nearest(__global const uchar4 *src,
+                                 __global uchar4 *dst,
+                                 uint src_width, uint scale) {
+       uint x = get_global_id(0);
+       uint y = get_global_id(1);
+       uint dst_width = get_global_size(0);
+       dst[y * dst_width + x] = src[(y / scale) * src_width + x / scale];
+    }
+"#;
+
+#[test]
+fn saxpy_matches_reference() {
+    let mut data = DataGen::new(1);
+    let a: Vec<f32> = data.values(Distribution::Uniform, 256, 100.0);
+    let b: Vec<f32> = data.values(Distribution::Uniform, 256, 100.0);
+    let program = Program::build(SAXPY_SRC).unwrap();
+    let (mut a_buf, mut b_buf) = (Buffer::from_slice(&a), Buffer::from_slice(&b));
+    let mut c = Buffer::new::<f32>(a.len());
+    program
+        .kernel("saxpy_kernel")
+        .unwrap()
+        .arg(2.5f32)
+        .arg_buf(&mut a_buf)
+        .arg_buf(&mut b_buf)
+        .arg_buf(&mut c)
+        .global_work_size(a.len())
+        .run()
+        .unwrap();
+    reference::diff(&c.to_vec(), &reference::saxpy(2.5, &a, &b), 1e-6).unwrap();
+}
+
+#[test]
+fn matvec_matches_reference() {
+    let mut data = DataGen::new(2);
+    let mat: Vec<f32> = data.values(Distribution::Uniform, 32, 10.0);
+    let vec: Vec<f32> = data.values(Distribution::Uniform, 4, 10.0);
+    let program = Program::build(MATVEC_SRC).unwrap();
+    let (mut mat_buf, mut vec_buf) = (Buffer::from_slice(&mat), Buffer::from_slice(&vec));
+    let mut result = Buffer::new::<f32>(8);
+    program
+        .kernel("matvec_mult")
+        .unwrap()
+        .arg_buf(&mut mat_buf)
+        .arg_buf(&mut vec_buf)
+        .arg_buf(&mut result)
+        .global_work_size(8)
+        .run()
+        .unwrap();
+    reference::diff(&result.to_vec(), &reference::matvec(&mat, &vec), 1e-5).unwrap();
+}
+
+#[test]
+fn histogram_matches_reference() {
+    let mut data = DataGen::new(3);
+    let ints: Vec<i32> = data.values(Distribution::Normal, 3000, 1000.0);
+    let hist = Histogram::<i32>::new(Bins::uniform(40, 100.0, 900.0)).unwrap();
+    assert_eq!(hist.run_interpreted(&ints).unwrap(), reference::histogram(&hist, &ints));
+
+    let floats: Vec<f32> = data.values(Distribution::Uniform, 3000, 1.0);
+    let hist = Histogram::<f32>::new(Bins::Edges(vec![0.0, 0.1, 0.25, 0.5, 0.9])).unwrap().channels(3);
+    assert_eq!(hist.run_interpreted(&floats).unwrap(), reference::histogram(&hist, &floats));
+}
+
+#[test]
+fn spmv_matches_reference() {
+    let mut data = DataGen::new(4);
+    let (rows, cols) = (70, 50);
+    let columns: Vec<usize> = data.values(Distribution::Uniform, 600, cols as f64);
+    let values: Vec<f32> = data.values(Distribution::Uniform, 600, 2.0);
+    // Squares mod `rows` hit some rows often and others never, so row
+    // lengths vary and some rows are empty.
+    let triplets: Vec<_> =
+        columns.iter().zip(&values).enumerate().map(|(i, (&col, &value))| (i * i % rows, col, value)).collect();
+    let csr = Csr::from_triplets(rows, cols, &triplets).unwrap();
+    let x: Vec<f32> = data.values(Distribution::Uniform, cols, 1.0);
+    let expected = reference::spmv(&csr, &x);
+
+    reference::diff(&csr.spmv_interpreted(&x, Variant::Scalar).unwrap(), &expected, 1e-4).unwrap();
+    for lanes in [2, 8, 32] {
+        reference::diff(&csr.spmv_interpreted(&x, Variant::Vector { lanes }).unwrap(), &expected, 1e-4).unwrap();
+    }
+    reference::diff(&Ell::from_csr(&csr).spmv_interpreted(&x).unwrap(), &expected, 1e-4).unwrap();
+}
+
+fn complex(data: &mut DataGen, len: usize) -> Vec<Float2> {
+    let parts: Vec<f32> = data.values(Distribution::Uniform, 2 * len, 1.0);
+    parts.chunks(2).map(|c| Float2::new(c[0], c[1])).collect()
+}
+
+fn fft_error(actual: &[Float2], expected: &[Float2]) -> f64 {
+    let flatten = |v: &[Float2]| v.iter().flat_map(|c| [c[0], c[1]]).collect::<Vec<f32>>();
+    reference::relative_error(&flatten(actual), &flatten(expected))
+}
+
+#[test]
+fn fft_matches_reference() {
+    let mut data = DataGen::new(5);
+    // Radix 4, 2, 3, 5 and 7 passes, batched.
+    for (len, batch) in [(8, 3), (12, 2), (105, 1), (360, 2)] {
+        let fft = Fft::new(len).unwrap().batch(batch);
+        let input = complex(&mut data, len * batch);
+        let spectrum = fft.run_interpreted(Direction::Forward, &input).unwrap();
+        let error = fft_error(&spectrum, &reference::dft(&input, len, false));
+        assert!(error <= fft.error_bound() as f64, "{} x{}: error {:e}", len, batch, error);
+        let back = fft.run_interpreted(Direction::Inverse, &spectrum).unwrap();
+        let error = fft_error(&back, &input);
+        assert!(error <= fft.error_bound() as f64, "{} x{} round trip: error {:e}", len, batch, error);
+    }
+    for (height, width, batch) in [(4, 8, 1), (6, 10, 2)] {
+        let fft = Fft::new_2d(height, width).unwrap().batch(batch);
+        let input = complex(&mut data, fft.len() * batch);
+        let spectrum = fft.run_interpreted(Direction::Forward, &input).unwrap();
+        let error = fft_error(&spectrum, &reference::dft_2d(&input, height, width, false));
+        assert!(error <= fft.error_bound() as f64, "{}x{} x{}: error {:e}", height, width, batch, error);
+    }
+}
+
+#[test]
+fn real_fft_matches_reference() {
+    let mut data = DataGen::new(6);
+    for (height, width, batch) in [(1, 16, 3), (1, 90, 1), (5, 6, 2)] {
+        let fft = if height == 1 { Fft::new(width) } else { Fft::new_2d(height, width) }.unwrap().batch(batch);
+        let input: Vec<f32> = data.values(Distribution::Uniform, fft.len() * batch, 1.0);
+        let spectrum = fft.forward_real_interpreted(&input).unwrap();
+        // The full complex transform, cut down to the bins a real one keeps.
+        let complex: Vec<Float2> = input.iter().map(|&x| Float2::new(x, 0.0)).collect();
+        let full = if height == 1 {
+            reference::dft(&complex, width, false)
+        } else {
+            reference::dft_2d(&complex, height, width, false)
+        };
+        let expected: Vec<Float2> = full.chunks(width).flat_map(|row| row[..width / 2 + 1].iter().copied()).collect();
+        let error = fft_error(&spectrum, &expected);
+        assert!(error <= fft.error_bound() as f64, "real {}x{} x{}: error {:e}", height, width, batch, error);
+        let back = fft.inverse_real_interpreted(&spectrum).unwrap();
+        let error = reference::relative_error(&back, &input);
+        assert!(error <= fft.error_bound() as f64, "real {}x{} x{} round trip: error {:e}", height, width, batch, error);
+    }
+}
+
+#[test]
+fn polar_rect_matches_reference() {
+    let r = [2.0f32, 1.0, 3.0, 4.0];
+    let angles = [3.0 * PI / 8.0, 3.0 * PI / 4.0, 4.0 * PI / 3.0, 11.0 * PI / 6.0];
+    let program = Program::build(POLAR_RECT_SRC).unwrap();
+    let (mut r_buf, mut angles_buf) = (Buffer::from_slice(&r), Buffer::from_slice(&angles));
+    let (mut x, mut y) = (Buffer::new::<f32>(4), Buffer::new::<f32>(4));
+    program
+        .kernel("polar_rect")
+        .unwrap()
+        .arg_buf(&mut r_buf)
+        .arg_buf(&mut angles_buf)
+        .arg_buf(&mut x)
+        .arg_buf(&mut y)
+        .global_work_size(1)
+        .run()
+        .unwrap();
+    let (x_ref, y_ref) = reference::polar_rect(&r, &angles);
+    reference::diff(&x.to_vec(), &x_ref, 1e-5).unwrap();
+    reference::diff(&y.to_vec(), &y_ref, 1e-5).unwrap();
+}
+
+#[test]
+fn mod_round_matches_reference() {
+    // Halfway cases tell rint (ties to even) from round (ties away).
+    let mod_input = [317.0f32, 23.0];
+    let round_input = [-6.5f32, -3.5, 3.5, 6.5];
+    let program = Program::build(MOD_ROUND_SRC).unwrap();
+    let (mut mod_in, mut round_in) = (Buffer::from_slice(&mod_input), Buffer::from_slice(&round_input));
+    let (mut mod_out, mut round_out) = (Buffer::new::<f32>(2), Buffer::new::<f32>(20));
+    program
+        .kernel("mod_round")
+        .unwrap()
+        .arg_buf(&mut mod_in)
+        .arg_buf(&mut mod_out)
+        .arg_buf(&mut round_in)
+        .arg_buf(&mut round_out)
+        .global_work_size(1)
+        .run()
+        .unwrap();
+    let (mod_ref, round_ref) = reference::mod_round(mod_input, round_input);
+    reference::diff(&mod_out.to_vec(), &mod_ref, 1e-6).unwrap();
+    reference::diff_exact(&round_out.to_vec::<f32>(), round_ref.as_flattened()).unwrap();
+}
+
+#[test]
+fn bsort8_matches_reference() {
+    let program = Program::build(BSORT8_SRC).unwrap();
+    let mut datagen = DataGen::new(7);
+    for distribution in Distribution::ALL {
+        for dir in [0, -1] {
+            let data: Vec<f32> = datagen.values(distribution, 8, 100.0);
+            let mut buffer = Buffer::from_slice(&data);
+            program.kernel("bsort8").unwrap().arg_buf(&mut buffer).arg(dir).global_work_size(1).run().unwrap();
+            let mut expected: [f32; 8] = data.try_into().unwrap();
+            reference::bsort8(&mut expected, dir);
+            if let Err(mismatch) = reference::diff_exact(&buffer.to_vec::<f32>(), &expected) {
+                panic!("{} data, dir {}: {}", distribution, dir, mismatch);
+            }
+        }
+    }
+}
+
+#[test]
+fn radix_sort8_matches_reference() {
+    let program = Program::build(RADIX_SORT8_SRC).unwrap();
+    let mut datagen = DataGen::new(8);
+    for _ in 0..10 {
+        let mut data: [u16; 8] = std::array::from_fn(|i| i as u16);
+        datagen.shuffle(&mut data);
+        let mut buffer = Buffer::from_slice(&data);
+        program.kernel("radix_sort8").unwrap().arg_buf(&mut buffer).global_work_size(1).run().unwrap();
+        let mut expected = data;
+        reference::radix_sort8(&mut expected);
+        if let Err(mismatch) = reference::diff_exact(&buffer.to_vec::<u16>(), &expected) {
+            panic!("{:?}: {}", data, mismatch);
+        }
+    }
+}
+
+#[test]
+fn bitonic_step_matches_reference() {
+    let program = Program::build(BITONIC_STEP_SRC).unwrap();
+    let mut datagen = DataGen::new(9);
+    for distribution in Distribution::ALL {
+        for descending in [false, true] {
+            let data: Vec<f32> = datagen.values(distribution, 64, 1000.0);
+            let mut buffer = Buffer::from_slice(&data);
+            // One launch per stage, as `simple_gpu sort` does.
+            let mut k = 2;
+            while k <= data.len() {
+                let mut j = k / 2;
+                while j > 0 {
+                    program
+                        .kernel("bitonic_step")
+                        .unwrap()
+                        .arg_buf(&mut buffer)
+                        .arg(j as u32)
+                        .arg(k as u32)
+                        .arg(descending as i32)
+                        .global_work_size(data.len())
+                        .run()
+                        .unwrap();
+                    j /= 2;
+                }
+                k *= 2;
+            }
+            let mut expected = data;
+            reference::bitonic_sort(&mut expected, descending);
+            if let Err(mismatch) = reference::diff_exact(&buffer.to_vec::<f32>(), &expected) {
+                panic!("{} data, descending {}: {}", distribution, descending, mismatch);
+            }
+        }
+    }
+}
+
+#[test]
+fn reduction_kernels_match_reference() {
+    let program = Program::build(REDUCTION_SRC).unwrap();
+    let (local, groups) = (32, 4);
+    for (kernel, width) in [("reduction_scalar", 1), ("reduction_vector", 4)] {
+        let data: Vec<f32> = DataGen::new(10).values(Distribution::Uniform, local * width * groups, 1.0);
+        let mut data_buf = Buffer::from_slice(&data);
+        let mut sums = Buffer::new::<f32>(groups);
+        let launch = program.kernel(kernel).unwrap().arg_buf(&mut data_buf);
+        let launch = if width == 4 { launch.arg_local::<Float4>(local) } else { launch.arg_local::<f32>(local) };
+        launch.arg_buf(&mut sums).global_work_size(local * groups).local_work_size(local).run().unwrap();
+        let expected = reference::partial_sums(&data, local * width);
+        if let Err(mismatch) = reference::diff(&sums.to_vec::<f32>(), &expected, 1e-4) {
+            panic!("{}: {}", kernel, mismatch);
+        }
+    }
+}
+
+#[test]
+fn count_word_matches_reference() {
+    let program = Program::build(COUNT_WORD_SRC).unwrap();
+    let pattern = b"thatwithhavefrom";
+    let words: Vec<&str> = pattern.chunks(4).map(|w| std::str::from_utf8(w).unwrap()).collect();
+    let text = DataGen::new(11).text(2000, &words).into_bytes();
+    let mut text_buf = Buffer::from_slice(&text);
+    let mut counts = [0i32; 4];
+    for (word, count) in words.iter().zip(&mut counts) {
+        let mut word_buf = Buffer::from_slice(word.as_bytes());
+        let mut count_buf = Buffer::new::<i32>(1);
+        program
+            .kernel("count_word")
+            .unwrap()
+            .arg_buf(&mut text_buf)
+            .arg(text.len() as u32)
+            .arg_buf(&mut word_buf)
+            .arg(word.len() as u32)
+            .arg_buf(&mut count_buf)
+            .global_work_size(text.len().next_multiple_of(64))
+            .local_work_size(64)
+            .run()
+            .unwrap();
+        *count = count_buf.to_vec::<i32>()[0];
+    }
+    assert!(counts.iter().all(|&count| count > 0), "planted words not found: {:?}", counts);
+    assert_eq!(counts, reference::string_search(&text, pattern));
+}
+
+#[test]
+fn resize_nearest_matches_reference() {
+    let program = Program::build(RESIZE_NEAREST_SRC).unwrap();
+    let (width, height, scale) = (5, 3, 3);
+    let src = DataGen::new(12).rgba_image(width as u32, height as u32);
+    let mut src_buf = Buffer::from_slice(src.as_raw());
+    let mut dst = Buffer::new::<u8>(width * height * scale * scale * 4);
+    program
+        .kernel("resize_nearest")
+        .unwrap()
+        .arg_buf(&mut src_buf)
+        .arg_buf(&mut dst)
+        .arg(width as u32)
+        .arg(scale as u32)
+        .global_work_size([width * scale, height * scale])
+        .run()
+        .unwrap();
+    reference::diff_exact(&dst.to_vec::<u8>(), &reference::interp(src.as_raw(), width, height, scale)).unwrap();
+}